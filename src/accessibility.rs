@@ -0,0 +1,66 @@
+use bevy::prelude::*;
+
+// Options for users who can't rely on colors or small text: a toggles speed glyphs, the
+// arrows then being sized by the speed in a single color instead of hued by it, - and +
+// change the scale of the text panels and 0 cycles how much the smoke hides the arrows
+// behind it. The color-blind friendly colormaps are palettes.
+
+/// Largest scale of the text panels, in screen pixels per font pixel
+const MAX_UI_SCALE: usize = 4;
+/// Arrow occlusions cycled by 0
+const OCCLUSIONS: [f32; 3] = [0.0, 0.5, 1.0];
+
+pub struct AccessibilityPlugin;
+
+impl Plugin for AccessibilityPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(Accessibility::default())
+            .add_system(accessibility_keys_system.system());
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Accessibility {
+    /// Size the arrows by the speed instead of coloring them by it
+    pub speed_glyphs: bool,
+    /// Screen pixels per font pixel of the text panels
+    pub ui_scale: usize,
+    /// How much the arrows fade into the smoke in front of them, from 0 to 1, dense cells
+    /// hiding them the most
+    pub arrow_occlusion: f32,
+}
+
+impl Default for Accessibility {
+    fn default() -> Self {
+        Self {
+            speed_glyphs: false,
+            ui_scale: 2,
+            arrow_occlusion: 0.0,
+        }
+    }
+}
+
+fn accessibility_keys_system(
+    mut accessibility: ResMut<Accessibility>,
+    mut char_input_events: EventReader<ReceivedCharacter>,
+) {
+    for event in char_input_events.iter() {
+        match event.char {
+            'a' => {
+                accessibility.speed_glyphs = !accessibility.speed_glyphs;
+                info!("Speed glyphs: {}", accessibility.speed_glyphs);
+            }
+            '-' => accessibility.ui_scale = (accessibility.ui_scale - 1).max(1),
+            '+' | '=' => accessibility.ui_scale = (accessibility.ui_scale + 1).min(MAX_UI_SCALE),
+            '0' => {
+                let next = OCCLUSIONS
+                    .iter()
+                    .position(|&occlusion| occlusion > accessibility.arrow_occlusion)
+                    .unwrap_or_default();
+                accessibility.arrow_occlusion = OCCLUSIONS[next];
+                info!("Arrow occlusion: {}", accessibility.arrow_occlusion);
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,270 @@
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+
+use crate::errors::ErrorLog;
+use crate::layers::{Layer, OnLayer};
+use crate::scenes::SceneSelection;
+use crate::stepping::StepControl;
+use crate::viewport::{self, ViewSlot};
+use crate::{grid_to_world, AppState, Grid, Position, CELL_SIZE};
+
+// Time average: the density and velocity averaged over the simulated time since it started,
+// the steady structure of the flow standing out once the turbulent fluctuations around it
+// cancel out. Ctrl+A shows it over the density, the mean density as the lightness and the
+// mean velocity as the hue of its direction, saturated by its speed, then the Reynolds
+// decomposition's other half, the fluctuation of the velocity around its mean, then hides
+// it again, averaging all along. Ctrl+Shift+A starts the average over.
+
+pub struct AveragePlugin;
+
+impl Plugin for AveragePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(TimeAverage::default())
+            .add_system_set(
+                SystemSet::on_enter(AppState::Running).with_system(average_setup.system()),
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::Running)
+                    .with_system(average_keys_system.system())
+                    .with_system(time_average_system.system())
+                    .with_system(average_square_system.system()),
+            );
+    }
+}
+
+struct AverageSquare;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AverageView {
+    Off,
+    /// Mean density and velocity
+    Mean,
+    /// Speed of the velocity relative to its mean
+    Fluctuation,
+}
+
+/// Density and velocity of every cell integrated over the simulated time
+pub struct TimeAverage {
+    pub view: AverageView,
+    density: Vec<Vec<f32>>,
+    velocity: Vec<Vec<Vec2>>,
+    seconds: f32,
+    /// Simulated time of the last step added, so a paused frame isn't added twice
+    last_time: f32,
+}
+
+impl Default for TimeAverage {
+    fn default() -> Self {
+        Self {
+            view: AverageView::Off,
+            density: Vec::new(),
+            velocity: Vec::new(),
+            seconds: 0.0,
+            last_time: f32::NAN,
+        }
+    }
+}
+
+impl TimeAverage {
+    pub fn memory_bytes(&self) -> usize {
+        let cells: usize = self.density.iter().map(Vec::len).sum();
+        cells * (std::mem::size_of::<f32>() + std::mem::size_of::<Vec2>())
+    }
+
+    fn reset(&mut self, width: usize, height: usize) {
+        self.density = vec![vec![0.0; width]; height];
+        self.velocity = vec![vec![Vec2::ZERO; width]; height];
+        self.seconds = 0.0;
+    }
+
+    /// Mean density and velocity of a cell, none before any step was added
+    fn mean(&self, x: usize, y: usize) -> Option<(f32, Vec2)> {
+        if self.seconds == 0.0 {
+            return None;
+        }
+        let density = self.density.get(y)?.get(x)?;
+        let velocity = self.velocity.get(y)?.get(x)?;
+        Some((density / self.seconds, *velocity / self.seconds))
+    }
+}
+
+fn average_setup(
+    mut commands: Commands,
+    selection: Res<SceneSelection>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let (width, height) = selection.grid_size();
+    let (columns, rows) = viewport::view_size(width, height);
+    for y in 0..rows {
+        for x in 0..columns {
+            let position = Vec2::new(x as f32, y as f32);
+            let translation = grid_to_world(position, width, height).extend(0.7);
+
+            commands
+                .spawn_bundle(SpriteBundle {
+                    material: materials.add(Color::BLACK.into()),
+                    transform: Transform::from_translation(translation),
+                    sprite: Sprite::new(Vec2::new(CELL_SIZE, CELL_SIZE)),
+                    visible: Visible {
+                        is_visible: false,
+                        is_transparent: false,
+                    },
+                    ..Default::default()
+                })
+                .insert(AverageSquare)
+                .insert(OnLayer {
+                    layer: Layer::Density,
+                    offset: 0.7,
+                })
+                .insert(Position { x, y })
+                .insert(ViewSlot { x, y });
+        }
+    }
+}
+
+/// Ctrl+A cycles through the mean, the fluctuation and no time average, Ctrl+Shift+A starts
+/// it over
+fn average_keys_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut average: ResMut<TimeAverage>,
+    qg: Query<&Grid>,
+) {
+    let ctrl =
+        keyboard_input.pressed(KeyCode::LControl) || keyboard_input.pressed(KeyCode::RControl);
+    if !ctrl || !keyboard_input.just_pressed(KeyCode::A) {
+        return;
+    }
+
+    let shift = keyboard_input.pressed(KeyCode::LShift) || keyboard_input.pressed(KeyCode::RShift);
+    if shift {
+        if let Ok(grid) = qg.single() {
+            info!("Time average reset after {:.1} s", average.seconds);
+            average.reset(grid.width(), grid.height());
+        }
+    } else {
+        average.view = match average.view {
+            AverageView::Off => AverageView::Mean,
+            AverageView::Mean => AverageView::Fluctuation,
+            AverageView::Fluctuation => AverageView::Off,
+        };
+    }
+}
+
+/// Integrate every step while the average is shown, starting over when the grid size
+/// changes
+fn time_average_system(
+    control: Res<StepControl>,
+    mut average: ResMut<TimeAverage>,
+    qg: Query<&Grid>,
+) {
+    if average.view == AverageView::Off || control.time == average.last_time {
+        return;
+    }
+    let grid = match qg.single() {
+        Ok(grid) => grid,
+        Err(_) => return,
+    };
+    if average.density.len() != grid.height() || average.density[0].len() != grid.width() {
+        average.reset(grid.width(), grid.height());
+    }
+
+    let dt = control.dt;
+    let TimeAverage {
+        density, velocity, ..
+    } = &mut *average;
+    for (y, row) in grid.0.iter().enumerate() {
+        for (x, cell) in row.iter().enumerate() {
+            density[y][x] += cell.density * dt;
+            velocity[y][x] += cell.velocity * dt;
+        }
+    }
+    average.seconds += dt;
+    average.last_time = control.time;
+}
+
+/// Black at rest through red and yellow to white at the largest fluctuation, apart from the
+/// hues of the mean
+fn fluctuation_color(v: f32) -> Color {
+    let v = v.clamp(0.0, 1.0);
+    Color::rgb(
+        (3.0 * v).min(1.0),
+        (3.0 * v - 1.0).clamp(0.0, 1.0),
+        (3.0 * v - 2.0).clamp(0.0, 1.0),
+    )
+}
+
+/// Display the mean density as the lightness and the mean velocity as the hue of its
+/// direction, saturated by its speed relative to the fastest cell, or the speed of the
+/// fluctuation normalized by the largest one
+fn average_square_system(
+    average: Res<TimeAverage>,
+    mut errors: ResMut<ErrorLog>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    qg: Query<&Grid>,
+    mut query: Query<(
+        &AverageSquare,
+        &Position,
+        &Handle<ColorMaterial>,
+        &mut Visible,
+    )>,
+) {
+    let max_speed = average
+        .velocity
+        .iter()
+        .flatten()
+        .map(|velocity| velocity.length())
+        .fold(0.0, f32::max)
+        / average.seconds.max(f32::EPSILON);
+    let grid = qg.single().ok();
+    let fluctuation = |x: usize, y: usize, grid: &Grid| {
+        average.mean(x, y).map_or(0.0, |(_, velocity)| {
+            (grid.0[y][x].velocity - velocity).length()
+        })
+    };
+    let max_fluctuation = match grid {
+        Some(grid) if average.view == AverageView::Fluctuation => (0..grid.height())
+            .flat_map(|y| (0..grid.width()).map(move |x| (x, y)))
+            .map(|(x, y)| fluctuation(x, y, grid))
+            .fold(0.0, f32::max),
+        _ => 0.0,
+    };
+
+    for (_average_square, position, color, mut visible) in query.iter_mut() {
+        visible.is_visible = average.view != AverageView::Off && grid.is_some();
+        let grid = match grid {
+            Some(grid) if visible.is_visible => grid,
+            _ => continue,
+        };
+
+        let color_mat = match materials.get_mut(&*color) {
+            Some(material) => material,
+            None => {
+                errors.report("Missing material of a time average square");
+                continue;
+            }
+        };
+        let Position { x, y } = *position;
+        if average.view == AverageView::Fluctuation {
+            color_mat.color = if max_fluctuation > 0.0 {
+                fluctuation_color(fluctuation(x, y, grid) / max_fluctuation)
+            } else {
+                Color::BLACK
+            };
+            continue;
+        }
+        color_mat.color = match average.mean(x, y) {
+            Some((density, velocity)) => {
+                let hue = (velocity.y.atan2(velocity.x) * 180.0 / PI).rem_euclid(360.0);
+                let saturation = if max_speed > 0.0 {
+                    velocity.length() / max_speed
+                } else {
+                    0.0
+                };
+                let lightness = 0.1 + 0.8 * density.clamp(0.0, 1.0);
+                Color::hsl(hue, saturation, lightness)
+            }
+            None => Color::BLACK,
+        };
+    }
+}
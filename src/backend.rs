@@ -0,0 +1,252 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Barrier, Mutex};
+use std::thread::{self, JoinHandle};
+
+#[cfg(debug_assertions)]
+use crate::alloc_counter::{add_allocations, allocations};
+
+// Solver backends: the scalar one runs the stages on the main thread, the threaded one
+// splits the grid rows between threads, one per CPU core unless told otherwise, and
+// relies on the compiler vectorizing the inner loops. Its threads are spawned once and
+// kept in the solver scratch. There's no GPU backend yet: the renderer has no compute
+// shader support.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Backend {
+    Gpu,
+    /// Number of threads
+    Threaded(usize),
+    Scalar,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "gpu" => Ok(Self::Gpu),
+            "threaded" => Ok(Self::Threaded(num_cpus::get())),
+            "scalar" => Ok(Self::Scalar),
+            _ => Err(format!(
+                "unknown backend {:?}, expected gpu, threaded or scalar",
+                s
+            )),
+        }
+    }
+}
+
+/// Whether the renderer can run compute shaders, which the GPU backend would need
+fn compute_shaders_supported() -> bool {
+    false
+}
+
+/// Pick the best backend the machine supports, or the requested one if it's available,
+/// printing the choice and why. `threads` overrides the thread count of the threaded backend.
+pub fn select(requested: Option<Backend>, threads: Option<usize>) -> Backend {
+    let cores = num_cpus::get();
+    let threads = threads.unwrap_or(cores);
+    let gpu = compute_shaders_supported();
+
+    let (mut backend, reason) = match requested {
+        Some(Backend::Gpu) if !gpu => (
+            fallback(threads),
+            "requested gpu but compute shaders aren't supported".to_string(),
+        ),
+        Some(backend) => (backend, "requested".to_string()),
+        None if gpu => (Backend::Gpu, "compute shaders are supported".to_string()),
+        None => (fallback(threads), format!("{} CPU cores", cores)),
+    };
+
+    if let Backend::Threaded(count) = &mut backend {
+        *count = threads;
+    }
+
+    println!("Solver backend: {:?} ({})", backend, reason);
+    backend
+}
+
+fn fallback(threads: usize) -> Backend {
+    if threads > 1 {
+        Backend::Threaded(threads)
+    } else {
+        Backend::Scalar
+    }
+}
+
+#[cfg(not(debug_assertions))]
+fn allocations() -> usize {
+    0
+}
+
+#[cfg(not(debug_assertions))]
+fn add_allocations(_: usize) {}
+
+/// Threads of the threaded backend, spawned once and waiting for work between the stages.
+/// The thread handing them the rows works on a band of them too.
+#[derive(Default)]
+pub struct Workers {
+    shared: Option<Arc<Shared>>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+struct Shared {
+    /// Work of the current call, none telling the workers to stop
+    job: Mutex<Option<Job>>,
+    start: Barrier,
+    done: Barrier,
+    /// Allocations the workers made for the current call, which count as the caller's
+    allocations: AtomicUsize,
+    panicked: AtomicBool,
+}
+
+/// Borrowed for longer than it lives: `Workers::run` waits for every worker to be done
+/// with it before returning
+type Job = &'static (dyn Fn() + Sync);
+
+impl Workers {
+    /// `threads` threads in all, counting the caller
+    pub fn new(threads: usize) -> Self {
+        let spawned = threads.max(1) - 1;
+        if spawned == 0 {
+            return Self::default();
+        }
+        let shared = Arc::new(Shared {
+            job: Mutex::new(None),
+            start: Barrier::new(spawned + 1),
+            done: Barrier::new(spawned + 1),
+            allocations: AtomicUsize::new(0),
+            panicked: AtomicBool::new(false),
+        });
+        let handles = (0..spawned)
+            .map(|_| {
+                let shared = Arc::clone(&shared);
+                thread::spawn(move || work(&shared))
+            })
+            .collect();
+        Self {
+            shared: Some(shared),
+            handles,
+        }
+    }
+
+    pub fn threads(&self) -> usize {
+        self.handles.len() + 1
+    }
+
+    /// Have as many threads as the backend runs on, only spawning them when the count
+    /// changed
+    pub fn prepare(&mut self, backend: Backend) {
+        let threads = match backend {
+            Backend::Threaded(threads) => threads.max(1),
+            Backend::Gpu | Backend::Scalar => 1,
+        };
+        if self.threads() != threads {
+            *self = Self::new(threads);
+        }
+    }
+
+    /// Run `f` on every row with its index, one band of rows per thread
+    pub fn for_each_row<T: Send>(&self, rows: &mut [Vec<T>], f: impl Fn(usize, &mut [T]) + Sync) {
+        let total = rows.len();
+        let band = ((total + self.handles.len()) / self.threads()).max(1);
+        let remaining = Mutex::new(rows);
+        // Every thread takes the next band off the rows left
+        self.run(&|| {
+            let (start, rows) = {
+                let mut remaining = remaining.lock().unwrap();
+                let rows = std::mem::take(&mut *remaining);
+                let start = total - rows.len();
+                let (taken, rest) = rows.split_at_mut(band.min(rows.len()));
+                *remaining = rest;
+                (start, taken)
+            };
+            for (i, row) in rows.iter_mut().enumerate() {
+                f(start + i, row);
+            }
+        });
+    }
+
+    /// Run `job` once on every thread, returning when all of them are done
+    fn run(&self, job: &(dyn Fn() + Sync)) {
+        let shared = match &self.shared {
+            Some(shared) => shared,
+            None => return job(),
+        };
+        // SAFETY: the workers only call the job between the two barriers, and it outlives
+        // the second one, waited for even when the job panics here
+        let job = unsafe { std::mem::transmute::<&(dyn Fn() + Sync), Job>(job) };
+        *shared.job.lock().unwrap() = Some(job);
+        shared.start.wait();
+        let result = panic::catch_unwind(AssertUnwindSafe(job));
+        shared.done.wait();
+        *shared.job.lock().unwrap() = None;
+
+        add_allocations(shared.allocations.swap(0, Ordering::Relaxed));
+        if let Err(payload) = result {
+            panic::resume_unwind(payload);
+        }
+        if shared.panicked.swap(false, Ordering::Relaxed) {
+            panic!("a solver worker panicked");
+        }
+    }
+}
+
+impl Drop for Workers {
+    fn drop(&mut self) {
+        if let Some(shared) = &self.shared {
+            // Starting without a job stops the workers
+            shared.start.wait();
+        }
+        for handle in self.handles.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Run the jobs `Workers::run` hands out until there's none
+fn work(shared: &Shared) {
+    loop {
+        shared.start.wait();
+        let job = match *shared.job.lock().unwrap() {
+            Some(job) => job,
+            None => return,
+        };
+        let before = allocations();
+        if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+            shared.panicked.store(true, Ordering::Relaxed);
+        }
+        let made = allocations() - before;
+        shared.allocations.fetch_add(made, Ordering::Relaxed);
+        shared.done.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_row_is_visited_once_with_its_index() {
+        for &threads in &[1, 3, 8] {
+            let workers = Workers::new(threads);
+            let mut rows = vec![vec![0; 2]; 7];
+            // The same workers run every call
+            for _ in 0..3 {
+                workers.for_each_row(&mut rows, |y, row| row.iter_mut().for_each(|v| *v += y));
+            }
+            let expected: Vec<_> = (0..7).map(|y| vec![3 * y; 2]).collect();
+            assert_eq!(rows, expected, "{} threads", threads);
+        }
+    }
+
+    #[test]
+    fn prepare_spawns_as_many_threads_as_the_backend_runs_on() {
+        let mut workers = Workers::default();
+        workers.prepare(Backend::Threaded(4));
+        assert_eq!(workers.threads(), 4);
+        workers.prepare(Backend::Scalar);
+        assert_eq!(workers.threads(), 1);
+    }
+}
@@ -0,0 +1,222 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use serde::Deserialize;
+
+use crate::memory::MemoryBudget;
+use crate::pod::{self, PodOptions};
+use crate::poster::{self, PosterOptions};
+use crate::render;
+use crate::settings::SolverSettings;
+
+// Batch runs: a RON file lists jobs, each a scene file simulated for a while then exported,
+// which run one after the other without a window, e.g. overnight:
+//
+// ```ron
+// (
+//     log_dir: Some("logs"),
+//     jobs: [
+//         (scene: "plume.ron", export: Frames(frames: 600, out: "plume")),
+//         (scene: "fire.ron", export: Poster(steps: 300, scale: 16, out: "fire.png")),
+//         (scene: "fire.ron", export: Pod(snapshots: 64, interval: 5, modes: 6, out: "pod")),
+//     ],
+// )
+// ```
+//
+// Relative paths start from the directory of the batch file. A failing job doesn't stop the
+// next ones: each job writes its log to the log directory, where the summary of them all is
+// written at the end.
+
+#[derive(Debug, Deserialize)]
+pub struct BatchFile {
+    /// Where the logs and the summary go, batch_logs when missing
+    #[serde(default)]
+    pub log_dir: Option<PathBuf>,
+    pub jobs: Vec<BatchJob>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BatchJob {
+    pub scene: PathBuf,
+    pub export: Export,
+}
+
+/// How long a job simulates its scene and what it writes
+#[derive(Debug, Deserialize)]
+pub enum Export {
+    /// Every frame as a PNG, see `render`
+    Frames { frames: usize, out: PathBuf },
+    /// The last frame as one large PNG, see `poster`
+    Poster {
+        steps: usize,
+        #[serde(default = "poster_scale")]
+        scale: u32,
+        out: PathBuf,
+    },
+    /// The most energetic modes of the velocity, see `pod`
+    Pod {
+        snapshots: usize,
+        interval: usize,
+        modes: usize,
+        out: PathBuf,
+    },
+}
+
+fn poster_scale() -> u32 {
+    16
+}
+
+impl Export {
+    fn out(&self) -> &Path {
+        match self {
+            Self::Frames { out, .. } | Self::Poster { out, .. } | Self::Pod { out, .. } => out,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Self::Frames { frames, out } => format!("{} frames to {}", frames, out.display()),
+            Self::Poster { steps, out, .. } => {
+                format!("poster after {} steps to {}", steps, out.display())
+            }
+            Self::Pod { snapshots, out, .. } => {
+                format!("POD of {} snapshots to {}", snapshots, out.display())
+            }
+        }
+    }
+}
+
+/// How the jobs of a batch went
+pub struct Report {
+    pub succeeded: usize,
+    pub failed: usize,
+    pub summary: PathBuf,
+}
+
+/// Run every job of the batch file, returning an error only when the batch itself can't run
+pub fn run(
+    batch_path: &Path,
+    settings: &SolverSettings,
+    budget: &MemoryBudget,
+) -> Result<Report, String> {
+    let text = fs::read_to_string(batch_path).map_err(|err| err.to_string())?;
+    let batch: BatchFile = ron::from_str(&text).map_err(|err| err.to_string())?;
+    let dir = batch_path.parent().unwrap_or_else(|| Path::new(""));
+    let log_dir = dir.join(
+        batch
+            .log_dir
+            .as_deref()
+            .unwrap_or_else(|| Path::new("batch_logs")),
+    );
+    fs::create_dir_all(&log_dir).map_err(|err| format!("{}: {}", log_dir.display(), err))?;
+
+    let mut summary = String::from("job  result  seconds  scene  export\n");
+    let (mut succeeded, mut failed) = (0, 0);
+    for (i, job) in batch.jobs.iter().enumerate() {
+        println!(
+            "[{}/{}] {}: {}",
+            i + 1,
+            batch.jobs.len(),
+            job.scene.display(),
+            job.export.describe()
+        );
+        let started = Instant::now();
+        let result = run_job(job, dir, settings, budget);
+        let seconds = started.elapsed().as_secs_f32();
+
+        let mut log = format!(
+            "scene: {}\nexport: {}\nseconds: {:.1}\n",
+            dir.join(&job.scene).display(),
+            job.export.describe(),
+            seconds
+        );
+        let status = match &result {
+            Ok(details) => {
+                succeeded += 1;
+                let _ = writeln!(log, "result: ok\n{}", details);
+                "ok"
+            }
+            Err(err) => {
+                failed += 1;
+                eprintln!("Job {} failed: {}", i + 1, err);
+                let _ = writeln!(log, "result: failed\n{}", err);
+                "failed"
+            }
+        };
+        let log_path = log_dir.join(format!("job_{:03}.log", i + 1));
+        if let Err(err) = fs::write(&log_path, log) {
+            eprintln!("Couldn't write {}: {}", log_path.display(), err);
+        }
+        let _ = writeln!(
+            summary,
+            "{:03}  {:6}  {:7.1}  {}  {}",
+            i + 1,
+            status,
+            seconds,
+            job.scene.display(),
+            job.export.describe()
+        );
+    }
+
+    let _ = writeln!(summary, "{} succeeded, {} failed", succeeded, failed);
+    let summary_path = log_dir.join("summary.txt");
+    fs::write(&summary_path, summary)
+        .map_err(|err| format!("{}: {}", summary_path.display(), err))?;
+    Ok(Report {
+        succeeded,
+        failed,
+        summary: summary_path,
+    })
+}
+
+/// Run a job, returning the details worth logging
+fn run_job(
+    job: &BatchJob,
+    dir: &Path,
+    settings: &SolverSettings,
+    budget: &MemoryBudget,
+) -> Result<String, String> {
+    let scene = dir.join(&job.scene);
+    let out = dir.join(job.export.out());
+    match job.export {
+        Export::Frames { frames, .. } => {
+            render::render_scene(&scene, frames, &out, settings, budget)?;
+            Ok(format!("rendered {} frames", frames))
+        }
+        Export::Poster { steps, scale, .. } => {
+            let options = PosterOptions {
+                grid_size: None,
+                steps,
+                scale,
+            };
+            poster::render_poster(&scene, &out, &options, settings, budget)?;
+            Ok(format!("rendered the poster at {} pixels per cell", scale))
+        }
+        Export::Pod {
+            snapshots,
+            interval,
+            modes,
+            ..
+        } => {
+            let options = PodOptions {
+                grid_size: None,
+                snapshots,
+                interval,
+                modes,
+            };
+            let fractions = pod::run(&scene, &out, &options, settings, budget)?;
+            let mut details = String::new();
+            for (i, fraction) in fractions.iter().take(modes).enumerate() {
+                let _ = writeln!(
+                    details,
+                    "mode {}: {:.1}% of the energy",
+                    i + 1,
+                    fraction * 100.0
+                );
+            }
+            Ok(details.trim_end().to_string())
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use std::time::{Duration, Instant};
+
+use crate::memory::{self, MemoryBudget};
+use crate::scenes::ScenePreset;
+use crate::settings::SolverSettings;
+use crate::solver::{self, Scratch, Splats, Stage};
+
+// Headless benchmark: runs the vortex scene on square grids of each size and prints
+// a table of the step rate and the average time spent in every stage.
+
+const STEP_DT: f32 = 1.0 / 60.0;
+const STAGES: [Stage; 4] = [Stage::Forces, Stage::Diffuse, Stage::Project, Stage::Advect];
+
+pub fn run(
+    sizes: &[usize],
+    steps: usize,
+    settings: &SolverSettings,
+    budget: &MemoryBudget,
+) -> Result<(), String> {
+    for &size in sizes {
+        memory::check(size, size, budget)?;
+    }
+
+    println!(
+        "{:>6} {:>10} {:>12} {:>12} {:>12} {:>12}",
+        "grid", "steps/s", "forces ms", "diffuse ms", "project ms", "advect ms"
+    );
+
+    for &size in sizes {
+        let mut grid = ScenePreset::Vortex.build(size, size);
+        let mut splats = Splats::default();
+        let mut scratch = Scratch::default();
+        let mut stage_times = [Duration::default(); STAGES.len()];
+
+        let start = Instant::now();
+        for _ in 0..steps {
+            for (stage, time) in STAGES.iter().zip(&mut stage_times) {
+                let stage_start = Instant::now();
+                solver::run_stage(
+                    &mut grid,
+                    *stage,
+                    STEP_DT,
+                    settings,
+                    &mut splats,
+                    &mut scratch,
+                );
+                *time += stage_start.elapsed();
+            }
+        }
+        let elapsed = start.elapsed().as_secs_f64();
+
+        let average_ms = |time: Duration| time.as_secs_f64() * 1000.0 / steps as f64;
+        println!(
+            "{:>6} {:>10.1} {:>12.3} {:>12.3} {:>12.3} {:>12.3}",
+            format!("{}x{}", size, size),
+            steps as f64 / elapsed,
+            average_ms(stage_times[0]),
+            average_ms(stage_times[1]),
+            average_ms(stage_times[2]),
+            average_ms(stage_times[3]),
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,256 @@
+use std::str::FromStr;
+
+use bevy::prelude::*;
+use bevy::render::pipeline::PipelineDescriptor;
+
+use crate::errors::ErrorLog;
+use crate::layers::{Layer, Layers};
+use crate::lines::{self, Segment, ShaderSupport};
+use crate::settings::SolverSettings;
+use crate::{grid_to_world, Grid, CELL_SIZE};
+
+// Boundary conditions: every solver pass reads the cells outside of the grid as ghost
+// cells standing for them, so the edges behave the same whatever the pass. Periodic edges
+// take the cell on the opposite edge, walls the edge cell itself with its velocity
+// reflected, and the scalars like the density and the pressure unchanged so nothing
+// flows through.
+//
+// Debug overlay of the boundaries: s outlines the cells along the edges in the color of
+// the boundary mode, and draws from the middle of each outer face the velocity of the
+// ghost cell behind it.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BoundaryMode {
+    /// What leaves through an edge comes back through the opposite one
+    Periodic,
+    /// Solid walls the fluid sticks to, the velocity along them is zero
+    NoSlip,
+    /// Solid walls the fluid slides along, only the velocity into them is zero
+    FreeSlip,
+}
+
+impl Default for BoundaryMode {
+    fn default() -> Self {
+        Self::Periodic
+    }
+}
+
+impl FromStr for BoundaryMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "periodic" => Ok(Self::Periodic),
+            "no-slip" => Ok(Self::NoSlip),
+            "free-slip" => Ok(Self::FreeSlip),
+            _ => Err(format!(
+                "unknown boundary mode {:?}, expected periodic, no-slip or free-slip",
+                s
+            )),
+        }
+    }
+}
+
+impl BoundaryMode {
+    fn color(self) -> [f32; 3] {
+        match self {
+            Self::Periodic => [0.0, 0.8, 0.8],
+            Self::NoSlip => [0.9, 0.2, 0.2],
+            Self::FreeSlip => [0.9, 0.7, 0.1],
+        }
+    }
+
+    /// The cell standing for (x, y), with the factors its velocity is multiplied by.
+    /// Cells inside of the grid stand for themselves.
+    pub fn ghost(self, x: isize, y: isize, width: usize, height: usize) -> (usize, usize, Vec2) {
+        let (w, h) = (width as isize, height as isize);
+        if self == Self::Periodic {
+            return (
+                x.rem_euclid(w) as usize,
+                y.rem_euclid(h) as usize,
+                Vec2::ONE,
+            );
+        }
+
+        let outside_x = x < 0 || x >= w;
+        let outside_y = y < 0 || y >= h;
+        let reflection = match self {
+            Self::NoSlip if outside_x || outside_y => -Vec2::ONE,
+            Self::FreeSlip => Vec2::new(
+                if outside_x { -1.0 } else { 1.0 },
+                if outside_y { -1.0 } else { 1.0 },
+            ),
+            _ => Vec2::ONE,
+        };
+        let (x, y) = (x.max(0).min(w - 1), y.max(0).min(h - 1));
+        (x as usize, y as usize, reflection)
+    }
+
+    /// Bring back a fractional cell position that left the grid, through the opposite edge
+    /// or against the wall
+    pub fn confine(self, pos: Vec2, width: usize, height: usize) -> Vec2 {
+        let (width, height) = (width as f32, height as f32);
+        match self {
+            Self::Periodic => Vec2::new(pos.x.rem_euclid(width), pos.y.rem_euclid(height)),
+            Self::NoSlip | Self::FreeSlip => pos
+                .max(Vec2::splat(-0.5))
+                .min(Vec2::new(width - 0.5, height - 0.5)),
+        }
+    }
+}
+
+pub struct BoundaryPlugin;
+
+impl Plugin for BoundaryPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(BoundaryOverlay { active: false })
+            .add_startup_system(boundary_setup.system())
+            .add_system(boundary_keys_system.system())
+            .add_system(boundary_render_system.system());
+    }
+}
+
+struct BoundaryLayer;
+
+pub struct BoundaryOverlay {
+    pub active: bool,
+}
+
+fn boundary_setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut pipelines: ResMut<Assets<PipelineDescriptor>>,
+    mut shaders: ResMut<Assets<Shader>>,
+    support: Res<ShaderSupport>,
+    layers: Res<Layers>,
+) {
+    if let Some(pipeline) = lines::vertex_color_pipeline(
+        &support,
+        &mut pipelines,
+        &mut shaders,
+        layers.blend(Layer::Overlays),
+    ) {
+        lines::spawn_line_layer(&mut commands, &mut meshes, pipeline, 0.35, BoundaryLayer);
+    }
+}
+
+fn boundary_keys_system(
+    mut overlay: ResMut<BoundaryOverlay>,
+    mut char_input_events: EventReader<ReceivedCharacter>,
+) {
+    for event in char_input_events.iter() {
+        if event.char == 's' {
+            overlay.active = !overlay.active;
+        }
+    }
+}
+
+/// Ghost cells just outside the edges with the cell inside next to them, without the corners
+fn ghost_cells(width: usize, height: usize) -> Vec<((isize, isize), (usize, usize))> {
+    let mut cells = Vec::with_capacity(2 * (width + height));
+    for x in 0..width {
+        cells.push(((x as isize, -1), (x, 0)));
+        cells.push(((x as isize, height as isize), (x, height - 1)));
+    }
+    for y in 0..height {
+        cells.push(((-1, y as isize), (0, y)));
+        cells.push(((width as isize, y as isize), (width - 1, y)));
+    }
+    cells
+}
+
+fn boundary_render_system(
+    overlay: Res<BoundaryOverlay>,
+    settings: Res<SolverSettings>,
+    qg: Query<&Grid>,
+    mut errors: ResMut<ErrorLog>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut query: Query<(&BoundaryLayer, &Handle<Mesh>, &mut Visible)>,
+) {
+    if let Ok(grid) = qg.single() {
+        for (_layer, mesh_handle, mut visible) in query.iter_mut() {
+            visible.is_visible = overlay.active;
+            if !overlay.active {
+                continue;
+            }
+
+            let (width, height) = (grid.width(), grid.height());
+            let ghosts: Vec<_> = ghost_cells(width, height)
+                .into_iter()
+                .map(|((gx, gy), (x, y))| {
+                    let velocity = grid.velocity_at(gx, gy, settings.boundary);
+                    let ghost = Vec2::new(gx as f32, gy as f32);
+                    (Vec2::new(x as f32, y as f32), ghost, velocity)
+                })
+                .collect();
+
+            // Scale so the fastest ghost cell spans half a cell
+            let max_len = ghosts
+                .iter()
+                .map(|(_, _, vel)| vel.length())
+                .fold(0.0, f32::max);
+            let scale = if max_len > 0.0 {
+                CELL_SIZE / 2.0 / max_len
+            } else {
+                0.0
+            };
+
+            let color = settings.boundary.color();
+            let half = CELL_SIZE / 2.0 - 1.0;
+            let mut segments: Vec<Segment> = Vec::with_capacity(ghosts.len() * 5);
+            for (cell, ghost, velocity) in ghosts {
+                let center = grid_to_world(cell, width, height);
+                let corners = [
+                    center + Vec2::new(-half, -half),
+                    center + Vec2::new(half, -half),
+                    center + Vec2::new(half, half),
+                    center + Vec2::new(-half, half),
+                ];
+                let edges = corners.iter().zip(corners.iter().cycle().skip(1));
+                segments.extend(edges.map(|(&start, &end)| (start, end, color)));
+
+                let face = grid_to_world((cell + ghost) / 2.0, width, height);
+                segments.push((face, face + velocity * scale, [1.0, 1.0, 1.0]));
+            }
+
+            match meshes.get_mut(&*mesh_handle) {
+                Some(mesh) => lines::set_segments(mesh, &segments),
+                None => errors.report("Missing mesh of the boundary overlay"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn periodic_positions_wrap_around() {
+        let confine = |x, y| BoundaryMode::Periodic.confine(Vec2::new(x, y), 8, 4);
+        assert_eq!(confine(9.5, -1.0), Vec2::new(1.5, 3.0));
+        assert_eq!(confine(-0.25, 4.0), Vec2::new(7.75, 0.0));
+        assert_eq!(confine(3.0, 2.0), Vec2::new(3.0, 2.0));
+    }
+
+    #[test]
+    fn walls_stop_positions_half_a_cell_outside() {
+        for &mode in &[BoundaryMode::NoSlip, BoundaryMode::FreeSlip] {
+            let confine = |x, y| mode.confine(Vec2::new(x, y), 8, 4);
+            assert_eq!(confine(9.5, -1.0), Vec2::new(7.5, -0.5));
+            assert_eq!(confine(-3.0, 10.0), Vec2::new(-0.5, 3.5));
+            assert_eq!(confine(3.0, 2.0), Vec2::new(3.0, 2.0));
+        }
+    }
+
+    #[test]
+    fn ghost_cells_reflect_the_velocity_into_walls() {
+        assert_eq!(BoundaryMode::Periodic.ghost(-1, 4, 8, 4), (7, 0, Vec2::ONE));
+        assert_eq!(BoundaryMode::NoSlip.ghost(-1, 2, 8, 4), (0, 2, -Vec2::ONE));
+        assert_eq!(
+            BoundaryMode::FreeSlip.ghost(-1, 2, 8, 4),
+            (0, 2, Vec2::new(-1.0, 1.0))
+        );
+        assert_eq!(BoundaryMode::NoSlip.ghost(3, 2, 8, 4), (3, 2, Vec2::ONE));
+    }
+}
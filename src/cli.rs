@@ -0,0 +1,322 @@
+use std::env;
+use std::path::PathBuf;
+use std::process;
+
+use bevy::math::Vec2;
+
+use crate::backend::Backend;
+use crate::boundary::BoundaryMode;
+use crate::compare::ConfigSpec;
+use crate::scene_file::GRID_SIDE;
+use crate::settings::{
+    AdvectionScheme, Backtrace, ForceField, Material, SolverBackend, SolverPreset,
+};
+use crate::units::Units;
+
+const USAGE: &str = "\
+Usage: fluid_simulation [OPTIONS]
+
+Options:
+    --preset <fast|balanced|accurate>    Solver settings to start with
+    --material <air|water|honey|smoke>   Viscosity, buoyancy and damping of the fluid, unless
+                                         the scene file has one [default: smoke]
+    --backend <gpu|threaded|scalar>      Solver backend instead of the best one available
+    --threads <N>                        Threads of the threaded backend and the task pools
+    --half-precision                     Round the density and dye to f16 after every step
+    --vorticity <EPSILON>                Strength of the vorticity confinement [default: preset]
+    --advection <semi-lagrangian|maccormack|bfecc>
+                                         Advection scheme [default: preset]
+    --backtrace <euler|rk2|rk4>          Integration of the advection backtrace
+                                         [default: preset]
+    --flip <BLEND>                       Carry the velocity on marker particles, from PIC at 0
+                                         to FLIP at 1, instead of advecting it on the grid
+    --pressure-solver <gauss-seidel|pcg|multigrid>
+                                         Solver of the projection, iterations being V-cycles
+                                         for multigrid [default: gauss-seidel]
+    --boundary <periodic|no-slip|free-slip>
+                                         Edges of the grid [default: periodic]
+    --meters-per-cell <M>                Side of a cell, showing lengths and speeds in meters
+    --seconds-per-step <S>               Simulated time of a step instead of the frame time
+    --viscosity <M2/S>                   Kinematic viscosity, needs --meters-per-cell
+    --force <X,Y>                        Acceleration of every cell like gravity, in cells/s²
+                                         or m/s² with --meters-per-cell [default: 0,0]
+    --force-field <swirl|shear>[:STRENGTH]
+                                         Acceleration varying over the grid, in cells/s²
+    --density-dissipation <FACTOR>       Factor the density and dye are multiplied by every
+                                         step [default: 0.999]
+    --velocity-dissipation <FACTOR>      Factor the velocity is multiplied by every step
+                                         [default: 0.9995]
+    --conductivity <K>                   Heat flowing through the obstacles, per second
+                                         [default: 1]
+    --diffusion-iterations <N>           Iterations of the diffusion instead of the preset's
+    --diffusion-tolerance <RESIDUAL>     Residual the diffusion stops iterating at
+                                         [default: 0, every iteration]
+    --tutorial                           Start with the guided tutorial
+    --text <TEXT>                        Text stamped as dye with the t key
+    --image <PATH>                       PNG or JPEG image used as the initial dye
+    --field <PATH>                       EXR or .npy density and velocity used as the
+                                         initial condition, like the exported EXR
+    --target <PATH>                      Image the smoke is steered toward with q,
+                                         instead of the stamp text
+    --phase-period <SECONDS>             Period of the phase averaging instead of the one
+                                         of the scene file's fans or inflows
+    --control-window                     Show the state and key bindings in a second window
+    --status <SECONDS>                   Print a text summary of the simulation every SECONDS
+    --scene <PATH>                       RON scene file with the scene and its post effects
+    --render <PATH>                      Render a scene file to PNG frames without a window
+    --frames <N>                         Number of frames to render [default: 600]
+    --poster <PATH>                      Render the end of a scene file as one large PNG
+    --steps <N>                          Simulation steps of the poster or the decomposition
+                                         [default: 300], of each benchmark [default: 100]
+                                         or between two POD snapshots [default: 5]
+    --grid <WIDTHxHEIGHT>                Grid size of the poster or the POD, overriding the
+                                         scene file
+    --scale <PIXELS>                     Pixels per cell of the poster [default: 16]
+    --assert-no-alloc                    Panic if a solver step allocates (debug builds only)
+    --memory-budget <MB>                 Largest memory the simulation may use [default: 1024]
+    --pod <PATH>                         Decompose the velocity of a scene file into its
+                                         most energetic modes, written as images
+    --snapshots <N>                      Snapshots of the POD [default: 64]
+    --modes <N>                          Modes of the POD written as images [default: 6]
+    --decompose <PATH>                   Step a scene file split into strips on worker
+                                         processes exchanging their edge rows
+    --workers <N>                        Worker processes of the decomposition [default: 2]
+    --worker                             Internal: step a strip of --decompose over the
+                                         standard input and output
+    --bench-grid <SIZES>                 Benchmark the solver on square grids, e.g. 64,128,256
+    --batch <PATH>                       Run the jobs of a RON batch file one after the
+                                         other without a window, with their logs and a
+                                         summary in its log directory
+    --self-test                          Check the solver stages on small fields and exit
+    --compare <A,B>                      Vote blindly between two scene files side by side,
+                                         each optionally with @preset, e.g. a.ron@fast,b.ron
+    --out <PATH>                         Rendered frames directory [default: frames],
+                                         poster file [default: poster.png], POD
+                                         directory [default: pod] or decomposition
+                                         frame [default: decomposed.png]
+    -h, --help                           Print this message";
+
+/// Command line options
+#[derive(Default)]
+pub struct Args {
+    pub preset: Option<SolverPreset>,
+    pub material: Option<Material>,
+    pub backend: Option<Backend>,
+    pub threads: Option<usize>,
+    pub half_precision: bool,
+    pub vorticity: Option<f32>,
+    pub advection: Option<AdvectionScheme>,
+    pub backtrace: Option<Backtrace>,
+    pub flip: Option<f32>,
+    pub pressure_solver: Option<SolverBackend>,
+    pub boundary: Option<BoundaryMode>,
+    pub units: Units,
+    pub force: Option<Vec2>,
+    pub force_field: Option<ForceField>,
+    pub conductivity: Option<f32>,
+    pub diffusion_iterations: Option<usize>,
+    pub diffusion_tolerance: Option<f32>,
+    pub density_dissipation: Option<f32>,
+    pub velocity_dissipation: Option<f32>,
+    pub tutorial: bool,
+    pub text: Option<String>,
+    pub image: Option<PathBuf>,
+    pub field: Option<PathBuf>,
+    pub target: Option<PathBuf>,
+    pub scene: Option<PathBuf>,
+    pub phase_period: Option<f32>,
+    pub control_window: bool,
+    pub status: Option<f32>,
+    pub render: Option<PathBuf>,
+    pub frames: Option<usize>,
+    pub poster: Option<PathBuf>,
+    pub steps: Option<usize>,
+    pub grid: Option<(usize, usize)>,
+    pub scale: Option<u32>,
+    pub pod: Option<PathBuf>,
+    pub snapshots: Option<usize>,
+    pub modes: Option<usize>,
+    pub decompose: Option<PathBuf>,
+    pub workers: Option<usize>,
+    pub worker: bool,
+    pub bench_grid: Option<Vec<usize>>,
+    pub batch: Option<PathBuf>,
+    pub self_test: bool,
+    pub compare: Option<[ConfigSpec; 2]>,
+    pub memory_budget: Option<usize>,
+    pub assert_no_alloc: bool,
+    pub out: Option<PathBuf>,
+}
+
+impl Args {
+    /// Parse the process arguments, exiting with the usage on errors
+    pub fn parse() -> Self {
+        match Self::try_parse(env::args().skip(1)) {
+            Ok(args) => args,
+            Err(err) => {
+                eprintln!("error: {}\n\n{}", err, USAGE);
+                process::exit(2);
+            }
+        }
+    }
+
+    fn try_parse(mut raw: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut args = Self::default();
+
+        while let Some(arg) = raw.next() {
+            let mut value =
+                |name: &str| raw.next().ok_or_else(|| format!("{} needs a value", name));
+
+            match arg.as_str() {
+                "--preset" => args.preset = Some(value("--preset")?.parse()?),
+                "--material" => args.material = Some(value("--material")?.parse()?),
+                "--backend" => args.backend = Some(value("--backend")?.parse()?),
+                "--threads" => match number(&value("--threads")?)? {
+                    0 => return Err("--threads needs at least one thread".to_string()),
+                    threads => args.threads = Some(threads),
+                },
+                "--half-precision" => args.half_precision = true,
+                "--vorticity" => match number(&value("--vorticity")?)? {
+                    epsilon if epsilon >= 0.0 => args.vorticity = Some(epsilon),
+                    _ => return Err("--vorticity can't be negative".to_string()),
+                },
+                "--advection" => args.advection = Some(value("--advection")?.parse()?),
+                "--backtrace" => args.backtrace = Some(value("--backtrace")?.parse()?),
+                "--flip" => args.flip = Some(factor(&value("--flip")?)?),
+                "--pressure-solver" => {
+                    args.pressure_solver = Some(value("--pressure-solver")?.parse()?)
+                }
+                "--boundary" => args.boundary = Some(value("--boundary")?.parse()?),
+                "--meters-per-cell" => match number(&value("--meters-per-cell")?)? {
+                    meters if meters > 0.0 => args.units.meters_per_cell = Some(meters),
+                    _ => return Err("--meters-per-cell needs a positive length".to_string()),
+                },
+                "--seconds-per-step" => match number(&value("--seconds-per-step")?)? {
+                    seconds if seconds > 0.0 => args.units.seconds_per_step = Some(seconds),
+                    _ => return Err("--seconds-per-step needs a positive duration".to_string()),
+                },
+                "--viscosity" => match number(&value("--viscosity")?)? {
+                    viscosity if viscosity >= 0.0 => args.units.viscosity = Some(viscosity),
+                    _ => return Err("--viscosity can't be negative".to_string()),
+                },
+                "--force" => args.force = Some(vector(&value("--force")?)?),
+                "--force-field" => args.force_field = Some(value("--force-field")?.parse()?),
+                "--density-dissipation" => {
+                    args.density_dissipation = Some(factor(&value("--density-dissipation")?)?)
+                }
+                "--velocity-dissipation" => {
+                    args.velocity_dissipation = Some(factor(&value("--velocity-dissipation")?)?)
+                }
+                "--conductivity" => match number(&value("--conductivity")?)? {
+                    conductivity if conductivity >= 0.0 => args.conductivity = Some(conductivity),
+                    _ => return Err("--conductivity can't be negative".to_string()),
+                },
+                "--diffusion-iterations" => {
+                    args.diffusion_iterations = Some(number(&value("--diffusion-iterations")?)?)
+                }
+                "--diffusion-tolerance" => match number(&value("--diffusion-tolerance")?)? {
+                    tolerance if tolerance >= 0.0 => args.diffusion_tolerance = Some(tolerance),
+                    _ => return Err("--diffusion-tolerance can't be negative".to_string()),
+                },
+                "--tutorial" => args.tutorial = true,
+                "--text" => args.text = Some(value("--text")?),
+                "--image" => args.image = Some(value("--image")?.into()),
+                "--field" => args.field = Some(value("--field")?.into()),
+                "--target" => args.target = Some(value("--target")?.into()),
+                "--scene" => args.scene = Some(value("--scene")?.into()),
+                "--phase-period" => match number(&value("--phase-period")?)? {
+                    seconds if seconds > 0.0 => args.phase_period = Some(seconds),
+                    _ => return Err("--phase-period needs a positive duration".to_string()),
+                },
+                "--control-window" => args.control_window = true,
+                "--status" => match number(&value("--status")?)? {
+                    seconds if seconds > 0.0 => args.status = Some(seconds),
+                    _ => return Err("--status needs a positive number of seconds".to_string()),
+                },
+                "--render" => args.render = Some(value("--render")?.into()),
+                "--frames" => args.frames = Some(number(&value("--frames")?)?),
+                "--poster" => args.poster = Some(value("--poster")?.into()),
+                "--steps" => args.steps = Some(number(&value("--steps")?)?),
+                "--grid" => args.grid = Some(grid_size(&value("--grid")?)?),
+                "--scale" => args.scale = Some(number(&value("--scale")?)?),
+                "--pod" => args.pod = Some(value("--pod")?.into()),
+                "--snapshots" => args.snapshots = Some(number(&value("--snapshots")?)?),
+                "--modes" => args.modes = Some(number(&value("--modes")?)?),
+                "--decompose" => args.decompose = Some(value("--decompose")?.into()),
+                "--workers" => args.workers = Some(number(&value("--workers")?)?),
+                "--worker" => args.worker = true,
+                "--bench-grid" => {
+                    let sizes = value("--bench-grid")?;
+                    let sizes = sizes.split(',').map(|size| grid_side(size.trim()));
+                    args.bench_grid = Some(sizes.collect::<Result<_, _>>()?);
+                }
+                "--batch" => args.batch = Some(value("--batch")?.into()),
+                "--self-test" => args.self_test = true,
+                "--compare" => {
+                    let configs = value("--compare")?;
+                    let mut configs = configs
+                        .split(',')
+                        .map(|config| config.trim().parse::<ConfigSpec>());
+                    match (configs.next(), configs.next(), configs.next()) {
+                        (Some(a), Some(b), None) => args.compare = Some([a?, b?]),
+                        _ => return Err("--compare needs two configurations".to_string()),
+                    }
+                }
+                "--assert-no-alloc" => args.assert_no_alloc = true,
+                "--memory-budget" => args.memory_budget = Some(number(&value("--memory-budget")?)?),
+                "--out" => args.out = Some(value("--out")?.into()),
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    process::exit(0);
+                }
+                _ => return Err(format!("unexpected argument {:?}", arg)),
+            }
+        }
+
+        if args.units.viscosity.is_some() && args.units.meters_per_cell.is_none() {
+            return Err("--viscosity needs --meters-per-cell".to_string());
+        }
+        Ok(args)
+    }
+}
+
+fn number<T: std::str::FromStr>(s: &str) -> Result<T, String> {
+    s.parse().map_err(|_| format!("invalid number {:?}", s))
+}
+
+/// Parse a factor between 0 and 1
+fn factor(s: &str) -> Result<f32, String> {
+    match number(s)? {
+        factor if (0.0..=1.0).contains(&factor) => Ok(factor),
+        factor => Err(format!("{} isn't a factor between 0 and 1", factor)),
+    }
+}
+
+/// Parse a vector like 0,-9.8
+fn vector(s: &str) -> Result<Vec2, String> {
+    let (x, y) = s
+        .split_once(',')
+        .ok_or_else(|| format!("invalid vector {:?}", s))?;
+    Ok(Vec2::new(number(x.trim())?, number(y.trim())?))
+}
+
+/// Parse a grid size like 400x300
+fn grid_size(s: &str) -> Result<(usize, usize), String> {
+    let (width, height) = s
+        .split_once('x')
+        .ok_or_else(|| format!("invalid grid size {:?}", s))?;
+    Ok((grid_side(width)?, grid_side(height)?))
+}
+
+/// Parse the number of cells on a side of the grid, within `GRID_SIDE`
+fn grid_side(s: &str) -> Result<usize, String> {
+    match number(s)? {
+        side if GRID_SIDE.contains(&side) => Ok(side),
+        side => Err(format!(
+            "a grid side of {} is outside of {} to {}",
+            side,
+            GRID_SIDE.start(),
+            GRID_SIDE.end()
+        )),
+    }
+}
@@ -0,0 +1,349 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bevy::prelude::*;
+use bevy::render::texture::{Extent3d, TextureDimension, TextureFormat};
+
+use crate::emitters;
+use crate::fans::Fan;
+use crate::inflow::Inflows;
+use crate::layers::Layers;
+use crate::memory::{self, MemoryBudget};
+use crate::palette::Palette;
+use crate::post;
+use crate::render;
+use crate::scene_file::SceneFile;
+use crate::settings::{SolverPreset, SolverSettings};
+use crate::solver::{self, Scratch, Splats};
+use crate::weather::Weather;
+use crate::Grid;
+
+// Blind A/B comparisons: two configurations run side by side, randomly swapped every
+// round so nothing tells which one is which. 1 votes for the left one, 2 for the right
+// one and 0 for a tie, then both restart and get shuffled again. Every vote is appended
+// to a CSV file with the settings of both configurations.
+
+const VOTES_PATH: &str = "votes.csv";
+const VOTES_HEADER: &str = "time,round,vote,a_scene,a_settings,b_scene,b_settings";
+/// Side of the square each configuration is fitted in, in pixels
+const VIEW_SIZE: f32 = 480.0;
+const GAP: f32 = 16.0;
+const FRAME_DT: f32 = 1.0 / 60.0;
+
+/// A scene file, optionally followed by the solver preset it runs with, e.g. smoke.ron@fast
+#[derive(Clone, Debug)]
+pub struct ConfigSpec {
+    pub scene: PathBuf,
+    pub preset: Option<SolverPreset>,
+}
+
+impl FromStr for ConfigSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('@') {
+            Some((scene, preset)) => Ok(Self {
+                scene: scene.into(),
+                preset: Some(preset.parse()?),
+            }),
+            None => Ok(Self {
+                scene: s.into(),
+                preset: None,
+            }),
+        }
+    }
+}
+
+/// One of the two configurations, with its own simulation
+struct Side {
+    spec: ConfigSpec,
+    file: SceneFile,
+    settings: SolverSettings,
+    layers: Layers,
+    grid: Grid,
+    splats: Splats,
+    scratch: Scratch,
+    weather: Weather,
+    fans: Vec<Fan>,
+    inflows: Inflows,
+}
+
+impl Side {
+    fn load(
+        spec: &ConfigSpec,
+        settings: &SolverSettings,
+        budget: &MemoryBudget,
+    ) -> Result<Self, String> {
+        let file = SceneFile::load(&spec.scene)
+            .map_err(|err| format!("{}: {}", spec.scene.display(), err))?;
+        let (width, height) = file.grid_size();
+        memory::check(width, height, budget)?;
+        let mut settings = match spec.preset {
+            Some(preset) => preset.apply(settings),
+            None => SolverSettings { ..*settings },
+        };
+        if let Some(material) = file.material {
+            material.apply(&mut settings);
+        }
+
+        Ok(Self {
+            spec: spec.clone(),
+            grid: file.scene.build(width, height),
+            layers: Layers::from_styles(&file.layers),
+            file,
+            settings,
+            splats: Splats::default(),
+            scratch: Scratch::default(),
+            weather: Weather::new(file.wind),
+            fans: file.fans.clone(),
+            inflows: Inflows::new(file.inflows.clone()),
+        })
+    }
+
+    fn restart(&mut self) {
+        let (width, height) = self.file.grid_size();
+        self.grid = self.file.scene.build(width, height);
+        self.weather = Weather::new(self.file.wind);
+        self.fans = self.file.fans.clone();
+        self.inflows = Inflows::new(self.file.inflows.clone());
+    }
+
+    fn step(&mut self) {
+        emitters::inject(&mut self.grid, &self.file.emitters, FRAME_DT);
+        for fan in self.fans.iter_mut() {
+            fan.blow(&mut self.grid, FRAME_DT);
+        }
+        self.weather.blow(&mut self.grid, FRAME_DT);
+        self.inflows
+            .feed(&mut self.grid, FRAME_DT, &mut self.scratch);
+        solver::step(
+            &mut self.grid,
+            FRAME_DT,
+            &self.settings,
+            &mut self.splats,
+            &mut self.scratch,
+        );
+    }
+
+    /// RGBA bytes of the current frame, top row first like textures
+    fn pixels(&self) -> Vec<u8> {
+        render::frame_rgba(&post::compose(
+            &self.grid,
+            &Palette::default(),
+            &self.layers,
+            &self.file.post_effects,
+        ))
+    }
+
+    /// Everything that may differ between the configurations, for the log
+    fn describe(&self) -> String {
+        let settings = &self.settings;
+        format!(
+            "preset {} diffusion {} tolerance {} advection {} projection {} {:?} viscosity {} diffusivity {} vorticity {} advection {:?} backtrace {:?} \
+             buoyancy {:?} damping {} species {:?} conductivity {} dissipation {:?} external forces {:?} boundary {:?} interpolation {:?} post effects {:?} layers {:?}",
+            self.spec
+                .preset
+                .map_or("default".to_string(), |preset| format!("{:?}", preset)),
+            settings.diffusion_iterations,
+            settings.diffusion_tolerance,
+            settings.advection_iterations,
+            settings.projection_iterations,
+            settings.pressure_solver,
+            settings.viscosity,
+            settings.diffusion,
+            settings.vorticity,
+            settings.advection,
+            settings.backtrace,
+            settings.buoyancy,
+            settings.damping,
+            settings.species,
+            settings.conductivity,
+            settings.dissipation,
+            settings.external,
+            settings.boundary,
+            settings.interpolation,
+            self.file.post_effects,
+            self.file.layers,
+        )
+    }
+}
+
+struct Comparison {
+    /// A then B
+    sides: [Side; 2],
+    /// Whether B is the one on the left this round
+    swapped: bool,
+    round: usize,
+}
+
+impl Comparison {
+    /// Index of the side shown on the left or on the right
+    fn shown(&self, right: bool) -> usize {
+        (right != self.swapped) as usize
+    }
+}
+
+/// The sprite showing the left or the right configuration
+struct ComparisonView {
+    right: bool,
+}
+
+/// Open a window comparing the two configurations until it's closed
+pub fn run(
+    specs: &[ConfigSpec; 2],
+    settings: &SolverSettings,
+    budget: &MemoryBudget,
+) -> Result<(), String> {
+    let comparison = Comparison {
+        sides: [
+            Side::load(&specs[0], settings, budget)?,
+            Side::load(&specs[1], settings, budget)?,
+        ],
+        swapped: rand::random(),
+        round: 1,
+    };
+
+    App::build()
+        .insert_resource(WindowDescriptor {
+            width: VIEW_SIZE * 2.0 + GAP * 3.0,
+            height: VIEW_SIZE + GAP * 2.0,
+            title: title(comparison.round),
+            ..Default::default()
+        })
+        .insert_resource(comparison)
+        .add_plugins(DefaultPlugins)
+        .add_startup_system(comparison_setup.system())
+        .add_system(comparison_step_system.system())
+        .add_system(vote_system.system())
+        .run();
+    Ok(())
+}
+
+fn title(round: usize) -> String {
+    format!(
+        "Fluid Simulation - A/B comparison - round {} - 1 left, 2 right, 0 tie",
+        round
+    )
+}
+
+fn comparison_setup(
+    mut commands: Commands,
+    comparison: Res<Comparison>,
+    mut textures: ResMut<Assets<Texture>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    commands.spawn_bundle(OrthographicCameraBundle::new_2d());
+
+    for &right in &[false, true] {
+        let (width, height) = comparison.sides[comparison.shown(right)].file.grid_size();
+        let texture = textures.add(Texture::new(
+            Extent3d::new(width as u32, height as u32, 1),
+            TextureDimension::D2,
+            vec![0; width * height * 4],
+            TextureFormat::Rgba8UnormSrgb,
+        ));
+        let x = (VIEW_SIZE + GAP) / 2.0 * if right { 1.0 } else { -1.0 };
+        commands
+            .spawn_bundle(SpriteBundle {
+                material: materials.add(texture.into()),
+                transform: Transform::from_translation(Vec3::new(x, 0.0, 0.0)),
+                ..Default::default()
+            })
+            .insert(ComparisonView { right });
+    }
+}
+
+/// Step both simulations and show them on their side
+fn comparison_step_system(
+    mut comparison: ResMut<Comparison>,
+    mut textures: ResMut<Assets<Texture>>,
+    materials: Res<Assets<ColorMaterial>>,
+    mut query: Query<(&ComparisonView, &Handle<ColorMaterial>, &mut Transform)>,
+) {
+    for side in comparison.sides.iter_mut() {
+        side.step();
+    }
+
+    for (view, material, mut transform) in query.iter_mut() {
+        let side = &comparison.sides[comparison.shown(view.right)];
+        let (width, height) = (side.grid.width(), side.grid.height());
+        let handle = materials.get(material).and_then(|m| m.texture.as_ref());
+        let texture = match handle.and_then(|handle| textures.get_mut(handle)) {
+            Some(texture) => texture,
+            None => continue,
+        };
+
+        // The sides swap between rounds, and their grids may not have the same size
+        if texture.size.width as usize != width || texture.size.height as usize != height {
+            texture.resize(Extent3d::new(width as u32, height as u32, 1));
+        }
+        texture.data = side.pixels();
+        transform.scale = Vec3::splat(VIEW_SIZE / width.max(height) as f32);
+    }
+}
+
+fn vote_system(
+    mut comparison: ResMut<Comparison>,
+    mut windows: ResMut<Windows>,
+    mut char_input_events: EventReader<ReceivedCharacter>,
+) {
+    for event in char_input_events.iter() {
+        let vote = match event.char {
+            '1' => Some(comparison.shown(false)),
+            '2' => Some(comparison.shown(true)),
+            '0' => None,
+            _ => continue,
+        };
+
+        match log_vote(&comparison, vote, Path::new(VOTES_PATH)) {
+            Ok(()) => info!("Round {}: vote saved", comparison.round),
+            Err(err) => error!("Couldn't save the vote to {}: {}", VOTES_PATH, err),
+        }
+
+        comparison.round += 1;
+        comparison.swapped = rand::random();
+        for side in comparison.sides.iter_mut() {
+            side.restart();
+        }
+        if let Some(window) = windows.get_primary_mut() {
+            window.set_title(title(comparison.round));
+        }
+    }
+}
+
+/// Append a line for the vote, `vote` being the index of the winner or None for a tie
+fn log_vote(comparison: &Comparison, vote: Option<usize>, path: &Path) -> std::io::Result<()> {
+    let new = !path.exists();
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    if new {
+        writeln!(file, "{}", VOTES_HEADER)?;
+    }
+
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    let [a, b] = &comparison.sides;
+    writeln!(
+        file,
+        "{},{},{},{},{},{},{}",
+        time,
+        comparison.round,
+        match vote {
+            Some(0) => "A",
+            Some(_) => "B",
+            None => "tie",
+        },
+        csv_field(&a.spec.scene.display().to_string()),
+        csv_field(&a.describe()),
+        csv_field(&b.spec.scene.display().to_string()),
+        csv_field(&b.describe()),
+    )
+}
+
+/// Quote a field, the settings are full of commas
+fn csv_field(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "\"\""))
+}
@@ -0,0 +1,512 @@
+use bevy::prelude::*;
+use bevy::render::camera::{ActiveCameras, Camera};
+use bevy::render::pass::{
+    LoadOp, Operations, PassDescriptor, RenderPassDepthStencilAttachmentDescriptor,
+    TextureAttachment,
+};
+use bevy::render::render_graph::base::MainPass;
+use bevy::render::render_graph::{
+    CameraNode, PassNode, RenderGraph, WindowSwapChainNode, WindowTextureNode,
+};
+use bevy::render::texture::{
+    Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsage,
+};
+use bevy::window::{CreateWindow, WindowId};
+use bevy_egui::{egui, EguiContext, EguiSettings, RenderGraphConfig};
+
+use crate::accessibility::Accessibility;
+use crate::boundary::BoundaryOverlay;
+use crate::courant::CourantOverlay;
+use crate::fluid::Fluid;
+use crate::ftle::Ftle;
+use crate::gestures::Gestures;
+use crate::grid_to_world;
+use crate::memory::{self, MemoryUsage};
+use crate::offscreen::RenderTarget;
+use crate::palette::Palette;
+use crate::piv::Piv;
+use crate::quiver::QuiverOverlay;
+use crate::scenes::SceneSelection;
+use crate::settings::{ForceField, Material, Precision, SolverPreset, SolverSettings};
+use crate::snapshot::Snapshot;
+use crate::solver::Scratch;
+use crate::species::SpeciesBrush;
+use crate::steering::Steering;
+use crate::stepping::StepControl;
+use crate::stylus::Stylus;
+use crate::symmetry::Symmetry;
+use crate::tracers::Tracers;
+use crate::units::Units;
+use crate::viewport::Viewport;
+use crate::weather::Weather;
+
+// Second window with an egui panel showing the state of the simulation and the key
+// bindings, so the main window only shows the fluid, e.g. when projecting it. Both windows
+// draw the same world: the camera of the panel looks far away from the grid, at nothing.
+// An overview rendered off-screen shows the whole grid, even when the main window
+// is scrolled.
+
+const CONTROL_CAMERA: &str = "Control";
+const PANEL_ORIGIN: Vec2 = Vec2::new(100_000.0, 0.0);
+/// Side of the overview of the whole grid, in pixels
+const OVERVIEW_SIZE: u32 = 160;
+/// Egui texture of the overview, after the ones of the menu thumbnails
+const OVERVIEW_TEXTURE: u64 = 1000;
+
+const KEY_HELP: [&str; 21] = [
+    "SPACE PAUSE   , STAGE   . STEP",
+    "R RESET   O PRESET   M SYMMETRY",
+    "1 DIVERGENCE   2 COURANT   3 PADDLE",
+    "4 MATERIAL   5 BRUSH SPECIES   6 FAN",
+    "7 ADVECTION   9 BACKTRACE   0 ARROW OCCLUSION",
+    "P PATHLINES   K STREAKLINE   W SWEEP",
+    "U QUIVER   I INTERPOLATION   F FTLE   S BOUNDARIES",
+    "H PALETTE   [ ] PALETTE SPEED",
+    "C COPY   V PASTE   B ADD   CTRL V TABLE",
+    "T STAMP TEXT   Q STEER   ARROWS SCROLL",
+    "X PNG   N CSV   J VTK   CTRL J EXR",
+    "G LEAF   E EXPLOSION   CTRL P PHASE AVERAGE",
+    "CTRL A MEAN / FLUCTUATION   CTRL SHIFT A RESET",
+    "CTRL S STREAM FUNCTION   CTRL I PIV",
+    "8 GIF OF THE LAST 5 SECONDS   CTRL X FLOW MAP",
+    "A SPEED GLYPHS   MINUS PLUS UI SCALE",
+    "Z RECORD GESTURE   Y REPLAY   D LOOP",
+    "MIDDLE DRAG OBSTACLES   SHIFT ERASE",
+    "CTRL MIDDLE DRAG HOT OBSTACLES",
+    "L THEME   F1 TUTORIAL   F5 SAVE   F9 RESTORE",
+    "F6 SLOW MOTION REPLAY   F7 REPLAY SPEED",
+];
+
+pub struct ControlWindowPlugin;
+
+impl Plugin for ControlWindowPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(ControlWindow {
+            id: WindowId::new(),
+            ready: false,
+        })
+        .add_startup_system(control_window_setup.system())
+        .add_system(control_pipeline_system.system())
+        .add_system(control_panel_system.system());
+    }
+}
+
+pub struct ControlWindow {
+    id: WindowId,
+    /// The render graph draws to the window, which only exists a frame after asking for it
+    ready: bool,
+}
+
+fn control_window_setup(
+    control: Res<ControlWindow>,
+    mut create_window_events: EventWriter<CreateWindow>,
+) {
+    create_window_events.send(CreateWindow {
+        id: control.id,
+        descriptor: WindowDescriptor {
+            width: 640.0,
+            height: 480.0,
+            title: "Fluid Simulation - Controls".to_string(),
+            ..Default::default()
+        },
+    });
+}
+
+/// Render the control camera to the window once it's created, like the main pass does
+#[allow(clippy::too_many_arguments)]
+fn control_pipeline_system(
+    mut commands: Commands,
+    windows: Res<Windows>,
+    msaa: Res<Msaa>,
+    mut control: ResMut<ControlWindow>,
+    mut active_cameras: ResMut<ActiveCameras>,
+    mut render_graph: ResMut<RenderGraph>,
+    mut egui_context: ResMut<EguiContext>,
+    mut textures: ResMut<Assets<Texture>>,
+) {
+    if control.ready || windows.get(control.id).is_none() {
+        return;
+    }
+    control.ready = true;
+    let window_id = control.id;
+
+    render_graph.add_node(
+        "control_window_swap_chain",
+        WindowSwapChainNode::new(window_id),
+    );
+    render_graph.add_node(
+        "control_window_depth_texture",
+        WindowTextureNode::new(
+            window_id,
+            TextureDescriptor {
+                format: TextureFormat::Depth32Float,
+                usage: TextureUsage::OUTPUT_ATTACHMENT,
+                sample_count: msaa.samples,
+                ..Default::default()
+            },
+        ),
+    );
+    render_graph.add_system_node("control_camera", CameraNode::new(CONTROL_CAMERA));
+
+    let mut pass = PassNode::<&MainPass>::new(PassDescriptor {
+        color_attachments: vec![msaa.color_attachment_descriptor(
+            TextureAttachment::Input("color_attachment".to_string()),
+            TextureAttachment::Input("color_resolve_target".to_string()),
+            Operations {
+                load: LoadOp::Clear(Color::rgb(0.1, 0.1, 0.1)),
+                store: true,
+            },
+        )],
+        depth_stencil_attachment: Some(RenderPassDepthStencilAttachmentDescriptor {
+            attachment: TextureAttachment::Input("depth".to_string()),
+            depth_ops: Some(Operations {
+                load: LoadOp::Clear(1.0),
+                store: true,
+            }),
+            stencil_ops: None,
+        }),
+        sample_count: msaa.samples,
+    });
+    pass.add_camera(CONTROL_CAMERA);
+    active_cameras.add(CONTROL_CAMERA);
+    render_graph.add_node("control_window_pass", pass);
+
+    render_graph
+        .add_slot_edge(
+            "control_window_swap_chain",
+            WindowSwapChainNode::OUT_TEXTURE,
+            "control_window_pass",
+            if msaa.samples > 1 {
+                "color_resolve_target"
+            } else {
+                "color_attachment"
+            },
+        )
+        .unwrap();
+    render_graph
+        .add_slot_edge(
+            "control_window_depth_texture",
+            WindowTextureNode::OUT_TEXTURE,
+            "control_window_pass",
+            "depth",
+        )
+        .unwrap();
+    render_graph
+        .add_node_edge("control_camera", "control_window_pass")
+        .unwrap();
+
+    if msaa.samples > 1 {
+        render_graph.add_node(
+            "control_window_multi_sampled_color_attachment",
+            WindowTextureNode::new(
+                window_id,
+                TextureDescriptor {
+                    size: Extent3d::new(1, 1, 1),
+                    mip_level_count: 1,
+                    sample_count: msaa.samples,
+                    dimension: TextureDimension::D2,
+                    format: TextureFormat::default(),
+                    usage: TextureUsage::OUTPUT_ATTACHMENT,
+                },
+            ),
+        );
+        render_graph
+            .add_slot_edge(
+                "control_window_multi_sampled_color_attachment",
+                WindowSwapChainNode::OUT_TEXTURE,
+                "control_window_pass",
+                "color_attachment",
+            )
+            .unwrap();
+    }
+
+    let mut camera = OrthographicCameraBundle::new_2d();
+    camera.camera = Camera {
+        name: Some(CONTROL_CAMERA.to_string()),
+        window: window_id,
+        ..camera.camera
+    };
+    camera.transform.translation.x = PANEL_ORIGIN.x;
+    camera.transform.translation.y = PANEL_ORIGIN.y;
+    commands.spawn_bundle(camera);
+
+    // The panel is drawn over the pass of the window
+    bevy_egui::setup_pipeline(
+        &mut render_graph,
+        &msaa,
+        RenderGraphConfig {
+            window_id,
+            egui_pass: "control_window_egui_pass",
+            main_pass: "control_window_pass",
+            swap_chain_node: "control_window_swap_chain",
+            depth_texture: "control_window_depth_texture",
+        },
+    );
+
+    let overview = textures.add(Texture::new(
+        Extent3d::new(OVERVIEW_SIZE, OVERVIEW_SIZE, 1),
+        TextureDimension::D2,
+        vec![0; (OVERVIEW_SIZE * OVERVIEW_SIZE * 4) as usize],
+        TextureFormat::Rgba8UnormSrgb,
+    ));
+    egui_context.set_egui_texture(OVERVIEW_TEXTURE, overview.clone());
+    commands.spawn().insert(RenderTarget {
+        layer: None,
+        texture: overview,
+    });
+}
+
+/// Uppercase words naming a value, e.g. Rotational(3) becomes ROTATIONAL 3
+fn label(value: impl std::fmt::Debug) -> String {
+    format!("{:?}", value)
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect::<String>()
+        .trim()
+        .to_uppercase()
+}
+
+/// The fluid under the cursor, and how much of it there is above up to the top of the grid
+fn probe(
+    fluid: &Fluid,
+    windows: &Windows,
+    viewport: &Viewport,
+    units: &Units,
+    size: (usize, usize),
+) -> String {
+    let (width, height) = size;
+    let cursor = windows
+        .get_primary()
+        .and_then(|window| window.cursor_position());
+    let pos = match cursor {
+        Some(cursor) => viewport.cursor_to_grid(cursor),
+        None => return "PROBE -".to_string(),
+    };
+    let world = grid_to_world(pos, width, height);
+    let top = grid_to_world(Vec2::new(pos.x, height as f32 - 0.5), width, height);
+
+    match (fluid.sample_density(world), fluid.sample_velocity(world)) {
+        (Some(density), Some(velocity)) => format!(
+            "PROBE DENSITY {:.3} SPEED {} ABOVE {:.2}",
+            density,
+            units.format_speed(velocity.length()),
+            fluid.ray_march_density(world, top)
+        ),
+        _ => "PROBE -".to_string(),
+    }
+}
+
+/// Draw the panel, once its window exists
+#[allow(clippy::too_many_arguments)]
+fn control_panel_system(
+    control: Res<ControlWindow>,
+    egui_context: Res<EguiContext>,
+    mut egui_settings: ResMut<EguiSettings>,
+    selection: Res<SceneSelection>,
+    preset: Res<SolverPreset>,
+    settings: Res<SolverSettings>,
+    scratch: Res<Scratch>,
+    step_control: Res<StepControl>,
+    symmetry: Res<Symmetry>,
+    palette: Res<Palette>,
+    (tracers, quiver, ftle, boundary, courant, piv): (
+        Res<Tracers>,
+        Res<QuiverOverlay>,
+        Res<Ftle>,
+        Res<BoundaryOverlay>,
+        Res<CourantOverlay>,
+        Res<Piv>,
+    ),
+    (memory_usage, snapshot, steering, units, material): (
+        Res<MemoryUsage>,
+        Res<Snapshot>,
+        Res<Steering>,
+        Res<Units>,
+        Res<Material>,
+    ),
+    (fluid, windows, viewport): (Fluid, Res<Windows>, Res<Viewport>),
+    (accessibility, stylus, gestures, brush, weather): (
+        Res<Accessibility>,
+        Res<Stylus>,
+        Res<Gestures>,
+        Res<SpeciesBrush>,
+        Res<Weather>,
+    ),
+) {
+    if !control.ready {
+        return;
+    }
+    let ctx = match egui_context.try_ctx_for_window(control.id) {
+        Some(ctx) => ctx,
+        None => return,
+    };
+    let on_off = |on: bool| if on { "ON" } else { "OFF" };
+    let (width, height) = selection.grid_size();
+    let lines = vec![
+        format!("SCENE {}", label(selection.scene())),
+        match units.meters_per_cell {
+            Some(_) => format!(
+                "GRID {} X {} - {} X {}",
+                width,
+                height,
+                units.format_length(width as f32),
+                units.format_length(height as f32)
+            ),
+            None => format!("GRID {} X {}", width, height),
+        },
+        format!(
+            "VISCOSITY {}   DIFFUSION {}",
+            units.format_viscosity(settings.viscosity),
+            units.format_viscosity(settings.diffusion)
+        ),
+        format!(
+            "DIFFUSION ITERATIONS {} OF {}   RESIDUAL {:.1e}",
+            scratch.diffusion.iterations, settings.diffusion_iterations, scratch.diffusion.residual
+        ),
+        format!(
+            "PRESET {}   VORTICITY {}",
+            label(*preset),
+            settings.vorticity
+        ),
+        format!(
+            "ADVECTION {}   BACKTRACE {}",
+            label(settings.advection),
+            label(settings.backtrace)
+        ),
+        format!(
+            "PRESSURE {} - {} ITERATIONS",
+            label(settings.pressure_solver),
+            settings.projection_iterations
+        ),
+        format!(
+            "MATERIAL {}   DAMPING {}   STEP {}",
+            label(*material),
+            settings.damping,
+            units
+                .seconds_per_step
+                .map_or("FRAME TIME".to_string(), |seconds| format!("{} S", seconds))
+        ),
+        format!(
+            "BUOYANCY ALPHA {}   BETA {}",
+            settings.buoyancy.alpha, settings.buoyancy.beta
+        ),
+        format!(
+            "FORCE X {} Y {}   FIELD {}",
+            settings.external.body.x,
+            settings.external.body.y,
+            match settings.external.field {
+                Some(ForceField::Swirl(strength)) => format!("SWIRL {}", strength),
+                Some(ForceField::Shear(strength)) => format!("SHEAR {}", strength),
+                None => "NONE".to_string(),
+            }
+        ),
+        format!(
+            "DISSIPATION DENSITY {}   VELOCITY {}",
+            settings.dissipation.density, settings.dissipation.velocity
+        ),
+        match &weather.0 {
+            Some(wind) => {
+                let acceleration = wind.acceleration();
+                format!("WIND X {:.2} Y {:.2}", acceleration.x, acceleration.y)
+            }
+            None => "WIND NONE".to_string(),
+        },
+        if step_control.paused {
+            format!("PAUSED - NEXT {}", label(step_control.next_stage))
+        } else {
+            "RUNNING".to_string()
+        },
+        format!("SYMMETRY {}   BRUSH {}", label(*symmetry), brush.label()),
+        format!("PALETTE {}", label(palette.mode)),
+        format!("STEERING {}", on_off(steering.active)),
+        format!(
+            "PATHLINES {}   STREAKLINE {}",
+            on_off(tracers.show_pathlines),
+            on_off(tracers.show_streaklines)
+        ),
+        format!(
+            "QUIVER {}   FTLE {}",
+            on_off(quiver.active),
+            on_off(ftle.active)
+        ),
+        if courant.active {
+            format!(
+                "COURANT MAX {:.2} - {} CELLS OVER 1",
+                courant.max, courant.exceeding
+            )
+        } else {
+            "COURANT OFF".to_string()
+        },
+        if piv.active {
+            format!(
+                "PIV RMS ERROR {} - {} WINDOWS",
+                units.format_speed(piv.rms_error),
+                piv.vectors.len()
+            )
+        } else {
+            "PIV OFF".to_string()
+        },
+        format!(
+            "BOUNDARIES {}   OVERLAY {}",
+            label(settings.boundary),
+            on_off(boundary.active)
+        ),
+        format!(
+            "MEMORY {} GRID   {} BUFFERS",
+            memory::format_mb(memory_usage.grid),
+            memory::format_mb(memory_usage.buffers)
+        ),
+        match settings.precision {
+            Precision::Full => "PRECISION FULL".to_string(),
+            Precision::Half => format!("PRECISION HALF - ERROR {:.1e}", scratch.rounding_error),
+        },
+        format!(
+            "SNAPSHOT {} BYTES - RATIO {:.1}",
+            snapshot.compressed_bytes,
+            snapshot.ratio()
+        ),
+        probe(&fluid, &windows, &viewport, &units, (width, height)),
+        format!(
+            "SPEED GLYPHS {}   UI SCALE {}   OCCLUSION {}",
+            on_off(accessibility.speed_glyphs),
+            accessibility.ui_scale,
+            accessibility.arrow_occlusion
+        ),
+        match stylus.pressure {
+            Some(pressure) => format!("PEN PRESSURE {:.2}", pressure),
+            None => "PEN -".to_string(),
+        },
+        if gestures.recording() {
+            "GESTURE RECORDING".to_string()
+        } else {
+            format!(
+                "GESTURE {:.1} S   LOOP {}",
+                gestures.duration(),
+                on_off(gestures.looping())
+            )
+        },
+    ];
+
+    // The default UI scale, 2, is the size egui draws at
+    let scale_factor = accessibility.ui_scale as f64 / 2.0;
+    if egui_settings.scale_factor != scale_factor {
+        egui_settings.scale_factor = scale_factor;
+    }
+
+    egui::CentralPanel::default().show(ctx, |ui| {
+        ui.horizontal(|ui| {
+            ui.vertical(|ui| {
+                for line in &lines {
+                    ui.label(line);
+                }
+            });
+            let overview = egui::TextureId::User(OVERVIEW_TEXTURE);
+            ui.image(overview, [OVERVIEW_SIZE as f32; 2]);
+        });
+        ui.collapsing("KEYS", |ui| {
+            for line in KEY_HELP.iter() {
+                ui.label(*line);
+            }
+        });
+    });
+}
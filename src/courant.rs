@@ -0,0 +1,147 @@
+use bevy::prelude::*;
+
+use crate::errors::ErrorLog;
+use crate::layers::{Layer, OnLayer};
+use crate::scenes::SceneSelection;
+use crate::stepping::StepControl;
+use crate::viewport::{self, ViewSlot};
+use crate::{grid_to_world, AppState, Grid, Position, CELL_SIZE};
+
+// Courant number heatmap: 2 colors every cell by its local CFL number, the distance in
+// cells the fluid crosses during a step of the last time step. Past 1 the advection
+// reaches beyond the neighbors of a cell, where the instabilities usually start, so those
+// cells are highlighted.
+
+/// Courant number the heatmap saturates at, below the highlighted ones
+const CFL_LIMIT: f32 = 1.0;
+const HIGHLIGHT: Color = Color::rgb(1.0, 0.0, 1.0);
+
+pub struct CourantPlugin;
+
+impl Plugin for CourantPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(CourantOverlay::default())
+            .add_system_set(
+                SystemSet::on_enter(AppState::Running).with_system(courant_setup.system()),
+            )
+            .add_system(courant_keys_system.system())
+            .add_system(courant_square_system.system());
+    }
+}
+
+struct CourantSquare;
+
+#[derive(Default)]
+pub struct CourantOverlay {
+    pub active: bool,
+    /// Largest Courant number of the grid, while the overlay is active
+    pub max: f32,
+    /// Cells over the limit, while the overlay is active
+    pub exceeding: usize,
+}
+
+/// Cells crossed in a step along both axes, the usual CFL number in two dimensions
+fn courant_number(velocity: Vec2, dt: f32) -> f32 {
+    (velocity.x.abs() + velocity.y.abs()) * dt
+}
+
+fn courant_setup(
+    mut commands: Commands,
+    selection: Res<SceneSelection>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let (width, height) = selection.grid_size();
+
+    let (columns, rows) = viewport::view_size(width, height);
+    for y in 0..rows {
+        for x in 0..columns {
+            let position = Vec2::new(x as f32, y as f32);
+            let translation = grid_to_world(position, width, height).extend(0.6);
+
+            commands
+                .spawn_bundle(SpriteBundle {
+                    material: materials.add(Color::BLACK.into()),
+                    transform: Transform::from_translation(translation),
+                    sprite: Sprite::new(Vec2::new(CELL_SIZE, CELL_SIZE)),
+                    visible: Visible {
+                        is_visible: false,
+                        is_transparent: false,
+                    },
+                    ..Default::default()
+                })
+                .insert(CourantSquare)
+                .insert(OnLayer {
+                    layer: Layer::Density,
+                    offset: 0.6,
+                })
+                .insert(Position { x, y })
+                .insert(ViewSlot { x, y });
+        }
+    }
+}
+
+/// 2 toggles the heatmap
+fn courant_keys_system(
+    mut overlay: ResMut<CourantOverlay>,
+    mut char_input_events: EventReader<ReceivedCharacter>,
+) {
+    for event in char_input_events.iter() {
+        if event.char == '2' {
+            overlay.active = !overlay.active;
+        }
+    }
+}
+
+/// Dark blue at rest to yellow near the limit, the cells over it highlighted
+fn heat_color(courant: f32) -> Color {
+    if !(courant <= CFL_LIMIT) {
+        return HIGHLIGHT;
+    }
+    let v = courant / CFL_LIMIT;
+    Color::rgb(v, v * v * 0.9, 0.3 * (1.0 - v))
+}
+
+fn courant_square_system(
+    control: Res<StepControl>,
+    mut overlay: ResMut<CourantOverlay>,
+    mut errors: ResMut<ErrorLog>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    qg: Query<&Grid>,
+    mut query: Query<(
+        &CourantSquare,
+        &Position,
+        &Handle<ColorMaterial>,
+        &mut Visible,
+    )>,
+) {
+    let grid = match qg.single() {
+        Ok(grid) => grid,
+        Err(_) => return,
+    };
+    if overlay.active {
+        let numbers = grid
+            .0
+            .iter()
+            .flatten()
+            .map(|cell| courant_number(cell.velocity, control.dt));
+        overlay.max = numbers.clone().fold(0.0, f32::max);
+        overlay.exceeding = numbers.filter(|courant| !(*courant <= CFL_LIMIT)).count();
+    }
+
+    for (_courant_square, position, color, mut visible) in query.iter_mut() {
+        visible.is_visible = overlay.active;
+        if !overlay.active {
+            continue;
+        }
+
+        let color_mat = match materials.get_mut(&*color) {
+            Some(material) => material,
+            None => {
+                errors.report("Missing material of a Courant square");
+                continue;
+            }
+        };
+        let Position { x, y } = position;
+        color_mat.color = heat_color(courant_number(grid.0[*y][*x].velocity, control.dt));
+    }
+}
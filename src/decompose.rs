@@ -0,0 +1,265 @@
+use std::env;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::time::{Duration, Instant};
+
+use crate::boundary::BoundaryMode;
+use crate::emitters::{self, Emitter};
+use crate::layers::Layers;
+use crate::memory::{self, MemoryBudget};
+use crate::palette::Palette;
+use crate::post;
+use crate::render;
+use crate::scene_file::SceneFile;
+use crate::settings::SolverSettings;
+use crate::snapshot;
+use crate::solver::{self, Scratch, Splats};
+use crate::{Cell, Grid, CELL_SIZE};
+
+// Experimental domain decomposition: the grid of a scene file is split into horizontal
+// strips, each stepped by its own worker process, a copy of this executable started with
+// --worker and driven over its standard input and output. Before every step the workers
+// send the rows along their edges, which are relayed to their neighbors as halos, so each
+// strip steps with the rows around it. The halos only reach so far while the pressure
+// spans the whole grid, so the flow drifts from a single process run across the strips:
+// it's a sketch of how the solver would scale, not a replacement for it. The scene file's
+// emitters are fed, its fans, wind and inflows aren't.
+//
+// Every message is a command byte from the coordinator, rows of cells being sent as their
+// count then the fields of the snapshots of every cell as little endian f32:
+// - E: the worker answers with its bottom then top halo rows
+// - H: the halo rows below then above the strip follow, the worker steps once
+// - G: the worker answers with all its rows
+// - Q: the worker exits
+
+/// Rows of the halos, and least rows of a strip
+const HALO: usize = 8;
+const STEP_DT: f32 = 1.0 / 60.0;
+
+/// A worker process and its pipes
+struct Strip {
+    child: Child,
+    input: BufWriter<ChildStdin>,
+    output: BufReader<ChildStdout>,
+}
+
+/// Run the scene file on `workers` processes, writing its last frame to `out` and returning
+/// the time the steps took
+pub fn run(
+    scene_path: &Path,
+    workers: usize,
+    steps: usize,
+    out: &Path,
+    settings: &SolverSettings,
+    budget: &MemoryBudget,
+) -> Result<Duration, String> {
+    if workers == 0 {
+        return Err("the decomposition needs at least one worker".to_string());
+    }
+    let file = SceneFile::load(scene_path)?;
+    let (width, height) = file.grid_size();
+    memory::check(width, height, budget)?;
+    if height / workers < HALO {
+        return Err(format!(
+            "{} rows can't be split into {} strips of at least {} rows",
+            height, workers, HALO
+        ));
+    }
+    let grid = file.scene.build(width, height);
+    let (strips, elapsed) = coordinate(&grid, workers, steps, settings.boundary)
+        .map_err(|err| format!("worker: {}", err))?;
+
+    let frame = post::compose(
+        &Grid(strips),
+        &Palette::default(),
+        &Layers::from_styles(&file.layers),
+        &file.post_effects,
+    );
+    render::frame_image(&frame, CELL_SIZE as u32)
+        .save(out)
+        .map_err(|err| format!("{}: {}", out.display(), err))?;
+    Ok(elapsed)
+}
+
+/// Start the workers, step them and gather their rows back
+fn coordinate(
+    grid: &Grid,
+    workers: usize,
+    steps: usize,
+    boundary: BoundaryMode,
+) -> io::Result<(Vec<Vec<Cell>>, Duration)> {
+    let (width, height) = (grid.width(), grid.height());
+    let exe = env::current_exe()?;
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut strips = Vec::with_capacity(workers);
+    for i in 0..workers {
+        let (start, end) = (i * height / workers, (i + 1) * height / workers);
+        let mut child = Command::new(&exe)
+            .args(&args)
+            .arg("--worker")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let mut input = BufWriter::new(child.stdin.take().expect("piped stdin"));
+        let output = BufReader::new(child.stdout.take().expect("piped stdout"));
+        input.write_all(&(width as u32).to_le_bytes())?;
+        input.write_all(&(start as u32).to_le_bytes())?;
+        write_rows(&mut input, &grid.0[start..end])?;
+        input.flush()?;
+        strips.push(Strip {
+            child,
+            input,
+            output,
+        });
+    }
+
+    let periodic = boundary == BoundaryMode::Periodic;
+    let started = Instant::now();
+    for _ in 0..steps {
+        for strip in strips.iter_mut() {
+            strip.input.write_all(b"E")?;
+            strip.input.flush()?;
+        }
+        let mut edges = Vec::with_capacity(workers);
+        for strip in strips.iter_mut() {
+            let bottom = read_rows(&mut strip.output, width)?;
+            let top = read_rows(&mut strip.output, width)?;
+            edges.push((bottom, top));
+        }
+
+        for (i, strip) in strips.iter_mut().enumerate() {
+            // The top rows of the strip below, the bottom ones of the strip above
+            let below = match i {
+                0 if periodic => Some(&edges[workers - 1].1),
+                0 => None,
+                _ => Some(&edges[i - 1].1),
+            };
+            let above = match i + 1 {
+                next if next < workers => Some(&edges[next].0),
+                _ if periodic => Some(&edges[0].0),
+                _ => None,
+            };
+            strip.input.write_all(b"H")?;
+            write_rows(&mut strip.input, below.map_or(&[], Vec::as_slice))?;
+            write_rows(&mut strip.input, above.map_or(&[], Vec::as_slice))?;
+            strip.input.flush()?;
+        }
+    }
+
+    for strip in strips.iter_mut() {
+        strip.input.write_all(b"G")?;
+        strip.input.flush()?;
+    }
+    let mut rows = Vec::with_capacity(height);
+    for strip in strips.iter_mut() {
+        rows.extend(read_rows(&mut strip.output, width)?);
+    }
+    let elapsed = started.elapsed();
+    for strip in strips.iter_mut() {
+        strip.input.write_all(b"Q")?;
+        strip.input.flush()?;
+        strip.child.wait()?;
+    }
+    Ok((rows, elapsed))
+}
+
+/// Step a strip of the scene file as the coordinator commands, over the standard input and
+/// output
+pub fn worker(scene_path: &Path, settings: &SolverSettings) -> Result<(), String> {
+    let file = SceneFile::load(scene_path)?;
+    let mut settings = SolverSettings { ..*settings };
+    if let Some(material) = file.material {
+        material.apply(&mut settings);
+    }
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let mut input = BufReader::new(stdin.lock());
+    let mut output = BufWriter::new(stdout.lock());
+    serve(&mut input, &mut output, &file.emitters, &settings).map_err(|err| err.to_string())
+}
+
+fn serve(
+    input: &mut impl Read,
+    output: &mut impl Write,
+    emitters: &[Emitter],
+    settings: &SolverSettings,
+) -> io::Result<()> {
+    let width = read_u32(input)? as usize;
+    let start = read_u32(input)? as usize;
+    let mut strip = read_rows(input, width)?;
+    let mut splats = Splats::default();
+    let mut scratch = Scratch::default();
+
+    loop {
+        let mut command = [0];
+        input.read_exact(&mut command)?;
+        match command[0] {
+            b'E' => {
+                write_rows(output, &strip[..HALO])?;
+                write_rows(output, &strip[strip.len() - HALO..])?;
+                output.flush()?;
+            }
+            b'H' => {
+                let below = read_rows(input, width)?;
+                let above = read_rows(input, width)?;
+                let rows = below.iter().chain(strip.iter()).chain(above.iter());
+                let mut grid = Grid(rows.cloned().collect());
+
+                // The emitters where they fall on the strip and its halos
+                let offset = (start as f32) - below.len() as f32;
+                let local: Vec<Emitter> = emitters
+                    .iter()
+                    .map(|emitter| Emitter {
+                        position: (emitter.position.0, emitter.position.1 - offset),
+                        ..*emitter
+                    })
+                    .collect();
+                emitters::inject(&mut grid, &local, STEP_DT);
+                solver::step(&mut grid, STEP_DT, settings, &mut splats, &mut scratch);
+                let own = below.len()..below.len() + strip.len();
+                strip = grid.0.drain(own).collect();
+            }
+            b'G' => {
+                write_rows(output, &strip)?;
+                output.flush()?;
+            }
+            b'Q' => return Ok(()),
+            command => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unknown command {}", command),
+                ))
+            }
+        }
+    }
+}
+
+fn read_u32(input: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn write_rows(output: &mut impl Write, rows: &[Vec<Cell>]) -> io::Result<()> {
+    output.write_all(&(rows.len() as u32).to_le_bytes())?;
+    for cell in rows.iter().flatten() {
+        for i in 0..snapshot::FIELDS {
+            output.write_all(&snapshot::field(cell, i).to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+fn read_rows(input: &mut impl Read, width: usize) -> io::Result<Vec<Vec<Cell>>> {
+    let count = read_u32(input)? as usize;
+    let mut rows = Grid::new(width, count).0;
+    for cell in rows.iter_mut().flatten() {
+        for i in 0..snapshot::FIELDS {
+            let mut bytes = [0; 4];
+            input.read_exact(&mut bytes)?;
+            snapshot::set_field(cell, i, f32::from_le_bytes(bytes));
+        }
+    }
+    Ok(rows)
+}
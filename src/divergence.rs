@@ -0,0 +1,226 @@
+use bevy::prelude::*;
+use bevy::render::texture::{Extent3d, TextureDimension, TextureFormat};
+
+use crate::accessibility::Accessibility;
+use crate::font;
+use crate::solver::Scratch;
+use crate::viewport::MainCamera;
+
+// Divergence diagnostics: 1 shows in the bottom left corner the histogram of the divergence
+// the last projection left in every cell. A working projection keeps it around zero, the
+// panel turns red when the largest one goes over the threshold, a sign the projection
+// iterations can't keep up with the velocities or that the field blew up.
+
+/// Largest divergence a projection may leave before the alarm goes off
+const DEFAULT_THRESHOLD: f32 = 1.0;
+const BINS: usize = 32;
+/// Width of a bar of the histogram, in pixels
+const BIN_WIDTH: usize = 4;
+const PLOT_HEIGHT: usize = 48;
+/// The histogram spans minus to plus this many thresholds, the divergence past it counting
+/// in the first and last bins
+const RANGE: f32 = 2.0;
+const PADDING: usize = 4;
+const LINE_SPACING: usize = 3;
+const MARGIN: f32 = 8.0;
+
+pub struct DivergencePlugin;
+
+impl Plugin for DivergencePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(DivergencePanel {
+            active: false,
+            threshold: DEFAULT_THRESHOLD,
+        })
+        .add_startup_system(divergence_setup.system())
+        .add_system(divergence_keys_system.system())
+        .add_system(divergence_panel_system.system());
+    }
+}
+
+pub struct DivergencePanel {
+    pub active: bool,
+    pub threshold: f32,
+}
+
+struct DivergenceSprite;
+
+fn divergence_setup(
+    mut commands: Commands,
+    mut textures: ResMut<Assets<Texture>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let texture = textures.add(panel_texture(&[0; BINS], 0.0, DEFAULT_THRESHOLD));
+    commands
+        .spawn_bundle(SpriteBundle {
+            material: materials.add(texture.into()),
+            visible: Visible {
+                is_visible: false,
+                is_transparent: true,
+            },
+            ..Default::default()
+        })
+        .insert(DivergenceSprite);
+}
+
+fn divergence_keys_system(
+    mut panel: ResMut<DivergencePanel>,
+    mut char_input_events: EventReader<ReceivedCharacter>,
+) {
+    for event in char_input_events.iter() {
+        if event.char == '1' {
+            panel.active = !panel.active;
+        }
+    }
+}
+
+/// Cells per bin of the histogram, with the largest absolute divergence. A NaN sticks as
+/// the largest one, so a field that blew up sets the alarm off.
+fn histogram(divergence: impl Iterator<Item = f32>, threshold: f32) -> ([usize; BINS], f32) {
+    let mut bins = [0; BINS];
+    let mut max: f32 = 0.0;
+    for value in divergence {
+        let bin = (value / (RANGE * threshold) + 1.0) / 2.0 * BINS as f32;
+        bins[bin.max(0.0).min(BINS as f32 - 1.0) as usize] += 1;
+        if value.abs() > max || value.is_nan() {
+            max = value.abs();
+        }
+    }
+    (bins, max)
+}
+
+/// The summary over the histogram, its bars on a logarithmic scale so the few cells the
+/// projection fails on stay visible. The bars past the threshold are orange.
+fn panel_texture(bins: &[usize; BINS], max: f32, threshold: f32) -> Texture {
+    let alarm = !(max <= threshold);
+    let lines = [
+        format!("DIVERGENCE MAX {:.3}", max),
+        format!("ALARM OVER {}", threshold),
+    ];
+    let text: Vec<_> = lines.iter().map(|line| font::rasterize(line, 1)).collect();
+    let text_width = text.iter().map(|pixels| pixels[0].len()).max().unwrap_or(0);
+    let line_height = font::GLYPH_HEIGHT + LINE_SPACING;
+    let width = text_width.max(BINS * BIN_WIDTH) + 2 * PADDING;
+    let height = text.len() * line_height + PLOT_HEIGHT + 2 * PADDING;
+
+    let background = if alarm {
+        [160, 0, 0, 220]
+    } else {
+        [0, 0, 0, 160]
+    };
+    let mut data: Vec<u8> = background.repeat(width * height);
+    let mut put = |x: usize, y: usize, rgba: [u8; 4]| {
+        let start = (y * width + x) * 4;
+        data[start..start + 4].copy_from_slice(&rgba);
+    };
+
+    for (i, pixels) in text.iter().enumerate() {
+        for (y, row) in pixels.iter().enumerate() {
+            for (x, &pixel) in row.iter().enumerate() {
+                if pixel {
+                    put(
+                        PADDING + x,
+                        PADDING + i * line_height + y,
+                        [255, 255, 255, 255],
+                    );
+                }
+            }
+        }
+    }
+
+    let plot_top = PADDING + text.len() * line_height;
+    let plot_bottom = plot_top + PLOT_HEIGHT;
+    // Where the threshold falls, on both sides of zero
+    for side in [-1.0, 1.0] {
+        let x = ((side / RANGE + 1.0) / 2.0 * (BINS * BIN_WIDTH) as f32) as usize;
+        for y in plot_top..plot_bottom {
+            put(PADDING + x.min(BINS * BIN_WIDTH - 1), y, [255, 255, 0, 255]);
+        }
+    }
+
+    let most = bins.iter().copied().max().unwrap_or(0);
+    for (i, &count) in bins.iter().enumerate() {
+        if count == 0 {
+            continue;
+        }
+        let bar = ((count as f32).ln_1p() / (most as f32).ln_1p() * PLOT_HEIGHT as f32)
+            .ceil()
+            .max(1.0) as usize;
+        let center = ((i as f32 + 0.5) / BINS as f32 * 2.0 - 1.0) * RANGE;
+        let color = if center.abs() > 1.0 {
+            [255, 160, 0, 255]
+        } else {
+            [255, 255, 255, 255]
+        };
+        for y in plot_bottom - bar.min(PLOT_HEIGHT)..plot_bottom {
+            // Leave a pixel between the bars
+            for x in i * BIN_WIDTH..(i + 1) * BIN_WIDTH - 1 {
+                put(PADDING + x, y, color);
+            }
+        }
+    }
+
+    Texture::new(
+        Extent3d::new(width as u32, height as u32, 1),
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn divergence_panel_system(
+    panel: Res<DivergencePanel>,
+    scratch: Res<Scratch>,
+    windows: Res<Windows>,
+    accessibility: Res<Accessibility>,
+    mut textures: ResMut<Assets<Texture>>,
+    materials: Res<Assets<ColorMaterial>>,
+    cameras: Query<&Transform, With<MainCamera>>,
+    mut query: Query<
+        (&Handle<ColorMaterial>, &mut Transform, &mut Visible),
+        (With<DivergenceSprite>, Without<MainCamera>),
+    >,
+) {
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    // The camera follows the view when scrolling
+    let center = cameras
+        .single()
+        .map_or(Vec3::ZERO, |transform| transform.translation);
+    // The histogram only changes with a projection, not while paused
+    let changed = scratch.is_changed() || panel.is_changed();
+
+    for (material, mut transform, mut visible) in query.iter_mut() {
+        visible.is_visible = panel.active;
+        if !panel.active {
+            continue;
+        }
+        let handle = match materials.get(material).and_then(|m| m.texture.as_ref()) {
+            Some(handle) => handle,
+            None => continue,
+        };
+        if changed {
+            let (bins, max) = histogram(scratch.residual_divergence(), panel.threshold);
+            if let Some(texture) = textures.get_mut(handle) {
+                *texture = panel_texture(&bins, max, panel.threshold);
+            }
+        }
+        let texture = match textures.get(handle) {
+            Some(texture) => texture,
+            None => continue,
+        };
+
+        // Bottom left corner of the window, which can change size
+        let scale = accessibility.ui_scale as f32;
+        let size = Vec2::new(texture.size.width as f32, texture.size.height as f32) * scale;
+        transform.scale = Vec3::new(scale, scale, 1.0);
+        transform.translation = Vec3::new(
+            center.x + (size.x - window.width()) / 2.0 + MARGIN,
+            center.y + (size.y - window.height()) / 2.0 + MARGIN,
+            10.0,
+        );
+    }
+}
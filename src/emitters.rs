@@ -0,0 +1,110 @@
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::{AppState, Grid};
+
+// Emitters: continuous sources declared in the scene file, each adding density and fuel to
+// the cells within its radius every step and, if it has a jet, setting their velocity, so a
+// steady smoke plume, a burner or a jet doesn't need the mouse. They're spawned as entities
+// when the simulation starts, the stepping injecting them at the start of every step.
+
+pub struct EmitterPlugin;
+
+impl Plugin for EmitterPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system_set(
+            SystemSet::on_enter(AppState::Running).with_system(emitter_setup.system()),
+        );
+    }
+}
+
+/// Source of density and velocity, in grid coordinates, e.g. a plume rising from the bottom
+/// `(position: (30.0, 2.0), radius: 2.0, density_rate: 3.0, jet: (0.0, 10.0))`
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub struct Emitter {
+    /// Center, in cells
+    pub position: (f32, f32),
+    /// Cells whose center is this close to the position are fed, in cells
+    pub radius: f32,
+    /// Density added to each of its cells per second
+    pub density_rate: f32,
+    /// Velocity of the fluid leaving it, in cells per second, none when zero
+    #[serde(default)]
+    pub jet: (f32, f32),
+    /// Fuel added to each of its cells per second, burning once something heats it
+    #[serde(default)]
+    pub fuel_rate: f32,
+}
+
+impl Emitter {
+    /// Check the parameters are in range, naming the wrong one
+    pub fn validate(&self) -> Result<(), String> {
+        let (x, y) = self.position;
+        let (jx, jy) = self.jet;
+        if !x.is_finite() || !y.is_finite() {
+            Err(format!("position must be numbers, not ({}, {})", x, y))
+        } else if !(self.radius.is_finite() && self.radius > 0.0) {
+            Err(format!(
+                "radius must be a positive number, not {}",
+                self.radius
+            ))
+        } else if !(self.density_rate.is_finite() && self.density_rate >= 0.0) {
+            Err(format!(
+                "density_rate must be a nonnegative number, not {}",
+                self.density_rate
+            ))
+        } else if !jx.is_finite() || !jy.is_finite() {
+            Err(format!("jet must be numbers, not ({}, {})", jx, jy))
+        } else if !(self.fuel_rate.is_finite() && self.fuel_rate >= 0.0) {
+            Err(format!(
+                "fuel_rate must be a nonnegative number, not {}",
+                self.fuel_rate
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Emitters of the scene file the simulation started with
+#[derive(Default)]
+pub struct SceneEmitters(pub Vec<Emitter>);
+
+fn emitter_setup(mut commands: Commands, scene_emitters: Res<SceneEmitters>) {
+    for emitter in scene_emitters.0.iter() {
+        commands.spawn().insert(*emitter);
+    }
+}
+
+/// Feed the cells of the emitters for a step of `dt` seconds, the cells outside of the
+/// grid and the obstacles being left out
+pub fn inject<'a>(grid: &mut Grid, emitters: impl IntoIterator<Item = &'a Emitter>, dt: f32) {
+    let (width, height) = (grid.width(), grid.height());
+    for emitter in emitters {
+        let center = Vec2::from(emitter.position);
+        let jet = Vec2::from(emitter.jet);
+        let min = (center - Vec2::splat(emitter.radius))
+            .ceil()
+            .max(Vec2::ZERO);
+        let max = (center + Vec2::splat(emitter.radius)).floor();
+        let max = max.min(Vec2::new(width as f32 - 1.0, height as f32 - 1.0));
+        if min.x > max.x || min.y > max.y {
+            continue;
+        }
+
+        for y in min.y as usize..=max.y as usize {
+            for x in min.x as usize..=max.x as usize {
+                let cell = &mut grid.0[y][x];
+                let distance = Vec2::new(x as f32, y as f32).distance(center);
+                if cell.obstacle || distance > emitter.radius {
+                    continue;
+                }
+                cell.density += emitter.density_rate * dt;
+                cell.fuel += emitter.fuel_rate * dt;
+                if jet != Vec2::ZERO {
+                    cell.velocity = jet;
+                }
+            }
+        }
+    }
+}
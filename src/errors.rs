@@ -0,0 +1,147 @@
+use bevy::prelude::*;
+
+use crate::accessibility::Accessibility;
+use crate::font;
+use crate::viewport::MainCamera;
+
+// Errors worth telling the user about, like a broken scene file or a missing asset, are
+// logged and shown at the top of the main window for a few seconds instead of panicking.
+
+/// Seconds an error stays on screen
+const SHOW_SECONDS: f32 = 8.0;
+const MAX_SHOWN: usize = 5;
+const MARGIN: f32 = 8.0;
+
+pub struct ErrorPanelPlugin;
+
+impl Plugin for ErrorPanelPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<ErrorLog>()
+            .add_startup_system(error_panel_setup.system())
+            .add_system(error_panel_system.system());
+    }
+}
+
+#[derive(Default)]
+pub struct ErrorLog {
+    /// Reported but not logged yet, errors can be reported before the logger exists
+    pending: Vec<String>,
+    /// Messages on screen with their remaining seconds
+    shown: Vec<(String, f32)>,
+}
+
+impl ErrorLog {
+    /// Log an error and show it. An error reported again while shown only stays longer,
+    /// so systems can report the same problem every frame.
+    pub fn report(&mut self, message: impl Into<String>) {
+        let message = message.into();
+        match self.shown.iter_mut().find(|(shown, _)| *shown == message) {
+            Some((_, seconds)) => *seconds = SHOW_SECONDS,
+            None if !self.pending.contains(&message) => self.pending.push(message),
+            None => {}
+        }
+    }
+}
+
+struct ErrorPanel;
+
+fn error_panel_setup(mut commands: Commands, mut materials: ResMut<Assets<ColorMaterial>>) {
+    commands
+        .spawn_bundle(SpriteBundle {
+            material: materials.add(Color::rgb(1.0, 0.3, 0.3).into()),
+            visible: Visible {
+                is_visible: false,
+                is_transparent: true,
+            },
+            ..Default::default()
+        })
+        .insert(ErrorPanel);
+}
+
+/// Keep only what the font can draw, e.g. the slashes of paths become spaces
+fn printable(message: &str) -> String {
+    message
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "!?.-".contains(c) {
+                c
+            } else {
+                ' '
+            }
+        })
+        .collect()
+}
+
+fn error_panel_system(
+    time: Res<Time>,
+    windows: Res<Windows>,
+    accessibility: Res<Accessibility>,
+    mut errors: ResMut<ErrorLog>,
+    mut textures: ResMut<Assets<Texture>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    cameras: Query<&Transform, With<MainCamera>>,
+    mut query: Query<
+        (&Handle<ColorMaterial>, &mut Transform, &mut Visible),
+        (With<ErrorPanel>, Without<MainCamera>),
+    >,
+) {
+    let shown_before = errors.shown.len();
+    for (_, seconds) in errors.shown.iter_mut() {
+        *seconds -= time.delta_seconds();
+    }
+    errors.shown.retain(|(_, seconds)| *seconds > 0.0);
+
+    let pending: Vec<_> = errors.pending.drain(..).collect();
+    let changed =
+        errors.shown.len() != shown_before || !pending.is_empty() || accessibility.is_changed();
+    for message in pending {
+        error!("{}", message);
+        errors.shown.push((message, SHOW_SECONDS));
+    }
+    let excess = errors.shown.len().saturating_sub(MAX_SHOWN);
+    errors.shown.drain(..excess);
+
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    // The camera follows the view when scrolling
+    let center = cameras
+        .single()
+        .map_or(Vec3::ZERO, |transform| transform.translation);
+
+    for (material, mut transform, mut visible) in query.iter_mut() {
+        visible.is_visible = !errors.shown.is_empty();
+        let material = match materials.get_mut(material) {
+            Some(material) => material,
+            None => continue,
+        };
+
+        if changed {
+            let lines: Vec<_> = errors
+                .shown
+                .iter()
+                .map(|(message, _)| printable(message))
+                .collect();
+            let lines: Vec<_> = lines.iter().map(String::as_str).collect();
+            let texture = textures.add(font::texture(&lines, accessibility.ui_scale));
+            if let Some(old) = material.texture.replace(texture) {
+                textures.remove(old);
+            }
+        }
+
+        // Top left corner of the window, which can change size
+        let size = material
+            .texture
+            .as_ref()
+            .and_then(|handle| textures.get(handle))
+            .map_or(Vec2::ZERO, |texture| {
+                Vec2::new(texture.size.width as f32, texture.size.height as f32)
+            });
+        transform.translation = Vec3::new(
+            center.x + (size.x - window.width()) / 2.0 + MARGIN,
+            center.y + (window.height() - size.y) / 2.0 - MARGIN,
+            10.0,
+        );
+    }
+}
@@ -0,0 +1,473 @@
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use bevy::prelude::*;
+use bevy::tasks::IoTaskPool;
+use exr::prelude::{
+    AnyChannel, AnyChannels, Encoding, FlatSamples, LayerAttributes, WritableImage,
+};
+use image::gif::GifEncoder;
+use image::imageops::{self, FilterType};
+use image::{Delay, ImageBuffer, Rgb, RgbaImage};
+
+use crate::jobs::{Job, Jobs};
+use crate::layers::Layers;
+use crate::palette::Palette;
+use crate::post::{self, PostEffects};
+use crate::render;
+use crate::snapshot;
+use crate::solver::Scratch;
+use crate::{AppState, Grid, CELL_SIZE};
+
+// Exports written on the IO task pool from a copy of the data, so the frame loop never
+// waits for the disk: x saves the displayed frame as a PNG, n the density as CSV and
+// j the density, temperature and velocity as a VTK file. A compressed snapshot is
+// autosaved every minute. The last seconds of the display are kept at a low frame rate,
+// 8 writing them as a GIF to catch what just happened. Ctrl+X bakes the velocity into a
+// flow map texture for game engine shaders, Ctrl+Shift+X its average since the last one.
+// Ctrl+J writes the density, velocity and pressure as 32 bit floats in an OpenEXR file,
+// unclamped for compositing and analysis. Every export is a job, the GIF advancing with its
+// frames.
+
+const EXPORT_DIR: &str = "exports";
+const AUTOSAVE_PATH: &str = "autosave.fsnp";
+const AUTOSAVE_SECONDS: f32 = 60.0;
+/// Length of the GIF
+const GIF_SECONDS: f32 = 5.0;
+const GIF_FPS: u32 = 15;
+/// Pixels per cell of the GIF
+const GIF_SCALE: u32 = 4;
+
+pub struct ExportPlugin;
+
+impl Plugin for ExportPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(Exports::default())
+            .insert_resource(GifBuffer::default())
+            .insert_resource(FlowAverage::default())
+            .add_system_set(
+                SystemSet::on_update(AppState::Running)
+                    .with_system(export_keys_system.system())
+                    .with_system(gif_capture_system.system())
+                    .with_system(flow_average_system.system())
+                    .with_system(ctrl_export_keys_system.system())
+                    .with_system(autosave_system.system()),
+            );
+    }
+}
+
+#[derive(Default)]
+pub struct Exports {
+    /// Numbers the exported files
+    count: usize,
+    since_autosave: f32,
+    /// Set while an autosave is being written, so they don't pile up on a slow disk
+    autosaving: Arc<AtomicBool>,
+}
+
+impl Exports {
+    fn next_path(&mut self, extension: &str) -> PathBuf {
+        self.count += 1;
+        Path::new(EXPORT_DIR).join(format!("export_{:04}.{}", self.count, extension))
+    }
+}
+
+/// Recent frames of the display for the GIF, at one pixel per cell
+#[derive(Default)]
+pub struct GifBuffer {
+    /// RGBA bytes of the frames, oldest first
+    frames: VecDeque<Vec<u8>>,
+    /// Width and height of the frames, in cells
+    size: (u32, u32),
+    since_frame: f32,
+}
+
+impl GifBuffer {
+    pub fn memory_bytes(&self) -> usize {
+        self.frames.iter().map(Vec::len).sum()
+    }
+}
+
+/// Velocity of every cell integrated over the time since the last averaged flow map
+#[derive(Default)]
+pub struct FlowAverage {
+    sum: Vec<Vec<Vec2>>,
+    seconds: f32,
+}
+
+impl FlowAverage {
+    pub fn memory_bytes(&self) -> usize {
+        self.sum.iter().map(|row| row.len()).sum::<usize>() * std::mem::size_of::<Vec2>()
+    }
+
+    fn reset(&mut self, width: usize, height: usize) {
+        self.sum = vec![vec![Vec2::ZERO; width]; height];
+        self.seconds = 0.0;
+    }
+
+    /// Mean velocity of every cell, the current one if no time went by yet
+    fn field(&self, grid: &Grid) -> Vec<Vec<Vec2>> {
+        if self.seconds == 0.0 {
+            return velocity_field(grid);
+        }
+        self.sum
+            .iter()
+            .map(|row| row.iter().map(|&v| v / self.seconds).collect())
+            .collect()
+    }
+}
+
+/// Write the file produced by `encode` in the background as a job of `steps` steps,
+/// logging the outcome. A cancelled export is removed.
+fn spawn_write(
+    pool: &IoTaskPool,
+    jobs: &mut Jobs,
+    path: PathBuf,
+    steps: usize,
+    encode: impl FnOnce(&Path, &Job) -> Result<(), String> + Send + 'static,
+) {
+    let name = format!(
+        "EXPORT {}",
+        path.extension().unwrap_or_default().to_string_lossy()
+    );
+    let job = jobs.start(&name, steps);
+    pool.spawn(async move {
+        let result = match path.parent() {
+            Some(dir) => fs::create_dir_all(dir).map_err(|err| err.to_string()),
+            None => Ok(()),
+        }
+        .and_then(|()| encode(&path, &job));
+
+        if job.is_cancelled() {
+            let _ = fs::remove_file(&path);
+            info!("Cancelled the export of {}", path.display());
+            return;
+        }
+        match result {
+            Ok(()) => info!("Exported {}", path.display()),
+            Err(err) => error!("Couldn't export {}: {}", path.display(), err),
+        }
+    })
+    .detach();
+}
+
+fn density_csv(grid: &Grid) -> String {
+    let mut csv = String::new();
+    // Top row first, like the window shows it
+    for row in grid.0.iter().rev() {
+        let values: Vec<_> = row.iter().map(|cell| cell.density.to_string()).collect();
+        csv.push_str(&values.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+/// Legacy VTK structured points, readable by ParaView
+fn vtk(grid: &Grid) -> String {
+    let (width, height) = (grid.width(), grid.height());
+    let mut vtk = String::new();
+    let _ = write!(
+        vtk,
+        "# vtk DataFile Version 3.0\nfluid simulation\nASCII\nDATASET STRUCTURED_POINTS\n\
+         DIMENSIONS {} {} 1\nORIGIN 0 0 0\nSPACING 1 1 1\nPOINT_DATA {}\n",
+        width,
+        height,
+        width * height
+    );
+
+    vtk.push_str("SCALARS density float 1\nLOOKUP_TABLE default\n");
+    for cell in grid.0.iter().flatten() {
+        let _ = writeln!(vtk, "{}", cell.density);
+    }
+    vtk.push_str("SCALARS temperature float 1\nLOOKUP_TABLE default\n");
+    for cell in grid.0.iter().flatten() {
+        let _ = writeln!(vtk, "{}", cell.temperature);
+    }
+    vtk.push_str("VECTORS velocity float\n");
+    for cell in grid.0.iter().flatten() {
+        let _ = writeln!(vtk, "{} {} 0", cell.velocity.x, cell.velocity.y);
+    }
+    vtk
+}
+
+fn velocity_field(grid: &Grid) -> Vec<Vec<Vec2>> {
+    grid.0
+        .iter()
+        .map(|row| row.iter().map(|cell| cell.velocity).collect())
+        .collect()
+}
+
+/// Flow map of the velocity, a 16 bit PNG with the x component in red and the y one in
+/// green, 0.5 meaning still and 0 and 1 the fastest speed, which it returns in cells/s.
+/// Green points up like in Bevy and Unity, Unreal wanting it flipped. One pixel per cell,
+/// the engines filtering it.
+fn flow_map(field: &[Vec<Vec2>]) -> (ImageBuffer<Rgb<u16>, Vec<u16>>, f32) {
+    let height = field.len() as u32;
+    let width = field[0].len() as u32;
+    let max_speed = field
+        .iter()
+        .flatten()
+        .map(|velocity| velocity.length())
+        .fold(0.0, f32::max);
+    let scale = if max_speed > 0.0 {
+        0.5 / max_speed
+    } else {
+        0.0
+    };
+
+    let image = ImageBuffer::from_fn(width, height, |px, py| {
+        // Image rows go from top to bottom
+        let velocity = field[(height - 1 - py) as usize][px as usize];
+        let to_u16 = |c: f32| ((0.5 + c * scale).clamp(0.0, 1.0) * 65535.0).round() as u16;
+        Rgb([to_u16(velocity.x), to_u16(velocity.y), 0])
+    });
+    (image, max_speed)
+}
+
+/// OpenEXR image with a channel per field, top row first like images, the velocity still
+/// pointing up. The pressure is 0 until the first projection.
+fn encode_exr(path: &Path, grid: &Grid, pressure: &[Vec<f32>]) -> Result<(), String> {
+    let (width, height) = (grid.width(), grid.height());
+    let rows = || grid.0.iter().rev().flatten();
+    let channel = |name: &str, samples: Vec<f32>| AnyChannel::new(name, FlatSamples::F32(samples));
+    let pressure = if pressure.len() == height {
+        pressure.iter().rev().flatten().copied().collect()
+    } else {
+        vec![0.0; width * height]
+    };
+    let channels = vec![
+        channel("density", rows().map(|cell| cell.density).collect()),
+        channel("velocity.X", rows().map(|cell| cell.velocity.x).collect()),
+        channel("velocity.Y", rows().map(|cell| cell.velocity.y).collect()),
+        channel("pressure", pressure),
+    ];
+
+    let layer = exr::prelude::Layer::new(
+        (width, height),
+        LayerAttributes::named("fluid"),
+        Encoding::FAST_LOSSLESS,
+        AnyChannels::sort(channels.into()),
+    );
+    exr::prelude::Image::from_layer(layer)
+        .write()
+        .to_file(path)
+        .map_err(|err| err.to_string())
+}
+
+/// Animated GIF of the frames, scaled up without smoothing so the cells stay sharp,
+/// stopping early when the job is cancelled
+fn encode_gif(
+    path: &Path,
+    frames: Vec<Vec<u8>>,
+    (width, height): (u32, u32),
+    job: &Job,
+) -> Result<(), String> {
+    let file = File::create(path).map_err(|err| err.to_string())?;
+    let mut encoder = GifEncoder::new(BufWriter::new(file));
+    let delay = Delay::from_numer_denom_ms(1000, GIF_FPS);
+    for data in frames {
+        if job.is_cancelled() {
+            break;
+        }
+        let image = RgbaImage::from_raw(width, height, data)
+            .ok_or_else(|| "a frame doesn't match the grid size".to_string())?;
+        let image = imageops::resize(
+            &image,
+            width * GIF_SCALE,
+            height * GIF_SCALE,
+            FilterType::Nearest,
+        );
+        encoder
+            .encode_frame(image::Frame::from_parts(image, 0, 0, delay))
+            .map_err(|err| err.to_string())?;
+        job.advance();
+    }
+    Ok(())
+}
+
+/// Keep a frame every 1 / GIF_FPS seconds, dropping the ones older than GIF_SECONDS
+fn gif_capture_system(
+    time: Res<Time>,
+    palette: Res<Palette>,
+    layers: Res<Layers>,
+    post_effects: Res<PostEffects>,
+    mut gif: ResMut<GifBuffer>,
+    qg: Query<&Grid>,
+) {
+    gif.since_frame += time.delta_seconds();
+    if gif.since_frame < 1.0 / GIF_FPS as f32 {
+        return;
+    }
+    let grid = match qg.single() {
+        Ok(grid) => grid,
+        Err(_) => return,
+    };
+    gif.since_frame = 0.0;
+
+    // The frames before a new grid size can't go in the same GIF
+    let size = (grid.width() as u32, grid.height() as u32);
+    if gif.size != size {
+        gif.size = size;
+        gif.frames.clear();
+    }
+    let frame = post::compose(grid, &palette, &layers, &post_effects.0);
+    gif.frames.push_back(render::frame_rgba(&frame));
+    while gif.frames.len() > (GIF_SECONDS * GIF_FPS as f32) as usize {
+        gif.frames.pop_front();
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn export_keys_system(
+    pool: Res<IoTaskPool>,
+    palette: Res<Palette>,
+    layers: Res<Layers>,
+    post_effects: Res<PostEffects>,
+    gif: Res<GifBuffer>,
+    mut exports: ResMut<Exports>,
+    mut jobs: ResMut<Jobs>,
+    qg: Query<&Grid>,
+    mut char_input_events: EventReader<ReceivedCharacter>,
+) {
+    let grid = match qg.single() {
+        Ok(grid) => grid,
+        Err(_) => return,
+    };
+
+    for event in char_input_events.iter() {
+        match event.char {
+            'x' => {
+                let frame = post::compose(grid, &palette, &layers, &post_effects.0);
+                let path = exports.next_path("png");
+                spawn_write(&pool, &mut jobs, path, 1, move |path, _| {
+                    let image = render::frame_image(&frame, CELL_SIZE as u32);
+                    image.save(path).map_err(|err| err.to_string())
+                });
+            }
+            'n' => {
+                let grid = grid.clone();
+                let path = exports.next_path("csv");
+                spawn_write(&pool, &mut jobs, path, 1, move |path, _| {
+                    fs::write(path, density_csv(&grid)).map_err(|err| err.to_string())
+                });
+            }
+            'j' => {
+                let grid = grid.clone();
+                let path = exports.next_path("vtk");
+                spawn_write(&pool, &mut jobs, path, 1, move |path, _| {
+                    fs::write(path, vtk(&grid)).map_err(|err| err.to_string())
+                });
+            }
+            '8' if !gif.frames.is_empty() => {
+                let frames: Vec<_> = gif.frames.iter().cloned().collect();
+                let (size, steps) = (gif.size, frames.len());
+                let path = exports.next_path("gif");
+                spawn_write(&pool, &mut jobs, path, steps, move |path, job| {
+                    encode_gif(path, frames, size, job)
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Integrate the velocity for the averaged flow map, starting over when the grid size changes
+fn flow_average_system(time: Res<Time>, mut average: ResMut<FlowAverage>, qg: Query<&Grid>) {
+    let grid = match qg.single() {
+        Ok(grid) => grid,
+        Err(_) => return,
+    };
+    if average.sum.len() != grid.height() || average.sum[0].len() != grid.width() {
+        average.reset(grid.width(), grid.height());
+    }
+
+    let dt = time.delta_seconds();
+    average.seconds += dt;
+    for (sum_row, row) in average.sum.iter_mut().zip(grid.0.iter()) {
+        for (sum, cell) in sum_row.iter_mut().zip(row.iter()) {
+            *sum += cell.velocity * dt;
+        }
+    }
+}
+
+/// Ctrl+X exports the current velocity as a flow map, Ctrl+Shift+X its average since the
+/// last averaged one, starting a new average. Ctrl+J exports the fields as OpenEXR.
+fn ctrl_export_keys_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    pool: Res<IoTaskPool>,
+    scratch: Res<Scratch>,
+    mut exports: ResMut<Exports>,
+    mut average: ResMut<FlowAverage>,
+    mut jobs: ResMut<Jobs>,
+    qg: Query<&Grid>,
+) {
+    let ctrl =
+        keyboard_input.pressed(KeyCode::LControl) || keyboard_input.pressed(KeyCode::RControl);
+    if !ctrl {
+        return;
+    }
+    let grid = match qg.single() {
+        Ok(grid) => grid,
+        Err(_) => return,
+    };
+
+    if keyboard_input.just_pressed(KeyCode::X) {
+        let shift =
+            keyboard_input.pressed(KeyCode::LShift) || keyboard_input.pressed(KeyCode::RShift);
+        let field = if shift {
+            let field = average.field(grid);
+            info!("Averaging the flow map over {:.1} s", average.seconds);
+            average.reset(grid.width(), grid.height());
+            field
+        } else {
+            velocity_field(grid)
+        };
+        let path = exports.next_path("png");
+        spawn_write(&pool, &mut jobs, path, 1, move |path, _| {
+            let (image, max_speed) = flow_map(&field);
+            info!("Flow map range: {} cells/s", max_speed);
+            image.save(path).map_err(|err| err.to_string())
+        });
+    }
+    if keyboard_input.just_pressed(KeyCode::J) {
+        let grid = grid.clone();
+        let pressure = scratch.pressure().to_vec();
+        let path = exports.next_path("exr");
+        spawn_write(&pool, &mut jobs, path, 1, move |path, _| {
+            encode_exr(path, &grid, &pressure)
+        });
+    }
+}
+
+fn autosave_system(
+    time: Res<Time>,
+    pool: Res<IoTaskPool>,
+    mut exports: ResMut<Exports>,
+    qg: Query<&Grid>,
+) {
+    exports.since_autosave += time.delta_seconds();
+    if exports.since_autosave < AUTOSAVE_SECONDS || exports.autosaving.load(Ordering::Acquire) {
+        return;
+    }
+
+    if let Ok(grid) = qg.single() {
+        exports.since_autosave = 0.0;
+        exports.autosaving.store(true, Ordering::Release);
+        let autosaving = exports.autosaving.clone();
+        let grid = grid.clone();
+
+        pool.spawn(async move {
+            // Compressing is the slow part, it happens on the task too
+            if let Err(err) = fs::write(AUTOSAVE_PATH, snapshot::encode(&grid)) {
+                error!("Couldn't autosave: {}", err);
+            }
+            autosaving.store(false, Ordering::Release);
+        })
+        .detach();
+    }
+}
@@ -0,0 +1,212 @@
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::viewport::Viewport;
+use crate::{AppState, Grid};
+
+// Fans: sources of momentum blowing along their axis over a cone, weaker toward the end of
+// their reach, on the edges of the grid or inside it. They're declared in the scene file,
+// optionally sweeping from side to side, and spawned as entities when the simulation
+// starts. 6 adds a fan blowing up at the cursor, or removes the one there.
+
+/// Fan added with 6
+const DEFAULT_STRENGTH: f32 = 30.0;
+/// Fans this close to the cursor are removed by 6, in cells
+const PICK_DISTANCE: f32 = 1.5;
+
+pub struct FanPlugin;
+
+impl Plugin for FanPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system_set(SystemSet::on_enter(AppState::Running).with_system(fan_setup.system()))
+            .add_system_set(
+                SystemSet::on_update(AppState::Running).with_system(fan_keys_system.system()),
+            );
+    }
+}
+
+/// Cone blowing on the fluid, e.g. to the right from the middle of the left edge, sweeping
+/// 30 degrees up and down every 4 seconds
+/// `(position: (0.0, 20.0), direction: 0.0, strength: 30.0,
+///   oscillation: Some((amplitude: 30.0, period: 4.0)))`
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub struct Fan {
+    /// In cells
+    pub position: (f32, f32),
+    /// Direction of the axis, in degrees counterclockwise from the right
+    pub direction: f32,
+    /// Angle between the axis and the sides of the cone, in degrees
+    #[serde(default = "default_spread")]
+    pub spread: f32,
+    /// Distance it blows to, in cells
+    #[serde(default = "default_reach")]
+    pub reach: f32,
+    /// Acceleration at the fan, in cells/s²
+    pub strength: f32,
+    #[serde(default)]
+    pub oscillation: Option<Oscillation>,
+    /// Simulated seconds since it started, for the oscillation
+    #[serde(skip)]
+    time: f32,
+}
+
+/// Sweep of the axis around its direction
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub struct Oscillation {
+    /// Largest angle from the direction, in degrees
+    pub amplitude: f32,
+    /// Seconds of a sweep back and forth
+    pub period: f32,
+}
+
+fn default_spread() -> f32 {
+    20.0
+}
+
+fn default_reach() -> f32 {
+    10.0
+}
+
+impl Fan {
+    pub fn new(position: Vec2, direction: f32) -> Self {
+        Self {
+            position: position.into(),
+            direction,
+            spread: default_spread(),
+            reach: default_reach(),
+            strength: DEFAULT_STRENGTH,
+            oscillation: None,
+            time: 0.0,
+        }
+    }
+
+    /// Check the parameters are in range, naming the wrong one
+    pub fn validate(&self) -> Result<(), String> {
+        let (x, y) = self.position;
+        if !x.is_finite() || !y.is_finite() {
+            return Err(format!("position must be numbers, not ({}, {})", x, y));
+        }
+        if !self.direction.is_finite() {
+            return Err(format!(
+                "direction must be a number, not {}",
+                self.direction
+            ));
+        }
+        if !(0.0..=180.0).contains(&self.spread) {
+            return Err(format!(
+                "spread must be between 0 and 180 degrees, not {}",
+                self.spread
+            ));
+        }
+        if !(self.reach.is_finite() && self.reach > 0.0) {
+            return Err(format!(
+                "reach must be a positive number, not {}",
+                self.reach
+            ));
+        }
+        if !self.strength.is_finite() {
+            return Err(format!("strength must be a number, not {}", self.strength));
+        }
+        match self.oscillation {
+            Some(Oscillation { amplitude, .. }) if !amplitude.is_finite() => Err(format!(
+                "oscillation amplitude must be a number, not {}",
+                amplitude
+            )),
+            Some(Oscillation { period, .. }) if !(period.is_finite() && period > 0.0) => {
+                Err(format!(
+                    "oscillation period must be a positive number, not {}",
+                    period
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Unit vector along the axis now
+    fn axis(&self) -> Vec2 {
+        let sweep = self
+            .oscillation
+            .map_or(0.0, |Oscillation { amplitude, period }| {
+                amplitude * (2.0 * PI * self.time / period).sin()
+            });
+        let angle = (self.direction + sweep) * PI / 180.0;
+        Vec2::new(angle.cos(), angle.sin())
+    }
+
+    /// Blow on the fluid of its cone for a step of `dt` seconds, the obstacles left out
+    pub fn blow(&mut self, grid: &mut Grid, dt: f32) {
+        let (width, height) = (grid.width(), grid.height());
+        let axis = self.axis();
+        let min_cos = (self.spread * PI / 180.0).cos();
+        self.time += dt;
+
+        let center = Vec2::from(self.position);
+        let min = (center - Vec2::splat(self.reach)).ceil().max(Vec2::ZERO);
+        let max = (center + Vec2::splat(self.reach)).floor();
+        let max = max.min(Vec2::new(width as f32 - 1.0, height as f32 - 1.0));
+        if min.x > max.x || min.y > max.y {
+            return;
+        }
+
+        for y in min.y as usize..=max.y as usize {
+            for x in min.x as usize..=max.x as usize {
+                let offset = Vec2::new(x as f32, y as f32) - center;
+                let distance = offset.length();
+                // The cell of the fan itself blows along the axis
+                let inside = distance == 0.0 || offset.dot(axis) >= min_cos * distance;
+                let cell = &mut grid.0[y][x];
+                if cell.obstacle || distance > self.reach || !inside {
+                    continue;
+                }
+                let falloff = 1.0 - distance / self.reach;
+                cell.velocity += axis * self.strength * falloff * dt;
+            }
+        }
+    }
+}
+
+/// Fans of the scene file the simulation started with
+#[derive(Default)]
+pub struct SceneFans(pub Vec<Fan>);
+
+fn fan_setup(mut commands: Commands, scene_fans: Res<SceneFans>) {
+    for fan in scene_fans.0.iter() {
+        commands.spawn().insert(*fan);
+    }
+}
+
+/// 6 removes the fans at the cursor, or adds one blowing up if there's none
+fn fan_keys_system(
+    mut commands: Commands,
+    windows: Res<Windows>,
+    viewport: Res<Viewport>,
+    qg: Query<&Grid>,
+    fans: Query<(Entity, &Fan)>,
+    mut char_input_events: EventReader<ReceivedCharacter>,
+) {
+    if !char_input_events.iter().any(|event| event.char == '6') {
+        return;
+    }
+    let cursor = windows.get_primary().and_then(|w| w.cursor_position());
+    let (cursor, grid) = match (cursor, qg.single()) {
+        (Some(cursor), Ok(grid)) => (cursor, grid),
+        _ => return,
+    };
+    if viewport.cursor_cell(cursor, grid).is_none() {
+        return;
+    }
+
+    let pos = viewport.cursor_to_grid(cursor);
+    let mut removed = false;
+    for (entity, fan) in fans.iter() {
+        if Vec2::from(fan.position).distance(pos) <= PICK_DISTANCE {
+            commands.entity(entity).despawn();
+            removed = true;
+        }
+    }
+    if !removed {
+        commands.spawn().insert(Fan::new(pos.round(), 90.0));
+    }
+}
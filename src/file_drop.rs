@@ -0,0 +1,157 @@
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+use bevy::window::FileDragAndDrop;
+
+use crate::emitters::Emitter;
+use crate::errors::ErrorLog;
+use crate::fans::Fan;
+use crate::import;
+use crate::inflow::Inflows;
+use crate::layers::Layers;
+use crate::phase::PhaseAverage;
+use crate::post::PostEffects;
+use crate::scene_file::SceneFile;
+use crate::settings::{Material, SolverSettings};
+use crate::stepping::StepControl;
+use crate::units::Units;
+use crate::weather::Weather;
+use crate::{AppState, Grid};
+
+// Files dropped on the window: a RON scene file replaces the grid, the post effects, the
+// layers, the emitters, the fans, the wind, the inflows and the material if it has one, an
+// image asks in the window title what to load it as, d for dye or Escape to cancel. An EXR
+// or .npy field replaces the density and the velocity it has.
+
+pub struct FileDropPlugin;
+
+impl Plugin for FileDropPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(DroppedImage::default()).add_system_set(
+            SystemSet::on_update(AppState::Running)
+                .with_system(file_drop_system.system())
+                .with_system(dropped_image_keys_system.system()),
+        );
+    }
+}
+
+/// Image waiting for the user to pick what to load it as
+#[derive(Default)]
+pub struct DroppedImage(Option<PathBuf>);
+
+#[allow(clippy::too_many_arguments)]
+fn file_drop_system(
+    mut commands: Commands,
+    mut dropped_image: ResMut<DroppedImage>,
+    mut post_effects: ResMut<PostEffects>,
+    mut layers: ResMut<Layers>,
+    (units, mut material, mut settings): (Res<Units>, ResMut<Material>, ResMut<SolverSettings>),
+    (mut weather, mut inflows): (ResMut<Weather>, ResMut<Inflows>),
+    (mut control, mut phase): (ResMut<StepControl>, ResMut<PhaseAverage>),
+    mut errors: ResMut<ErrorLog>,
+    mut windows: ResMut<Windows>,
+    mut qg: Query<&mut Grid>,
+    (emitters, fans): (Query<Entity, With<Emitter>>, Query<Entity, With<Fan>>),
+    mut drop_events: EventReader<FileDragAndDrop>,
+) {
+    for event in drop_events.iter() {
+        let path = match event {
+            FileDragAndDrop::DroppedFile { path_buf, .. } => path_buf,
+            _ => continue,
+        };
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase());
+
+        match extension.as_deref() {
+            Some("ron") => match (SceneFile::load(path), qg.single_mut()) {
+                (Ok(file), Ok(mut grid)) => {
+                    // The grid keeps its size, the entities showing it were made for it
+                    *grid = file.scene.build(grid.width(), grid.height());
+                    post_effects.0 = file.post_effects;
+                    *layers = Layers::from_styles(&file.layers);
+                    for entity in emitters.iter() {
+                        commands.entity(entity).despawn();
+                    }
+                    for emitter in file.emitters.iter() {
+                        commands.spawn().insert(*emitter);
+                    }
+                    for entity in fans.iter() {
+                        commands.entity(entity).despawn();
+                    }
+                    for fan in file.fans.iter() {
+                        commands.spawn().insert(*fan);
+                    }
+                    *weather = Weather::new(file.wind);
+                    // The forcing starts over with the scene
+                    control.time = 0.0;
+                    phase.restart(file.forcing_period());
+                    *inflows = Inflows::new(file.inflows);
+                    if let Some(file_material) = file.material {
+                        *material = file_material;
+                        material.apply(&mut settings);
+                        units.apply_viscosity(&mut settings);
+                    }
+                    info!("Loaded {}", path.display());
+                }
+                (Err(err), _) => {
+                    errors.report(format!("Couldn't load {}: {}", path.display(), err))
+                }
+                _ => {}
+            },
+            Some("exr") | Some("npy") => match (import::load_field(path), qg.single_mut()) {
+                (Ok(field), Ok(mut grid)) => {
+                    import::apply_field(&mut grid, &field);
+                    info!("Loaded {}", path.display());
+                }
+                (Err(err), _) => {
+                    errors.report(format!("Couldn't load {}: {}", path.display(), err))
+                }
+                _ => {}
+            },
+            Some("png") | Some("jpg") | Some("jpeg") => {
+                if let Some(window) = windows.get_primary_mut() {
+                    window.set_title(format!(
+                        "Fluid Simulation - load {} as: d dye, Escape cancel",
+                        path.display()
+                    ));
+                }
+                dropped_image.0 = Some(path.clone());
+            }
+            _ => errors.report(format!("Don't know how to load {}", path.display())),
+        }
+    }
+}
+
+fn dropped_image_keys_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut dropped_image: ResMut<DroppedImage>,
+    mut errors: ResMut<ErrorLog>,
+    mut windows: ResMut<Windows>,
+    mut qg: Query<&mut Grid>,
+    mut char_input_events: EventReader<ReceivedCharacter>,
+) {
+    let path = match &dropped_image.0 {
+        Some(path) => path.clone(),
+        None => return,
+    };
+
+    let load_dye = char_input_events.iter().any(|e| e.char == 'd');
+    if load_dye {
+        if let Ok(mut grid) = qg.single_mut() {
+            let (width, height) = (grid.width(), grid.height());
+            match import::load_image_dye(&path, width, height) {
+                Ok(dye) => import::apply_dye(&mut grid, &dye),
+                Err(err) => errors.report(format!("Couldn't load {}: {}", path.display(), err)),
+            }
+        }
+    }
+
+    if load_dye || keyboard_input.just_pressed(KeyCode::Escape) {
+        dropped_image.0 = None;
+        if let Some(window) = windows.get_primary_mut() {
+            window.set_title("Fluid Simulation".to_string());
+        }
+    }
+}
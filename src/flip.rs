@@ -0,0 +1,198 @@
+use bevy::prelude::*;
+
+use crate::boundary::BoundaryMode;
+use crate::Grid;
+
+// FLIP/PIC velocity transport: instead of advecting the velocity on the grid, marker
+// particles carry it. Every step the particles pick up the velocity the forces and the
+// projection left on the grid, move along it, and are transferred back to the grid for the
+// next step, each cell taking the weighted average of the particles around it. PIC takes
+// the grid velocity as it is, smoothing the flow like the semi-Lagrangian advection does;
+// FLIP only adds what changed on the grid since the transfer to the velocity of the
+// particles, keeping the small swirls but getting noisy. The blend of the settings mixes
+// both. The other fields are still advected on the grid, the velocity isn't. The cells the
+// particles leave get new ones, and the crowded ones lose some, so that every fluid cell
+// has particles to take its velocity from.
+
+/// Particles seeded in every fluid cell, on a 2 by 2 lattice
+const PER_CELL: usize = 4;
+/// Most particles a cell keeps
+const MAX_PER_CELL: usize = 2 * PER_CELL;
+
+/// The marker particles, kept from one step to the next
+#[derive(Default)]
+pub struct Particles {
+    size: (usize, usize),
+    positions: Vec<Vec2>,
+    velocities: Vec<Vec2>,
+    /// Velocity of the cells as the particles left it, FLIP adding the change since
+    transferred: Vec<Vec<Vec2>>,
+    /// Weights of the particles in every cell during a transfer
+    weights: Vec<Vec<f32>>,
+    /// Particles in every cell while reseeding
+    counts: Vec<Vec<usize>>,
+}
+
+impl Particles {
+    pub fn memory_bytes(&self) -> usize {
+        let (width, height) = self.size;
+        let per_cell =
+            std::mem::size_of::<Vec2>() + std::mem::size_of::<f32>() + std::mem::size_of::<usize>();
+        2 * self.positions.capacity() * std::mem::size_of::<Vec2>() + width * height * per_cell
+    }
+
+    /// Seed the particles on a new grid, the only time they allocate
+    pub fn prepare(&mut self, grid: &Grid) {
+        if self.size != (grid.width(), grid.height()) || self.positions.is_empty() {
+            self.seed(grid);
+        }
+    }
+
+    /// Lattice of particles over the fluid cells with their velocity, when the grid is new.
+    /// Room is made for as many particles as the cells can keep, so reseeding doesn't
+    /// allocate.
+    fn seed(&mut self, grid: &Grid) {
+        let (width, height) = (grid.width(), grid.height());
+        self.size = (width, height);
+        self.positions.clear();
+        self.velocities.clear();
+        self.positions.reserve(MAX_PER_CELL * width * height);
+        self.velocities.reserve(MAX_PER_CELL * width * height);
+        for (y, row) in grid.0.iter().enumerate() {
+            for (x, cell) in row.iter().enumerate().filter(|(_, cell)| !cell.obstacle) {
+                for i in 0..PER_CELL {
+                    self.positions.push(lattice(x, y, i));
+                    self.velocities.push(cell.velocity);
+                }
+            }
+        }
+        self.transferred = vec![vec![Vec2::ZERO; width]; height];
+        self.weights = vec![vec![0.0; width]; height];
+        self.counts = vec![vec![0; width]; height];
+        for (transferred, row) in self.transferred.iter_mut().zip(grid.0.iter()) {
+            for (transferred, cell) in transferred.iter_mut().zip(row.iter()) {
+                *transferred = cell.velocity;
+            }
+        }
+    }
+
+    /// Give the particles the velocity of the grid, FLIP and PIC blended by `blend`, and
+    /// move them along it for `dt` seconds with the midpoint method
+    pub fn advect(&mut self, grid: &Grid, blend: f32, dt: f32, boundary: BoundaryMode) {
+        self.prepare(grid);
+        let velocity = |pos: Vec2| grid.sample_bilinear(pos, |cell| cell.velocity, boundary);
+        let transferred = &self.transferred;
+        let transferred = |pos: Vec2| {
+            let cells = grid.bilinear_cells(pos, boundary);
+            cells
+                .iter()
+                .fold(Vec2::ZERO, |sum, &(x, y, reflection, weight)| {
+                    sum + transferred[y][x] * reflection * weight
+                })
+        };
+
+        for (pos, particle) in self.positions.iter_mut().zip(self.velocities.iter_mut()) {
+            let pic = velocity(*pos);
+            let change = pic - transferred(*pos);
+            *particle = blend * (*particle + change) + (1.0 - blend) * pic;
+
+            let midpoint = *pos + velocity(*pos) * dt / 2.0;
+            let next =
+                boundary.confine(*pos + velocity(midpoint) * dt, grid.width(), grid.height());
+            // Particles stop against the obstacles instead of entering them
+            let (x, y) = (next.x.round() as usize, next.y.round() as usize);
+            if !grid.0[y.min(grid.height() - 1)][x.min(grid.width() - 1)].obstacle {
+                *pos = next;
+            }
+        }
+        self.reseed(grid, boundary);
+    }
+
+    /// Remove particles from the cells keeping more than `MAX_PER_CELL`, and give the fluid
+    /// cells left with fewer than `PER_CELL` new ones on their lattice, with their velocity
+    fn reseed(&mut self, grid: &Grid, boundary: BoundaryMode) {
+        let (width, height) = self.size;
+        let cell = |pos: Vec2| {
+            let (x, y) = (pos.x.round() as isize, pos.y.round() as isize);
+            let (x, y, _) = boundary.ghost(x, y, width, height);
+            (x, y)
+        };
+        let Self {
+            positions,
+            velocities,
+            counts,
+            ..
+        } = self;
+        counts.iter_mut().flatten().for_each(|count| *count = 0);
+
+        let mut i = 0;
+        while i < positions.len() {
+            let (x, y) = cell(positions[i]);
+            if counts[y][x] < MAX_PER_CELL {
+                counts[y][x] += 1;
+                i += 1;
+            } else {
+                positions.swap_remove(i);
+                velocities.swap_remove(i);
+            }
+        }
+
+        for (y, row) in grid.0.iter().enumerate() {
+            for (x, fluid) in row.iter().enumerate().filter(|(_, cell)| !cell.obstacle) {
+                for i in counts[y][x]..PER_CELL {
+                    // Within the room made when seeding, which the cells can't exceed
+                    if positions.len() == positions.capacity() {
+                        return;
+                    }
+                    positions.push(lattice(x, y, i));
+                    velocities.push(fluid.velocity);
+                }
+            }
+        }
+    }
+
+    /// Set the velocity of the fluid cells around the particles to their weighted average,
+    /// remembering it for the next FLIP update
+    pub fn transfer_to_grid(&mut self, grid: &mut Grid, boundary: BoundaryMode) {
+        let (width, height) = (grid.width(), grid.height());
+        if self.size != (width, height) {
+            return;
+        }
+        let Self {
+            positions,
+            velocities,
+            transferred,
+            weights,
+            ..
+        } = self;
+        transferred
+            .iter_mut()
+            .flatten()
+            .for_each(|sum| *sum = Vec2::ZERO);
+        weights
+            .iter_mut()
+            .flatten()
+            .for_each(|weight| *weight = 0.0);
+
+        for (pos, velocity) in positions.iter().zip(velocities.iter()) {
+            for &(x, y, _, weight) in grid.bilinear_cells(*pos, boundary).iter() {
+                transferred[y][x] += *velocity * weight;
+                weights[y][x] += weight;
+            }
+        }
+        for (y, row) in grid.0.iter_mut().enumerate() {
+            for (x, cell) in row.iter_mut().enumerate() {
+                if weights[y][x] > 0.0 && !cell.obstacle {
+                    cell.velocity = transferred[y][x] / weights[y][x];
+                }
+                transferred[y][x] = cell.velocity;
+            }
+        }
+    }
+}
+
+/// Spot `i` of the 2 by 2 lattice of particles in the cell (x, y)
+fn lattice(x: usize, y: usize, i: usize) -> Vec2 {
+    let offset = Vec2::new((i % 2) as f32 - 0.5, (i / 2) as f32 - 0.5) / 2.0;
+    Vec2::new(x as f32, y as f32) + offset
+}
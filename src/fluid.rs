@@ -0,0 +1,317 @@
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+
+use crate::layers::{Layer, OnLayer};
+use crate::viewport::Viewport;
+use crate::{grid_to_world, world_to_grid, AppState, Grid, SolverSettings, CELL_SIZE};
+
+// Read access to the fluid for game systems reacting to it, in world coordinates, e.g.
+//
+//     fn balloon_system(fluid: Fluid, mut query: Query<&mut Transform, With<Balloon>>) {
+//         for mut transform in query.iter_mut() {
+//             let position = transform.translation.truncate();
+//             if let Some(velocity) = fluid.sample_velocity(position) {
+//                 transform.translation += velocity.extend(0.0);
+//             }
+//         }
+//     }
+
+//
+// Entities with a `FluidAffected` component feel the drag and lift of the flow around
+// them, sent as `FluidForce` events. The ones with a `FluidBody` are moved by them,
+// like the leaves dropped at the cursor with g.
+//
+// An `ExplosionEvent` pushes the fluid away from its center, e sets one off at the cursor.
+
+/// Distance between the samples of a ray march, in cells
+const MARCH_STEP: f32 = 0.25;
+/// Mass of the fluid per world unit squared, for the forces
+const FLUID_MASS_DENSITY: f32 = 0.01;
+/// Fraction of the velocity a body keeps after a second, so it settles when the flow stops
+const BODY_DAMPING: f32 = 0.5;
+
+pub struct FluidPlugin;
+
+impl Plugin for FluidPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_event::<FluidForce>()
+            .add_event::<ExplosionEvent>()
+            .add_system_set(
+                SystemSet::on_update(AppState::Running)
+                    .with_system(fluid_force_system.system().label("fluid_force"))
+                    .with_system(fluid_body_system.system().after("fluid_force"))
+                    .with_system(leaf_keys_system.system())
+                    .with_system(explosion_keys_system.system())
+                    .with_system(explosion_system.system()),
+            );
+    }
+}
+
+/// Entity pushed by the flow around it
+pub struct FluidAffected {
+    /// Cross section facing the flow, in world units
+    pub area: f32,
+    /// Drag coefficient, also scaling the lift of entities at an angle to the flow
+    pub drag_coeff: f32,
+}
+
+/// Entity moved by the forces of the fluid
+pub struct FluidBody {
+    pub mass: f32,
+    /// In world units per second
+    pub velocity: Vec2,
+}
+
+/// Force the fluid applies to a `FluidAffected` entity this frame, in world units
+pub struct FluidForce {
+    pub entity: Entity,
+    pub force: Vec2,
+}
+
+#[derive(SystemParam)]
+pub struct Fluid<'a> {
+    grid: Query<'a, &'static Grid>,
+    settings: Res<'a, SolverSettings>,
+}
+
+impl<'a> Fluid<'a> {
+    /// The grid and the fractional cell position at a world position, if it's inside the grid
+    fn locate(&self, world_pos: Vec2) -> Option<(&Grid, Vec2)> {
+        let grid = self.grid.single().ok()?;
+        let pos = world_to_grid(world_pos, grid.width(), grid.height());
+        let max = Vec2::new(grid.width() as f32, grid.height() as f32) - Vec2::splat(0.5);
+        if pos.cmpge(Vec2::splat(-0.5)).all() && pos.cmplt(max).all() {
+            Some((grid, pos))
+        } else {
+            None
+        }
+    }
+
+    /// Velocity of the fluid with the interpolation of the solver, in cells per second,
+    /// or None outside of the grid
+    pub fn sample_velocity(&self, world_pos: Vec2) -> Option<Vec2> {
+        let (grid, pos) = self.locate(world_pos)?;
+        Some(grid.sample_velocity(pos, self.settings.interpolation, self.settings.boundary))
+    }
+
+    /// Density of the fluid, or None outside of the grid
+    pub fn sample_density(&self, world_pos: Vec2) -> Option<f32> {
+        let (grid, pos) = self.locate(world_pos)?;
+        Some(grid.sample_density(pos, self.settings.boundary))
+    }
+
+    /// Density integrated along a segment, in density times cells, e.g. how much smoke
+    /// a line of sight goes through. What's outside of the grid counts as empty.
+    pub fn ray_march_density(&self, from: Vec2, to: Vec2) -> f32 {
+        let cells = from.distance(to) / CELL_SIZE;
+        let samples = (cells / MARCH_STEP).ceil().max(1.0) as usize;
+        let step = cells / samples as f32;
+
+        // Midpoint rule, every sample standing for the piece of segment around it
+        (0..samples)
+            .filter_map(|i| {
+                let t = (i as f32 + 0.5) / samples as f32;
+                self.sample_density(from.lerp(to, t))
+            })
+            .sum::<f32>()
+            * step
+    }
+}
+
+/// Radial burst of velocity, falling off linearly to nothing at `radius`
+pub struct ExplosionEvent {
+    /// In world coordinates
+    pub center: Vec2,
+    /// In world units
+    pub radius: f32,
+    /// Velocity given at the center, in cells per second
+    pub strength: f32,
+    /// Dye added at the center, falling off like the velocity
+    pub dye: Option<Vec3>,
+    /// Temperature added at the center, falling off like the velocity
+    pub heat: f32,
+}
+
+fn explosion_system(mut explosions: EventReader<ExplosionEvent>, mut qg: Query<&mut Grid>) {
+    let mut grid = match qg.single_mut() {
+        Ok(grid) => grid,
+        Err(_) => return,
+    };
+    let (width, height) = (grid.width(), grid.height());
+
+    for explosion in explosions.iter() {
+        let center = world_to_grid(explosion.center, width, height);
+        let radius = explosion.radius / CELL_SIZE;
+        if radius <= 0.0 {
+            continue;
+        }
+
+        // Only the cells in the bounding box of the explosion
+        let low = (center - Vec2::splat(radius)).max(Vec2::ZERO);
+        let high =
+            (center + Vec2::splat(radius)).min(Vec2::new(width as f32, height as f32) - Vec2::ONE);
+        if low.cmpgt(high).any() {
+            continue;
+        }
+
+        for y in low.y.ceil() as usize..=high.y.floor() as usize {
+            for x in low.x.ceil() as usize..=high.x.floor() as usize {
+                let offset = Vec2::new(x as f32, y as f32) - center;
+                let distance = offset.length();
+                if distance >= radius {
+                    continue;
+                }
+
+                let falloff = 1.0 - distance / radius;
+                let cell = &mut grid.0[y][x];
+                if distance > 0.0 {
+                    cell.velocity += offset / distance * explosion.strength * falloff;
+                }
+                if let Some(dye) = explosion.dye {
+                    cell.dye += dye * falloff;
+                }
+                cell.temperature += explosion.heat * falloff;
+            }
+        }
+    }
+}
+
+/// e sets off an orange explosion at the cursor
+fn explosion_keys_system(
+    windows: Res<Windows>,
+    viewport: Res<Viewport>,
+    qg: Query<&Grid>,
+    mut char_input_events: EventReader<ReceivedCharacter>,
+    mut explosions: EventWriter<ExplosionEvent>,
+) {
+    if !char_input_events.iter().any(|event| event.char == 'e') {
+        return;
+    }
+
+    let cursor = windows
+        .get_primary()
+        .and_then(|window| window.cursor_position());
+    if let (Some(cursor), Ok(grid)) = (cursor, qg.single()) {
+        let (width, height) = (grid.width(), grid.height());
+        explosions.send(ExplosionEvent {
+            center: grid_to_world(viewport.cursor_to_grid(cursor), width, height),
+            radius: 5.0 * CELL_SIZE,
+            strength: 20.0,
+            dye: Some(Vec3::new(1.0, 0.4, 0.1)),
+            heat: 4.0,
+        });
+    }
+}
+
+/// Drag along the flow relative to the entity, and lift across it for entities at an angle,
+/// modelled as flat plates along their local x axis
+fn fluid_force_system(
+    fluid: Fluid,
+    query: Query<(Entity, &FluidAffected, &Transform, Option<&FluidBody>)>,
+    mut forces: EventWriter<FluidForce>,
+) {
+    for (entity, affected, transform, body) in query.iter() {
+        let position = transform.translation.truncate();
+        let flow = match fluid.sample_velocity(position) {
+            Some(velocity) => velocity * CELL_SIZE,
+            None => continue,
+        };
+        let relative = flow - body.map_or(Vec2::ZERO, |body| body.velocity);
+        let speed = relative.length();
+        if speed == 0.0 {
+            continue;
+        }
+
+        let direction = relative / speed;
+        let pressure = 0.5 * FLUID_MASS_DENSITY * speed * speed * affected.area;
+        let drag = direction * pressure * affected.drag_coeff;
+
+        // The plate turns the flow along itself and gets pushed the other way
+        let axis = (transform.rotation * Vec3::X).truncate();
+        let (cos, sin) = (direction.dot(axis), direction.perp_dot(axis));
+        let lift = -direction.perp() * 2.0 * sin * cos * pressure * affected.drag_coeff;
+
+        forces.send(FluidForce {
+            entity,
+            force: drag + lift,
+        });
+    }
+}
+
+fn fluid_body_system(
+    time: Res<Time>,
+    mut forces: EventReader<FluidForce>,
+    mut query: Query<(&mut FluidBody, &mut Transform)>,
+) {
+    let dt = time.delta_seconds();
+    for event in forces.iter() {
+        if let Ok((mut body, _)) = query.get_mut(event.entity) {
+            let acceleration = event.force / body.mass;
+            body.velocity += acceleration * dt;
+        }
+    }
+
+    for (mut body, mut transform) in query.iter_mut() {
+        body.velocity *= BODY_DAMPING.powf(dt);
+        transform.translation += (body.velocity * dt).extend(0.0);
+    }
+}
+
+struct Leaf;
+
+/// g drops a leaf at the cursor, leaves blown out of the grid disappear
+fn leaf_keys_system(
+    mut commands: Commands,
+    windows: Res<Windows>,
+    viewport: Res<Viewport>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    qg: Query<&Grid>,
+    leaves: Query<(Entity, &Transform), With<Leaf>>,
+    mut char_input_events: EventReader<ReceivedCharacter>,
+) {
+    let grid = match qg.single() {
+        Ok(grid) => grid,
+        Err(_) => return,
+    };
+    let (width, height) = (grid.width(), grid.height());
+
+    for (entity, transform) in leaves.iter() {
+        let pos = world_to_grid(transform.translation.truncate(), width, height);
+        let max = Vec2::new(width as f32, height as f32) - Vec2::splat(0.5);
+        if pos.cmplt(Vec2::splat(-0.5)).any() || pos.cmpge(max).any() {
+            commands.entity(entity).despawn();
+        }
+    }
+
+    let drop_leaf = char_input_events.iter().any(|event| event.char == 'g');
+    let cursor = windows
+        .get_primary()
+        .and_then(|window| window.cursor_position());
+    if let (true, Some(cursor)) = (drop_leaf, cursor) {
+        let position = grid_to_world(viewport.cursor_to_grid(cursor), width, height);
+        commands
+            .spawn_bundle(SpriteBundle {
+                material: materials.add(Color::rgb(0.3, 0.8, 0.2).into()),
+                sprite: Sprite::new(Vec2::new(CELL_SIZE, CELL_SIZE / 4.0)),
+                transform: Transform {
+                    translation: position.extend(0.0),
+                    rotation: Quat::from_rotation_z(0.3),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert(Leaf)
+            .insert(OnLayer {
+                layer: Layer::Particles,
+                offset: 0.0,
+            })
+            .insert(FluidAffected {
+                area: CELL_SIZE,
+                drag_coeff: 1.0,
+            })
+            .insert(FluidBody {
+                mass: 1.0,
+                velocity: Vec2::ZERO,
+            });
+    }
+}
@@ -0,0 +1,244 @@
+use bevy::prelude::*;
+
+use crate::boundary::BoundaryMode;
+use crate::errors::ErrorLog;
+use crate::layers::{Layer, OnLayer};
+use crate::scenes::SceneSelection;
+use crate::stepping::StepControl;
+use crate::viewport::{self, ViewSlot};
+use crate::{grid_to_world, AppState, Grid, Position, SolverSettings, CELL_SIZE};
+
+// Finite-time Lyapunov exponents: one virtual tracer starts at every cell center,
+// they're advected over a time window, then the stretching of the resulting flow map
+// is measured. Ridges of the field are the transport barriers of the flow.
+
+/// Length of the integration window in seconds
+const FTLE_WINDOW: f32 = 2.0;
+
+pub struct FtlePlugin;
+
+impl Plugin for FtlePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(Ftle::new(0, 0))
+            .add_system_set(SystemSet::on_enter(AppState::Running).with_system(ftle_setup.system()))
+            .add_system(ftle_keys_system.system())
+            .add_system(ftle_advection_system.system())
+            .add_system(ftle_square_system.system());
+    }
+}
+
+struct FtleSquare;
+
+pub struct Ftle {
+    pub active: bool,
+    elapsed: f32,
+    width: usize,
+    height: usize,
+    /// Unwrapped position of the tracer started at each cell, indexed by y * width + x
+    flow_map: Vec<Vec2>,
+    /// Last computed field, kept on screen while the next window is integrated
+    field: Vec<f32>,
+}
+
+impl Ftle {
+    pub fn new(width: usize, height: usize) -> Self {
+        let mut ftle = Self {
+            active: false,
+            elapsed: 0.0,
+            width,
+            height,
+            flow_map: Vec::new(),
+            field: vec![0.0; width * height],
+        };
+        ftle.restart();
+        ftle
+    }
+
+    pub fn memory_bytes(&self) -> usize {
+        self.flow_map.capacity() * std::mem::size_of::<Vec2>()
+            + self.field.capacity() * std::mem::size_of::<f32>()
+    }
+
+    /// Put the tracers back on the cell centers
+    pub fn restart(&mut self) {
+        let width = self.width;
+        self.elapsed = 0.0;
+        self.flow_map = (0..self.height)
+            .flat_map(|y| (0..width).map(move |x| Vec2::new(x as f32, y as f32)))
+            .collect();
+    }
+
+    /// Largest stretching rate of the flow map at each cell
+    fn compute_field(&mut self, boundary: BoundaryMode) {
+        let (width, height) = (self.width, self.height);
+        let domain_x = Vec2::new(width as f32, 0.0);
+        let domain_y = Vec2::new(0.0, height as f32);
+        let periodic = boundary == BoundaryMode::Periodic;
+
+        // The neighbors before and after a cell along an axis of `size` cells, wrapping
+        // around the periodic edges and stopping at the walls, where the difference is
+        // one-sided
+        let around = |i: usize, size: usize| {
+            if periodic {
+                ((i + size - 1) % size, (i + 1) % size)
+            } else {
+                (i.saturating_sub(1), (i + 1).min(size - 1))
+            }
+        };
+
+        for y in 0..height {
+            for x in 0..width {
+                let (x_minus, x_plus) = around(x, width);
+                let (y_minus, y_plus) = around(y, height);
+
+                // Neighbors across the periodic edges started one domain away
+                let mut east = self.flow_map[y * width + x_plus];
+                let mut west = self.flow_map[y * width + x_minus];
+                let mut north = self.flow_map[y_plus * width + x];
+                let mut south = self.flow_map[y_minus * width + x];
+                if x_plus < x {
+                    east += domain_x;
+                }
+                if x_minus > x {
+                    west -= domain_x;
+                }
+                if y_plus < y {
+                    north += domain_y;
+                }
+                if y_minus > y {
+                    south -= domain_y;
+                }
+
+                // Columns of the flow map jacobian, over the cells between the neighbors
+                let span = |minus: usize, plus: usize| if periodic { 2 } else { plus - minus };
+                let dx = (east - west) / span(x_minus, x_plus) as f32;
+                let dy = (north - south) / span(y_minus, y_plus) as f32;
+
+                // Largest eigenvalue of the Cauchy-Green tensor J^T J
+                let c11 = dx.dot(dx);
+                let c12 = dx.dot(dy);
+                let c22 = dy.dot(dy);
+                let trace = c11 + c22;
+                let det = c11 * c22 - c12 * c12;
+                let lambda_max = (trace + (trace * trace - 4.0 * det).max(0.0).sqrt()) / 2.0;
+
+                self.field[y * width + x] = if lambda_max > 0.0 {
+                    lambda_max.ln() / (2.0 * self.elapsed)
+                } else {
+                    0.0
+                };
+            }
+        }
+    }
+}
+
+fn ftle_setup(
+    mut commands: Commands,
+    selection: Res<SceneSelection>,
+    mut ftle: ResMut<Ftle>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let (width, height) = selection.grid_size();
+    *ftle = Ftle::new(width, height);
+
+    let (columns, rows) = viewport::view_size(width, height);
+    for y in 0..rows {
+        for x in 0..columns {
+            let position = Vec2::new(x as f32, y as f32);
+            let translation = grid_to_world(position, width, height).extend(0.5);
+
+            commands
+                .spawn_bundle(SpriteBundle {
+                    material: materials.add(Color::BLACK.into()),
+                    transform: Transform::from_translation(translation),
+                    sprite: Sprite::new(Vec2::new(CELL_SIZE, CELL_SIZE)),
+                    visible: Visible {
+                        is_visible: false,
+                        is_transparent: false,
+                    },
+                    ..Default::default()
+                })
+                .insert(FtleSquare)
+                .insert(OnLayer {
+                    layer: Layer::Density,
+                    offset: 0.5,
+                })
+                .insert(Position { x, y })
+                .insert(ViewSlot { x, y });
+        }
+    }
+}
+
+/// f toggles the FTLE analysis mode
+fn ftle_keys_system(mut ftle: ResMut<Ftle>, mut char_input_events: EventReader<ReceivedCharacter>) {
+    for event in char_input_events.iter() {
+        if event.char == 'f' {
+            ftle.active = !ftle.active;
+            ftle.restart();
+            ftle.field.iter_mut().for_each(|v| *v = 0.0);
+        }
+    }
+}
+
+fn ftle_advection_system(
+    time: Res<Time>,
+    control: Res<StepControl>,
+    settings: Res<SolverSettings>,
+    mut ftle: ResMut<Ftle>,
+    qg: Query<&Grid>,
+) {
+    if !ftle.active || control.paused {
+        return;
+    }
+
+    if let Ok(grid) = qg.single() {
+        let dt = time.delta_seconds();
+        let (width, height) = (ftle.width, ftle.height);
+        let boundary = settings.boundary;
+        for pos in &mut ftle.flow_map {
+            *pos += grid.sample_velocity(*pos, settings.interpolation, boundary) * dt;
+            // The tracers stay unwrapped across periodic edges, but not through the walls
+            if boundary != BoundaryMode::Periodic {
+                *pos = boundary.confine(*pos, width, height);
+            }
+        }
+        ftle.elapsed += dt;
+
+        if ftle.elapsed >= FTLE_WINDOW {
+            ftle.compute_field(boundary);
+            ftle.restart();
+        }
+    }
+}
+
+/// Display the FTLE field over the density, normalized by its maximum
+fn ftle_square_system(
+    ftle: Res<Ftle>,
+    mut errors: ResMut<ErrorLog>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut query: Query<(&FtleSquare, &Position, &Handle<ColorMaterial>, &mut Visible)>,
+) {
+    let max = ftle.field.iter().cloned().fold(0.0, f32::max);
+
+    for (_ftle_square, position, color, mut visible) in query.iter_mut() {
+        visible.is_visible = ftle.active;
+        if !ftle.active {
+            continue;
+        }
+
+        let color_mat = match materials.get_mut(&*color) {
+            Some(material) => material,
+            None => {
+                errors.report("Missing material of an FTLE square");
+                continue;
+            }
+        };
+        let Position { x, y } = position;
+        let v = if max > 0.0 {
+            ftle.field[y * ftle.width + x].max(0.0) / max
+        } else {
+            0.0
+        };
+        color_mat.color = Color::rgb(v, v * v * 0.6, 0.1 * (1.0 - v));
+    }
+}
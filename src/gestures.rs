@@ -0,0 +1,225 @@
+use bevy::input::mouse::MouseMotion;
+use bevy::prelude::*;
+
+use crate::solver::{Splat, Splats};
+use crate::symmetry::Symmetry;
+use crate::viewport::Viewport;
+use crate::{trail_cells, AppState, Grid};
+
+// Gesture macros: z starts recording the cursor, its push and the dye brush relative to
+// where the cursor was, z again stops. y replays the gesture once at the cursor and d
+// loops it there, d again stopping every loop. Only the last gesture is kept.
+
+/// Longest gesture, the recording stops by itself after it
+const MAX_SECONDS: f32 = 10.0;
+
+pub struct GesturePlugin;
+
+impl Plugin for GesturePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(Gestures::default()).add_system_set(
+            SystemSet::on_update(AppState::Running)
+                .with_system(gesture_keys_system.system())
+                .with_system(gesture_record_system.system())
+                .with_system(gesture_replay_system.system()),
+        );
+    }
+}
+
+/// What the cursor did during one frame of the recording
+#[derive(Clone, Debug)]
+struct GestureFrame {
+    /// Seconds since the start of the recording
+    time: f32,
+    /// Cursor position relative to the start, in cells
+    offset: Vec2,
+    /// Velocity given by the mouse motion during the frame, spread along the path
+    force: Vec2,
+    /// Dye injected in every cell of the path, zero when the brush wasn't held
+    density: f32,
+}
+
+struct Recording {
+    start: Vec2,
+    time: f32,
+    frames: Vec<GestureFrame>,
+}
+
+struct Replay {
+    anchor: Vec2,
+    time: f32,
+    /// Index of the next frame to play
+    next: usize,
+    looping: bool,
+}
+
+#[derive(Default)]
+pub struct Gestures {
+    recording: Option<Recording>,
+    gesture: Vec<GestureFrame>,
+    replays: Vec<Replay>,
+}
+
+impl Gestures {
+    pub fn recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Length of the recorded gesture in seconds
+    pub fn duration(&self) -> f32 {
+        self.gesture.last().map_or(0.0, |frame| frame.time)
+    }
+
+    pub fn looping(&self) -> bool {
+        self.replays.iter().any(|replay| replay.looping)
+    }
+
+    fn stop_recording(&mut self) {
+        if let Some(recording) = self.recording.take() {
+            self.gesture = recording.frames;
+            info!("Recorded a gesture of {:.1} seconds", self.duration());
+        }
+    }
+}
+
+fn cursor_in_grid(windows: &Windows, viewport: &Viewport, grid: &Grid) -> Option<Vec2> {
+    let cursor = windows.get_primary()?.cursor_position()?;
+    viewport.cursor_cell(cursor, grid)?;
+    Some(viewport.cursor_to_grid(cursor))
+}
+
+fn gesture_keys_system(
+    windows: Res<Windows>,
+    viewport: Res<Viewport>,
+    qg: Query<&Grid>,
+    mut gestures: ResMut<Gestures>,
+    mut char_input_events: EventReader<ReceivedCharacter>,
+) {
+    let grid = match qg.single() {
+        Ok(grid) => grid,
+        Err(_) => return,
+    };
+
+    for event in char_input_events.iter() {
+        let cursor = cursor_in_grid(&windows, &viewport, grid);
+        match event.char {
+            'z' if gestures.recording() => gestures.stop_recording(),
+            'z' => match cursor {
+                Some(start) => {
+                    gestures.recording = Some(Recording {
+                        start,
+                        time: 0.0,
+                        frames: Vec::new(),
+                    });
+                    info!("Recording a gesture, z to stop");
+                }
+                None => info!("Put the cursor on the grid to record a gesture"),
+            },
+            'y' | 'd' if gestures.gesture.is_empty() => {
+                info!("No gesture recorded, z to record one")
+            }
+            'd' if gestures.looping() => gestures.replays.retain(|replay| !replay.looping),
+            'y' | 'd' => match cursor {
+                Some(anchor) => gestures.replays.push(Replay {
+                    anchor,
+                    time: 0.0,
+                    next: 0,
+                    looping: event.char == 'd',
+                }),
+                None => info!("Put the cursor on the grid to replay the gesture"),
+            },
+            _ => {}
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn gesture_record_system(
+    time: Res<Time>,
+    windows: Res<Windows>,
+    viewport: Res<Viewport>,
+    mouse_button_input: Res<Input<MouseButton>>,
+    qg: Query<&Grid>,
+    mut gestures: ResMut<Gestures>,
+    mut mouse_motion_events: EventReader<MouseMotion>,
+) {
+    // Read the events even when not recording, so old ones don't end up in the next gesture
+    let motion: Vec2 = mouse_motion_events.iter().map(|event| event.delta).sum();
+    let dt = time.delta_seconds();
+    let grid = match qg.single() {
+        Ok(grid) => grid,
+        Err(_) => return,
+    };
+    let cursor = cursor_in_grid(&windows, &viewport, grid);
+    let recording = match &mut gestures.recording {
+        Some(recording) => recording,
+        None => return,
+    };
+
+    recording.time += dt;
+    if let Some(cursor) = cursor {
+        // Same push and brush as the mouse
+        recording.frames.push(GestureFrame {
+            time: recording.time,
+            offset: cursor - recording.start,
+            force: 0.1 * motion,
+            density: if mouse_button_input.pressed(MouseButton::Left) {
+                20.0 * dt
+            } else {
+                0.0
+            },
+        });
+    }
+    if recording.time > MAX_SECONDS {
+        gestures.stop_recording();
+    }
+}
+
+fn gesture_replay_system(
+    time: Res<Time>,
+    qg: Query<&Grid>,
+    symmetry: Res<Symmetry>,
+    mut gestures: ResMut<Gestures>,
+    mut splats: ResMut<Splats>,
+) {
+    let grid = match qg.single() {
+        Ok(grid) => grid,
+        Err(_) => return,
+    };
+    let (width, height) = (grid.width(), grid.height());
+    let gestures = &mut *gestures;
+    let gesture = &gestures.gesture;
+    let duration = gesture.last().map_or(0.0, |frame| frame.time);
+
+    for replay in gestures.replays.iter_mut() {
+        replay.time += time.delta_seconds();
+        while replay.next < gesture.len() && gesture[replay.next].time <= replay.time {
+            let frame = &gesture[replay.next];
+            let previous = &gesture[replay.next.saturating_sub(1)];
+            let from = replay.anchor + previous.offset;
+            let to = replay.anchor + frame.offset;
+            replay.next += 1;
+
+            let cells = trail_cells(from, to, width, height);
+            for &(x, y) in &cells {
+                let splat = Splat {
+                    x,
+                    y,
+                    velocity: frame.force / cells.len() as f32,
+                    density: frame.density,
+                    temperature: frame.density,
+                    species: None,
+                };
+                splats.0.extend(symmetry.expand(splat, width, height));
+            }
+        }
+
+        if replay.looping && replay.next == gesture.len() && replay.time >= duration {
+            replay.time -= duration;
+            replay.next = 0;
+        }
+    }
+    gestures
+        .replays
+        .retain(|replay| replay.looping || replay.next < gesture.len());
+}
@@ -0,0 +1,280 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+use crate::boundary::BoundaryMode;
+use crate::snapshot;
+use crate::species::SPECIES;
+use crate::stepping::StepControl;
+use crate::{AppState, Cell, Grid, SolverSettings};
+
+// Slow-motion replay of the last seconds: snapshots of the grid are kept a few times per
+// second, compressed like the F5 one. F6 pauses the simulation and plays them back in a
+// loop at a fraction of the recorded speed, F7 cycling through the fractions. Between two
+// stored snapshots the carried fields are moved along the stored velocity, forward from
+// the earlier one and back from the later one, and blended, so the replay moves smoothly
+// instead of stuttering at the rate they were stored. F6 again goes back to the live grid.
+
+/// Length of the history
+const HISTORY_SECONDS: f32 = 10.0;
+/// Snapshots kept per second of the display
+const HISTORY_FPS: u32 = 10;
+/// Replay speeds F7 cycles through, as fractions of the recorded speed
+const SPEEDS: [f32; 4] = [0.5, 0.25, 0.125, 0.0625];
+
+pub struct HistoryPlugin;
+
+impl Plugin for HistoryPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(History::default()).add_system_set(
+            SystemSet::on_update(AppState::Running)
+                .with_system(history_capture_system.system())
+                .with_system(history_keys_system.system())
+                .with_system(history_playback_system.system()),
+        );
+    }
+}
+
+/// Recent snapshots of the grid, and the replay going through them
+#[derive(Default)]
+pub struct History {
+    /// Simulated time of every snapshot with its compressed grid, oldest first
+    frames: VecDeque<(f32, Vec<u8>)>,
+    size: (usize, usize),
+    since_frame: f32,
+    /// Index of the replay speed in `SPEEDS`
+    speed: usize,
+    replay: Option<Replay>,
+}
+
+impl History {
+    pub fn memory_bytes(&self) -> usize {
+        self.frames.iter().map(|(_, data)| data.len()).sum()
+    }
+}
+
+struct Replay {
+    /// The grid the simulation left, put back when the replay ends
+    live: Grid,
+    was_paused: bool,
+    /// Simulated time shown
+    time: f32,
+    /// Index of the earlier of the two decoded snapshots around the time shown
+    decoded: Option<(usize, Grid, Grid)>,
+}
+
+/// Keep a snapshot every 1 / HISTORY_FPS seconds while the simulation runs, dropping the
+/// ones older than HISTORY_SECONDS
+fn history_capture_system(
+    time: Res<Time>,
+    control: Res<StepControl>,
+    mut history: ResMut<History>,
+    qg: Query<&Grid>,
+) {
+    if history.replay.is_some() {
+        return;
+    }
+    history.since_frame += time.delta_seconds();
+    if history.since_frame < 1.0 / HISTORY_FPS as f32 {
+        return;
+    }
+    let grid = match qg.single() {
+        Ok(grid) => grid,
+        Err(_) => return,
+    };
+    // Nothing moved since the last one
+    let last_time = history.frames.back().map(|&(time, _)| time);
+    if last_time == Some(control.time) {
+        return;
+    }
+    history.since_frame = 0.0;
+
+    // A new grid or a restarted scene starts another history
+    let size = (grid.width(), grid.height());
+    if history.size != size || last_time.map_or(false, |last| control.time < last) {
+        history.size = size;
+        history.frames.clear();
+    }
+    history
+        .frames
+        .push_back((control.time, snapshot::encode(grid)));
+    while let Some(&(oldest, _)) = history.frames.front() {
+        if control.time - oldest <= HISTORY_SECONDS {
+            break;
+        }
+        history.frames.pop_front();
+    }
+}
+
+/// F6 starts and ends the replay, F7 changes its speed
+fn history_keys_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut control: ResMut<StepControl>,
+    mut history: ResMut<History>,
+    mut qg: Query<&mut Grid>,
+) {
+    if keyboard_input.just_pressed(KeyCode::F7) {
+        history.speed = (history.speed + 1) % SPEEDS.len();
+        info!("Replaying at {}x", SPEEDS[history.speed]);
+    }
+    if !keyboard_input.just_pressed(KeyCode::F6) {
+        return;
+    }
+    let mut grid = match qg.single_mut() {
+        Ok(grid) => grid,
+        Err(_) => return,
+    };
+
+    match history.replay.take() {
+        Some(replay) => {
+            *grid = replay.live;
+            control.paused = replay.was_paused;
+        }
+        None if history.frames.len() < 2 || history.size != (grid.width(), grid.height()) => {
+            info!("Nothing to replay yet, the last seconds of the simulation are kept")
+        }
+        None => {
+            history.replay = Some(Replay {
+                live: grid.clone(),
+                was_paused: control.paused,
+                time: history.frames[0].0,
+                decoded: None,
+            });
+        }
+    }
+}
+
+/// Show the grid at the replay time, which goes through the history at the replay speed
+fn history_playback_system(
+    time: Res<Time>,
+    settings: Res<SolverSettings>,
+    mut control: ResMut<StepControl>,
+    mut history: ResMut<History>,
+    mut qg: Query<&mut Grid>,
+) {
+    let History {
+        frames,
+        speed,
+        replay,
+        ..
+    } = &mut *history;
+    let replay = match replay {
+        Some(replay) => replay,
+        None => return,
+    };
+    let mut grid = match qg.single_mut() {
+        Ok(grid) => grid,
+        Err(_) => return,
+    };
+    // The simulation stays where the replay started
+    control.paused = true;
+
+    let (first, last) = (frames[0].0, frames[frames.len() - 1].0);
+    replay.time += time.delta_seconds() * SPEEDS[*speed];
+    if replay.time >= last {
+        replay.time = first;
+    }
+    let earlier = frames
+        .iter()
+        .rposition(|&(time, _)| time <= replay.time)
+        .unwrap_or(0)
+        .min(frames.len() - 2);
+
+    if replay.decoded.as_ref().map(|(index, _, _)| *index) != Some(earlier) {
+        let decode = |i: usize| snapshot::decode(&frames[i].1);
+        match (decode(earlier), decode(earlier + 1)) {
+            (Ok(a), Ok(b)) => replay.decoded = Some((earlier, a, b)),
+            (Err(err), _) | (_, Err(err)) => {
+                error!("Couldn't decode the history: {}", err);
+                return;
+            }
+        }
+    }
+    if let Some((_, a, b)) = &replay.decoded {
+        let (from, to) = (frames[earlier].0, frames[earlier + 1].0);
+        let t = ((replay.time - from) / (to - from)).max(0.0).min(1.0);
+        interpolate(a, b, t, to - from, settings.boundary, &mut grid);
+    }
+}
+
+/// The grid at the fraction `t` of the way between the snapshots `a` and `b`, taken `span`
+/// seconds apart. The carried fields of a cell blend where its velocity in `a` brings it
+/// from and where its velocity in `b` takes it, the velocity blends in place, and the
+/// obstacles are the ones of the nearest snapshot.
+fn interpolate(a: &Grid, b: &Grid, t: f32, span: f32, boundary: BoundaryMode, target: &mut Grid) {
+    for (y, row) in target.0.iter_mut().enumerate() {
+        for (x, cell) in row.iter_mut().enumerate() {
+            let (before, after) = (&a.0[y][x], &b.0[y][x]);
+            let nearest = if t < 0.5 { before } else { after };
+            if nearest.obstacle {
+                *cell = nearest.clone();
+                continue;
+            }
+
+            let pos = Vec2::new(x as f32, y as f32);
+            let from = pos - before.velocity * t * span;
+            let to = pos + after.velocity * (1.0 - t) * span;
+            let blend = |field: &dyn Fn(&Cell) -> f32| {
+                let (from, to) = (
+                    a.sample_bilinear(from, field, boundary),
+                    b.sample_bilinear(to, field, boundary),
+                );
+                from + (to - from) * t
+            };
+
+            let mut species = [0.0; SPECIES];
+            for (i, amount) in species.iter_mut().enumerate() {
+                *amount = blend(&|c| c.species[i]);
+            }
+            *cell = Cell {
+                velocity: before.velocity.lerp(after.velocity, t),
+                density: blend(&|c| c.density),
+                dye: Vec3::new(
+                    blend(&|c| c.dye.x),
+                    blend(&|c| c.dye.y),
+                    blend(&|c| c.dye.z),
+                ),
+                temperature: blend(&|c| c.temperature),
+                species,
+                fuel: blend(&|c| c.fuel),
+                level: blend(&|c| c.level),
+                ..nearest.clone()
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpolation_carries_the_density_along_the_velocity() {
+        // A blob at x = 2 moving right at 2 cells per second, one second between snapshots
+        let mut a = Grid::new(8, 1);
+        let mut b = Grid::new(8, 1);
+        for cell in a.0[0].iter_mut().chain(b.0[0].iter_mut()) {
+            cell.velocity = Vec2::new(2.0, 0.0);
+        }
+        a.0[0][2].density = 1.0;
+        b.0[0][4].density = 1.0;
+
+        let mut target = Grid::new(8, 1);
+        interpolate(&a, &b, 0.5, 1.0, BoundaryMode::Periodic, &mut target);
+        assert!((target.0[0][3].density - 1.0).abs() < 1e-5);
+        assert!(target.0[0][2].density.abs() < 1e-5);
+        assert!(target.0[0][4].density.abs() < 1e-5);
+    }
+
+    #[test]
+    fn interpolation_ends_on_the_snapshots() {
+        let mut a = Grid::new(4, 4);
+        let b = Grid::new(4, 4);
+        a.0[1][2].density = 0.7;
+        let mut target = Grid::new(4, 4);
+        interpolate(&a, &b, 0.0, 0.1, BoundaryMode::NoSlip, &mut target);
+        assert_eq!(target.0[1][2].density, 0.7);
+        interpolate(&a, &b, 1.0, 0.1, BoundaryMode::NoSlip, &mut target);
+        assert_eq!(target.0[1][2].density, 0.0);
+    }
+}
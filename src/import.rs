@@ -0,0 +1,350 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+use image::imageops::FilterType;
+
+use crate::errors::ErrorLog;
+use crate::Grid;
+
+/// Image given on the command line, loaded as the initial dye of the scene
+pub struct ImageDye(pub Option<PathBuf>);
+
+/// EXR or .npy file given on the command line, loaded as the initial density and velocity
+pub struct InitialField(pub Option<PathBuf>);
+
+/// Load an image resized to the grid, as dye colors indexed by row then column.
+/// Rows go up like the grid, so the image isn't upside down.
+pub fn load_image_dye(path: &Path, width: usize, height: usize) -> Result<Vec<Vec<Vec3>>, String> {
+    let image = image::open(path).map_err(|err| err.to_string())?;
+    let image = image
+        .resize_exact(width as u32, height as u32, FilterType::Triangle)
+        .to_rgb8();
+
+    let dye = (0..height)
+        .rev()
+        .map(|row| {
+            (0..width)
+                .map(|column| {
+                    let [r, g, b] = image.get_pixel(column as u32, row as u32).0;
+                    Vec3::new(r as f32, g as f32, b as f32) / 255.0
+                })
+                .collect()
+        })
+        .collect();
+
+    Ok(dye)
+}
+
+/// Replace the dye of the grid
+pub fn apply_dye(grid: &mut Grid, dye: &[Vec<Vec3>]) {
+    for (row, dye_row) in grid.0.iter_mut().zip(dye) {
+        for (cell, color) in row.iter_mut().zip(dye_row) {
+            cell.dye = *color;
+        }
+    }
+}
+
+/// Parse a table of numbers, one row per line from top to bottom, separated by commas,
+/// semicolons or whitespace like spreadsheets copy them. Short rows are padded with zeros.
+pub fn parse_field(text: &str) -> Result<Vec<Vec<f32>>, String> {
+    let mut rows = Vec::new();
+    for line in text.lines() {
+        let row = line
+            .split(|c: char| c == ',' || c == ';' || c.is_whitespace())
+            .filter(|value| !value.is_empty())
+            .map(|value| {
+                value
+                    .parse::<f32>()
+                    .map_err(|_| format!("invalid number {:?}", value))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if !row.is_empty() {
+            rows.push(row);
+        }
+    }
+
+    let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+    if columns == 0 {
+        return Err("no numbers found".to_string());
+    }
+    for row in &mut rows {
+        row.resize(columns, 0.0);
+    }
+    Ok(rows)
+}
+
+/// Bilinearly resample a table parsed by `parse_field` to the grid, flipping its rows
+/// so the first line ends up at the top
+pub fn resample_field(values: &[Vec<f32>], width: usize, height: usize) -> Vec<Vec<f32>> {
+    let rows = values.len();
+    let columns = values[0].len();
+    let at = |x: usize, y: usize| values[rows - 1 - y.min(rows - 1)][x.min(columns - 1)];
+    // Map the first and last cells of the grid onto the first and last values
+    let ratio = |from: usize, to: usize| {
+        if to > 1 {
+            (from - 1) as f32 / (to - 1) as f32
+        } else {
+            0.0
+        }
+    };
+    let (ratio_x, ratio_y) = (ratio(columns, width), ratio(rows, height));
+
+    (0..height)
+        .map(|y| {
+            (0..width)
+                .map(|x| {
+                    let (fx, fy) = (x as f32 * ratio_x, y as f32 * ratio_y);
+                    let (x0, y0) = (fx as usize, fy as usize);
+                    let (tx, ty) = (fx.fract(), fy.fract());
+                    let bottom = at(x0, y0) * (1.0 - tx) + at(x0 + 1, y0) * tx;
+                    let top = at(x0, y0 + 1) * (1.0 - tx) + at(x0 + 1, y0 + 1) * tx;
+                    bottom * (1.0 - ty) + top * ty
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Ctrl+V replaces the density with the table of numbers in the clipboard
+pub fn clipboard_paste_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut errors: ResMut<ErrorLog>,
+    mut qg: Query<&mut Grid>,
+) {
+    let ctrl =
+        keyboard_input.pressed(KeyCode::LControl) || keyboard_input.pressed(KeyCode::RControl);
+    if !ctrl || !keyboard_input.just_pressed(KeyCode::V) {
+        return;
+    }
+
+    let text = arboard::Clipboard::new().and_then(|mut clipboard| clipboard.get_text());
+    let values = match text {
+        Ok(text) => parse_field(&text),
+        Err(err) => Err(err.to_string()),
+    };
+
+    match (values, qg.single_mut()) {
+        (Ok(values), Ok(mut grid)) => {
+            let (width, height) = (grid.width(), grid.height());
+            let field = resample_field(&values, width, height);
+            for (row, field_row) in grid.0.iter_mut().zip(field) {
+                for (cell, density) in row.iter_mut().zip(field_row) {
+                    cell.density = density;
+                }
+            }
+            info!(
+                "Pasted a {}x{} table into the density",
+                values[0].len(),
+                values.len()
+            );
+        }
+        (Err(err), _) => errors.report(format!("Couldn't paste the clipboard: {}", err)),
+        _ => {}
+    }
+}
+
+/// Density and velocity read from a file, as tables with the top row first like
+/// `parse_field` returns them, the velocity in cells per second of the file's grid
+#[derive(Default)]
+pub struct FieldFile {
+    pub density: Option<Vec<Vec<f32>>>,
+    /// x then y, pointing up
+    pub velocity: Option<[Vec<Vec<f32>>; 2]>,
+}
+
+/// Load an OpenEXR file like the exported ones, with density, velocity.X and velocity.Y
+/// channels, or a .npy array of float32 or float64 shaped (rows, columns) for the density,
+/// (rows, columns, 2) for the velocity or (rows, columns, 3) for both
+pub fn load_field(path: &Path) -> Result<FieldFile, String> {
+    let extension = path.extension().and_then(|e| e.to_str());
+    match extension.map(str::to_lowercase).as_deref() {
+        Some("exr") => load_exr_field(path),
+        Some("npy") => {
+            let bytes = fs::read(path).map_err(|err| err.to_string())?;
+            let (shape, values) = parse_npy(&bytes)?;
+            npy_field(&shape, &values)
+        }
+        _ => Err("expected an .exr or .npy file".to_string()),
+    }
+}
+
+/// One channel of interleaved samples as a table, `width` samples per row
+fn table(values: &[f32], width: usize, channels: usize, channel: usize) -> Vec<Vec<f32>> {
+    values
+        .chunks_exact(width * channels)
+        .map(|row| {
+            row.iter()
+                .skip(channel)
+                .step_by(channels)
+                .copied()
+                .collect()
+        })
+        .collect()
+}
+
+fn load_exr_field(path: &Path) -> Result<FieldFile, String> {
+    let image =
+        exr::prelude::read_all_flat_layers_from_file(path).map_err(|err| err.to_string())?;
+    let layer = image
+        .layer_data
+        .first()
+        .ok_or_else(|| "the file has no layer".to_string())?;
+    let width = layer.size.width();
+    if width == 0 || layer.size.height() == 0 {
+        return Err("the layer is empty".to_string());
+    }
+    let channel = |name: &str| {
+        layer
+            .channel_data
+            .list
+            .iter()
+            .find(|channel| channel.name.to_string() == name)
+            .map(|channel| {
+                let values: Vec<f32> = channel.sample_data.values_as_f32().collect();
+                table(&values, width, 1, 0)
+            })
+    };
+
+    let field = FieldFile {
+        density: channel("density"),
+        velocity: match (channel("velocity.X"), channel("velocity.Y")) {
+            (Some(x), Some(y)) => Some([x, y]),
+            _ => None,
+        },
+    };
+    if field.density.is_none() && field.velocity.is_none() {
+        return Err("no density nor velocity.X and velocity.Y channels".to_string());
+    }
+    Ok(field)
+}
+
+/// Value of a key of the header dictionary of a .npy file, as written
+fn npy_header_value<'a>(header: &'a str, key: &str) -> Result<&'a str, String> {
+    let start = header
+        .find(&format!("'{}':", key))
+        .ok_or_else(|| format!("no {} in the header", key))?
+        + key.len()
+        + 3;
+    let rest = header[start..].trim_start();
+    let end = match rest.chars().next() {
+        Some('(') => rest.find(')').map(|i| i + 1),
+        Some('\'') => rest[1..].find('\'').map(|i| i + 2),
+        _ => rest.find(|c| c == ',' || c == '}'),
+    };
+    end.map(|end| &rest[..end])
+        .ok_or_else(|| format!("invalid {} in the header", key))
+}
+
+/// Shape and values of a .npy array of little-endian floats in C order
+fn parse_npy(bytes: &[u8]) -> Result<(Vec<usize>, Vec<f32>), String> {
+    if bytes.len() < 10 || !bytes.starts_with(b"\x93NUMPY") {
+        return Err("not a .npy file".to_string());
+    }
+    let (header_len, start) = match bytes[6] {
+        1 => (u16::from_le_bytes([bytes[8], bytes[9]]) as usize, 10),
+        2 | 3 if bytes.len() >= 12 => {
+            let len = u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]);
+            (len as usize, 12)
+        }
+        version => return Err(format!("unsupported .npy version {}", version)),
+    };
+    let header = bytes
+        .get(start..start + header_len)
+        .and_then(|header| std::str::from_utf8(header).ok())
+        .ok_or_else(|| "truncated header".to_string())?;
+    let data = &bytes[start + header_len..];
+
+    if npy_header_value(header, "fortran_order")? != "False" {
+        return Err("only C order arrays are supported".to_string());
+    }
+    let shape = npy_header_value(header, "shape")?
+        .trim_matches(|c| c == '(' || c == ')')
+        .split(',')
+        .map(str::trim)
+        .filter(|dimension| !dimension.is_empty())
+        .map(|dimension| {
+            dimension
+                .parse::<usize>()
+                .map_err(|_| format!("invalid dimension {:?}", dimension))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let count: usize = shape.iter().product();
+
+    let values: Vec<f32> = match npy_header_value(header, "descr")? {
+        "'<f4'" => data
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect(),
+        "'<f8'" => data
+            .chunks_exact(8)
+            .map(|b| f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]) as f32)
+            .collect(),
+        descr => {
+            return Err(format!(
+                "unsupported type {}, expected float32 or float64",
+                descr
+            ))
+        }
+    };
+    if values.len() < count {
+        return Err(format!(
+            "{} values for a shape of {:?}",
+            values.len(),
+            shape
+        ));
+    }
+    Ok((shape, values[..count].to_vec()))
+}
+
+fn npy_field(shape: &[usize], values: &[f32]) -> Result<FieldFile, String> {
+    match *shape {
+        [rows, columns] if rows > 0 && columns > 0 => Ok(FieldFile {
+            density: Some(table(values, columns, 1, 0)),
+            velocity: None,
+        }),
+        [rows, columns, 2] if rows > 0 && columns > 0 => Ok(FieldFile {
+            density: None,
+            velocity: Some([table(values, columns, 2, 0), table(values, columns, 2, 1)]),
+        }),
+        [rows, columns, 3] if rows > 0 && columns > 0 => Ok(FieldFile {
+            density: Some(table(values, columns, 3, 0)),
+            velocity: Some([table(values, columns, 3, 1), table(values, columns, 3, 2)]),
+        }),
+        _ => Err(format!(
+            "unsupported shape {:?}, expected (rows, columns) or (rows, columns, 2 or 3)",
+            shape
+        )),
+    }
+}
+
+/// Replace the density and the velocity of the grid by the ones of a file, resampled to
+/// it. The velocity is scaled to the cells of the grid, the obstacles keep theirs.
+pub fn apply_field(grid: &mut Grid, field: &FieldFile) {
+    let (width, height) = (grid.width(), grid.height());
+    if let Some(density) = &field.density {
+        let density = resample_field(density, width, height);
+        for (row, density_row) in grid.0.iter_mut().zip(density) {
+            for (cell, density) in row.iter_mut().zip(density_row) {
+                cell.density = density;
+            }
+        }
+    }
+    if let Some([x, y]) = &field.velocity {
+        let scale = Vec2::new(
+            width as f32 / x[0].len() as f32,
+            height as f32 / x.len() as f32,
+        );
+        let (x, y) = (
+            resample_field(x, width, height),
+            resample_field(y, width, height),
+        );
+        for (row, (x_row, y_row)) in grid.0.iter_mut().zip(x.into_iter().zip(y)) {
+            for (cell, (vx, vy)) in row.iter_mut().zip(x_row.into_iter().zip(y_row)) {
+                if !cell.obstacle {
+                    cell.velocity = Vec2::new(vx, vy) * scale;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::solver::Scratch;
+use crate::Grid;
+
+// Inflows: edges of the grid the fluid enters through at a velocity varying along them and
+// over time, read from a CSV time series so measured or designed inlet conditions can be
+// replayed. Each line holds a time in seconds then the speeds into the grid along the edge,
+// in cells/s, interpolated between the lines and between the samples, e.g.
+//
+//     time,bottom,middle,top
+//     0,0,0,0
+//     2,4,8,4
+//     5,2,6,2
+//
+// The header line is optional. Before the first time the profile is the first one, after
+// the last it stays at the last one, or starts over if the inflow loops.
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub enum Edge {
+    Left,
+    Right,
+    Bottom,
+    Top,
+}
+
+impl Edge {
+    /// Unit vector pointing into the grid
+    fn inward(self) -> Vec2 {
+        match self {
+            Self::Left => Vec2::X,
+            Self::Right => -Vec2::X,
+            Self::Bottom => Vec2::Y,
+            Self::Top => -Vec2::Y,
+        }
+    }
+
+    /// Number of cells along the edge
+    fn length(self, width: usize, height: usize) -> usize {
+        match self {
+            Self::Left | Self::Right => height,
+            Self::Bottom | Self::Top => width,
+        }
+    }
+
+    /// The `i`th cell along the edge, from the bottom or the left
+    fn cell(self, i: usize, width: usize, height: usize) -> (usize, usize) {
+        match self {
+            Self::Left => (0, i),
+            Self::Right => (width - 1, i),
+            Self::Bottom => (i, 0),
+            Self::Top => (i, height - 1),
+        }
+    }
+}
+
+/// Velocity profile of an edge over time, e.g. the left edge replaying a CSV file found next
+/// to the scene file, over and over
+/// `(edge: Left, profile: "inlet.csv", looping: true)`
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Inflow {
+    pub edge: Edge,
+    /// CSV time series, relative to the scene file
+    #[serde(rename = "profile")]
+    pub path: PathBuf,
+    #[serde(default)]
+    pub looping: bool,
+    /// Read from the file once the scene file is loaded
+    #[serde(skip)]
+    pub series: Vec<Keyframe>,
+}
+
+/// Speeds along the edge at a time
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Keyframe {
+    pub time: f32,
+    pub speeds: Vec<f32>,
+}
+
+impl Inflow {
+    /// Read the time series, `dir` being the directory of the scene file
+    pub fn load(&mut self, dir: &Path) -> Result<(), String> {
+        let path = dir.join(&self.path);
+        let text =
+            fs::read_to_string(&path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        self.series = parse_series(&text).map_err(|err| format!("{}: {}", path.display(), err))?;
+        Ok(())
+    }
+
+    /// Seconds before a looping inflow starts over
+    pub fn period(&self) -> Option<f32> {
+        let last = self.series.last().map(|keyframe| keyframe.time);
+        last.filter(|&time| self.looping && time > 0.0)
+    }
+
+    /// Number of speeds along the edge
+    fn samples(&self) -> usize {
+        self.series
+            .first()
+            .map_or(0, |keyframe| keyframe.speeds.len())
+    }
+
+    /// Write the speeds along the edge `time` seconds after the start into `speeds`
+    fn speeds_at(&self, time: f32, speeds: &mut Vec<f32>) {
+        speeds.clear();
+        let (first, last) = match (self.series.first(), self.series.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return,
+        };
+        let time = if self.looping && last.time > 0.0 {
+            time % last.time
+        } else {
+            time
+        };
+        let next = self.series.iter().position(|keyframe| keyframe.time > time);
+        match next {
+            None => speeds.extend_from_slice(&last.speeds),
+            Some(0) => speeds.extend_from_slice(&first.speeds),
+            Some(i) => {
+                let (a, b) = (&self.series[i - 1], &self.series[i]);
+                let t = (time - a.time) / (b.time - a.time);
+                let pairs = a.speeds.iter().zip(b.speeds.iter());
+                speeds.extend(pairs.map(|(a, b)| a + (b - a) * t));
+            }
+        }
+    }
+
+    /// Set the velocity of the cells of the edge, the obstacles left out, `speeds` being
+    /// the buffer of the speeds at `time`
+    fn apply(&self, grid: &mut Grid, time: f32, speeds: &mut Vec<f32>) {
+        self.speeds_at(time, speeds);
+        let (width, height) = (grid.width(), grid.height());
+        let length = self.edge.length(width, height);
+        if speeds.is_empty() || length == 0 {
+            return;
+        }
+
+        // The first and last samples at the ends of the edge
+        let ratio = if length > 1 {
+            (speeds.len() - 1) as f32 / (length - 1) as f32
+        } else {
+            0.0
+        };
+        for i in 0..length {
+            let (x, y) = self.edge.cell(i, width, height);
+            let position = i as f32 * ratio;
+            let index = (position as usize).min(speeds.len() - 1);
+            let next = (index + 1).min(speeds.len() - 1);
+            let speed = speeds[index] + (speeds[next] - speeds[index]) * position.fract();
+            let cell = &mut grid.0[y][x];
+            if !cell.obstacle {
+                cell.velocity = self.edge.inward() * speed;
+            }
+        }
+    }
+}
+
+/// Parse the lines of a time series, the times increasing and every line having the same
+/// number of speeds
+fn parse_series(text: &str) -> Result<Vec<Keyframe>, String> {
+    let mut series: Vec<Keyframe> = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let values: Result<Vec<f32>, _> = line
+            .split(',')
+            .map(|value| value.trim().parse::<f32>())
+            .collect();
+        let values = match values {
+            Ok(values) => values,
+            Err(_) if i == 0 => continue,
+            Err(_) if line.trim().is_empty() => continue,
+            Err(_) => return Err(format!("line {}: invalid number", i + 1)),
+        };
+        if values.len() < 2 {
+            return Err(format!("line {}: expected a time and speeds", i + 1));
+        }
+        if values.iter().any(|value| !value.is_finite()) {
+            return Err(format!("line {}: values must be numbers", i + 1));
+        }
+        let keyframe = Keyframe {
+            time: values[0],
+            speeds: values[1..].to_vec(),
+        };
+        if let Some(previous) = series.last() {
+            if keyframe.time <= previous.time {
+                return Err(format!("line {}: the times must increase", i + 1));
+            }
+            if keyframe.speeds.len() != previous.speeds.len() {
+                return Err(format!(
+                    "line {}: {} speeds instead of {}",
+                    i + 1,
+                    keyframe.speeds.len(),
+                    previous.speeds.len()
+                ));
+            }
+        }
+        series.push(keyframe);
+    }
+
+    if series.is_empty() {
+        return Err("no profile found".to_string());
+    }
+    Ok(series)
+}
+
+/// Inflows of the running simulation, from the scene file
+#[derive(Default)]
+pub struct Inflows {
+    inflows: Vec<Inflow>,
+    /// Simulated seconds since they started
+    time: f32,
+}
+
+impl Inflows {
+    pub fn new(inflows: Vec<Inflow>) -> Self {
+        Self { inflows, time: 0.0 }
+    }
+
+    /// Set the velocity of the edges for a step of `dt` seconds, the speeds going through
+    /// the buffer of the scratch so that the stepping doesn't allocate
+    pub fn feed(&mut self, grid: &mut Grid, dt: f32, scratch: &mut Scratch) {
+        scratch.prepare_inflows(self.samples());
+        let speeds = scratch.inflow_speeds();
+        for inflow in self.inflows.iter() {
+            inflow.apply(grid, self.time, speeds);
+        }
+        self.time += dt;
+    }
+
+    /// Most speeds along the edge of an inflow
+    pub fn samples(&self) -> usize {
+        self.inflows.iter().map(Inflow::samples).max().unwrap_or(0)
+    }
+}
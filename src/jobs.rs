@@ -0,0 +1,262 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use bevy::prelude::*;
+use bevy::render::texture::{Extent3d, TextureDimension, TextureFormat};
+
+use crate::accessibility::Accessibility;
+use crate::font::{self, GLYPH_HEIGHT, GLYPH_WIDTH};
+use crate::viewport::MainCamera;
+
+// Long operations running on the task pools, like the exports and the parameter sweeps,
+// are jobs: they report their progress through their `Job` handle and stop at their next
+// step once it's cancelled, without leaving half written files behind. The running jobs
+// are listed in the bottom left corner of the main window, each with a progress bar and a
+// cancel button, and leave the list once their task dropped its handle.
+
+/// Font pixels of the name of a job, the bar and the cancel button, and between the rows
+const LABEL_WIDTH: usize = 17 * (GLYPH_WIDTH + 1);
+const BAR_WIDTH: usize = 64;
+const GAP: usize = 6;
+const ROW_SPACING: usize = 4;
+const CANCEL: &str = "CANCEL";
+const MARGIN: f32 = 8.0;
+
+const WHITE: [u8; 4] = [255, 255, 255, 255];
+const BAR_COLOR: [u8; 4] = [80, 200, 120, 255];
+const CANCEL_COLOR: [u8; 4] = [255, 90, 90, 255];
+const CANCELLED_COLOR: [u8; 4] = [120, 120, 120, 255];
+
+pub struct JobsPlugin;
+
+impl Plugin for JobsPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<Jobs>()
+            .add_startup_system(jobs_panel_setup.system())
+            .add_system(jobs_panel_system.system());
+    }
+}
+
+/// Jobs still running, oldest first
+#[derive(Default)]
+pub struct Jobs(Vec<Job>);
+
+impl Jobs {
+    /// Add a job of `total` steps to the panel, returning the handle its task advances
+    pub fn start(&mut self, name: &str, total: usize) -> Job {
+        let job = Job(Arc::new(JobState {
+            name: name.to_string(),
+            total,
+            done: AtomicUsize::new(0),
+            cancelled: AtomicBool::new(false),
+        }));
+        self.0.push(job.clone());
+        job
+    }
+}
+
+/// Progress of a job shared between its task and the panel
+#[derive(Clone)]
+pub struct Job(Arc<JobState>);
+
+struct JobState {
+    name: String,
+    total: usize,
+    done: AtomicUsize,
+    cancelled: AtomicBool,
+}
+
+impl Job {
+    /// One more step done
+    pub fn advance(&self) {
+        self.0.done.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Whether the user cancelled the job, which should then stop
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::Acquire)
+    }
+
+    fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::Release);
+    }
+
+    fn percent(&self) -> usize {
+        let done = self.0.done.load(Ordering::Relaxed);
+        (100 * done / self.0.total.max(1)).min(100)
+    }
+
+    /// Whether the task dropped its handle, the panel holding the last one
+    fn is_finished(&self) -> bool {
+        Arc::strong_count(&self.0) == 1
+    }
+}
+
+/// What a row of the panel shows, to redraw it only when it changes
+#[derive(Clone, PartialEq)]
+struct Row {
+    name: String,
+    percent: usize,
+    cancelled: bool,
+}
+
+struct JobsPanel;
+
+fn jobs_panel_setup(mut commands: Commands, mut materials: ResMut<Assets<ColorMaterial>>) {
+    commands
+        .spawn_bundle(SpriteBundle {
+            material: materials.add(Color::WHITE.into()),
+            visible: Visible {
+                is_visible: false,
+                is_transparent: true,
+            },
+            ..Default::default()
+        })
+        .insert(JobsPanel);
+}
+
+/// Font pixels between the left of a row and its cancel button, and of the whole row
+fn cancel_span() -> (usize, usize) {
+    let start = LABEL_WIDTH + BAR_WIDTH + GAP;
+    (start, start + CANCEL.len() * (GLYPH_WIDTH + 1) - 1)
+}
+
+/// The rows drawn in a texture, a font pixel being `scale` by `scale` pixels
+fn panel_texture(rows: &[Row], scale: usize) -> Texture {
+    let row_height = GLYPH_HEIGHT + ROW_SPACING;
+    let (cancel_start, width) = cancel_span();
+    let height = row_height * rows.len().max(1);
+    let mut data = vec![0; width * scale * height * scale * 4];
+    let mut put = |x: usize, y: usize, color: [u8; 4]| {
+        for py in y * scale..(y + 1) * scale {
+            for px in x * scale..(x + 1) * scale {
+                let start = (py * width * scale + px) * 4;
+                data[start..start + 4].copy_from_slice(&color);
+            }
+        }
+    };
+
+    for (i, row) in rows.iter().enumerate() {
+        let top = i * row_height;
+        let mut text = |text: &str, left: usize, color: [u8; 4]| {
+            for (y, pixels) in font::rasterize(text, 1).iter().enumerate() {
+                for (x, &pixel) in pixels.iter().enumerate() {
+                    if pixel && left + x < width {
+                        put(left + x, top + y, color);
+                    }
+                }
+            }
+        };
+        text(&row.name, 0, WHITE);
+        let cancel_color = if row.cancelled {
+            CANCELLED_COLOR
+        } else {
+            CANCEL_COLOR
+        };
+        text(CANCEL, cancel_start, cancel_color);
+
+        // Outlined bar filled up to the progress
+        let filled = (BAR_WIDTH - 2) * row.percent / 100;
+        for y in 0..GLYPH_HEIGHT {
+            for x in 0..BAR_WIDTH {
+                let outline = y == 0 || y == GLYPH_HEIGHT - 1 || x == 0 || x == BAR_WIDTH - 1;
+                if outline {
+                    put(LABEL_WIDTH + x, top + y, WHITE);
+                } else if x <= filled {
+                    put(LABEL_WIDTH + x, top + y, BAR_COLOR);
+                }
+            }
+        }
+    }
+
+    Texture::new(
+        Extent3d::new((width * scale) as u32, (height * scale) as u32, 1),
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    )
+}
+
+/// Drop the finished jobs, cancel the one whose button was clicked and redraw the panel
+#[allow(clippy::too_many_arguments)]
+fn jobs_panel_system(
+    windows: Res<Windows>,
+    mouse_button_input: Res<Input<MouseButton>>,
+    accessibility: Res<Accessibility>,
+    mut jobs: ResMut<Jobs>,
+    mut textures: ResMut<Assets<Texture>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    cameras: Query<&Transform, With<MainCamera>>,
+    mut query: Query<
+        (&Handle<ColorMaterial>, &mut Transform, &mut Visible),
+        (With<JobsPanel>, Without<MainCamera>),
+    >,
+    mut shown: Local<Vec<Row>>,
+) {
+    jobs.0.retain(|job| !job.is_finished());
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    let scale = accessibility.ui_scale;
+
+    // The panel sits MARGIN pixels from the bottom left corner, the cursor counting from it
+    if mouse_button_input.just_pressed(MouseButton::Left) {
+        if let Some(cursor) = window.cursor_position() {
+            let row_height = ((GLYPH_HEIGHT + ROW_SPACING) * scale) as f32;
+            let (start, end) = cancel_span();
+            let x = cursor.x - MARGIN;
+            let from_top = row_height * jobs.0.len() as f32 - (cursor.y - MARGIN);
+            let on_button = x >= (start * scale) as f32 && x < (end * scale) as f32;
+            if on_button && from_top >= 0.0 {
+                if let Some(job) = jobs.0.get((from_top / row_height) as usize) {
+                    info!("Cancelling {}", job.0.name);
+                    job.cancel();
+                }
+            }
+        }
+    }
+
+    let rows: Vec<Row> = jobs
+        .0
+        .iter()
+        .map(|job| Row {
+            name: job.0.name.clone(),
+            percent: job.percent(),
+            cancelled: job.is_cancelled(),
+        })
+        .collect();
+    let changed = *shown != rows || accessibility.is_changed();
+    *shown = rows;
+
+    // The camera follows the view when scrolling
+    let center = cameras
+        .single()
+        .map_or(Vec3::ZERO, |transform| transform.translation);
+    for (material, mut transform, mut visible) in query.iter_mut() {
+        visible.is_visible = !shown.is_empty();
+        let material = match materials.get_mut(material) {
+            Some(material) => material,
+            None => continue,
+        };
+        if changed {
+            let texture = textures.add(panel_texture(&shown, scale));
+            if let Some(old) = material.texture.replace(texture) {
+                textures.remove(old);
+            }
+        }
+
+        let size = material
+            .texture
+            .as_ref()
+            .and_then(|handle| textures.get(handle))
+            .map_or(Vec2::ZERO, |texture| {
+                Vec2::new(texture.size.width as f32, texture.size.height as f32)
+            });
+        transform.translation = Vec3::new(
+            center.x + (size.x - window.width()) / 2.0 + MARGIN,
+            center.y + (size.y - window.height()) / 2.0 + MARGIN,
+            10.0,
+        );
+    }
+}
@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use bevy::render::pipeline::{
+    BlendFactor, BlendOperation, BlendState, PipelineDescriptor, RenderPipeline,
+};
+use serde::Deserialize;
+
+use crate::lines::{self, ShaderSupport};
+
+// Draw order and blending of the layers, configurable in the scene file, e.g.
+//
+//     layers: [
+//         (layer: Dye),
+//         (layer: Density, blend: Multiply),
+//         (layer: Overlays, blend: Additive),
+//         (layer: Arrows),
+//     ],
+//
+// lists them from the bottom up, the unlisted ones staying above in their default order.
+// The density and the dye are mixed into the same squares, the upper one blended onto the
+// other. The arrows and the overlays are blended by the GPU, while the particles and the
+// obstacles, being sprites, are always alpha blended.
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Hash)]
+pub enum Layer {
+    Density,
+    Dye,
+    /// The solid cells
+    Obstacles,
+    Arrows,
+    /// The leaves pushed by the flow
+    Particles,
+    /// Pathlines, streakline, quiver and region outline
+    Overlays,
+}
+
+impl Layer {
+    const DEFAULT_ORDER: [Layer; 6] = [
+        Layer::Density,
+        Layer::Dye,
+        Layer::Obstacles,
+        Layer::Arrows,
+        Layer::Particles,
+        Layer::Overlays,
+    ];
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Hash)]
+pub enum BlendMode {
+    Alpha,
+    Additive,
+    Multiply,
+}
+
+impl Default for BlendMode {
+    fn default() -> Self {
+        Self::Alpha
+    }
+}
+
+impl BlendMode {
+    /// Blend a color onto another, `top` being premultiplied by its brightest component
+    pub fn blend(self, bottom: Vec3, top: Vec3) -> Vec3 {
+        match self {
+            Self::Alpha => bottom * (1.0 - top.max_element().clamp(0.0, 1.0)) + top,
+            Self::Additive => bottom + top,
+            Self::Multiply => bottom * top,
+        }
+    }
+
+    fn state(self) -> BlendState {
+        let (src_factor, dst_factor) = match self {
+            Self::Alpha => (BlendFactor::SrcAlpha, BlendFactor::OneMinusSrcAlpha),
+            Self::Additive => (BlendFactor::One, BlendFactor::One),
+            Self::Multiply => (BlendFactor::DstColor, BlendFactor::Zero),
+        };
+        BlendState {
+            src_factor,
+            dst_factor,
+            operation: BlendOperation::Add,
+        }
+    }
+
+    /// Set the blending of a pipeline
+    pub fn configure(self, pipeline: &mut PipelineDescriptor) {
+        for target in &mut pipeline.color_target_states {
+            target.color_blend = self.state();
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub struct LayerStyle {
+    pub layer: Layer,
+    #[serde(default)]
+    pub blend: BlendMode,
+}
+
+/// Check the layers of a scene file, naming the wrong one
+pub fn validate(styles: &[LayerStyle]) -> Result<(), String> {
+    for (i, style) in styles.iter().enumerate() {
+        if styles[..i].iter().any(|other| other.layer == style.layer) {
+            return Err(format!("{:?} is listed twice", style.layer));
+        }
+        let sprites = matches!(style.layer, Layer::Particles | Layer::Obstacles);
+        if sprites && style.blend != BlendMode::Alpha {
+            return Err(format!("{:?} can only be alpha blended", style.layer));
+        }
+    }
+    Ok(())
+}
+
+/// Every layer from the bottom up
+#[derive(Clone, Debug)]
+pub struct Layers(Vec<LayerStyle>);
+
+impl Default for Layers {
+    fn default() -> Self {
+        Self::from_styles(&[])
+    }
+}
+
+impl Layers {
+    /// The listed layers first, then the others in their default order
+    pub fn from_styles(styles: &[LayerStyle]) -> Self {
+        let unlisted = Layer::DEFAULT_ORDER
+            .iter()
+            .filter(|layer| styles.iter().all(|style| style.layer != **layer))
+            .map(|&layer| LayerStyle {
+                layer,
+                blend: match layer {
+                    // The dye has always been added to the density
+                    Layer::Dye => BlendMode::Additive,
+                    _ => BlendMode::Alpha,
+                },
+            });
+        Self(styles.iter().copied().chain(unlisted).collect())
+    }
+
+    fn index(&self, layer: Layer) -> usize {
+        self.0
+            .iter()
+            .position(|style| style.layer == layer)
+            .unwrap_or_default()
+    }
+
+    pub fn blend(&self, layer: Layer) -> BlendMode {
+        self.0[self.index(layer)].blend
+    }
+
+    /// Depth of an entity of the layer, `offset` ordering the entities within it from 0 to 1.
+    /// The density squares also show the dye, they're drawn at the lower of the two.
+    pub fn z(&self, layer: Layer, offset: f32) -> f32 {
+        let index = match layer {
+            Layer::Density => self.index(Layer::Density).min(self.index(Layer::Dye)),
+            _ => self.index(layer),
+        };
+        index as f32 + offset
+    }
+
+    /// Color of a cell before the palette and post effects
+    pub fn cell_color(&self, density: f32, dye: Vec3) -> Vec3 {
+        let density = Vec3::splat(density);
+        if self.index(Layer::Dye) > self.index(Layer::Density) {
+            self.blend(Layer::Dye).blend(density, dye)
+        } else {
+            self.blend(Layer::Density).blend(dye, density)
+        }
+    }
+}
+
+/// Which layer an entity is drawn in, and where within it
+pub struct OnLayer {
+    pub layer: Layer,
+    pub offset: f32,
+}
+
+pub struct LayersPlugin;
+
+impl Plugin for LayersPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system(layer_depth_system.system())
+            .add_system(layer_blend_system.system());
+    }
+}
+
+/// Keep the entities at the depth of their layer, including the ones just spawned
+fn layer_depth_system(layers: Res<Layers>, mut query: Query<(&OnLayer, &mut Transform)>) {
+    for (on_layer, mut transform) in query.iter_mut() {
+        let z = layers.z(on_layer.layer, on_layer.offset);
+        if transform.translation.z != z {
+            transform.translation.z = z;
+        }
+    }
+}
+
+/// Switch the meshes to a pipeline with the blending of their layer when the layers change
+fn layer_blend_system(
+    layers: Res<Layers>,
+    support: Res<ShaderSupport>,
+    mut blended: Local<HashMap<BlendMode, Handle<PipelineDescriptor>>>,
+    mut pipelines: ResMut<Assets<PipelineDescriptor>>,
+    mut shaders: ResMut<Assets<Shader>>,
+    mut query: Query<(&OnLayer, &mut RenderPipelines)>,
+) {
+    if !layers.is_changed() {
+        return;
+    }
+
+    for (on_layer, mut render_pipelines) in query.iter_mut() {
+        let blend = layers.blend(on_layer.layer);
+        let pipeline = match blended.get(&blend) {
+            Some(pipeline) => pipeline.clone(),
+            None => {
+                match lines::vertex_color_pipeline(&support, &mut pipelines, &mut shaders, blend) {
+                    Some(pipeline) => blended.entry(blend).or_insert(pipeline).clone(),
+                    None => return,
+                }
+            }
+        };
+        *render_pipelines = RenderPipelines::from_pipelines(vec![RenderPipeline::new(pipeline)]);
+    }
+}
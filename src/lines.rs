@@ -0,0 +1,118 @@
+use bevy::prelude::*;
+use bevy::render::mesh::Indices;
+use bevy::render::pipeline::PipelineDescriptor;
+use bevy::render::pipeline::PrimitiveTopology;
+use bevy::render::pipeline::RenderPipeline;
+use bevy::render::shader::ShaderStage;
+use bevy::render::shader::ShaderStages;
+
+use crate::layers::{BlendMode, Layer, OnLayer};
+use crate::{FRAGMENT_SHADER, VERTEX_SHADER};
+
+/// A line segment in world coordinates with its color
+pub type Segment = (Vec2, Vec2, [f32; 3]);
+
+/// Whether the vertex color shaders compile on this platform, checked once before starting.
+/// Without them the arrows are drawn as sprites and the line layers aren't drawn.
+pub struct ShaderSupport(pub Result<(), String>);
+
+impl ShaderSupport {
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn check() -> Self {
+        let stages = [
+            (ShaderStage::Vertex, VERTEX_SHADER),
+            (ShaderStage::Fragment, FRAGMENT_SHADER),
+        ];
+        Self(stages.iter().try_for_each(|&(stage, source)| {
+            Shader::from_glsl(stage, source)
+                .get_spirv(None)
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        }))
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn check() -> Self {
+        Self(Err("custom pipelines aren't supported on WebGL".to_string()))
+    }
+}
+
+/// Warn once at startup when falling back, logging isn't set up yet when checking
+pub fn shader_support_warning_system(support: Res<ShaderSupport>) {
+    if let Err(err) = &support.0 {
+        warn!(
+            "Custom shaders unavailable, drawing sprite arrows without line overlays: {}",
+            err
+        );
+    }
+}
+
+/// Pipeline drawing meshes with per-vertex colors, shared by the arrows and the line layers,
+/// or None if the shaders don't compile here
+pub fn vertex_color_pipeline(
+    support: &ShaderSupport,
+    pipelines: &mut Assets<PipelineDescriptor>,
+    shaders: &mut Assets<Shader>,
+    blend: BlendMode,
+) -> Option<Handle<PipelineDescriptor>> {
+    support.0.as_ref().ok()?;
+    let mut pipeline = PipelineDescriptor::default_config(ShaderStages {
+        vertex: shaders.add(Shader::from_glsl(ShaderStage::Vertex, VERTEX_SHADER)),
+        fragment: Some(shaders.add(Shader::from_glsl(ShaderStage::Fragment, FRAGMENT_SHADER))),
+    });
+    blend.configure(&mut pipeline);
+    Some(pipelines.add(pipeline))
+}
+
+/// Spawn an empty line list mesh in the overlays, `offset` ordering it among them,
+/// tagged with `marker`
+pub fn spawn_line_layer<T: Component>(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    pipeline: Handle<PipelineDescriptor>,
+    offset: f32,
+    marker: T,
+) {
+    let mut mesh = Mesh::new(PrimitiveTopology::LineList);
+    set_segments(&mut mesh, &[]);
+
+    commands
+        .spawn_bundle(MeshBundle {
+            mesh: meshes.add(mesh),
+            render_pipelines: RenderPipelines::from_pipelines(vec![RenderPipeline::new(pipeline)]),
+            transform: Transform::default(),
+            visible: Visible {
+                is_visible: false,
+                is_transparent: false,
+            },
+            ..Default::default()
+        })
+        .insert(OnLayer {
+            layer: Layer::Overlays,
+            offset,
+        })
+        .insert(marker);
+}
+
+/// Replace the content of a line list mesh
+pub fn set_segments(mesh: &mut Mesh, segments: &[Segment]) {
+    let mut v_pos = Vec::with_capacity(segments.len() * 2);
+    let mut v_color = Vec::with_capacity(segments.len() * 2);
+    for (start, end, color) in segments {
+        v_pos.push([start.x, start.y, 0.0]);
+        v_pos.push([end.x, end.y, 0.0]);
+        v_color.push(*color);
+        v_color.push(*color);
+    }
+
+    // The render backend doesn't like empty vertex buffers, keep a degenerate line instead
+    if v_pos.is_empty() {
+        v_pos = vec![[0.0, 0.0, 0.0]; 2];
+        v_color = vec![[0.0, 0.0, 0.0]; 2];
+    }
+
+    let indices = (0..v_pos.len() as u32).collect();
+    mesh.set_attribute(Mesh::ATTRIBUTE_POSITION, v_pos);
+    mesh.set_attribute(Mesh::ATTRIBUTE_COLOR, v_color);
+    mesh.set_indices(Some(Indices::U32(indices)));
+}
@@ -0,0 +1,691 @@
+use bevy::prelude::*;
+
+use crate::backend::Workers;
+use crate::boundary::BoundaryMode;
+use crate::settings::{AdvectionScheme, Backtrace, SolverSettings};
+use crate::{bilinear_weights, Grid};
+
+// Staggered (MAC) velocity: the x component lives on the faces between a cell and its left
+// and right neighbors, the y component on the ones below and above, so the divergence of a
+// cell and the pressure gradient across a face only take direct neighbors. The collocated
+// differences reach two cells apart and can't see a pressure alternating from cell to
+// cell, which the projection leaves as a checkerboard.
+// The faces are the velocity the simulation keeps: every cell stores the x component of
+// its left face and the y component of its bottom one, the faces of the right and top
+// edges being the first ones again on a periodic grid and walls otherwise. The diffusion,
+// the projection and the advection load them, work on them and store them back, along with
+// the average of the faces of every cell as its velocity, which the display, the sampling
+// and the forces read. Whatever changes the velocity of a cell in between, the forces, the
+// emitters or the brushes, reaches the faces around it when they're loaded again.
+
+/// A component of the velocity on the faces
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Component {
+    /// x component, on the left and right faces of the cells
+    U,
+    /// y component, on the bottom and top faces of the cells
+    V,
+}
+
+const COMPONENTS: [Component; 2] = [Component::U, Component::V];
+
+impl Component {
+    /// From the center of the cell (x, y) to its face (x, y) of the component
+    fn offset(self) -> Vec2 {
+        match self {
+            Self::U => Vec2::new(-0.5, 0.0),
+            Self::V => Vec2::new(0.0, -0.5),
+        }
+    }
+}
+
+/// Both components on the faces of the cells
+#[derive(Default)]
+struct Faces {
+    /// x component on the left face of every cell, `width + 1` faces a row, the last one
+    /// on the right edge
+    u: Vec<Vec<f32>>,
+    /// y component on the bottom face of every cell, `height + 1` rows, the last one on the
+    /// top edge
+    v: Vec<Vec<f32>>,
+}
+
+impl Faces {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            u: vec![vec![0.0; width + 1]; height],
+            v: vec![vec![0.0; width]; height + 1],
+        }
+    }
+
+    fn get(&self, component: Component) -> &[Vec<f32>] {
+        match component {
+            Component::U => &self.u,
+            Component::V => &self.v,
+        }
+    }
+
+    fn get_mut(&mut self, component: Component) -> &mut Vec<Vec<f32>> {
+        match component {
+            Component::U => &mut self.u,
+            Component::V => &mut self.v,
+        }
+    }
+
+    /// Copy the faces of `other`, the same size, without allocating
+    fn copy_from(&mut self, other: &Self) {
+        self.u.clone_from(&other.u);
+        self.v.clone_from(&other.v);
+    }
+
+    /// A component at a fractional cell position, bilinearly interpolated between its faces
+    fn component_at(&self, component: Component, pos: Vec2, period: Period) -> f32 {
+        interpolate(self.get(component), pos - component.offset(), period)
+    }
+
+    /// Velocity at a fractional cell position, each component interpolated between the
+    /// faces it lives on: the x component half a cell to the left of the centers, the y
+    /// component half a cell below
+    fn sample(&self, pos: Vec2, period: Period) -> Vec2 {
+        Vec2::new(
+            self.component_at(Component::U, pos, period),
+            self.component_at(Component::V, pos, period),
+        )
+    }
+
+    /// Where the velocity carries `start` in `dt` seconds, back in time for a negative `dt`,
+    /// integrated along the way with `backtrace`
+    fn trace(&self, start: Vec2, dt: f32, backtrace: Backtrace, period: Period) -> Vec2 {
+        let velocity = |pos: Vec2| self.sample(pos, period);
+        let k1 = velocity(start);
+        match backtrace {
+            Backtrace::Euler => start + k1 * dt,
+            Backtrace::Rk2 => start + velocity(start + k1 * dt / 2.0) * dt,
+            Backtrace::Rk4 => {
+                let k2 = velocity(start + k1 * dt / 2.0);
+                let k3 = velocity(start + k2 * dt / 2.0);
+                let k4 = velocity(start + k3 * dt);
+                start + (k1 + 2.0 * k2 + 2.0 * k3 + k4) * dt / 6.0
+            }
+        }
+    }
+}
+
+/// Columns and rows of cells the faces wrap around, None when the edges are walls
+type Period = Option<(usize, usize)>;
+
+/// The faces of the grid being worked on, loaded from it and stored back
+#[derive(Default)]
+pub struct MacGrid {
+    width: usize,
+    height: usize,
+    faces: Faces,
+    /// Faces before a relaxation sweep or an advection pass, which read them
+    previous: Faces,
+    /// Faces the diffusion started from, and the result of the first pass of the MacCormack
+    /// and BFECC advection
+    forward: Faces,
+}
+
+impl MacGrid {
+    /// Size the faces like the grid, only allocating when its size changed
+    fn prepare(&mut self, width: usize, height: usize) {
+        if (self.width, self.height) == (width, height) && !self.faces.u.is_empty() {
+            return;
+        }
+        self.width = width;
+        self.height = height;
+        self.faces = Faces::new(width, height);
+        self.previous = Faces::new(width, height);
+        self.forward = Faces::new(width, height);
+    }
+
+    fn period(&self, boundary: BoundaryMode) -> Period {
+        (boundary == BoundaryMode::Periodic).then(|| (self.width, self.height))
+    }
+
+    /// Load the faces of the grid, adding what changed the velocity of its cells since the
+    /// faces were stored: every face gets the average of the changes of the two cells on
+    /// either side, like a force on the cells pushes the faces between them. A new grid
+    /// has no faces yet, they're averaged from its velocity.
+    pub fn sync(&mut self, grid: &Grid, boundary: BoundaryMode) {
+        self.prepare(grid.width(), grid.height());
+        let (width, height) = (self.width, self.height);
+        let periodic = boundary == BoundaryMode::Periodic;
+        let change = |x: usize, y: usize| {
+            let cell = &grid.0[y][x];
+            if cell.obstacle {
+                Vec2::ZERO
+            } else {
+                cell.velocity - stored_velocity(grid, x, y, periodic)
+            }
+        };
+
+        for (y, row) in self.faces.u.iter_mut().enumerate() {
+            for (x, face) in row.iter_mut().enumerate() {
+                let (left, right) = ((x + width - 1) % width, x % width);
+                let change = (change(left, y).x + change(right, y).x) / 2.0;
+                *face = grid.0[y][right].faces.x + change;
+            }
+        }
+        for (y, row) in self.faces.v.iter_mut().enumerate() {
+            let (below, above) = ((y + height - 1) % height, y % height);
+            for (x, face) in row.iter_mut().enumerate() {
+                let change = (change(x, below).y + change(x, above).y) / 2.0;
+                *face = grid.0[above][x].faces.y + change;
+            }
+        }
+        self.enforce(grid, boundary);
+    }
+
+    /// Store the faces in the grid, with the average of its faces as the velocity of every
+    /// fluid cell
+    pub fn store(&self, grid: &mut Grid) {
+        let Faces { u, v } = &self.faces;
+        for (y, row) in grid.0.iter_mut().enumerate() {
+            for (x, cell) in row.iter_mut().enumerate() {
+                cell.faces = Vec2::new(u[y][x], v[y][x]);
+                if !cell.obstacle {
+                    cell.velocity =
+                        Vec2::new((u[y][x] + u[y][x + 1]) / 2.0, (v[y][x] + v[y + 1][x]) / 2.0);
+                }
+            }
+        }
+    }
+
+    /// Set the faces of the walls to zero and the ones of the obstacles to their velocity,
+    /// nothing flowing through them. On a periodic grid the last faces are the first ones.
+    fn enforce(&mut self, grid: &Grid, boundary: BoundaryMode) {
+        let (width, height) = (self.width, self.height);
+        let periodic = boundary == BoundaryMode::Periodic;
+        for (y, row) in self.faces.u.iter_mut().enumerate() {
+            for (x, face) in row.iter_mut().enumerate() {
+                let (left, right) = ((x + width - 1) % width, x % width);
+                let (left, right) = (&grid.0[y][left], &grid.0[y][right]);
+                if !periodic && (x == 0 || x == width) {
+                    *face = 0.0;
+                } else if left.obstacle || right.obstacle {
+                    let obstacle = if left.obstacle { left } else { right };
+                    *face = obstacle.velocity.x;
+                }
+            }
+            if periodic {
+                row[width] = row[0];
+            }
+        }
+        for (y, row) in self.faces.v.iter_mut().enumerate() {
+            let (below, above) = ((y + height - 1) % height, y % height);
+            for (x, face) in row.iter_mut().enumerate() {
+                let (below, above) = (&grid.0[below][x], &grid.0[above][x]);
+                if !periodic && (y == 0 || y == height) {
+                    *face = 0.0;
+                } else if below.obstacle || above.obstacle {
+                    let obstacle = if below.obstacle { below } else { above };
+                    *face = obstacle.velocity.y;
+                }
+            }
+        }
+        if periodic {
+            let (first, rest) = self.faces.v.split_first_mut().unwrap();
+            rest[height - 1].copy_from_slice(first);
+        }
+    }
+
+    /// Flow out of a cell through its four faces
+    fn divergence(&self, x: usize, y: usize) -> f32 {
+        let Faces { u, v } = &self.faces;
+        u[y][x + 1] - u[y][x] + v[y + 1][x] - v[y][x]
+    }
+
+    /// Fill the divergence of every cell divided by 4, zero in the obstacles
+    pub fn fill_quarter_divergence(&self, grid: &Grid, quarter: &mut [Vec<f32>]) {
+        for (y, row) in quarter.iter_mut().enumerate() {
+            for (x, value) in row.iter_mut().enumerate() {
+                *value = if grid.0[y][x].obstacle {
+                    0.0
+                } else {
+                    self.divergence(x, y) / 4.0
+                };
+            }
+        }
+    }
+
+    /// Subtract the pressure gradient from the faces between two fluid cells, the walls
+    /// and the obstacles keeping theirs
+    pub fn subtract_gradient(
+        &mut self,
+        grid: &Grid,
+        pressure: &[Vec<f32>],
+        boundary: BoundaryMode,
+    ) {
+        let (width, height) = (self.width, self.height);
+        for (y, row) in self.faces.u.iter_mut().enumerate() {
+            for (x, face) in row.iter_mut().enumerate() {
+                let (left, right) = ((x + width - 1) % width, x % width);
+                if !grid.0[y][left].obstacle && !grid.0[y][right].obstacle {
+                    *face -= pressure[y][right] - pressure[y][left];
+                }
+            }
+        }
+        for (y, row) in self.faces.v.iter_mut().enumerate() {
+            let (below, above) = ((y + height - 1) % height, y % height);
+            for (x, face) in row.iter_mut().enumerate() {
+                if !grid.0[below][x].obstacle && !grid.0[above][x].obstacle {
+                    *face -= pressure[above][x] - pressure[below][x];
+                }
+            }
+        }
+        // Back to zero on the walls, whose faces read across the grid above
+        self.enforce(grid, boundary);
+    }
+
+    /// Keep the faces as the source of the diffusion, see `relax`
+    pub fn begin_diffusion(&mut self) {
+        self.forward.copy_from(&self.faces);
+    }
+
+    /// One red-black sweep of the implicit diffusion of the faces by `k`, solving
+    /// `(1 + k) * u - k * avg(u) = source` for every face like the diffusion of the cells,
+    /// the source being the faces `begin_diffusion` kept. Returns the largest residual of
+    /// the equations the sweep relaxed, before it.
+    pub fn relax(&mut self, grid: &Grid, k: f32, boundary: BoundaryMode, workers: &Workers) -> f32 {
+        let periodic = boundary == BoundaryMode::Periodic;
+        let MacGrid {
+            faces,
+            previous,
+            forward: source,
+            ..
+        } = self;
+        let mut residual: f32 = 0.0;
+        // The faces around a face have the other color, like the cells around a cell
+        for color in 0..2 {
+            previous.copy_from(faces);
+            for &component in &COMPONENTS {
+                let (before, source) = (previous.get(component), source.get(component));
+                workers.for_each_row(faces.get_mut(component), |y, row| {
+                    for x in ((y + color) % 2..row.len()).step_by(2) {
+                        if fixed(grid, component, x, y, periodic) {
+                            continue;
+                        }
+                        let at = |dx, dy| neighbor(before, component, x, y, dx, dy, boundary);
+                        let avg = (at(1, 0) + at(-1, 0) + at(0, 1) + at(0, -1)) / 4.0;
+                        row[x] = (source[y][x] + k * avg) / (1.0 + k);
+                    }
+                });
+                // Each face changed by its residual divided by `1 + k`
+                let after = faces.get(component).iter().flatten();
+                let change = before
+                    .iter()
+                    .flatten()
+                    .zip(after)
+                    .fold(0.0f32, |max, (before, after)| {
+                        max.max((after - before).abs())
+                    });
+                residual = residual.max((1.0 + k) * change);
+            }
+        }
+        self.enforce(grid, boundary);
+        residual
+    }
+
+    /// Advect the faces along themselves for `dt` seconds with the scheme and the
+    /// backtrace of the settings, like the cells are advected along the faces. The faces of
+    /// the walls and the obstacles keep their value.
+    pub fn advect(&mut self, grid: &Grid, dt: f32, settings: &SolverSettings, workers: &Workers) {
+        let period = self.period(settings.boundary);
+        let periodic = period.is_some();
+        let MacGrid {
+            faces,
+            previous,
+            forward,
+            ..
+        } = self;
+        previous.copy_from(faces);
+        let velocity = &*previous;
+
+        for &component in &COMPONENTS {
+            // Where the velocity carries the face (x, y) in `dt`, back in time if negative
+            let trace = |x: usize, y: usize, dt: f32| {
+                let start = Vec2::new(x as f32, y as f32) + component.offset();
+                velocity.trace(start, dt, settings.backtrace, period)
+            };
+            let at = |faces: &Faces, pos: Vec2| faces.component_at(component, pos, period);
+            // The component clamped within the faces around where the face comes from, so a
+            // correction can't create new extremes
+            let limit = |value: f32, x: usize, y: usize| {
+                let pos = trace(x, y, -dt) - component.offset();
+                let (low, high) = corners_range(velocity.get(component), pos, period);
+                value.max(low).min(high)
+            };
+            let kept = velocity.get(component);
+            let fill = |target: &mut Vec<Vec<f32>>, face: &(dyn Fn(usize, usize) -> f32 + Sync)| {
+                fill_faces(target, kept, grid, component, periodic, workers, face)
+            };
+
+            match settings.advection {
+                AdvectionScheme::SemiLagrangian => {
+                    fill(faces.get_mut(component), &|x: usize, y: usize| {
+                        at(velocity, trace(x, y, -dt))
+                    });
+                }
+                AdvectionScheme::MacCormack => {
+                    fill(forward.get_mut(component), &|x: usize, y: usize| {
+                        at(velocity, trace(x, y, -dt))
+                    });
+                    let f = &*forward;
+                    fill(faces.get_mut(component), &|x: usize, y: usize| {
+                        let predicted = f.get(component)[y][x];
+                        let traced = at(f, trace(x, y, dt));
+                        limit(predicted + 0.5 * (kept[y][x] - traced), x, y)
+                    });
+                }
+                AdvectionScheme::Bfecc => {
+                    fill(forward.get_mut(component), &|x: usize, y: usize| {
+                        at(velocity, trace(x, y, -dt))
+                    });
+                    // The faces compensated by half the error of a round trip, forward then
+                    // back, advected again
+                    let f = &*forward;
+                    fill(faces.get_mut(component), &|x: usize, y: usize| {
+                        let round_trip = at(f, trace(x, y, dt));
+                        kept[y][x] + 0.5 * (kept[y][x] - round_trip)
+                    });
+                    let source = &*faces;
+                    fill(forward.get_mut(component), &|x: usize, y: usize| {
+                        limit(at(source, trace(x, y, -dt)), x, y)
+                    });
+                    std::mem::swap(faces.get_mut(component), forward.get_mut(component));
+                }
+            }
+        }
+        self.enforce(grid, settings.boundary);
+    }
+
+    /// Velocity at a fractional cell position, each component bilinearly interpolated
+    /// between the faces it lives on
+    pub fn sample(&self, pos: Vec2, boundary: BoundaryMode) -> Vec2 {
+        self.faces.sample(pos, self.period(boundary))
+    }
+
+    /// Where the face velocity carries `start` in `dt` seconds, back in time for a negative
+    /// `dt`, integrated along the way with the backtrace of the settings
+    pub fn trace(&self, start: Vec2, dt: f32, settings: &SolverSettings) -> Vec2 {
+        let period = self.period(settings.boundary);
+        self.faces.trace(start, dt, settings.backtrace, period)
+    }
+}
+
+/// Velocity `MacGrid::store` gives the cell (x, y) from the faces stored in the grid
+fn stored_velocity(grid: &Grid, x: usize, y: usize, periodic: bool) -> Vec2 {
+    let (width, height) = (grid.width(), grid.height());
+    let faces = grid.0[y][x].faces;
+    let right = if x + 1 < width || periodic {
+        grid.0[y][(x + 1) % width].faces.x
+    } else {
+        0.0
+    };
+    let top = if y + 1 < height || periodic {
+        grid.0[(y + 1) % height][x].faces.y
+    } else {
+        0.0
+    };
+    Vec2::new((faces.x + right) / 2.0, (faces.y + top) / 2.0)
+}
+
+/// Whether the face (x, y) of a component keeps its value through the diffusion and the
+/// advection: the faces of the walls and of the obstacles, and the last ones of a periodic
+/// grid, copies of the first ones
+fn fixed(grid: &Grid, component: Component, x: usize, y: usize, periodic: bool) -> bool {
+    let (width, height) = (grid.width(), grid.height());
+    let (along, count) = match component {
+        Component::U => (x, width),
+        Component::V => (y, height),
+    };
+    if along == count || (along == 0 && !periodic) {
+        return true;
+    }
+    let (a, b) = match component {
+        Component::U => (&grid.0[y][(x + width - 1) % width], &grid.0[y][x]),
+        Component::V => (&grid.0[(y + height - 1) % height][x], &grid.0[y][x]),
+    };
+    a.obstacle || b.obstacle
+}
+
+/// Write `face(x, y)` to the faces of a component that aren't fixed, see `fixed`, the
+/// fixed ones getting their value in `kept`
+fn fill_faces(
+    target: &mut [Vec<f32>],
+    kept: &[Vec<f32>],
+    grid: &Grid,
+    component: Component,
+    periodic: bool,
+    workers: &Workers,
+    face: impl Fn(usize, usize) -> f32 + Sync,
+) {
+    workers.for_each_row(target, |y, row| {
+        for (x, value) in row.iter_mut().enumerate() {
+            *value = if fixed(grid, component, x, y, periodic) {
+                kept[y][x]
+            } else {
+                face(x, y)
+            };
+        }
+    });
+}
+
+/// Face (x + dx, y + dy) of a component around the face (x, y), the walls along the faces
+/// reflecting them like they reflect the ghost cells
+fn neighbor(
+    faces: &[Vec<f32>],
+    component: Component,
+    x: usize,
+    y: usize,
+    dx: isize,
+    dy: isize,
+    boundary: BoundaryMode,
+) -> f32 {
+    let (nx, ny) = (x as isize + dx, y as isize + dy);
+    match component {
+        Component::U => {
+            let (width, height) = (faces[0].len() - 1, faces.len());
+            let (_, ny, reflection) = boundary.ghost(0, ny, width, height);
+            faces[ny][nx.rem_euclid(width as isize) as usize] * reflection.x
+        }
+        Component::V => {
+            let (width, height) = (faces[0].len(), faces.len() - 1);
+            let (nx, _, reflection) = boundary.ghost(nx, 0, width, height);
+            faces[ny.rem_euclid(height as isize) as usize][nx] * reflection.y
+        }
+    }
+}
+
+/// Face at the index (x, y), wrapping around `period` columns and rows or clamped to the
+/// faces without one
+fn face_index(faces: &[Vec<f32>], x: isize, y: isize, period: Period) -> (usize, usize) {
+    let (rows, columns) = (faces.len() as isize, faces[0].len() as isize);
+    match period {
+        Some((width, height)) => (
+            x.rem_euclid(width as isize) as usize,
+            y.rem_euclid(height as isize) as usize,
+        ),
+        None => (
+            x.max(0).min(columns - 1) as usize,
+            y.max(0).min(rows - 1) as usize,
+        ),
+    }
+}
+
+/// Bilinear interpolation of the faces of a component at a fractional index
+fn interpolate(faces: &[Vec<f32>], pos: Vec2, period: Period) -> f32 {
+    bilinear_weights(pos)
+        .iter()
+        .map(|&(x, y, weight)| {
+            let (x, y) = face_index(faces, x, y, period);
+            faces[y][x] * weight
+        })
+        .sum()
+}
+
+/// Smallest and largest of the faces the interpolation at a fractional index weighs
+fn corners_range(faces: &[Vec<f32>], pos: Vec2, period: Period) -> (f32, f32) {
+    bilinear_weights(pos)
+        .iter()
+        .map(|&(x, y, _)| {
+            let (x, y) = face_index(faces, x, y, period);
+            faces[y][x]
+        })
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(low, high), face| {
+            (low.min(face), high.max(face))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uniform_flow_has_no_divergence() {
+        let mut grid = Grid::new(5, 4);
+        for cell in grid.0.iter_mut().flatten() {
+            cell.velocity = Vec2::new(1.5, -0.5);
+        }
+        let mut mac = MacGrid::default();
+        mac.sync(&grid, BoundaryMode::Periodic);
+
+        let mut quarter = vec![vec![1.0; 5]; 4];
+        mac.fill_quarter_divergence(&grid, &mut quarter);
+        assert!(quarter.iter().flatten().all(|&q| q == 0.0));
+        let sampled = mac.sample(Vec2::new(4.7, -0.2), BoundaryMode::Periodic);
+        assert!((sampled - Vec2::new(1.5, -0.5)).length() < 1e-6);
+    }
+
+    #[test]
+    fn linear_flow_samples_exactly_inside_of_the_walls() {
+        let mut grid = Grid::new(6, 6);
+        for (y, row) in grid.0.iter_mut().enumerate() {
+            for (x, cell) in row.iter_mut().enumerate() {
+                cell.velocity = Vec2::new(x as f32, 2.0 * y as f32);
+            }
+        }
+        let mut mac = MacGrid::default();
+        mac.sync(&grid, BoundaryMode::NoSlip);
+
+        let sampled = mac.sample(Vec2::new(2.25, 3.5), BoundaryMode::NoSlip);
+        assert!((sampled - Vec2::new(2.25, 7.0)).length() < 1e-5);
+        let mut centers = grid.clone();
+        mac.store(&mut centers);
+        assert_eq!(centers.0[3][2].velocity, grid.0[3][2].velocity);
+        // The wall stops the flow into it, on the right face of the last cell
+        assert_eq!(centers.0[3][5].velocity.x, 2.25);
+    }
+
+    #[test]
+    fn stored_faces_load_back_as_they_were() {
+        for &boundary in &[BoundaryMode::Periodic, BoundaryMode::FreeSlip] {
+            let mut grid = Grid::new(5, 4);
+            for (n, cell) in grid.0.iter_mut().flatten().enumerate() {
+                cell.velocity = Vec2::new((n % 3) as f32, (n % 4) as f32 - 1.5);
+            }
+            grid.0[2][2].obstacle = true;
+            let mut mac = MacGrid::default();
+            mac.sync(&grid, boundary);
+            // Faces the cell velocities can't be averaged from
+            mac.faces.u[1][2] += 0.75;
+            mac.faces.v[1][3] -= 0.5;
+            mac.store(&mut grid);
+
+            let mut loaded = MacGrid::default();
+            loaded.sync(&grid, boundary);
+            assert_eq!(loaded.faces.u, mac.faces.u, "{:?}", boundary);
+            assert_eq!(loaded.faces.v, mac.faces.v, "{:?}", boundary);
+        }
+    }
+
+    #[test]
+    fn changes_of_the_cells_push_the_faces_around_them() {
+        let mut grid = Grid::new(4, 4);
+        let mut mac = MacGrid::default();
+        mac.sync(&grid, BoundaryMode::Periodic);
+        mac.store(&mut grid);
+
+        grid.0[1][2].velocity = Vec2::new(2.0, -4.0);
+        mac.sync(&grid, BoundaryMode::Periodic);
+        assert_eq!((mac.faces.u[1][2], mac.faces.u[1][3]), (1.0, 1.0));
+        assert_eq!((mac.faces.v[1][2], mac.faces.v[2][2]), (-2.0, -2.0));
+        assert_eq!(mac.faces.u[1][1], 0.0);
+    }
+
+    #[test]
+    fn diffusion_spreads_the_faces() {
+        let mut grid = Grid::new(6, 6);
+        grid.0[3][3].velocity = Vec2::new(4.0, 0.0);
+        let mut mac = MacGrid::default();
+        mac.sync(&grid, BoundaryMode::Periodic);
+        let total: f32 = mac
+            .faces
+            .u
+            .iter()
+            .map(|row| row[..6].iter().sum::<f32>())
+            .sum();
+
+        mac.begin_diffusion();
+        for _ in 0..20 {
+            mac.relax(&grid, 1.0, BoundaryMode::Periodic, &Workers::default());
+        }
+        // The momentum spreads out without being lost
+        let diffused: f32 = mac
+            .faces
+            .u
+            .iter()
+            .map(|row| row[..6].iter().sum::<f32>())
+            .sum();
+        assert!((diffused - total).abs() < 1e-4, "{} of {}", diffused, total);
+        assert!(mac.faces.u[3][3] < 2.0 && mac.faces.u[3][1] > 0.0);
+    }
+
+    #[test]
+    fn uniform_flow_advects_into_itself() {
+        let mut grid = Grid::new(6, 5);
+        for cell in grid.0.iter_mut().flatten() {
+            cell.velocity = Vec2::new(0.7, -0.3);
+        }
+        let mut mac = MacGrid::default();
+        mac.sync(&grid, BoundaryMode::Periodic);
+        let settings = SolverSettings {
+            boundary: BoundaryMode::Periodic,
+            ..SolverSettings::default()
+        };
+        mac.advect(&grid, 0.4, &settings, &Workers::default());
+        for face in mac.faces.u.iter().flatten() {
+            assert!((face - 0.7).abs() < 1e-5);
+        }
+        for face in mac.faces.v.iter().flatten() {
+            assert!((face + 0.3).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn the_divergence_sees_a_checkerboard_pressure() {
+        // The gradient of a pressure alternating from cell to cell cancels on the centers
+        // but not across the faces
+        let grid = Grid::new(4, 4);
+        let pressure: Vec<Vec<f32>> = (0..4)
+            .map(|y| {
+                (0..4)
+                    .map(|x| if (x + y) % 2 == 0 { 1.0 } else { -1.0 })
+                    .collect()
+            })
+            .collect();
+        let mut mac = MacGrid::default();
+        mac.sync(&grid, BoundaryMode::Periodic);
+        mac.subtract_gradient(&grid, &pressure, BoundaryMode::Periodic);
+
+        let mut quarter = vec![vec![0.0; 4]; 4];
+        mac.fill_quarter_divergence(&grid, &mut quarter);
+        for (row, pressure) in quarter.iter().zip(pressure.iter()) {
+            for (quarter, pressure) in row.iter().zip(pressure.iter()) {
+                assert_eq!(*quarter, 2.0 * pressure);
+            }
+        }
+    }
+}
@@ -1,14 +1,102 @@
 use std::f32::consts::PI;
+use std::ops::{Add, Mul};
 
+use bevy::core::DefaultTaskPoolOptions;
 use bevy::input::mouse::MouseMotion;
 use bevy::prelude::*;
 use bevy::render::pipeline::PipelineDescriptor;
 use bevy::render::pipeline::RenderPipeline;
-use bevy::render::shader::ShaderStage;
-use bevy::render::shader::ShaderStages;
+use bevy::render::texture::{Extent3d, TextureDimension, TextureFormat};
 use bevy::window::CursorMoved;
 // use bevy::window::WindowResized;
 
+mod accessibility;
+#[cfg(debug_assertions)]
+mod alloc_counter;
+mod average;
+mod backend;
+mod batch;
+mod bench;
+mod boundary;
+mod cli;
+mod compare;
+mod control;
+mod courant;
+mod decompose;
+mod divergence;
+mod emitters;
+mod errors;
+mod export;
+mod fans;
+mod file_drop;
+mod flip;
+mod fluid;
+mod font;
+mod ftle;
+mod gestures;
+mod history;
+mod import;
+mod inflow;
+mod jobs;
+mod layers;
+mod lines;
+mod mac;
+mod memory;
+mod menu;
+mod multigrid;
+mod obstacles;
+mod offscreen;
+mod palette;
+mod patterns;
+mod phase;
+mod piv;
+mod pod;
+mod post;
+mod poster;
+mod prefs;
+mod quiver;
+mod region;
+mod render;
+mod scene_file;
+mod scenes;
+mod selftest;
+mod settings;
+mod snapshot;
+mod solver;
+mod species;
+mod sph;
+mod stamp;
+mod status;
+mod steering;
+mod stepping;
+mod stream;
+mod stylus;
+mod surface;
+mod sweep;
+mod symmetry;
+mod tracers;
+mod tutorial;
+mod units;
+mod viewport;
+mod weather;
+mod widget;
+
+use accessibility::Accessibility;
+use boundary::BoundaryMode;
+use errors::ErrorLog;
+use import::{ImageDye, InitialField};
+use layers::{Layer, Layers, OnLayer};
+use lines::ShaderSupport;
+use palette::Palette;
+use post::PostEffects;
+use scene_file::SceneFile;
+use scenes::SceneSelection;
+use settings::{ExternalForces, Precision, SolverSettings};
+use solver::{Splat, Splats};
+use species::{SpeciesBrush, SPECIES};
+use symmetry::Symmetry;
+use viewport::{MainCamera, ViewSlot, Viewport};
+
 // https://youtu.be/qsYE1wMEMPA
 // https://www.autodesk.com/research/publications/real-time-fluid-dynamics
 // ! Each call to angle_between should make sure the vector's length isn't zero
@@ -16,6 +104,11 @@ use bevy::window::CursorMoved;
 // const WIDTH: usize = 10;
 // const HEIGHT: usize = 10;
 // const CELL_SIZE: f32 = 50.0;
+#[cfg(debug_assertions)]
+#[global_allocator]
+static ALLOCATOR: alloc_counter::CountingAllocator = alloc_counter::CountingAllocator;
+
+/// Default grid size, also used for the window while picking a scene
 const WIDTH: usize = 50;
 const HEIGHT: usize = 50;
 const CELL_SIZE: f32 = 20.0;
@@ -50,30 +143,86 @@ void main() {
 #[derive(Clone)]
 struct Grid(Vec<Vec<Cell>>);
 struct DensitySquare;
-struct VelocityArrow;
+struct VelocityArrow {
+    /// Scale of the arrow at full length
+    scale: f32,
+}
 #[derive(Debug)]
 struct Position {
     x: usize,
     y: usize,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum AppState {
+    Menu,
+    Running,
+}
+
 #[derive(Clone, Debug)]
 struct Cell {
+    /// Average of the velocity on the faces of the cell, see `faces`
     velocity: Vec2,
+    /// Velocity the solver keeps, on the faces of the cell: the x component on its left
+    /// face and the y component on its bottom one, see `mac`
+    faces: Vec2,
     density: f32,
+    /// RGB dye carried along with the density
+    dye: Vec3,
+    /// Above the ambient temperature, the fluid rises with the buoyancy of the settings
+    temperature: f32,
+    /// Concentration of each dye species, see `species`
+    species: [f32; SPECIES],
+    /// Burns into heat, soot and expansion above the ignition temperature, see
+    /// `solver::burn_fuel`
+    fuel: f32,
+    /// Signed distance to the surface of the liquid, in cells, negative in the liquid, see
+    /// `surface`. New cells are in the liquid.
+    level: f32,
+    /// Solid cell the fluid flows around, it keeps no density nor dye but has its own
+    /// temperature, conducting heat with its neighbors. Its velocity is the one of the
+    /// obstacle, zero unless it moves.
+    obstacle: bool,
+    /// Curl of the velocity, derived from it at the end of every step, see
+    /// `Grid::update_vorticity`
+    vorticity: f32,
+    /// Stream function, its contours being the streamlines, relaxed towards the one of the
+    /// vorticity by `Grid::update_stream` and carried along as the start of the next solve
+    stream: f32,
 }
 
 impl Grid {
-    pub fn new() -> Self {
-        let mut grid = Vec::with_capacity(HEIGHT);
+    pub fn new(width: usize, height: usize) -> Self {
+        let mut grid = Vec::with_capacity(height);
 
-        for _ in 0..HEIGHT {
-            let mut row = Vec::with_capacity(WIDTH);
-            for _ in 0..WIDTH {
+        for _ in 0..height {
+            let mut row = Vec::with_capacity(width);
+            for _ in 0..width {
                 let velocity = Vec2::ZERO;
+                let faces = Vec2::ZERO;
                 let density = 0.0;
+                let dye = Vec3::ZERO;
+                let temperature = 0.0;
+                let species = [0.0; SPECIES];
+                let fuel = 0.0;
+                let level = -1.0;
+                let obstacle = false;
+                let vorticity = 0.0;
+                let stream = 0.0;
 
-                row.push(Cell { velocity, density })
+                row.push(Cell {
+                    velocity,
+                    faces,
+                    density,
+                    dye,
+                    temperature,
+                    species,
+                    fuel,
+                    level,
+                    obstacle,
+                    vorticity,
+                    stream,
+                })
             }
             grid.push(row);
         }
@@ -81,87 +230,377 @@ impl Grid {
         Self(grid)
     }
 
-    pub fn get_average<F: Fn(&Cell) -> f32>(&self, x: usize, y: usize, attr: F) -> f32 {
-        let x_plus = (x + 1) % WIDTH;
-        let x_minus = (x + WIDTH - 1) % WIDTH;
-        let y_plus = (y + 1) % HEIGHT;
-        let y_minus = (y + HEIGHT - 1) % HEIGHT;
+    pub fn width(&self) -> usize {
+        self.0[0].len()
+    }
 
-        let n1 = attr(&self.0[y][x_minus]);
-        let n2 = attr(&self.0[y][x_plus]);
-        let n3 = attr(&self.0[y_minus][x]);
-        let n4 = attr(&self.0[y_plus][x]);
+    pub fn height(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Cell at (x, y), or the ghost cell standing for it outside of the grid
+    fn cell_at(&self, x: isize, y: isize, boundary: BoundaryMode) -> Cell {
+        let (x, y, reflection) = boundary.ghost(x, y, self.width(), self.height());
+        let cell = &self.0[y][x];
+        Cell {
+            velocity: cell.velocity * reflection,
+            ..*cell
+        }
+    }
+
+    /// Neighbor of (x, y) as the solver passes read it. An obstacle reads like a wall moving
+    /// with it: the cell itself with its velocity reflected about the one of the obstacle,
+    /// so nothing flows into the obstacle and the fluid along it follows it.
+    fn neighbor(&self, x: usize, y: usize, dx: isize, dy: isize, boundary: BoundaryMode) -> Cell {
+        let neighbor = self.cell_at(x as isize + dx, y as isize + dy, boundary);
+        if !neighbor.obstacle {
+            return neighbor;
+        }
+        let cell = &self.0[y][x];
+        Cell {
+            velocity: 2.0 * neighbor.velocity - cell.velocity,
+            ..*cell
+        }
+    }
+
+    pub fn get_average<F: Fn(&Cell) -> f32>(
+        &self,
+        x: usize,
+        y: usize,
+        boundary: BoundaryMode,
+        attr: F,
+    ) -> f32 {
+        let n1 = attr(&self.neighbor(x, y, -1, 0, boundary));
+        let n2 = attr(&self.neighbor(x, y, 1, 0, boundary));
+        let n3 = attr(&self.neighbor(x, y, 0, -1, boundary));
+        let n4 = attr(&self.neighbor(x, y, 0, 1, boundary));
         let avg = (n1 + n2 + n3 + n4) / 4.0;
         avg
     }
 
-    pub fn get_velocity_gradient(&self, x: usize, y: usize) -> f32 {
-        let x_plus = (x + 1) % WIDTH;
-        let x_minus = (x + WIDTH - 1) % WIDTH;
-        let y_plus = (y + 1) % HEIGHT;
-        let y_minus = (y + HEIGHT - 1) % HEIGHT;
-
-        let vx1 = self.0[y][x_plus].velocity.x;
-        let vx2 = self.0[y][x_minus].velocity.x;
-        let vy1 = self.0[y_plus][x].velocity.y;
-        let vy2 = self.0[y_minus][x].velocity.y;
+    pub fn get_velocity_gradient(&self, x: usize, y: usize, boundary: BoundaryMode) -> f32 {
+        let velocity = |dx, dy| self.neighbor(x, y, dx, dy, boundary).velocity;
+        let vx1 = velocity(1, 0).x;
+        let vx2 = velocity(-1, 0).x;
+        let vy1 = velocity(0, 1).y;
+        let vy2 = velocity(0, -1).y;
         let vel_grad = (vx1 - vx2 + vy1 - vy2) / 2.0;
         vel_grad
     }
+
+    /// Curl of the velocity, positive where the fluid turns counterclockwise
+    pub fn get_curl(&self, x: usize, y: usize, boundary: BoundaryMode) -> f32 {
+        let velocity = |dx, dy| self.neighbor(x, y, dx, dy, boundary).velocity;
+        let dvy_dx = (velocity(1, 0).y - velocity(-1, 0).y) / 2.0;
+        let dvx_dy = (velocity(0, 1).x - velocity(0, -1).x) / 2.0;
+        dvy_dx - dvx_dy
+    }
+
+    /// Recompute the vorticity of every cell from the velocity, by central differences
+    pub fn update_vorticity(&mut self, boundary: BoundaryMode) {
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                self.0[y][x].vorticity = self.get_curl(x, y, boundary);
+            }
+        }
+    }
+
+    /// Vorticity of a cell as of the last update, positive where the fluid turns
+    /// counterclockwise
+    pub fn vorticity(&self, x: usize, y: usize) -> f32 {
+        self.0[y][x].vorticity
+    }
+
+    /// Relax the stream function of every cell towards the solution of ∇²ψ = -ω by `sweeps`
+    /// over-relaxed red-black Gauss-Seidel sweeps, from the last solution. It's zero along
+    /// the walls, and averages to zero on a periodic grid, the mean flow across it left out.
+    pub fn update_stream(&mut self, boundary: BoundaryMode, sweeps: usize) {
+        const OVER_RELAXATION: f32 = 1.8;
+        let (width, height) = (self.width(), self.height());
+        let periodic = boundary == BoundaryMode::Periodic;
+        for _ in 0..sweeps {
+            for (x, y) in solver::red_black(width, height) {
+                let stream = |dx: isize, dy: isize| {
+                    let (nx, ny) = (x as isize + dx, y as isize + dy);
+                    let outside = nx < 0 || ny < 0 || nx >= width as isize || ny >= height as isize;
+                    let (gx, gy, _) = boundary.ghost(nx, ny, width, height);
+                    // Zero halfway to the ghost cell behind a wall
+                    if outside && !periodic {
+                        -self.0[y][x].stream
+                    } else {
+                        self.0[gy][gx].stream
+                    }
+                };
+                let sum = stream(-1, 0) + stream(1, 0) + stream(0, -1) + stream(0, 1);
+                let cell = &mut self.0[y][x];
+                let relaxed = (sum + cell.vorticity) / 4.0;
+                cell.stream += OVER_RELAXATION * (relaxed - cell.stream);
+            }
+        }
+
+        if periodic {
+            let mean = self.0.iter().flatten().map(|cell| cell.stream).sum::<f32>()
+                / (width * height) as f32;
+            self.0
+                .iter_mut()
+                .flatten()
+                .for_each(|cell| cell.stream -= mean);
+        }
+    }
+
+    /// Stream function of a cell as of the last update
+    pub fn stream(&self, x: usize, y: usize) -> f32 {
+        self.0[y][x].stream
+    }
+
+    /// Velocity of a cell, or of the ghost cell standing for it outside of the grid
+    fn velocity_at(&self, x: isize, y: isize, boundary: BoundaryMode) -> Vec2 {
+        let (x, y, reflection) = boundary.ghost(x, y, self.width(), self.height());
+        self.0[y][x].velocity * reflection
+    }
+
+    /// Interpolated velocity at a fractional cell position, ghost cells standing for the
+    /// cells outside of the grid
+    pub fn sample_velocity(
+        &self,
+        pos: Vec2,
+        interpolation: InterpolationKind,
+        boundary: BoundaryMode,
+    ) -> Vec2 {
+        let x0 = pos.x.floor();
+        let y0 = pos.y.floor();
+        let tx = pos.x - x0;
+        let ty = pos.y - y0;
+        let x0 = x0 as isize;
+        let y0 = y0 as isize;
+
+        match interpolation {
+            InterpolationKind::Nearest => {
+                self.velocity_at(pos.x.round() as isize, pos.y.round() as isize, boundary)
+            }
+            InterpolationKind::Bilinear => {
+                let bottom = self
+                    .velocity_at(x0, y0, boundary)
+                    .lerp(self.velocity_at(x0 + 1, y0, boundary), tx);
+                let top = self
+                    .velocity_at(x0, y0 + 1, boundary)
+                    .lerp(self.velocity_at(x0 + 1, y0 + 1, boundary), tx);
+                bottom.lerp(top, ty)
+            }
+            InterpolationKind::CatmullRom => {
+                let rows = [-1, 0, 1, 2].map(|j| {
+                    let row = [-1, 0, 1, 2].map(|i| self.velocity_at(x0 + i, y0 + j, boundary));
+                    catmull_rom(row[0], row[1], row[2], row[3], tx)
+                });
+                catmull_rom(rows[0], rows[1], rows[2], rows[3], ty)
+            }
+        }
+    }
+
+    /// Bilinearly interpolated density at a fractional cell position, see `sample_bilinear`
+    pub fn sample_density(&self, pos: Vec2, boundary: BoundaryMode) -> f32 {
+        self.sample_bilinear(pos, |cell| cell.density, boundary)
+    }
+
+    /// Cells of the bilinear interpolation at a fractional cell position, with the factors
+    /// the boundary multiplies their velocity by and their weights. The position is confined
+    /// by the boundary and floored, so the positions traced back past the left or bottom
+    /// edge get the right corners, ghost cells standing for the ones outside of the grid.
+    /// Obstacles hold nothing to interpolate, they weigh nothing and the other corners make
+    /// up for them, all four weighing nothing when they're all obstacles.
+    fn bilinear_cells(&self, pos: Vec2, boundary: BoundaryMode) -> [(usize, usize, Vec2, f32); 4] {
+        let (width, height) = (self.width(), self.height());
+        let pos = boundary.confine(pos, width, height);
+        let mut cells = bilinear_weights(pos).map(|(x, y, weight)| {
+            let (x, y, reflection) = boundary.ghost(x, y, width, height);
+            (x, y, reflection, weight)
+        });
+        let total: f32 = cells
+            .iter()
+            .filter(|&&(x, y, _, _)| !self.0[y][x].obstacle)
+            .map(|&(_, _, _, weight)| weight)
+            .sum();
+        for (x, y, _, weight) in cells.iter_mut() {
+            *weight = if self.0[*y][*x].obstacle || total <= 0.0 {
+                0.0
+            } else {
+                *weight / total
+            };
+        }
+        cells
+    }
+
+    /// Corners of the bilinear interpolation at a fractional cell position with their
+    /// weights, see `bilinear_cells`
+    fn bilinear_corners(&self, pos: Vec2, boundary: BoundaryMode) -> [(Cell, f32); 4] {
+        self.bilinear_cells(pos, boundary)
+            .map(|(x, y, reflection, weight)| {
+                let cell = &self.0[y][x];
+                let corner = Cell {
+                    velocity: cell.velocity * reflection,
+                    ..*cell
+                };
+                (corner, weight)
+            })
+    }
+
+    /// Bilinear interpolation of a field of the cells at a fractional cell position, the
+    /// boundary giving the cells outside of the grid. Zero when surrounded by obstacles.
+    pub fn sample_bilinear<T>(
+        &self,
+        pos: Vec2,
+        field: impl Fn(&Cell) -> T,
+        boundary: BoundaryMode,
+    ) -> T
+    where
+        T: Default + Add<Output = T> + Mul<f32, Output = T>,
+    {
+        self.bilinear_corners(pos, boundary)
+            .iter()
+            .filter(|(_, weight)| *weight > 0.0)
+            .fold(T::default(), |sum, (corner, weight)| {
+                sum + field(corner) * *weight
+            })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum InterpolationKind {
+    Nearest,
+    Bilinear,
+    CatmullRom,
 }
 
-fn setup(mut commands: Commands, mut materials: ResMut<Assets<ColorMaterial>>) {
-    // Camera
-    commands.spawn_bundle(OrthographicCameraBundle::new_2d());
+impl InterpolationKind {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Nearest => Self::Bilinear,
+            Self::Bilinear => Self::CatmullRom,
+            Self::CatmullRom => Self::Nearest,
+        }
+    }
+}
+
+/// Corners of the bilinear interpolation at a fractional position on a lattice with integer
+/// nodes, and their weights
+fn bilinear_weights(pos: Vec2) -> [(isize, isize, f32); 4] {
+    let (x0, y0) = (pos.x.floor(), pos.y.floor());
+    let (tx, ty) = (pos.x - x0, pos.y - y0);
+    let (x0, y0) = (x0 as isize, y0 as isize);
+    [
+        (x0, y0, (1.0 - tx) * (1.0 - ty)),
+        (x0 + 1, y0, tx * (1.0 - ty)),
+        (x0, y0 + 1, (1.0 - tx) * ty),
+        (x0 + 1, y0 + 1, tx * ty),
+    ]
+}
+
+/// Cubic interpolation between p1 and p2
+fn catmull_rom(p0: Vec2, p1: Vec2, p2: Vec2, p3: Vec2, t: f32) -> Vec2 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    0.5 * (2.0 * p1
+        + (p2 - p0) * t
+        + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2
+        + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t3)
+}
+
+/// Convert a fractional cell position to world coordinates, cell centers being on integers
+fn grid_to_world(pos: Vec2, width: usize, height: usize) -> Vec2 {
+    let half_cell = CELL_SIZE / 2.0;
+    let half_x = width as f32 * half_cell - half_cell;
+    let half_y = height as f32 * half_cell - half_cell;
+    Vec2::new(pos.x * CELL_SIZE - half_x, pos.y * CELL_SIZE - half_y)
+}
+
+/// Convert world coordinates to a fractional cell position, the inverse of `grid_to_world`
+fn world_to_grid(pos: Vec2, width: usize, height: usize) -> Vec2 {
+    (pos - grid_to_world(Vec2::ZERO, width, height)) / CELL_SIZE
+}
+
+fn camera_setup(mut commands: Commands) {
+    commands
+        .spawn_bundle(OrthographicCameraBundle::new_2d())
+        .insert(MainCamera);
     commands.spawn_bundle(UiCameraBundle::default());
+}
 
+fn setup(
+    mut commands: Commands,
+    selection: Res<SceneSelection>,
+    image_dye: Res<ImageDye>,
+    initial_field: Res<InitialField>,
+    mut errors: ResMut<ErrorLog>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
     // Grid
-    let mut grid = Grid::new();
-    for cell in &mut grid.0[4] {
-        cell.density = 20.0;
+    let (width, height) = selection.grid_size();
+    let mut grid = selection.scene().build(width, height);
+    if let Some(path) = &image_dye.0 {
+        match import::load_image_dye(path, width, height) {
+            Ok(dye) => import::apply_dye(&mut grid, &dye),
+            Err(err) => errors.report(format!("Couldn't load {}: {}", path.display(), err)),
+        }
+    }
+    if let Some(path) = &initial_field.0 {
+        match import::load_field(path) {
+            Ok(field) => import::apply_field(&mut grid, &field),
+            Err(err) => errors.report(format!("Couldn't load {}: {}", path.display(), err)),
+        }
     }
-    // grid.0[4][4].density = 20.0;
-    // grid.0[4][4].velocity.x = 20.0;
-    // grid.0[4][4].velocity.y = -20.0;
     commands.spawn().insert(grid);
 
-    let half_cell = CELL_SIZE / 2.0;
-    let half_x = WIDTH as f32 * half_cell - half_cell;
-    let half_y = HEIGHT as f32 * half_cell - half_cell;
-
-    for y in 0..HEIGHT {
-        for x in 0..WIDTH {
+    // Only the visible cells get a square, starting from the bottom left corner
+    let (columns, rows) = viewport::view_size(width, height);
+    for y in 0..rows {
+        for x in 0..columns {
             let v = 0.0;
             let cell_material = materials.add(Color::rgb(v, v, v).into());
-
-            let transform_x = x as f32 * CELL_SIZE - half_x;
-            let transform_y = y as f32 * CELL_SIZE - half_y;
+            let position = grid_to_world(Vec2::new(x as f32, y as f32), width, height);
 
             commands
                 .spawn_bundle(SpriteBundle {
                     material: cell_material,
-                    transform: Transform::from_xyz(transform_x, transform_y, 0.0),
+                    transform: Transform::from_translation(position.extend(0.0)),
                     sprite: Sprite::new(Vec2::new(CELL_SIZE, CELL_SIZE)),
                     ..Default::default()
                 })
                 .insert(DensitySquare)
-                .insert(Position { x, y });
+                .insert(OnLayer {
+                    layer: Layer::Density,
+                    offset: 0.0,
+                })
+                .insert(Position { x, y })
+                .insert(ViewSlot { x, y });
         }
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn arrows_setup(
     mut commands: Commands,
-    // mut materials: ResMut<Assets<ColorMaterial>>,
+    selection: Res<SceneSelection>,
+    support: Res<ShaderSupport>,
+    layers: Res<Layers>,
+    materials: ResMut<Assets<ColorMaterial>>,
+    textures: ResMut<Assets<Texture>>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut pipelines: ResMut<Assets<PipelineDescriptor>>,
     mut shaders: ResMut<Assets<Shader>>,
 ) {
     // Arrow
-    let pipeline_handle = pipelines.add(PipelineDescriptor::default_config(ShaderStages {
-        vertex: shaders.add(Shader::from_glsl(ShaderStage::Vertex, VERTEX_SHADER)),
-        fragment: Some(shaders.add(Shader::from_glsl(ShaderStage::Fragment, FRAGMENT_SHADER))),
-    }));
+    let pipeline_handle = match lines::vertex_color_pipeline(
+        &support,
+        &mut pipelines,
+        &mut shaders,
+        layers.blend(Layer::Arrows),
+    ) {
+        Some(pipeline) => pipeline,
+        None => {
+            sprite_arrows_setup(commands, selection, materials, textures);
+            return;
+        }
+    };
 
     let mut arrow = Mesh::new(bevy::render::pipeline::PrimitiveTopology::TriangleList);
 
@@ -192,17 +631,15 @@ pub fn arrows_setup(
     let render_pipelines =
         RenderPipelines::from_pipelines(vec![RenderPipeline::new(pipeline_handle)]);
 
-    let half_cell = CELL_SIZE / 2.0;
-    let half_x = WIDTH as f32 * half_cell - half_cell;
-    let half_y = HEIGHT as f32 * half_cell - half_cell;
+    let (width, height) = selection.grid_size();
+    let (columns, rows) = viewport::view_size(width, height);
 
-    for y in 0..HEIGHT {
-        for x in 0..WIDTH {
+    for y in 0..rows {
+        for x in 0..columns {
             // let arrow_material = materials.add(Color::hsl(0.0, 1.0, 0.5).into());
 
-            let transform_x = x as f32 * CELL_SIZE - half_x;
-            let transform_y = y as f32 * CELL_SIZE - half_y;
-            let translation = Vec3::new(transform_x, transform_y, 1.0);
+            let translation =
+                grid_to_world(Vec2::new(x as f32, y as f32), width, height).extend(1.0);
 
             commands
                 .spawn_bundle(MeshBundle {
@@ -215,189 +652,192 @@ pub fn arrows_setup(
                     },
                     ..Default::default()
                 })
-                .insert(VelocityArrow)
-                .insert(Position { x, y });
+                .insert(VelocityArrow {
+                    scale: CELL_SIZE / 15.0,
+                })
+                .insert(OnLayer {
+                    layer: Layer::Arrows,
+                    offset: 0.0,
+                })
+                .insert(Position { x, y })
+                .insert(ViewSlot { x, y });
         }
     }
 }
 
-fn window_startup_system(mut windows: ResMut<Windows>) {
-    let window = windows.get_primary_mut().unwrap();
-    let width = WIDTH as f32 * CELL_SIZE;
-    let height = HEIGHT as f32 * CELL_SIZE;
-    window.set_resolution(width, height);
-    window.set_title("Fluid Simulation".to_string());
-}
-
-fn diffusion_system(time: Res<Time>, mut qg: Query<&mut Grid>) {
-    if let Ok(mut grid) = qg.single_mut() {
-        let mut new_grid = grid.clone();
-        let k = 5.0 * time.delta_seconds();
-        for _ in 0..5 {
-            for y in 0..HEIGHT {
-                for x in 0..WIDTH {
-                    // d_n = (d_c + k*s_n) / (1 + k)
-                    let avg = new_grid.get_average(x, y, |cell| cell.density);
-                    new_grid.0[y][x].density = (grid.0[y][x].density + k * avg) / (1.0 + k);
+/// Pixels per arrow mesh unit in the sprite arrow texture
+const ARROW_TEXTURE_SCALE: usize = 2;
 
-                    let avg = new_grid.get_average(x, y, |cell| cell.velocity.x);
-                    new_grid.0[y][x].velocity.x = (grid.0[y][x].velocity.x + k * avg) / (1.0 + k);
-
-                    let avg = new_grid.get_average(x, y, |cell| cell.velocity.y);
-                    new_grid.0[y][x].velocity.y = (grid.0[y][x].velocity.y + k * avg) / (1.0 + k);
-                }
-            }
-        }
-        *grid = new_grid;
-    }
-}
-
-fn advection_system(time: Res<Time>, mut qg: Query<&mut Grid>) {
-    if let Ok(mut grid) = qg.single_mut() {
-        let mut new_grid = grid.clone();
-        let dt = time.delta_seconds();
-        for _ in 0..5 {
-            for y in 0..HEIGHT {
-                for x in 0..WIDTH {
-                    let pos = Vec2::new(x as f32, y as f32);
-                    let f = pos - new_grid.0[y][x].velocity * dt;
-                    let ix = f.x as usize;
-                    let iy = f.y as usize;
-                    let jx = f.x - ix as f32;
-                    let jy = f.y - iy as f32;
-
-                    let lerp = |a, b, k| a + k * (b - a);
-                    let z1 = lerp(
-                        new_grid.0[iy][ix].density,
-                        new_grid.0[iy][(ix + 1) % WIDTH].density,
-                        jx,
-                    );
-                    let z2 = lerp(
-                        new_grid.0[(iy + 1) % HEIGHT][ix].density,
-                        new_grid.0[iy][ix].density,
-                        jx,
-                    );
-
-                    new_grid.0[y][x].density = lerp(z1, z2, jy);
-                }
+/// The arrow mesh drawn into a texture, with its base at the center so it rotates the same way
+fn arrow_texture() -> Texture {
+    let (width, height) = (6 * ARROW_TEXTURE_SCALE, 32 * ARROW_TEXTURE_SCALE);
+    let mut data = vec![0; width * height * 4];
+    for row in 0..height {
+        for column in 0..width {
+            let x = (column as f32 + 0.5) / ARROW_TEXTURE_SCALE as f32 - 3.0;
+            let y = 16.0 - (row as f32 + 0.5) / ARROW_TEXTURE_SCALE as f32;
+            let head = (10.0..16.0).contains(&y) && x.abs() <= (16.0 - y) / 2.0;
+            let shaft = (0.0..10.0).contains(&y) && x.abs() <= 1.0;
+            if head || shaft {
+                let start = (row * width + column) * 4;
+                data[start..start + 4].copy_from_slice(&[255, 255, 255, 255]);
             }
         }
-        *grid = new_grid;
-    }
-}
-
-struct PField(Vec<Vec<f32>>);
-
-impl PField {
-    pub fn new() -> Self {
-        Self(vec![vec![0.0; WIDTH]; HEIGHT])
-    }
-
-    fn get_gradient(&self, x: usize, y: usize) -> Vec2 {
-        let x_plus = (x + 1) % WIDTH;
-        let x_minus = (x + WIDTH - 1) % WIDTH;
-        let y_plus = (y + 1) % HEIGHT;
-        let y_minus = (y + HEIGHT - 1) % HEIGHT;
-
-        let i = (self.0[y][x_plus] - self.0[y][x_minus]) / 2.0;
-        let j = (self.0[y_plus][x] - self.0[y_minus][x]) / 2.0;
-
-        Vec2::new(i, j)
     }
 
-    fn get_average(&self, x: usize, y: usize) -> f32 {
-        let x_plus = (x + 1) % WIDTH;
-        let x_minus = (x + WIDTH - 1) % WIDTH;
-        let y_plus = (y + 1) % HEIGHT;
-        let y_minus = (y + HEIGHT - 1) % HEIGHT;
+    Texture::new(
+        Extent3d::new(width as u32, height as u32, 1),
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    )
+}
 
-        let px1 = self.0[y][x_plus];
-        let px2 = self.0[y][x_minus];
-        let py1 = self.0[y_plus][x];
-        let py2 = self.0[y_minus][x];
+/// Arrows as sprites tinted by their material, when the vertex color shaders are unavailable
+fn sprite_arrows_setup(
+    mut commands: Commands,
+    selection: Res<SceneSelection>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut textures: ResMut<Assets<Texture>>,
+) {
+    let texture = textures.add(arrow_texture());
+    let (width, height) = selection.grid_size();
+    let (columns, rows) = viewport::view_size(width, height);
 
-        let p = (px1 + px2 + py1 + py2) / 4.0;
-        p
-    }
-}
+    for y in 0..rows {
+        for x in 0..columns {
+            let translation =
+                grid_to_world(Vec2::new(x as f32, y as f32), width, height).extend(1.0);
 
-fn create_velocity_gradient_quarter_field(grid: &Grid) -> Vec<Vec<f32>> {
-    let mut vel_grad_field = Vec::with_capacity(HEIGHT);
-    for y in 0..HEIGHT {
-        let mut row = Vec::with_capacity(WIDTH);
-        for x in 0..WIDTH {
-            let vel_grad = grid.get_velocity_gradient(x, y) / 4.0;
-            row.push(vel_grad);
+            commands
+                .spawn_bundle(SpriteBundle {
+                    material: materials.add(ColorMaterial::modulated_texture(
+                        texture.clone(),
+                        Color::YELLOW,
+                    )),
+                    transform: Transform {
+                        translation,
+                        scale: Vec3::ONE * CELL_SIZE / 15.0 / ARROW_TEXTURE_SCALE as f32,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .insert(VelocityArrow {
+                    scale: CELL_SIZE / 15.0 / ARROW_TEXTURE_SCALE as f32,
+                })
+                .insert(OnLayer {
+                    layer: Layer::Arrows,
+                    offset: 0.0,
+                })
+                .insert(Position { x, y })
+                .insert(ViewSlot { x, y });
         }
-        vel_grad_field.push(row);
     }
-
-    vel_grad_field
 }
 
-fn clear_divergence_system(mut qg: Query<&mut Grid>) {
-    if let Ok(mut grid) = qg.single_mut() {
-        let mut p = PField::new();
-        // vel_grad_field_quarter contains the value of the velocity gradient divided by 4
-        let vel_grad_field_quarter = create_velocity_gradient_quarter_field(&grid);
-
-        for _ in 0..5 {
-            for y in 0..HEIGHT {
-                for x in 0..WIDTH {
-                    p.0[y][x] = p.get_average(x, y) - vel_grad_field_quarter[y][x];
-                }
-            }
-        }
+fn window_startup_system(mut windows: ResMut<Windows>) {
+    let window = match windows.get_primary_mut() {
+        Some(window) => window,
+        None => return,
+    };
+    let width = WIDTH as f32 * CELL_SIZE;
+    let height = HEIGHT as f32 * CELL_SIZE;
+    window.set_resolution(width, height);
+    window.set_title("Fluid Simulation".to_string());
+}
 
-        // Substracting the curl-free vector field from the original field
-        // to get a divergence-free field
-        for y in 0..HEIGHT {
-            for x in 0..WIDTH {
-                let grad_p = p.get_gradient(x, y);
-                grid.0[y][x].velocity -= grad_p;
-            }
-        }
-    }
+/// Fit the window to the grid picked in the menu, up to the largest view
+fn window_resize_system(selection: Res<SceneSelection>, mut windows: ResMut<Windows>) {
+    let window = match windows.get_primary_mut() {
+        Some(window) => window,
+        None => return,
+    };
+    let (width, height) = selection.grid_size();
+    let (columns, rows) = viewport::view_size(width, height);
+    window.set_resolution(columns as f32 * CELL_SIZE, rows as f32 * CELL_SIZE);
+    window.set_title(format!("Fluid Simulation - {}", selection.scene().name()));
 }
 
 /// Display the grid density values as squares
 fn density_square_system(
     qg: Query<&Grid>,
+    palette: Res<Palette>,
+    layers: Res<Layers>,
+    post_effects: Res<PostEffects>,
+    mut errors: ResMut<ErrorLog>,
     mut materials: ResMut<Assets<ColorMaterial>>,
     mut query: Query<(&DensitySquare, &Position, &mut Handle<ColorMaterial>)>,
 ) {
     if let Ok(grid) = qg.single() {
+        let frame = post::compose(grid, &palette, &layers, &post_effects.0);
         for (_density_square, position, color) in query.iter_mut() {
-            let color_mat = materials.get_mut(&*color).unwrap();
             let Position { x, y } = position;
-            let v = grid.0[*y][*x].density;
-            color_mat.color = Color::rgb(v, v, v);
+            let c = frame[*y][*x];
+            match materials.get_mut(&*color) {
+                Some(color_mat) => color_mat.color = Color::rgb(c.x, c.y, c.z),
+                None => errors.report("Missing material of a density square"),
+            }
         }
     }
 }
 
+/// Speed of the reddest and, with speed glyphs, longest arrows
+const ARROW_MAX_SPEED: f32 = 0.1;
+
 //// Display the velocity of each cell as colored arrows
 fn velocity_arrow_direction_system(
     qg: Query<&Grid>,
+    accessibility: Res<Accessibility>,
     mut query: Query<(&VelocityArrow, &Position, &mut Transform)>,
 ) {
     if let Ok(grid) = qg.single() {
-        for (_velocity_arrow, position, mut transform) in query.iter_mut() {
-            let rotation = &mut transform.rotation;
-
+        for (velocity_arrow, position, mut transform) in query.iter_mut() {
             let Position { x, y } = position;
             let vel: Vec2 = grid.0[*y][*x].velocity;
 
             let angle = vel.angle_between(Vec2::Y);
-            *rotation = Quat::from_rotation_z(angle + PI);
+            transform.rotation = Quat::from_rotation_z(angle + PI);
             // println!("{:?} {:?}", vel, rotation);
+
+            transform.scale = Vec3::splat(velocity_arrow.scale * arrow_length(vel, &accessibility));
         }
         // println!("{:?}", grid.0[0][0].velocity);
     }
 }
 
+/// Length of an arrow from 0 to 1. With speed glyphs the length shows the speed instead
+/// of the hue, a small arrow still showing the direction.
+fn arrow_length(velocity: Vec2, accessibility: &Accessibility) -> f32 {
+    if accessibility.speed_glyphs {
+        0.2 + 0.8 * (velocity.length() / ARROW_MAX_SPEED).min(1.0)
+    } else {
+        1.0
+    }
+}
+
+/// Color of the arrow of a cell, faded into the gray of its smoke by the arrow occlusion
+fn arrow_color(cell: &Cell, accessibility: &Accessibility) -> Color {
+    let color = if accessibility.speed_glyphs {
+        Color::WHITE
+    } else {
+        let len = cell.velocity.length();
+        // Hue goes from 180 to 9
+        let hue = 180.0 - len.min(ARROW_MAX_SPEED) * 180.0 / ARROW_MAX_SPEED;
+        Color::hsl(hue, 1.0, 0.5)
+    };
+    let density = cell.density.clamp(0.0, 1.0);
+    let [r, g, b, _] = color.as_rgba_f32();
+    let faded = Vec3::new(r, g, b).lerp(
+        Vec3::splat(density),
+        accessibility.arrow_occlusion * density,
+    );
+    Color::rgb(faded.x, faded.y, faded.z)
+}
+
 fn velocity_arrow_color_system(
     qg: Query<&Grid>,
+    accessibility: Res<Accessibility>,
+    mut errors: ResMut<ErrorLog>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut query: Query<(&VelocityArrow, &Position, &mut Handle<Mesh>)>,
 ) {
@@ -405,38 +845,120 @@ fn velocity_arrow_color_system(
         for (_velocity_arrow, position, mesh_handle) in query.iter_mut() {
             // println!("{:?} {:?}", position, mesh_handle);
             let Position { x, y } = position;
-            let len = grid.0[*y][*x].velocity.length();
-            // Hue goes from 180 to 9
-            let len_max_value = 0.1;
-            let hue = 180.0 - len.min(len_max_value) * 180.0 / len_max_value;
+            let [r, g, b, _] = arrow_color(&grid.0[*y][*x], &accessibility).as_rgba_f32();
+            match meshes.get_mut(&*mesh_handle) {
+                Some(mesh) => mesh.set_attribute(Mesh::ATTRIBUTE_COLOR, vec![[r, g, b]; 7]),
+                None => errors.report("Missing mesh of a velocity arrow"),
+            }
+        }
+    }
+}
+
+/// Same colors as the mesh arrows, through the material of the sprite arrows
+fn sprite_arrow_color_system(
+    qg: Query<&Grid>,
+    accessibility: Res<Accessibility>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    query: Query<(&Position, &Handle<ColorMaterial>), With<VelocityArrow>>,
+) {
+    if let Ok(grid) = qg.single() {
+        for (Position { x, y }, material) in query.iter() {
+            if let Some(material) = materials.get_mut(material) {
+                material.color = arrow_color(&grid.0[*y][*x], &accessibility);
+            }
+        }
+    }
+}
+
+/// Where the cursor last pushed the fluid, and the smoothed force it pushed with
+#[derive(Default)]
+struct CursorTrail {
+    /// In grid coordinates, None once the cursor left the grid or stopped for a while
+    last: Option<Vec2>,
+    force: Vec2,
+    last_seconds: f64,
+}
 
-            let [r, g, b, _] = Color::hsl(hue, 1.0, 0.5).as_rgba_f32();
-            let mesh = meshes.get_mut(&*mesh_handle).unwrap();
-            mesh.set_attribute(Mesh::ATTRIBUTE_COLOR, vec![[r, g, b]; 7]);
+/// Weight of the newest mouse motion in the moving average of the force
+const TRAIL_SMOOTHING: f32 = 0.4;
+/// Seconds without motion after which the trail starts over
+const TRAIL_TIMEOUT: f64 = 0.1;
+
+/// Cells along a segment in grid coordinates, in order and without repeats
+fn trail_cells(from: Vec2, to: Vec2, width: usize, height: usize) -> Vec<(usize, usize)> {
+    let steps = ((to - from).abs().max_element() * 2.0).ceil().max(1.0) as usize;
+    let mut cells = Vec::new();
+    for i in 0..=steps {
+        let pos = from.lerp(to, i as f32 / steps as f32) + Vec2::splat(0.5);
+        if pos.x < 0.0 || pos.y < 0.0 {
+            continue;
+        }
+        let cell = (pos.x as usize, pos.y as usize);
+        if cell.0 < width && cell.1 < height && cells.last() != Some(&cell) {
+            cells.push(cell);
         }
     }
+    cells
 }
 
-/// https://github.com/bevyengine/bevy/blob/main/crates/bevy_window/src/event.rs
-///
-/// This system prints out all mouse events as they come in
+/// Push the fluid along the cursor path, with the force smoothed over the mouse events and
+/// spread over the cells crossed since the last one, so fast flicks leave a streak
+#[allow(clippy::too_many_arguments)]
 fn mouse_events_system(
-    mut qg: Query<&mut Grid>,
+    time: Res<Time>,
+    qg: Query<&Grid>,
+    symmetry: Res<Symmetry>,
+    viewport: Res<Viewport>,
+    mut splats: ResMut<Splats>,
+    mut trail: Local<CursorTrail>,
     mut mouse_motion_events: EventReader<MouseMotion>,
     mut cursor_moved_events: EventReader<CursorMoved>,
     // mut window_resized_events: EventReader<WindowResized>,
 ) {
-    if let Ok(mut grid) = qg.single_mut() {
-        for (mouse_event, cursor_event) in
-            mouse_motion_events.iter().zip(cursor_moved_events.iter())
-        {
-            // info!("{:?} {:?}", mouse_event.delta, cursor_event.position);
-
-            let x = (cursor_event.position.x / CELL_SIZE) as usize;
-            let y = (cursor_event.position.y / CELL_SIZE) as usize;
-            if x < WIDTH && y < HEIGHT {
-                grid.0[y][x].velocity += 0.1 * mouse_event.delta;
+    let grid = match qg.single() {
+        Ok(grid) => grid,
+        Err(_) => return,
+    };
+    let (width, height) = (grid.width(), grid.height());
+    let now = time.seconds_since_startup();
+    if now - trail.last_seconds > TRAIL_TIMEOUT {
+        trail.last = None;
+    }
+
+    for (mouse_event, cursor_event) in mouse_motion_events.iter().zip(cursor_moved_events.iter()) {
+        // info!("{:?} {:?}", mouse_event.delta, cursor_event.position);
+        trail.last_seconds = now;
+        if viewport.cursor_cell(cursor_event.position, grid).is_none() {
+            trail.last = None;
+            continue;
+        }
+
+        let force = 0.1 * mouse_event.delta;
+        let pos = viewport.cursor_to_grid(cursor_event.position);
+        let from = match trail.last {
+            Some(last) => {
+                trail.force = trail.force.lerp(force, TRAIL_SMOOTHING);
+                last
+            }
+            None => {
+                trail.force = force;
+                pos
             }
+        };
+        trail.last = Some(pos);
+
+        let cells = trail_cells(from, pos, width, height);
+        let velocity = trail.force / cells.len() as f32;
+        for (x, y) in cells {
+            let splat = Splat {
+                x,
+                y,
+                velocity,
+                density: 0.0,
+                temperature: 0.0,
+                species: None,
+            };
+            splats.0.extend(symmetry.expand(splat, width, height));
         }
     }
     // for event in cursor_moved_events.iter() {
@@ -447,6 +969,52 @@ fn mouse_events_system(
     // }
 }
 
+/// Inject dye under the cursor while the left mouse button is held, along the whole
+/// path since the last frame so fast strokes have no gaps
+#[allow(clippy::too_many_arguments)]
+fn dye_brush_system(
+    time: Res<Time>,
+    windows: Res<Windows>,
+    mouse_button_input: Res<Input<MouseButton>>,
+    qg: Query<&Grid>,
+    symmetry: Res<Symmetry>,
+    viewport: Res<Viewport>,
+    brush: Res<SpeciesBrush>,
+    mut splats: ResMut<Splats>,
+    // Cursor in grid coordinates the previous frame, to paint the cells in between
+    mut last: Local<Option<Vec2>>,
+) {
+    if !mouse_button_input.pressed(MouseButton::Left) {
+        *last = None;
+        return;
+    }
+
+    let cursor = windows.get_primary().and_then(|w| w.cursor_position());
+    if let (Some(cursor), Ok(grid)) = (cursor, qg.single()) {
+        if viewport.cursor_cell(cursor, grid).is_none() {
+            *last = None;
+            return;
+        }
+
+        let pos = viewport.cursor_to_grid(cursor);
+        let (width, height) = (grid.width(), grid.height());
+        let density = 20.0 * time.delta_seconds();
+        for (x, y) in trail_cells(last.unwrap_or(pos), pos, width, height) {
+            // The brush injects hot smoke, rising with the buoyancy
+            let splat = Splat {
+                x,
+                y,
+                velocity: Vec2::ZERO,
+                density,
+                temperature: density,
+                species: brush.0,
+            };
+            splats.0.extend(symmetry.expand(splat, width, height));
+        }
+        *last = Some(pos);
+    }
+}
+
 /// https://github.com/bevyengine/bevy/blob/main/examples/input/char_input_events.rs
 fn char_event_system(
     mut qg: Query<&mut Grid>,
@@ -455,27 +1023,330 @@ fn char_event_system(
     for event in char_input_events.iter() {
         if event.char == 'r' {
             if let Ok(mut grid) = qg.single_mut() {
-                *grid = Grid::new();
+                *grid = Grid::new(grid.width(), grid.height());
             }
         }
     }
 }
 
 fn main() {
-    App::build()
+    let args = cli::Args::parse();
+    let preset = args.preset.unwrap_or_default();
+    let mut settings = SolverSettings {
+        backend: backend::select(args.backend, args.threads),
+        diffusion_tolerance: args.diffusion_tolerance.unwrap_or(0.0),
+        flip: args.flip,
+        pressure_solver: args.pressure_solver.unwrap_or_default(),
+        boundary: args.boundary.unwrap_or_default(),
+        external: ExternalForces {
+            body: args.force.map_or(Vec2::ZERO, |force| {
+                Vec2::new(args.units.cells(force.x), args.units.cells(force.y))
+            }),
+            field: args.force_field,
+        },
+        precision: if args.half_precision {
+            Precision::Half
+        } else {
+            Precision::Full
+        },
+        ..preset.settings()
+    };
+    // The scheme and the vorticity given on the command line override the preset's
+    if let Some(vorticity) = args.vorticity {
+        settings.vorticity = vorticity;
+    }
+    if let Some(advection) = args.advection {
+        settings.advection = advection;
+    }
+    if let Some(backtrace) = args.backtrace {
+        settings.backtrace = backtrace;
+    }
+    let mut material = args.material.unwrap_or_default();
+    material.apply(&mut settings);
+    args.units.apply_viscosity(&mut settings);
+    if let Some(iterations) = args.diffusion_iterations {
+        settings.diffusion_iterations = iterations;
+    }
+    if let Some(conductivity) = args.conductivity {
+        settings.conductivity = conductivity;
+    }
+    if let Some(density) = args.density_dissipation {
+        settings.dissipation.density = density;
+    }
+    if let Some(velocity) = args.velocity_dissipation {
+        settings.dissipation.velocity = velocity;
+    }
+    let budget =
+        memory::MemoryBudget::from_mb(args.memory_budget.unwrap_or(memory::DEFAULT_BUDGET_MB));
+
+    if args.worker {
+        if let Some(scene) = &args.decompose {
+            if let Err(err) = decompose::worker(scene, &settings) {
+                eprintln!("Worker of {} failed: {}", scene.display(), err);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Some(scene) = &args.render {
+        let frames = args.frames.unwrap_or(600);
+        let out = args.out.clone().unwrap_or_else(|| "frames".into());
+        match render::render_scene(scene, frames, &out, &settings, &budget) {
+            Ok(()) => println!("Rendered {} frames to {}", frames, out.display()),
+            Err(err) => {
+                eprintln!("Couldn't render {}: {}", scene.display(), err);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if args.self_test {
+        if let Err(err) = selftest::run(&settings) {
+            eprintln!("Self-test failed: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(sizes) = &args.bench_grid {
+        if let Err(err) = bench::run(sizes, args.steps.unwrap_or(100), &settings, &budget) {
+            eprintln!("Couldn't run the benchmark: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(scene) = &args.poster {
+        let options = poster::PosterOptions {
+            grid_size: args.grid,
+            steps: args.steps.unwrap_or(300),
+            scale: args.scale.unwrap_or(16),
+        };
+        let out = args.out.clone().unwrap_or_else(|| "poster.png".into());
+        match poster::render_poster(scene, &out, &options, &settings, &budget) {
+            Ok(()) => println!("Rendered poster to {}", out.display()),
+            Err(err) => {
+                eprintln!("Couldn't render {}: {}", scene.display(), err);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Some(scene) = &args.pod {
+        let options = pod::PodOptions {
+            grid_size: args.grid,
+            snapshots: args.snapshots.unwrap_or(64),
+            interval: args.steps.unwrap_or(5),
+            modes: args.modes.unwrap_or(6),
+        };
+        let out = args.out.clone().unwrap_or_else(|| "pod".into());
+        match pod::run(scene, &out, &options, &settings, &budget) {
+            Ok(fractions) => {
+                println!("Wrote the POD modes to {}", out.display());
+                for (i, fraction) in fractions.iter().take(options.modes).enumerate() {
+                    println!("mode {}: {:.1}% of the energy", i + 1, fraction * 100.0);
+                }
+            }
+            Err(err) => {
+                eprintln!("Couldn't decompose {}: {}", scene.display(), err);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Some(scene) = &args.decompose {
+        let workers = args.workers.unwrap_or(2);
+        let steps = args.steps.unwrap_or(300);
+        let out = args.out.clone().unwrap_or_else(|| "decomposed.png".into());
+        match decompose::run(scene, workers, steps, &out, &settings, &budget) {
+            Ok(elapsed) => println!(
+                "Stepped {} on {} workers at {:.1} steps/s, last frame in {}",
+                scene.display(),
+                workers,
+                steps as f64 / elapsed.as_secs_f64(),
+                out.display()
+            ),
+            Err(err) => {
+                eprintln!("Couldn't decompose {}: {}", scene.display(), err);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Some(path) = &args.batch {
+        match batch::run(path, &settings, &budget) {
+            Ok(report) => {
+                println!(
+                    "{} jobs succeeded and {} failed, summary in {}",
+                    report.succeeded,
+                    report.failed,
+                    report.summary.display()
+                );
+                if report.failed > 0 {
+                    std::process::exit(1);
+                }
+            }
+            Err(err) => {
+                eprintln!("Couldn't run the batch {}: {}", path.display(), err);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Some(configs) = &args.compare {
+        if let Err(err) = compare::run(configs, &settings, &budget) {
+            eprintln!("Couldn't compare the configurations: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let user_prefs = prefs::UserPrefs::load();
+    // A broken scene file shows an error and starts like without it
+    let mut errors = ErrorLog::default();
+    let scene_file = args.scene.as_ref().and_then(|path| {
+        SceneFile::load(path)
+            .map_err(|err| errors.report(format!("Couldn't load {}: {}", path.display(), err)))
+            .ok()
+    });
+    if let Some(file_material) = scene_file.as_ref().and_then(|file| file.material) {
+        material = file_material;
+        material.apply(&mut settings);
+        args.units.apply_viscosity(&mut settings);
+    }
+    let phase_period = args
+        .phase_period
+        .or_else(|| scene_file.as_ref().and_then(SceneFile::forcing_period));
+    let (selection, post_effects, layers, scene_emitters, scene_fans, weather, inflows) =
+        match scene_file {
+            Some(file) => (
+                SceneSelection::with_scene(file.scene, file.grid_size()),
+                PostEffects(file.post_effects),
+                Layers::from_styles(&file.layers),
+                emitters::SceneEmitters(file.emitters),
+                fans::SceneFans(file.fans),
+                weather::Weather::new(file.wind),
+                inflow::Inflows::new(file.inflows),
+            ),
+            None => (
+                SceneSelection::with_scene(user_prefs.scene, user_prefs.grid_size),
+                PostEffects::default(),
+                Layers::default(),
+                emitters::SceneEmitters::default(),
+                fans::SceneFans::default(),
+                weather::Weather::default(),
+                inflow::Inflows::default(),
+            ),
+        };
+
+    let mut app = App::build();
+    app
         // .insert_resource(ClearColor(Color::rgb(0.4, 0.4, 0.4)))
+        .insert_resource(preset)
+        .insert_resource(settings)
+        .insert_resource(material)
+        .insert_resource(args.units)
+        .insert_resource(budget)
+        .insert_resource(stepping::AllocationCheck(args.assert_no_alloc))
+        .insert_resource(match args.threads {
+            Some(threads) => DefaultTaskPoolOptions::with_num_threads(threads),
+            None => DefaultTaskPoolOptions::default(),
+        })
+        .insert_resource(selection)
+        .insert_resource(user_prefs)
+        .insert_resource(post_effects)
+        .insert_resource(layers)
+        .insert_resource(scene_emitters)
+        .insert_resource(scene_fans)
+        .insert_resource(weather)
+        .insert_resource(inflows)
+        .insert_resource(phase::PhaseAverage::new(phase_period))
+        .insert_resource(Splats::default())
+        .insert_resource(ImageDye(args.image))
+        .insert_resource(InitialField(args.field))
+        .insert_resource(ShaderSupport::check())
+        .insert_resource(errors)
         .add_plugins(DefaultPlugins)
-        .add_startup_system(setup.system())
+        .add_plugin(bevy_egui::EguiPlugin)
+        .add_state(AppState::Menu)
+        .add_plugin(errors::ErrorPanelPlugin)
+        .add_plugin(jobs::JobsPlugin)
+        .add_plugin(menu::MenuPlugin)
+        .add_plugin(widget::FluidWidgetPlugin)
+        .add_plugin(stepping::SteppingPlugin)
+        .add_plugin(tracers::TracerPlugin)
+        .add_plugin(ftle::FtlePlugin)
+        .add_plugin(phase::PhasePlugin)
+        .add_plugin(average::AveragePlugin)
+        .add_plugin(stream::StreamPlugin)
+        .add_plugin(courant::CourantPlugin)
+        .add_plugin(quiver::QuiverPlugin)
+        .add_plugin(piv::PivPlugin)
+        .add_plugin(sph::SphPlugin)
+        .add_plugin(boundary::BoundaryPlugin)
+        .add_plugin(obstacles::ObstaclePlugin)
+        .add_plugin(emitters::EmitterPlugin)
+        .add_plugin(fans::FanPlugin)
+        .add_plugin(divergence::DivergencePlugin)
+        .add_plugin(region::RegionPlugin)
+        .add_plugin(symmetry::SymmetryPlugin)
+        .add_plugin(species::SpeciesPlugin)
+        .add_plugin(viewport::ViewportPlugin)
+        .add_plugin(file_drop::FileDropPlugin)
+        .add_plugin(prefs::PrefsPlugin)
+        .add_plugin(memory::MemoryPlugin)
+        .add_plugin(snapshot::SnapshotPlugin)
+        .add_plugin(history::HistoryPlugin)
+        .add_plugin(export::ExportPlugin)
+        .add_plugin(sweep::SweepPlugin)
+        .add_plugin(layers::LayersPlugin)
+        .add_plugin(offscreen::OffscreenPlugin)
+        .add_plugin(accessibility::AccessibilityPlugin)
+        .add_plugin(status::StatusPlugin {
+            interval: args.status,
+        })
+        .add_plugin(fluid::FluidPlugin)
+        .add_plugin(palette::PalettePlugin)
+        .add_plugin(stamp::StampPlugin { text: args.text })
+        .add_plugin(stylus::StylusPlugin)
+        .add_plugin(gestures::GesturePlugin)
+        .add_plugin(steering::SteeringPlugin {
+            target: args.target,
+        })
+        .add_plugin(tutorial::TutorialPlugin {
+            enabled: args.tutorial,
+        })
+        .add_startup_system(camera_setup.system())
         .add_startup_system(window_startup_system.system())
-        .add_startup_system(arrows_setup.system())
+        .add_startup_system(lines::shader_support_warning_system.system())
+        .add_system_set(
+            SystemSet::on_enter(AppState::Running)
+                .with_system(setup.system())
+                .with_system(arrows_setup.system())
+                .with_system(window_resize_system.system()),
+        )
         // .add_system(testing_system.system())
-        .add_system(diffusion_system.system())
-        .add_system(advection_system.system())
-        .add_system(clear_divergence_system.system())
         .add_system(velocity_arrow_direction_system.system())
         .add_system(velocity_arrow_color_system.system())
+        .add_system(sprite_arrow_color_system.system())
         .add_system(density_square_system.system())
         .add_system(mouse_events_system.system())
+        .add_system(dye_brush_system.system())
         .add_system(char_event_system.system())
-        .run();
+        .add_system(settings::preset_keys_system.system())
+        .add_system(settings::material_keys_system.system())
+        .add_system(settings::advection_keys_system.system())
+        .add_system(import::clipboard_paste_system.system());
+
+    if args.control_window {
+        app.add_plugin(control::ControlWindowPlugin);
+    }
+
+    app.run();
 }
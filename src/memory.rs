@@ -0,0 +1,137 @@
+use std::mem::size_of;
+
+use bevy::prelude::*;
+
+use crate::average::TimeAverage;
+use crate::export::{FlowAverage, GifBuffer};
+use crate::ftle::Ftle;
+use crate::history::History;
+use crate::phase::PhaseAverage;
+use crate::piv::Piv;
+use crate::region::RegionTool;
+use crate::solver::Scratch;
+use crate::sph::Sph;
+use crate::tracers::Tracers;
+use crate::{Cell, Grid};
+
+// Memory footprint of the simulation: estimated before creating a grid, to refuse the
+// ones that wouldn't fit the budget, and measured while running for the control panel.
+
+/// Default budget, in megabytes
+pub const DEFAULT_BUDGET_MB: usize = 1024;
+const MB: f32 = 1024.0 * 1024.0;
+
+pub struct MemoryPlugin;
+
+impl Plugin for MemoryPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(MemoryUsage::default())
+            .add_system(memory_usage_system.system());
+    }
+}
+
+/// Most bytes the simulation should use
+pub struct MemoryBudget(pub usize);
+
+impl MemoryBudget {
+    pub fn from_mb(mb: usize) -> Self {
+        Self(mb * 1024 * 1024)
+    }
+}
+
+/// Measured memory, in bytes
+#[derive(Default)]
+pub struct MemoryUsage {
+    pub grid: usize,
+    /// Tracers, FTLE flow map, copied region and FLIP particles
+    pub buffers: usize,
+    over_budget: bool,
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> usize {
+        self.grid + self.buffers
+    }
+}
+
+pub fn grid_bytes(width: usize, height: usize) -> usize {
+    width * height * size_of::<Cell>() + height * size_of::<Vec<Cell>>()
+}
+
+/// Peak memory of a simulation on a grid: the grid, the copy the solver stages work on and
+/// the one of the MacCormack and BFECC advection, the pressure and divergence fields of the
+/// projection and the four of its conjugate gradient or multigrid, the faces the solver
+/// works on with the two copies of the diffusion and the advection, the expansion of the
+/// burning fuel, and the FTLE buffers
+pub fn estimate(width: usize, height: usize) -> usize {
+    let cells = width * height;
+    3 * grid_bytes(width, height)
+        + cells * (9 * size_of::<f32>() + 3 * size_of::<Vec2>() + size_of::<f32>())
+}
+
+/// Refuse grids whose estimated memory exceeds the budget
+pub fn check(width: usize, height: usize, budget: &MemoryBudget) -> Result<(), String> {
+    let needed = estimate(width, height);
+    if needed > budget.0 {
+        Err(format!(
+            "a {}x{} grid needs about {:.1} MB, over the {:.1} MB memory budget",
+            width,
+            height,
+            needed as f32 / MB,
+            budget.0 as f32 / MB
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+pub fn format_mb(bytes: usize) -> String {
+    format!("{:.1} MB", bytes as f32 / MB)
+}
+
+fn memory_usage_system(
+    budget: Res<MemoryBudget>,
+    tracers: Res<Tracers>,
+    ftle: Res<Ftle>,
+    region: Res<RegionTool>,
+    gif: Res<GifBuffer>,
+    history: Res<History>,
+    flow_average: Res<FlowAverage>,
+    phase: Res<PhaseAverage>,
+    time_average: Res<TimeAverage>,
+    piv: Res<Piv>,
+    sph: Res<Sph>,
+    scratch: Res<Scratch>,
+    mut usage: ResMut<MemoryUsage>,
+    qg: Query<&Grid>,
+) {
+    let grid = qg
+        .single()
+        .map_or(0, |grid| grid_bytes(grid.width(), grid.height()));
+    let buffers = tracers.memory_bytes()
+        + ftle.memory_bytes()
+        + region.memory_bytes()
+        + gif.memory_bytes()
+        + history.memory_bytes()
+        + flow_average.memory_bytes()
+        + phase.memory_bytes()
+        + time_average.memory_bytes()
+        + piv.memory_bytes()
+        + sph.memory_bytes()
+        + scratch.particles().memory_bytes();
+    if grid == usage.grid && buffers == usage.buffers {
+        return;
+    }
+    usage.grid = grid;
+    usage.buffers = buffers;
+
+    let over_budget = usage.total() > budget.0;
+    if over_budget && !usage.over_budget {
+        warn!(
+            "Using {}, over the {} memory budget",
+            format_mb(usage.total()),
+            format_mb(budget.0)
+        );
+    }
+    usage.over_budget = over_budget;
+}
@@ -0,0 +1,248 @@
+use bevy::prelude::*;
+use bevy::render::texture::{Extent3d, TextureDimension, TextureFormat};
+use bevy_egui::{egui, EguiContext};
+
+use crate::errors::ErrorLog;
+use crate::memory::{self, MemoryBudget};
+use crate::scenes::{ScenePreset, SceneSelection, GRID_SIZES};
+use crate::widget::{FluidWidget, FluidWidgetBundle};
+use crate::{AppState, Grid};
+
+// Start menu: an egui window with rows of scene thumbnails, the selected one highlighted,
+// the grid sizes and a start button. The keys work too: Left/Right picks the scene,
+// Up/Down the grid size and Enter starts the simulation. A fluid widget in the corner
+// previews the selected scene running.
+
+const THUMBNAIL_SIZE: f32 = 120.0;
+const THUMBNAILS_PER_ROW: usize = 4;
+/// Resolution of the grids rendered in the thumbnails
+const THUMBNAIL_GRID: usize = 40;
+const PREVIEW_SIZE: f32 = 120.0;
+const PREVIEW_SECONDS: f32 = 6.0;
+
+pub struct MenuPlugin;
+
+impl Plugin for MenuPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system_set(SystemSet::on_enter(AppState::Menu).with_system(menu_setup.system()))
+            .add_system_set(
+                SystemSet::on_update(AppState::Menu)
+                    .with_system(menu_input_system.system())
+                    .with_system(menu_ui_system.system())
+                    .with_system(menu_preview_system.system())
+                    .with_system(menu_title_system.system()),
+            )
+            .add_system_set(
+                SystemSet::on_exit(AppState::Menu).with_system(menu_cleanup_system.system()),
+            );
+    }
+}
+
+/// Everything spawned by the menu, despawned when leaving it
+struct MenuItem;
+
+/// Egui texture of the thumbnail of a scene, numbered like the scenes
+fn thumbnail_id(index: usize) -> u64 {
+    index as u64
+}
+
+/// Render the density of a grid, with the speed in the blue channel so flows without dye show up
+fn thumbnail(grid: &Grid) -> Texture {
+    let (width, height) = (grid.width(), grid.height());
+    let max_speed = grid
+        .0
+        .iter()
+        .flatten()
+        .map(|cell| cell.velocity.length())
+        .fold(0.0, f32::max);
+
+    let mut data = Vec::with_capacity(width * height * 4);
+    // Textures rows go from top to bottom
+    for row in grid.0.iter().rev() {
+        for cell in row {
+            let v = cell.density.min(1.0).max(0.0);
+            let speed = if max_speed > 0.0 {
+                0.5 * cell.velocity.length() / max_speed
+            } else {
+                0.0
+            };
+            let to_byte = |c: f32| (c * 255.0) as u8;
+            data.extend_from_slice(&[to_byte(v), to_byte(v), to_byte(v.max(speed)), 255]);
+        }
+    }
+
+    Texture::new(
+        Extent3d::new(width as u32, height as u32, 1),
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    )
+}
+
+fn menu_setup(
+    mut commands: Commands,
+    selection: Res<SceneSelection>,
+    mut egui_context: ResMut<EguiContext>,
+    mut textures: ResMut<Assets<Texture>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    for (i, scene) in ScenePreset::ALL.iter().enumerate() {
+        let texture = textures.add(thumbnail(&scene.build(THUMBNAIL_GRID, THUMBNAIL_GRID)));
+        egui_context.set_egui_texture(thumbnail_id(i), texture);
+    }
+
+    let mut preview = FluidWidget::new(selection.scene(), THUMBNAIL_GRID, THUMBNAIL_GRID);
+    preview.restart_every = Some(PREVIEW_SECONDS);
+    let mut bundle = FluidWidgetBundle::new(
+        preview,
+        Size::new(Val::Px(PREVIEW_SIZE), Val::Px(PREVIEW_SIZE)),
+        &mut textures,
+        &mut materials,
+    );
+    bundle.image.style.position_type = PositionType::Absolute;
+    bundle.image.style.position = Rect {
+        right: Val::Px(16.0),
+        bottom: Val::Px(16.0),
+        ..Default::default()
+    };
+    commands.spawn_bundle(bundle).insert(MenuItem);
+}
+
+/// Start the simulation if the selected grid fits in the memory budget
+fn start(
+    selection: &SceneSelection,
+    budget: &MemoryBudget,
+    errors: &mut ErrorLog,
+    state: &mut State<AppState>,
+) {
+    let (width, height) = selection.grid_size();
+    match memory::check(width, height, budget) {
+        Ok(()) => {
+            if let Err(err) = state.set(AppState::Running) {
+                errors.report(format!("Can't start: {:?}", err));
+            }
+        }
+        Err(err) => errors.report(format!("Can't start: {}", err)),
+    }
+}
+
+fn menu_input_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    budget: Res<MemoryBudget>,
+    mut errors: ResMut<ErrorLog>,
+    mut selection: ResMut<SceneSelection>,
+    mut state: ResMut<State<AppState>>,
+) {
+    let scene_count = ScenePreset::ALL.len();
+    if keyboard_input.just_pressed(KeyCode::Right) {
+        selection.scene_index = (selection.scene_index + 1) % scene_count;
+    }
+    if keyboard_input.just_pressed(KeyCode::Left) {
+        selection.scene_index = (selection.scene_index + scene_count - 1) % scene_count;
+    }
+    if keyboard_input.just_pressed(KeyCode::Up) {
+        let next = selection.size_index + 1;
+        selection.pick_size(next);
+    }
+    if keyboard_input.just_pressed(KeyCode::Down) {
+        let previous = selection.size_index.saturating_sub(1);
+        selection.pick_size(previous);
+    }
+    if keyboard_input.just_pressed(KeyCode::Return) {
+        start(&selection, &budget, &mut errors, &mut state);
+    }
+}
+
+fn menu_ui_system(
+    egui_context: Res<EguiContext>,
+    budget: Res<MemoryBudget>,
+    mut errors: ResMut<ErrorLog>,
+    mut selection: ResMut<SceneSelection>,
+    mut state: ResMut<State<AppState>>,
+) {
+    egui::Window::new("Fluid Simulation")
+        .collapsible(false)
+        .resizable(false)
+        .show(egui_context.ctx(), |ui| {
+            ui.heading("Scene");
+            egui::Grid::new("scenes").show(ui, |ui| {
+                for (i, scene) in ScenePreset::ALL.iter().enumerate() {
+                    ui.vertical(|ui| {
+                        let texture = egui::TextureId::User(thumbnail_id(i));
+                        let button = egui::ImageButton::new(texture, [THUMBNAIL_SIZE; 2])
+                            .selected(i == selection.scene_index);
+                        if ui.add(button).clicked() {
+                            selection.scene_index = i;
+                        }
+                        ui.label(scene.name());
+                    });
+                    if i % THUMBNAILS_PER_ROW == THUMBNAILS_PER_ROW - 1 {
+                        ui.end_row();
+                    }
+                }
+            });
+
+            ui.separator();
+            ui.heading("Grid size");
+            ui.horizontal(|ui| {
+                if let Some((width, height)) = selection.custom_size {
+                    ui.selectable_label(true, format!("{} x {}", width, height));
+                }
+                for (i, (width, height)) in GRID_SIZES.iter().enumerate() {
+                    let selected = selection.custom_size.is_none() && i == selection.size_index;
+                    let text = format!("{} x {}", width, height);
+                    if ui.selectable_label(selected, text).clicked() {
+                        selection.pick_size(i);
+                    }
+                }
+            });
+
+            ui.separator();
+            if ui.button("Start").clicked() {
+                start(&selection, &budget, &mut errors, &mut state);
+            }
+        });
+}
+
+fn menu_preview_system(
+    selection: Res<SceneSelection>,
+    mut query: Query<&mut FluidWidget, With<MenuItem>>,
+) {
+    for mut preview in query.iter_mut() {
+        if preview.scene() != selection.scene() {
+            preview.set_scene(selection.scene());
+        }
+    }
+}
+
+/// The window title names the selected scene and grid size
+fn menu_title_system(selection: Res<SceneSelection>, mut windows: ResMut<Windows>) {
+    if !selection.is_changed() {
+        return;
+    }
+
+    let window = match windows.get_primary_mut() {
+        Some(window) => window,
+        None => return,
+    };
+    let (width, height) = selection.grid_size();
+    window.set_title(format!(
+        "Fluid Simulation - {} - {}x{} grid",
+        selection.scene().name(),
+        width,
+        height
+    ));
+}
+
+fn menu_cleanup_system(
+    mut commands: Commands,
+    mut egui_context: ResMut<EguiContext>,
+    query: Query<(Entity, &MenuItem)>,
+) {
+    for (entity, _menu_item) in query.iter() {
+        commands.entity(entity).despawn();
+    }
+    for i in 0..ScenePreset::ALL.len() {
+        egui_context.remove_egui_texture(thumbnail_id(i));
+    }
+}
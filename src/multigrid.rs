@@ -0,0 +1,248 @@
+use crate::boundary::BoundaryMode;
+use crate::solver::{red_black, PRESSURE_TOLERANCE};
+use crate::Grid;
+
+// Geometric multigrid for the pressure equations: V-cycles smoothing the error with a few
+// Gauss-Seidel sweeps, then solving for what's left on a grid of half the size, down to a
+// grid too small or odd to halve. The large swirls relaxation takes many iterations to
+// clear are only a few cells wide on the coarse grids, so a cycle costs about two fine
+// sweeps whatever the grid size. Power-of-two grids halve all the way down, the others
+// stop halving at their odd size and solve that grid by relaxation.
+
+/// Gauss-Seidel sweeps before and after solving on the coarser grid
+const SWEEPS: usize = 2;
+/// Grids no smaller than this are halved
+const MIN_COARSENED: usize = 4;
+
+static DIRECTIONS: [(isize, isize); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+static CHILDREN: [(usize, usize); 4] = [(0, 0), (1, 0), (0, 1), (1, 1)];
+
+/// Unknowns of one grid of the hierarchy, the finest first
+struct Level {
+    width: usize,
+    height: usize,
+    /// Cells left out of the equations, the obstacles and, on the coarse grids, the cells
+    /// covering only obstacles
+    solid: Vec<Vec<bool>>,
+    /// Pressure on the finest grid, its correction on the coarser ones
+    value: Vec<Vec<f32>>,
+    rhs: Vec<Vec<f32>>,
+    residual: Vec<Vec<f32>>,
+}
+
+impl Level {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            solid: vec![vec![false; width]; height],
+            value: vec![vec![0.0; width]; height],
+            rhs: vec![vec![0.0; width]; height],
+            residual: vec![vec![0.0; width]; height],
+        }
+    }
+
+    /// Neighbors of a cell taking part in its equation, the walls and solid cells
+    /// standing in for the cell itself and being left out
+    fn open_neighbors(
+        &self,
+        x: usize,
+        y: usize,
+        boundary: BoundaryMode,
+    ) -> impl Iterator<Item = (usize, usize)> + '_ {
+        DIRECTIONS
+            .iter()
+            .map(move |&(dx, dy)| {
+                let (nx, ny, _) =
+                    boundary.ghost(x as isize + dx, y as isize + dy, self.width, self.height);
+                (nx, ny)
+            })
+            .filter(move |&(nx, ny)| (nx, ny) != (x, y) && !self.solid[ny][nx])
+    }
+
+    /// Red-black Gauss-Seidel sweeps over `value - avg(value) = rhs`, each cell solved for
+    /// exactly
+    fn smooth(&mut self, boundary: BoundaryMode, sweeps: usize) {
+        for _ in 0..sweeps {
+            for (x, y) in red_black(self.width, self.height) {
+                if self.solid[y][x] {
+                    continue;
+                }
+                let (sum, open) = self
+                    .open_neighbors(x, y, boundary)
+                    .fold((0.0, 0), |(sum, open), (nx, ny)| {
+                        (sum + self.value[ny][nx], open + 1)
+                    });
+                if open > 0 {
+                    self.value[y][x] = (sum + 4.0 * self.rhs[y][x]) / open as f32;
+                }
+            }
+        }
+    }
+
+    /// Fill the residual of the equations, returning the largest
+    fn compute_residual(&mut self, boundary: BoundaryMode) -> f32 {
+        let mut largest: f32 = 0.0;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let residual = if self.solid[y][x] {
+                    0.0
+                } else {
+                    let (sum, open) = self
+                        .open_neighbors(x, y, boundary)
+                        .fold((0.0, 0), |(sum, open), (nx, ny)| {
+                            (sum + self.value[ny][nx], open + 1)
+                        });
+                    self.rhs[y][x] - (open as f32 * self.value[y][x] - sum) / 4.0
+                };
+                self.residual[y][x] = residual;
+                largest = largest.max(residual.abs());
+            }
+        }
+        largest
+    }
+}
+
+/// Grids of the V-cycles, kept from one projection to the next
+#[derive(Default)]
+pub struct Multigrid {
+    levels: Vec<Level>,
+}
+
+impl Multigrid {
+    /// Size the hierarchy like the grid, only allocating when its size changed
+    fn prepare(&mut self, width: usize, height: usize) {
+        if self.levels.first().map_or(false, |level| {
+            (level.width, level.height) == (width, height)
+        }) {
+            return;
+        }
+        self.levels.clear();
+        let (mut width, mut height) = (width, height);
+        loop {
+            self.levels.push(Level::new(width, height));
+            let halves = width % 2 == 0 && height % 2 == 0;
+            if !halves || width.min(height) < MIN_COARSENED {
+                break;
+            }
+            width /= 2;
+            height /= 2;
+        }
+    }
+
+    /// Solve the pressure equations of the projection, `p - avg(p) = -quarter_divergence`,
+    /// with up to `cycles` V-cycles. Like the conjugate gradient, the mean of the
+    /// divergence, which no pressure can clear, is left out.
+    pub fn solve(
+        &mut self,
+        grid: &Grid,
+        boundary: BoundaryMode,
+        quarter_divergence: &[Vec<f32>],
+        pressure: &mut [Vec<f32>],
+        cycles: usize,
+    ) {
+        self.prepare(grid.width(), grid.height());
+
+        let finest = &mut self.levels[0];
+        for (solid_row, row) in finest.solid.iter_mut().zip(grid.0.iter()) {
+            for (solid, cell) in solid_row.iter_mut().zip(row.iter()) {
+                *solid = cell.obstacle;
+            }
+        }
+        for i in 1..self.levels.len() {
+            let (finer, coarser) = self.levels.split_at_mut(i);
+            let (fine, coarse) = (&finer[i - 1], &mut coarser[0]);
+            for y in 0..coarse.height {
+                for x in 0..coarse.width {
+                    coarse.solid[y][x] = children(x, y).all(|(cx, cy)| fine.solid[cy][cx]);
+                }
+            }
+        }
+
+        let finest = &mut self.levels[0];
+        let mut sum = 0.0;
+        let mut count = 0;
+        for y in 0..finest.height {
+            for x in 0..finest.width {
+                let open = finest.open_neighbors(x, y, boundary).count();
+                if finest.solid[y][x] || open == 0 {
+                    continue;
+                }
+                sum += quarter_divergence[y][x] as f64;
+                count += 1;
+            }
+        }
+        let mean = if count > 0 {
+            (sum / count as f64) as f32
+        } else {
+            0.0
+        };
+        for y in 0..finest.height {
+            for x in 0..finest.width {
+                let open = finest.open_neighbors(x, y, boundary).count();
+                finest.rhs[y][x] = if finest.solid[y][x] || open == 0 {
+                    0.0
+                } else {
+                    mean - quarter_divergence[y][x]
+                };
+                finest.value[y][x] = pressure[y][x];
+            }
+        }
+
+        for _ in 0..cycles {
+            if self.levels[0].compute_residual(boundary) <= PRESSURE_TOLERANCE {
+                break;
+            }
+            v_cycle(&mut self.levels, boundary);
+        }
+
+        for (row, value_row) in pressure.iter_mut().zip(self.levels[0].value.iter()) {
+            row.copy_from_slice(value_row);
+        }
+    }
+}
+
+/// The four cells of the finer grid a coarse cell covers
+fn children(x: usize, y: usize) -> impl Iterator<Item = (usize, usize)> {
+    CHILDREN
+        .iter()
+        .map(move |&(dx, dy)| (2 * x + dx, 2 * y + dy))
+}
+
+/// Reduce the error of the first level of `levels` by a V-cycle
+fn v_cycle(levels: &mut [Level], boundary: BoundaryMode) {
+    let (fine, coarser) = match levels.split_first_mut() {
+        Some(split) => split,
+        None => return,
+    };
+    let coarse = match coarser.first_mut() {
+        Some(coarse) => coarse,
+        None => {
+            // Relaxation is enough on a grid this small, or the best there is on an odd one
+            fine.smooth(boundary, fine.width + fine.height);
+            return;
+        }
+    };
+
+    fine.smooth(boundary, SWEEPS);
+    fine.compute_residual(boundary);
+    // The equations of a grid twice as coarse are 4 times as large for the same field
+    for y in 0..coarse.height {
+        for x in 0..coarse.width {
+            let sum: f32 = children(x, y).map(|(cx, cy)| fine.residual[cy][cx]).sum();
+            coarse.rhs[y][x] = if coarse.solid[y][x] { 0.0 } else { sum };
+            coarse.value[y][x] = 0.0;
+        }
+    }
+    v_cycle(coarser, boundary);
+
+    let coarse = &coarser[0];
+    for y in 0..fine.height {
+        for x in 0..fine.width {
+            if !fine.solid[y][x] {
+                fine.value[y][x] += coarse.value[y / 2][x / 2];
+            }
+        }
+    }
+    fine.smooth(boundary, SWEEPS);
+}
@@ -0,0 +1,355 @@
+use bevy::prelude::*;
+
+use crate::errors::ErrorLog;
+use crate::layers::{Layer, OnLayer};
+use crate::scenes::SceneSelection;
+use crate::species::SPECIES;
+use crate::viewport::{self, ViewSlot, Viewport};
+use crate::{grid_to_world, trail_cells, AppState, Cell, Grid, Position, CELL_SIZE};
+
+// Obstacles: solid cells the solver passes treat like walls, the fluid flowing around them.
+// Dragging with the middle mouse button draws obstacles, holding shift erases them. They're
+// drawn as squares of their own layer, above the density and the dye by default.
+//
+// Obstacles have their own temperature, conducting heat between them and with the fluid
+// along them. Holding ctrl draws hot ones, radiators heating the fluid flowing past.
+//
+// Moving obstacles are rectangles rasterized again every frame, their cells carrying their
+// velocity so the walls drag the fluid along and the cells they leave keep it. 3 puts a
+// paddle in the middle of the grid, the arrows driving it instead of scrolling. They stay
+// at the ambient temperature, the heat they take or give being left behind when they move.
+
+pub const OBSTACLE_COLOR: [f32; 3] = [0.45, 0.4, 0.35];
+/// Color of the obstacles at HOT_TEMPERATURE and above
+const HOT_COLOR: [f32; 3] = [0.9, 0.3, 0.1];
+/// Temperature of the obstacles drawn with ctrl
+const HOT_TEMPERATURE: f32 = 5.0;
+/// Half of the width and height of the paddle, in cells
+const PADDLE_HALF_SIZE: Vec2 = Vec2::new(1.0, 4.0);
+/// Cells per second
+const PADDLE_SPEED: f32 = 20.0;
+
+pub struct ObstaclePlugin;
+
+impl Plugin for ObstaclePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system_set(
+            SystemSet::on_enter(AppState::Running).with_system(obstacle_setup.system()),
+        )
+        .add_system_set(
+            SystemSet::on_update(AppState::Running)
+                .with_system(obstacle_draw_system.system())
+                .with_system(paddle_keys_system.system())
+                .with_system(paddle_drive_system.system())
+                .with_system(moving_obstacle_system.system())
+                .with_system(obstacle_square_system.system()),
+        );
+    }
+}
+
+struct ObstacleSquare;
+
+/// Rectangle of solid cells moving through the grid
+pub struct MovingObstacle {
+    /// Center in grid coordinates
+    pub center: Vec2,
+    /// Half of the width and height, in cells
+    pub half_size: Vec2,
+    /// Cells per second
+    pub velocity: Vec2,
+    /// Cells it turned solid, given back to the fluid when it moves away
+    covered: Vec<(usize, usize)>,
+}
+
+impl MovingObstacle {
+    pub fn new(center: Vec2, half_size: Vec2) -> Self {
+        Self {
+            center,
+            half_size,
+            velocity: Vec2::ZERO,
+            covered: Vec::new(),
+        }
+    }
+
+    /// First and last columns and rows of the cells whose center is inside
+    fn bounds(&self, width: usize, height: usize) -> (usize, usize, usize, usize) {
+        let min = (self.center - self.half_size).ceil().max(Vec2::ZERO);
+        let max = (self.center + self.half_size).floor();
+        let max = max.min(Vec2::new(width as f32 - 1.0, height as f32 - 1.0));
+        (
+            min.x as usize,
+            min.y as usize,
+            max.x as usize,
+            max.y as usize,
+        )
+    }
+
+    /// Give the cells back to the fluid, moving like the obstacle was
+    fn release(&mut self, grid: &mut Grid) {
+        for (x, y) in self.covered.drain(..) {
+            // The grid may have been replaced by a smaller one since
+            if let Some(cell) = grid.0.get_mut(y).and_then(|row| row.get_mut(x)) {
+                set_obstacle(cell, false);
+                cell.velocity = self.velocity;
+            }
+        }
+    }
+}
+
+/// The moving obstacle driven by the arrows
+pub struct Paddle;
+
+/// Turn a cell into an obstacle, emptying it, or back into fluid
+fn set_obstacle(cell: &mut Cell, obstacle: bool) {
+    if obstacle {
+        cell.velocity = Vec2::ZERO;
+        cell.density = 0.0;
+        cell.dye = Vec3::ZERO;
+        cell.temperature = 0.0;
+        cell.species = [0.0; SPECIES];
+        cell.fuel = 0.0;
+    }
+    cell.obstacle = obstacle;
+}
+
+fn obstacle_setup(
+    mut commands: Commands,
+    selection: Res<SceneSelection>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let (width, height) = selection.grid_size();
+    let (columns, rows) = viewport::view_size(width, height);
+    for y in 0..rows {
+        for x in 0..columns {
+            let position = grid_to_world(Vec2::new(x as f32, y as f32), width, height);
+
+            commands
+                .spawn_bundle(SpriteBundle {
+                    material: materials.add(obstacle_color(0.0).into()),
+                    transform: Transform::from_translation(position.extend(0.0)),
+                    sprite: Sprite::new(Vec2::new(CELL_SIZE, CELL_SIZE)),
+                    visible: Visible {
+                        is_visible: false,
+                        is_transparent: false,
+                    },
+                    ..Default::default()
+                })
+                .insert(ObstacleSquare)
+                .insert(OnLayer {
+                    layer: Layer::Obstacles,
+                    offset: 0.0,
+                })
+                .insert(Position { x, y })
+                .insert(ViewSlot { x, y });
+        }
+    }
+}
+
+/// Draw or erase obstacles along the cursor path while the middle button is held
+fn obstacle_draw_system(
+    windows: Res<Windows>,
+    viewport: Res<Viewport>,
+    keyboard_input: Res<Input<KeyCode>>,
+    mouse_button_input: Res<Input<MouseButton>>,
+    mut qg: Query<&mut Grid>,
+    // Cursor in grid coordinates the previous frame, to fill the cells in between
+    mut last: Local<Option<Vec2>>,
+) {
+    if !mouse_button_input.pressed(MouseButton::Middle) {
+        *last = None;
+        return;
+    }
+
+    let cursor = windows.get_primary().and_then(|w| w.cursor_position());
+    if let (Some(cursor), Ok(mut grid)) = (cursor, qg.single_mut()) {
+        if viewport.cursor_cell(cursor, &grid).is_none() {
+            *last = None;
+            return;
+        }
+
+        let erase =
+            keyboard_input.pressed(KeyCode::LShift) || keyboard_input.pressed(KeyCode::RShift);
+        let hot =
+            keyboard_input.pressed(KeyCode::LControl) || keyboard_input.pressed(KeyCode::RControl);
+        let pos = viewport.cursor_to_grid(cursor);
+        let (width, height) = (grid.width(), grid.height());
+        for (x, y) in trail_cells(last.unwrap_or(pos), pos, width, height) {
+            let cell = &mut grid.0[y][x];
+            set_obstacle(cell, !erase);
+            if hot && !erase {
+                cell.temperature = HOT_TEMPERATURE;
+            }
+        }
+        *last = Some(pos);
+    }
+}
+
+/// Obstacle color at a temperature, from the plain one at the ambient temperature to the
+/// hot one, the cold ones staying plain
+fn obstacle_color(temperature: f32) -> Color {
+    let t = (temperature / HOT_TEMPERATURE).max(0.0).min(1.0);
+    let color = Vec3::from(OBSTACLE_COLOR).lerp(Vec3::from(HOT_COLOR), t);
+    Color::rgb(color.x, color.y, color.z)
+}
+
+fn obstacle_square_system(
+    mut errors: ResMut<ErrorLog>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    qg: Query<&Grid>,
+    mut query: Query<(
+        &ObstacleSquare,
+        &Position,
+        &Handle<ColorMaterial>,
+        &mut Visible,
+    )>,
+) {
+    if let Ok(grid) = qg.single() {
+        for (_obstacle_square, position, color, mut visible) in query.iter_mut() {
+            let Position { x, y } = position;
+            let cell = &grid.0[*y][*x];
+            visible.is_visible = cell.obstacle;
+            if !cell.obstacle {
+                continue;
+            }
+
+            match materials.get_mut(&*color) {
+                Some(material) => material.color = obstacle_color(cell.temperature),
+                None => errors.report("Missing material of an obstacle square"),
+            }
+        }
+    }
+}
+
+/// 3 adds the paddle, or removes it
+fn paddle_keys_system(
+    mut commands: Commands,
+    mut qg: Query<&mut Grid>,
+    mut paddles: Query<(Entity, &mut MovingObstacle), With<Paddle>>,
+    mut char_input_events: EventReader<ReceivedCharacter>,
+) {
+    for event in char_input_events.iter() {
+        if event.char != '3' {
+            continue;
+        }
+        let mut grid = match qg.single_mut() {
+            Ok(grid) => grid,
+            Err(_) => return,
+        };
+        match paddles.iter_mut().next() {
+            Some((entity, mut paddle)) => {
+                paddle.release(&mut grid);
+                commands.entity(entity).despawn();
+            }
+            None => {
+                let center = Vec2::new(grid.width() as f32, grid.height() as f32) / 2.0;
+                commands
+                    .spawn()
+                    .insert(MovingObstacle::new(center.floor(), PADDLE_HALF_SIZE))
+                    .insert(Paddle);
+            }
+        }
+    }
+}
+
+fn paddle_drive_system(
+    time: Res<Time>,
+    keyboard_input: Res<Input<KeyCode>>,
+    qg: Query<&Grid>,
+    mut paddles: Query<&mut MovingObstacle, With<Paddle>>,
+) {
+    let grid = match qg.single() {
+        Ok(grid) => grid,
+        Err(_) => return,
+    };
+
+    let mut direction = Vec2::ZERO;
+    if keyboard_input.pressed(KeyCode::Left) {
+        direction.x -= 1.0;
+    }
+    if keyboard_input.pressed(KeyCode::Right) {
+        direction.x += 1.0;
+    }
+    if keyboard_input.pressed(KeyCode::Down) {
+        direction.y -= 1.0;
+    }
+    if keyboard_input.pressed(KeyCode::Up) {
+        direction.y += 1.0;
+    }
+
+    let dt = time.delta_seconds();
+    let max = Vec2::new(grid.width() as f32 - 1.0, grid.height() as f32 - 1.0);
+    for mut paddle in paddles.iter_mut() {
+        // Stays inside the grid, stopping against its edges
+        let min_center = paddle.half_size;
+        let max_center = (max - paddle.half_size).max(min_center);
+        let center = paddle.center + direction * PADDLE_SPEED * dt;
+        let center = center.max(min_center).min(max_center);
+        paddle.velocity = if dt > 0.0 {
+            (center - paddle.center) / dt
+        } else {
+            Vec2::ZERO
+        };
+        paddle.center = center;
+    }
+}
+
+/// Rasterize the moving obstacles where they are now. The cells they leave become fluid
+/// moving with them, the fluid of the cells they move into is pushed just ahead of them.
+fn moving_obstacle_system(mut qg: Query<&mut Grid>, mut query: Query<&mut MovingObstacle>) {
+    let mut grid = match qg.single_mut() {
+        Ok(grid) => grid,
+        Err(_) => return,
+    };
+    let (width, height) = (grid.width(), grid.height());
+
+    for mut obstacle in query.iter_mut() {
+        let velocity = obstacle.velocity;
+        obstacle.release(&mut grid);
+
+        let (min_x, min_y, max_x, max_y) = obstacle.bounds(width, height);
+        for y in min_y..=max_y {
+            for x in min_x..=max_x {
+                let cell = grid.0[y][x].clone();
+                // Drawn obstacles stay as they are under the moving ones
+                if cell.obstacle {
+                    continue;
+                }
+
+                // Along the main direction of the motion, to the first cell past the edge
+                let ahead = if velocity == Vec2::ZERO {
+                    None
+                } else if velocity.x.abs() >= velocity.y.abs() {
+                    let ax = if velocity.x > 0.0 {
+                        max_x.checked_add(1)
+                    } else {
+                        min_x.checked_sub(1)
+                    };
+                    ax.map(|ax| (ax, y))
+                } else {
+                    let ay = if velocity.y > 0.0 {
+                        max_y.checked_add(1)
+                    } else {
+                        min_y.checked_sub(1)
+                    };
+                    ay.map(|ay| (x, ay))
+                };
+                if let Some((ax, ay)) = ahead.filter(|&(ax, ay)| ax < width && ay < height) {
+                    let target = &mut grid.0[ay][ax];
+                    if !target.obstacle {
+                        target.density += cell.density;
+                        target.dye += cell.dye;
+                        target.temperature += cell.temperature;
+                        for (amount, pushed) in target.species.iter_mut().zip(cell.species.iter()) {
+                            *amount += pushed;
+                        }
+                    }
+                }
+
+                let cell = &mut grid.0[y][x];
+                set_obstacle(cell, true);
+                cell.velocity = velocity;
+                obstacle.covered.push((x, y));
+            }
+        }
+    }
+}
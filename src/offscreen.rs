@@ -0,0 +1,176 @@
+use bevy::prelude::*;
+use bevy::render::texture::TextureFormat;
+
+use crate::accessibility::Accessibility;
+use crate::errors::ErrorLog;
+use crate::layers::{Layer, Layers};
+use crate::obstacles::OBSTACLE_COLOR;
+use crate::palette::Palette;
+use crate::post::{self, PostEffects};
+use crate::species;
+use crate::{arrow_color, arrow_length, Cell, Grid};
+
+// Off-screen rendering: a RenderTarget draws one visualization of the grid into a texture
+// supplied by the user, at the size of that texture whatever the window and the camera
+// show. The drawing happens on the CPU, so only what's computed from the grid can be
+// drawn: the particles and the overlays are meshes and sprites only the window renders.
+
+/// Length of the longest arrows, in cells, like the arrow mesh
+const ARROW_CELLS: f32 = 0.8;
+
+pub struct OffscreenPlugin;
+
+impl Plugin for OffscreenPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system(render_target_system.system());
+    }
+}
+
+/// Redraws `texture` every frame, which has to be in an RGBA8 format
+pub struct RenderTarget {
+    /// None draws the cells as the window shows them, with the palette and post effects
+    pub layer: Option<Layer>,
+    pub texture: Handle<Texture>,
+}
+
+/// What the drawing depends on besides the grid
+pub struct Look<'a> {
+    pub palette: &'a Palette,
+    pub layers: &'a Layers,
+    pub post_effects: &'a PostEffects,
+    pub accessibility: &'a Accessibility,
+}
+
+/// RGBA bytes of a layer drawn at `width` by `height` pixels, top row first like textures
+pub fn render_layer(
+    grid: &Grid,
+    layer: Option<Layer>,
+    look: &Look,
+    width: usize,
+    height: usize,
+) -> Result<Vec<u8>, String> {
+    let frame = match layer {
+        None => post::compose(grid, look.palette, look.layers, &look.post_effects.0),
+        Some(Layer::Density) => cell_frame(grid, |cell| Vec3::splat(cell.density)),
+        Some(Layer::Dye) => cell_frame(grid, |cell| cell.dye + species::color(cell)),
+        Some(Layer::Obstacles) => cell_frame(grid, |cell| {
+            if cell.obstacle {
+                Vec3::from(OBSTACLE_COLOR)
+            } else {
+                Vec3::ZERO
+            }
+        }),
+        Some(Layer::Arrows) => return Ok(arrows(grid, look.accessibility, width, height)),
+        Some(layer) => {
+            return Err(format!(
+                "the {:?} layer can't be drawn off-screen, only in the window",
+                layer
+            ))
+        }
+    };
+
+    let to_byte = |c: f32| (c.min(1.0).max(0.0) * 255.0) as u8;
+    let (scale_x, scale_y) = (
+        grid.width() as f32 / width as f32,
+        grid.height() as f32 / height as f32,
+    );
+    let mut data = Vec::with_capacity(width * height * 4);
+    for py in 0..height {
+        for px in 0..width {
+            // Texture rows go from top to bottom, grid rows from bottom to top
+            let x = (px as f32 + 0.5) * scale_x - 0.5;
+            let y = ((height - 1 - py) as f32 + 0.5) * scale_y - 0.5;
+            let color = post::sample(&frame, x, y);
+            data.extend_from_slice(&[to_byte(color.x), to_byte(color.y), to_byte(color.z), 255]);
+        }
+    }
+    Ok(data)
+}
+
+fn cell_frame(grid: &Grid, color: impl Fn(&Cell) -> Vec3) -> post::Frame {
+    grid.0
+        .iter()
+        .map(|row| row.iter().map(&color).collect())
+        .collect()
+}
+
+/// A line from the center of every cell along its velocity, on a transparent background
+fn arrows(grid: &Grid, accessibility: &Accessibility, width: usize, height: usize) -> Vec<u8> {
+    let mut data = vec![0; width * height * 4];
+    let cell_size = Vec2::new(
+        width as f32 / grid.width() as f32,
+        height as f32 / grid.height() as f32,
+    );
+
+    for (y, row) in grid.0.iter().enumerate() {
+        for (x, cell) in row.iter().enumerate() {
+            let speed = cell.velocity.length();
+            if speed == 0.0 {
+                continue;
+            }
+            let direction = cell.velocity / speed;
+            let color = arrow_color(cell, accessibility);
+            let rgba = [color.r(), color.g(), color.b(), 1.0].map(|c| (c * 255.0) as u8);
+
+            let start = (Vec2::new(x as f32, y as f32) + Vec2::splat(0.5)) * cell_size;
+            let end = start
+                + direction * ARROW_CELLS * arrow_length(cell.velocity, accessibility) * cell_size;
+            let steps = (end - start).abs().max_element().ceil().max(1.0) as usize;
+            for i in 0..=steps {
+                let point = start.lerp(end, i as f32 / steps as f32);
+                let (px, py) = (point.x as usize, point.y as usize);
+                if px < width && py < height {
+                    let start = ((height - 1 - py) * width + px) * 4;
+                    data[start..start + 4].copy_from_slice(&rgba);
+                }
+            }
+        }
+    }
+    data
+}
+
+#[allow(clippy::too_many_arguments)]
+fn render_target_system(
+    qg: Query<&Grid>,
+    palette: Res<Palette>,
+    layers: Res<Layers>,
+    post_effects: Res<PostEffects>,
+    accessibility: Res<Accessibility>,
+    mut errors: ResMut<ErrorLog>,
+    mut textures: ResMut<Assets<Texture>>,
+    query: Query<&RenderTarget>,
+) {
+    let grid = match qg.single() {
+        Ok(grid) => grid,
+        Err(_) => return,
+    };
+    let look = Look {
+        palette: &palette,
+        layers: &layers,
+        post_effects: &post_effects,
+        accessibility: &accessibility,
+    };
+
+    for target in query.iter() {
+        let texture = match textures.get_mut(&target.texture) {
+            Some(texture) => texture,
+            None => continue,
+        };
+        if !matches!(
+            texture.format,
+            TextureFormat::Rgba8UnormSrgb | TextureFormat::Rgba8Unorm
+        ) {
+            errors.report(format!(
+                "Render targets need an RGBA8 texture, not {:?}",
+                texture.format
+            ));
+            continue;
+        }
+
+        let (width, height) = (texture.size.width as usize, texture.size.height as usize);
+        match render_layer(grid, target.layer, &look, width, height) {
+            Ok(data) => texture.data = data,
+            Err(err) => errors.report(format!("Couldn't render off-screen: {}", err)),
+        }
+    }
+}
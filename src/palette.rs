@@ -0,0 +1,144 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+// Animated coloring of the dye, or colormaps readable with color vision deficiencies, for
+// display only: the grid values are left untouched. h cycles through the modes, [ and ]
+// slow down or speed up the animation.
+
+pub struct PalettePlugin;
+
+impl Plugin for PalettePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(Palette::default())
+            .add_system(palette_keys_system.system())
+            .add_system(palette_animation_system.system());
+    }
+}
+
+/// Colors of the cycled palette, the intensity of a cell picks a position along it
+const PALETTE: [[f32; 3]; 5] = [
+    [0.1, 0.0, 0.4],
+    [0.8, 0.1, 0.5],
+    [1.0, 0.6, 0.1],
+    [0.2, 0.8, 0.6],
+    [0.1, 0.0, 0.4],
+];
+
+/// Perceptually uniform colormaps, from matplotlib
+const VIRIDIS: [[f32; 3]; 5] = [
+    [0.267, 0.005, 0.329],
+    [0.229, 0.322, 0.546],
+    [0.128, 0.567, 0.551],
+    [0.369, 0.789, 0.383],
+    [0.993, 0.906, 0.144],
+];
+/// Also the same for the most common color vision deficiencies
+const CIVIDIS: [[f32; 3]; 5] = [
+    [0.000, 0.135, 0.305],
+    [0.264, 0.305, 0.424],
+    [0.488, 0.482, 0.471],
+    [0.735, 0.670, 0.444],
+    [0.995, 0.909, 0.217],
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum PaletteMode {
+    Off,
+    /// Rotate the hue of the dye
+    HueShift,
+    /// Map the intensity of the dye to a palette scrolling over time
+    Cycle,
+    /// Map the intensity to a color-blind friendly colormap
+    Viridis,
+    Cividis,
+}
+
+impl PaletteMode {
+    fn next(self) -> Self {
+        match self {
+            Self::Off => Self::HueShift,
+            Self::HueShift => Self::Cycle,
+            Self::Cycle => Self::Viridis,
+            Self::Viridis => Self::Cividis,
+            Self::Cividis => Self::Off,
+        }
+    }
+}
+
+pub struct Palette {
+    pub mode: PaletteMode,
+    /// Turns per second
+    pub speed: f32,
+    /// Current position of the animation, from 0 to 1
+    phase: f32,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self {
+            mode: PaletteMode::Off,
+            speed: 0.1,
+            phase: 0.0,
+        }
+    }
+}
+
+impl Palette {
+    /// Color a cell is displayed with
+    pub fn apply(&self, color: Vec3) -> Vec3 {
+        match self.mode {
+            PaletteMode::Off => color,
+            PaletteMode::HueShift => hue_rotate(color, self.phase * std::f32::consts::TAU),
+            PaletteMode::Cycle => {
+                let intensity = color.max_element().min(1.0).max(0.0);
+                // Empty cells stay black
+                colormap(&PALETTE, (intensity + self.phase).fract()) * intensity
+            }
+            PaletteMode::Viridis => colormap(&VIRIDIS, color.max_element().min(1.0).max(0.0)),
+            PaletteMode::Cividis => colormap(&CIVIDIS, color.max_element().min(1.0).max(0.0)),
+        }
+    }
+}
+
+/// Color at `t` from 0 to 1 along evenly spaced colors
+fn colormap(colors: &[[f32; 3]], t: f32) -> Vec3 {
+    let t = t * (colors.len() - 1) as f32;
+    let i = (t as usize).min(colors.len() - 2);
+    Vec3::from(colors[i]).lerp(Vec3::from(colors[i + 1]), t - i as f32)
+}
+
+/// Rotate a color around the gray axis, which shifts its hue and keeps its brightness
+fn hue_rotate(color: Vec3, angle: f32) -> Vec3 {
+    let axis = Vec3::ONE.normalize();
+    let (sin, cos) = angle.sin_cos();
+    color * cos + axis.cross(color) * sin + axis * axis.dot(color) * (1.0 - cos)
+}
+
+fn palette_keys_system(
+    mut palette: ResMut<Palette>,
+    mut char_input_events: EventReader<ReceivedCharacter>,
+) {
+    for event in char_input_events.iter() {
+        match event.char {
+            'h' => {
+                palette.mode = palette.mode.next();
+                info!("Palette: {:?}", palette.mode);
+            }
+            '[' => {
+                palette.speed /= 2.0;
+                info!("Palette speed: {} turns per second", palette.speed);
+            }
+            ']' => {
+                palette.speed *= 2.0;
+                info!("Palette speed: {} turns per second", palette.speed);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn palette_animation_system(time: Res<Time>, mut palette: ResMut<Palette>) {
+    if palette.mode != PaletteMode::Off {
+        palette.phase = (palette.phase + palette.speed * time.delta_seconds()).fract();
+    }
+}
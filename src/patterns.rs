@@ -0,0 +1,66 @@
+// Procedural fills for the density field, used as starting points by the scenes
+
+/// Hash of a lattice point, used to pick its gradient
+fn hash(x: i32, y: i32, seed: u32) -> u32 {
+    let mut h = (x as u32).wrapping_mul(0x8da6_b343)
+        ^ (y as u32).wrapping_mul(0xd816_3841)
+        ^ seed.wrapping_mul(0xcb1a_b31f);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0x5bd1_e995);
+    h ^ (h >> 15)
+}
+
+fn gradient(x: i32, y: i32, seed: u32) -> (f32, f32) {
+    let angle = hash(x, y, seed) as f32 / u32::MAX as f32 * std::f32::consts::TAU;
+    (angle.cos(), angle.sin())
+}
+
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+/// Perlin gradient noise, roughly in -0.7..0.7
+pub fn perlin(x: f32, y: f32, seed: u32) -> f32 {
+    let x0 = x.floor() as i32;
+    let y0 = y.floor() as i32;
+    let tx = x - x0 as f32;
+    let ty = y - y0 as f32;
+
+    let dot = |ix: i32, iy: i32| {
+        let (gx, gy) = gradient(ix, iy, seed);
+        gx * (x - ix as f32) + gy * (y - iy as f32)
+    };
+    let lerp = |a: f32, b: f32, k: f32| a + k * (b - a);
+
+    let bottom = lerp(dot(x0, y0), dot(x0 + 1, y0), fade(tx));
+    let top = lerp(dot(x0, y0 + 1), dot(x0 + 1, y0 + 1), fade(tx));
+    lerp(bottom, top, fade(ty))
+}
+
+/// Several octaves of perlin noise mapped to 0..1, `scale` being the size of the largest features
+pub fn clouds(x: f32, y: f32, scale: f32, seed: u32) -> f32 {
+    let mut value = 0.0;
+    let mut amplitude = 0.5;
+    let mut frequency = 1.0 / scale;
+    for octave in 0..4 {
+        value += amplitude * perlin(x * frequency, y * frequency, seed + octave);
+        amplitude /= 2.0;
+        frequency *= 2.0;
+    }
+    (value + 0.5).max(0.0).min(1.0)
+}
+
+/// Diagonal stripes, `period` cells wide
+pub fn stripes(x: f32, y: f32, period: f32) -> f32 {
+    let phase = (x + y) * std::f32::consts::TAU / period;
+    0.5 + 0.5 * phase.sin()
+}
+
+/// Squares of `size` cells, either 0 or 1
+pub fn checkerboard(x: usize, y: usize, size: usize) -> f32 {
+    if (x / size + y / size) % 2 == 0 {
+        1.0
+    } else {
+        0.0
+    }
+}
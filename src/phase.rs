@@ -0,0 +1,286 @@
+use bevy::prelude::*;
+
+use crate::errors::ErrorLog;
+use crate::layers::{Layer, OnLayer};
+use crate::scenes::SceneSelection;
+use crate::stepping::StepControl;
+use crate::viewport::{self, ViewSlot};
+use crate::{grid_to_world, AppState, Grid, Position, CELL_SIZE};
+
+// Phase averaging: in a scene forced periodically, by an oscillating fan or a looping
+// inflow, the density and velocity are averaged apart for every phase of the period, so
+// the mean periodic flow stands out from the turbulence around it. Ctrl+P starts averaging
+// and shows the mean density of the current phase over the density, then the fluctuation
+// of the velocity around its phase mean, then stops. The period is the one of the scene
+// file's forcing unless --phase-period sets it.
+
+/// Bins the period is split into
+const PHASES: usize = 8;
+
+pub struct PhasePlugin;
+
+impl Plugin for PhasePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system_set(
+            SystemSet::on_enter(AppState::Running).with_system(phase_setup.system()),
+        )
+        .add_system_set(
+            SystemSet::on_update(AppState::Running)
+                .with_system(phase_keys_system.system())
+                .with_system(phase_average_system.system())
+                .with_system(phase_square_system.system()),
+        );
+    }
+}
+
+struct PhaseSquare;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PhaseView {
+    Off,
+    /// Mean density of the current phase
+    Mean,
+    /// Speed of the velocity relative to the mean of its phase
+    Fluctuation,
+}
+
+/// Fields summed over the steps falling in one phase of the period
+#[derive(Default)]
+struct PhaseBin {
+    density: Vec<Vec<f32>>,
+    velocity: Vec<Vec<Vec2>>,
+    samples: usize,
+}
+
+pub struct PhaseAverage {
+    pub view: PhaseView,
+    /// Seconds of the forcing period, none if the scene isn't forced periodically
+    pub period: Option<f32>,
+    bins: Vec<PhaseBin>,
+    /// Simulated time of the last step added, so a paused frame isn't added twice
+    last_time: f32,
+}
+
+impl PhaseAverage {
+    pub fn new(period: Option<f32>) -> Self {
+        Self {
+            view: PhaseView::Off,
+            period,
+            bins: Vec::new(),
+            last_time: f32::NAN,
+        }
+    }
+
+    pub fn memory_bytes(&self) -> usize {
+        let cells: usize = self
+            .bins
+            .iter()
+            .map(|bin| bin.density.iter().map(Vec::len).sum::<usize>())
+            .sum();
+        cells * (std::mem::size_of::<f32>() + std::mem::size_of::<Vec2>())
+    }
+
+    /// Forget the averages, e.g. for a new scene with its own period
+    pub fn restart(&mut self, period: Option<f32>) {
+        *self = Self {
+            view: self.view,
+            ..Self::new(period.or(self.period))
+        };
+    }
+
+    /// Bin of the phase `time` seconds after the forcing started
+    fn phase(&self, time: f32) -> Option<usize> {
+        let period = self.period?;
+        let phase = time.rem_euclid(period) / period;
+        Some(((phase * PHASES as f32) as usize).min(PHASES - 1))
+    }
+
+    /// Add the fields of the grid to the bin of their phase, starting over when the grid
+    /// size changes
+    fn add(&mut self, grid: &Grid, time: f32) {
+        let (width, height) = (grid.width(), grid.height());
+        let sized = self.bins.first().map_or(false, |bin| {
+            bin.density.len() == height && bin.density[0].len() == width
+        });
+        if !sized {
+            self.bins = (0..PHASES)
+                .map(|_| PhaseBin {
+                    density: vec![vec![0.0; width]; height],
+                    velocity: vec![vec![Vec2::ZERO; width]; height],
+                    samples: 0,
+                })
+                .collect();
+        }
+
+        let bin = match self.phase(time) {
+            Some(phase) => &mut self.bins[phase],
+            None => return,
+        };
+        for (y, row) in grid.0.iter().enumerate() {
+            for (x, cell) in row.iter().enumerate() {
+                bin.density[y][x] += cell.density;
+                bin.velocity[y][x] += cell.velocity;
+            }
+        }
+        bin.samples += 1;
+    }
+
+    /// Mean density and velocity of a cell over the steps of a phase, none before any
+    fn mean(&self, phase: usize, x: usize, y: usize) -> Option<(f32, Vec2)> {
+        let bin = self.bins.get(phase).filter(|bin| bin.samples > 0)?;
+        let samples = bin.samples as f32;
+        Some((bin.density[y][x] / samples, bin.velocity[y][x] / samples))
+    }
+}
+
+fn phase_setup(
+    mut commands: Commands,
+    selection: Res<SceneSelection>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let (width, height) = selection.grid_size();
+    let (columns, rows) = viewport::view_size(width, height);
+    for y in 0..rows {
+        for x in 0..columns {
+            let position = Vec2::new(x as f32, y as f32);
+            let translation = grid_to_world(position, width, height).extend(0.6);
+
+            commands
+                .spawn_bundle(SpriteBundle {
+                    material: materials.add(Color::BLACK.into()),
+                    transform: Transform::from_translation(translation),
+                    sprite: Sprite::new(Vec2::new(CELL_SIZE, CELL_SIZE)),
+                    visible: Visible {
+                        is_visible: false,
+                        is_transparent: false,
+                    },
+                    ..Default::default()
+                })
+                .insert(PhaseSquare)
+                .insert(OnLayer {
+                    layer: Layer::Density,
+                    offset: 0.6,
+                })
+                .insert(Position { x, y })
+                .insert(ViewSlot { x, y });
+        }
+    }
+}
+
+/// Ctrl+P cycles through the mean, the fluctuation and no phase averaging, which forgets
+/// the averages
+fn phase_keys_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut phase: ResMut<PhaseAverage>,
+    mut errors: ResMut<ErrorLog>,
+) {
+    let ctrl =
+        keyboard_input.pressed(KeyCode::LControl) || keyboard_input.pressed(KeyCode::RControl);
+    if !ctrl || !keyboard_input.just_pressed(KeyCode::P) {
+        return;
+    }
+    let period = match phase.period {
+        Some(period) => period,
+        None => {
+            errors.report("No periodic forcing to average over, --phase-period sets a period");
+            return;
+        }
+    };
+
+    phase.view = match phase.view {
+        PhaseView::Off => {
+            info!("Averaging {} phases of {:.2} s", PHASES, period);
+            PhaseView::Mean
+        }
+        PhaseView::Mean => PhaseView::Fluctuation,
+        PhaseView::Fluctuation => {
+            phase.restart(None);
+            PhaseView::Off
+        }
+    };
+}
+
+/// Add every step to the average of its phase while averaging
+fn phase_average_system(
+    control: Res<StepControl>,
+    mut phase: ResMut<PhaseAverage>,
+    qg: Query<&Grid>,
+) {
+    if phase.view == PhaseView::Off || control.time == phase.last_time {
+        return;
+    }
+    if let Ok(grid) = qg.single() {
+        phase.add(grid, control.time);
+        phase.last_time = control.time;
+    }
+}
+
+/// Display the mean density of the current phase, or the speed of the fluctuation
+/// normalized by the largest one
+fn phase_square_system(
+    control: Res<StepControl>,
+    phase: Res<PhaseAverage>,
+    mut errors: ResMut<ErrorLog>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    qg: Query<&Grid>,
+    mut query: Query<(
+        &PhaseSquare,
+        &Position,
+        &Handle<ColorMaterial>,
+        &mut Visible,
+    )>,
+) {
+    let active = phase.view != PhaseView::Off;
+    let current = phase.phase(control.time);
+    let grid = match (qg.single(), current) {
+        (Ok(grid), Some(_)) if active => Some(grid),
+        _ => None,
+    };
+    let current = current.unwrap_or(0);
+    let fluctuation = |x: usize, y: usize, grid: &Grid| {
+        phase.mean(current, x, y).map_or(0.0, |(_, velocity)| {
+            (grid.0[y][x].velocity - velocity).length()
+        })
+    };
+    let max = match grid {
+        Some(grid) if phase.view == PhaseView::Fluctuation => (0..grid.height())
+            .flat_map(|y| (0..grid.width()).map(move |x| (x, y)))
+            .map(|(x, y)| fluctuation(x, y, grid))
+            .fold(0.0, f32::max),
+        _ => 0.0,
+    };
+
+    for (_phase_square, position, color, mut visible) in query.iter_mut() {
+        visible.is_visible = grid.is_some();
+        let grid = match grid {
+            Some(grid) => grid,
+            None => continue,
+        };
+
+        let color_mat = match materials.get_mut(&*color) {
+            Some(material) => material,
+            None => {
+                errors.report("Missing material of a phase average square");
+                continue;
+            }
+        };
+        let Position { x, y } = *position;
+        color_mat.color = match phase.view {
+            PhaseView::Fluctuation => {
+                let v = if max > 0.0 {
+                    fluctuation(x, y, grid) / max
+                } else {
+                    0.0
+                };
+                Color::rgb(v * v, v, 0.3 + 0.7 * v)
+            }
+            _ => {
+                let density = phase
+                    .mean(current, x, y)
+                    .map_or(grid.0[y][x].density, |(density, _)| density);
+                let d = density.clamp(0.0, 1.0);
+                Color::rgb(d, d, d)
+            }
+        };
+    }
+}
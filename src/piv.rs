@@ -0,0 +1,373 @@
+use bevy::prelude::*;
+use bevy::render::pipeline::PipelineDescriptor;
+
+use crate::errors::ErrorLog;
+use crate::layers::{Layer, Layers};
+use crate::lines::{self, Segment, ShaderSupport};
+use crate::stepping::StepControl;
+use crate::tracers;
+use crate::{grid_to_world, Grid, SolverSettings, CELL_SIZE};
+
+// Virtual particle image velocimetry: seeding particles follow the flow like tracers, and
+// after every step two synthetic images of them are taken a short time apart, like the two
+// laser pulses of an experiment. The images are cut into interrogation windows, and the
+// peak of the cross-correlation of each window of the first image with the second gives
+// the displacement of its particles, hence a velocity. Ctrl+I shows the measured vectors
+// in orange over the true grid velocity in white, the control panel their RMS error, so the
+// usual error sources show up: the velocity gradients within a window, the particles
+// leaving it between the images and the too sparse seeding.
+
+/// Pixels of the synthetic images per cell, along each axis
+const PIXELS_PER_CELL: usize = 4;
+/// Side of an interrogation window, in pixels
+const WINDOW: usize = 16;
+const PARTICLES_PER_WINDOW: usize = 12;
+/// Standard deviation of the Gaussian image of a particle, in pixels
+const PARTICLE_SIGMA: f32 = 1.0;
+/// Displacement of the fastest particles between the two images, a quarter window as the
+/// experiments aim for
+const TARGET_SHIFT: f32 = WINDOW as f32 / 4.0;
+/// Largest displacement the correlation looks for, in pixels
+const MAX_SHIFT: isize = WINDOW as isize / 2;
+const MEASURED_COLOR: [f32; 3] = [1.0, 0.6, 0.1];
+const TRUE_COLOR: [f32; 3] = [1.0, 1.0, 1.0];
+
+pub struct PivPlugin;
+
+impl Plugin for PivPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(Piv::default())
+            .add_startup_system(piv_setup.system())
+            .add_system(piv_keys_system.system())
+            .add_system(piv_system.system())
+            .add_system(piv_render_system.system());
+    }
+}
+
+struct PivLayer;
+
+/// Velocity of an interrogation window, measured and true
+pub struct PivVector {
+    /// Center of the window, in cells
+    pub center: Vec2,
+    pub measured: Vec2,
+    /// Mean velocity of the cells of the window
+    pub truth: Vec2,
+}
+
+pub struct Piv {
+    pub active: bool,
+    /// Seeding particles, in cells
+    particles: Vec<Vec2>,
+    pub vectors: Vec<PivVector>,
+    /// Root mean square of the difference between the measured and true velocities
+    pub rms_error: f32,
+    /// Simulated time of the last step measured, so a paused frame isn't measured again
+    last_time: f32,
+}
+
+impl Default for Piv {
+    fn default() -> Self {
+        Self {
+            active: false,
+            particles: Vec::new(),
+            vectors: Vec::new(),
+            rms_error: 0.0,
+            last_time: f32::NAN,
+        }
+    }
+}
+
+impl Piv {
+    pub fn memory_bytes(&self) -> usize {
+        self.particles.capacity() * std::mem::size_of::<Vec2>()
+            + self.vectors.capacity() * std::mem::size_of::<PivVector>()
+    }
+
+    /// Scatter the particles at random over the grid
+    fn seed(&mut self, width: usize, height: usize) {
+        let windows = (width * height * PIXELS_PER_CELL * PIXELS_PER_CELL) / (WINDOW * WINDOW);
+        self.particles = (0..windows.max(1) * PARTICLES_PER_WINDOW)
+            .map(|_| {
+                let (x, y): (f32, f32) = rand::random();
+                Vec2::new(x * width as f32, y * height as f32) - Vec2::splat(0.5)
+            })
+            .collect();
+    }
+}
+
+/// Synthetic image of the particles, row by row from the bottom like the grid
+struct ParticleImage {
+    width: usize,
+    height: usize,
+    pixels: Vec<f32>,
+}
+
+impl ParticleImage {
+    fn new(particles: &[Vec2], width: usize, height: usize) -> Self {
+        let mut image = Self {
+            width,
+            height,
+            pixels: vec![0.0; width * height],
+        };
+        let reach = (3.0 * PARTICLE_SIGMA).ceil() as isize;
+        for particle in particles {
+            // Pixel centers at (i + 0.5) / PIXELS_PER_CELL - 0.5 in cells
+            let center = (*particle + Vec2::splat(0.5)) * PIXELS_PER_CELL as f32 - Vec2::splat(0.5);
+            let (cx, cy) = (center.x.round() as isize, center.y.round() as isize);
+            for j in cy - reach..=cy + reach {
+                for i in cx - reach..=cx + reach {
+                    if i < 0 || j < 0 || i >= width as isize || j >= height as isize {
+                        continue;
+                    }
+                    let distance = Vec2::new(i as f32, j as f32).distance_squared(center);
+                    let intensity = (-distance / (2.0 * PARTICLE_SIGMA * PARTICLE_SIGMA)).exp();
+                    image.pixels[j as usize * width + i as usize] += intensity;
+                }
+            }
+        }
+        image
+    }
+
+    fn get(&self, i: isize, j: isize) -> f32 {
+        if i < 0 || j < 0 || i >= self.width as isize || j >= self.height as isize {
+            0.0
+        } else {
+            self.pixels[j as usize * self.width + i as usize]
+        }
+    }
+}
+
+/// Displacement of the window at `origin` from the first image to the second, in pixels:
+/// the peak of their cross-correlation, refined to a fraction of a pixel by fitting a
+/// Gaussian through it and its neighbors. None without any particle in the window.
+fn correlate(
+    first: &ParticleImage,
+    second: &ParticleImage,
+    origin: (usize, usize),
+) -> Option<Vec2> {
+    let (ox, oy) = (origin.0 as isize, origin.1 as isize);
+    let window = WINDOW as isize;
+    let mean = |image: &ParticleImage, dx: isize, dy: isize| {
+        let mut sum = 0.0;
+        for j in 0..window {
+            for i in 0..window {
+                sum += image.get(ox + i + dx, oy + j + dy);
+            }
+        }
+        sum / (window * window) as f32
+    };
+    let first_mean = mean(first, 0, 0);
+    if first_mean == 0.0 {
+        return None;
+    }
+
+    let side = (2 * MAX_SHIFT + 1) as usize;
+    let mut correlation = vec![0.0; side * side];
+    for dy in -MAX_SHIFT..=MAX_SHIFT {
+        for dx in -MAX_SHIFT..=MAX_SHIFT {
+            let second_mean = mean(second, dx, dy);
+            let mut sum = 0.0;
+            for j in 0..window {
+                for i in 0..window {
+                    let a = first.get(ox + i, oy + j) - first_mean;
+                    let b = second.get(ox + i + dx, oy + j + dy) - second_mean;
+                    sum += a * b;
+                }
+            }
+            correlation[(dy + MAX_SHIFT) as usize * side + (dx + MAX_SHIFT) as usize] = sum;
+        }
+    }
+
+    let peak = (0..correlation.len()).max_by(|&a, &b| {
+        correlation[a]
+            .partial_cmp(&correlation[b])
+            .unwrap_or(std::cmp::Ordering::Equal)
+    })?;
+    let (px, py) = (peak % side, peak / side);
+    let at = |x: usize, y: usize| correlation[y * side + x];
+    // Three point Gaussian fit, not on the edges of the search nor with nonpositive values
+    let refine = |low: Option<f32>, center: f32, high: Option<f32>| match (low, high) {
+        (Some(low), Some(high)) if low > 0.0 && center > 0.0 && high > 0.0 => {
+            let (low, center, high) = (low.ln(), center.ln(), high.ln());
+            let denominator = 2.0 * (low - 2.0 * center + high);
+            if denominator < 0.0 {
+                (low - high) / denominator
+            } else {
+                0.0
+            }
+        }
+        _ => 0.0,
+    };
+    let center = at(px, py);
+    let fraction_x = refine(
+        px.checked_sub(1).map(|x| at(x, py)),
+        center,
+        (px + 1 < side).then(|| at(px + 1, py)),
+    );
+    let fraction_y = refine(
+        py.checked_sub(1).map(|y| at(px, y)),
+        center,
+        (py + 1 < side).then(|| at(px, py + 1)),
+    );
+    Some(Vec2::new(
+        px as f32 - MAX_SHIFT as f32 + fraction_x,
+        py as f32 - MAX_SHIFT as f32 + fraction_y,
+    ))
+}
+
+/// Measure the velocity of every interrogation window, the second image taken once the
+/// fastest particles moved by the target shift
+fn measure(piv: &mut Piv, grid: &Grid, settings: &SolverSettings) {
+    piv.vectors.clear();
+    let max_speed = grid
+        .0
+        .iter()
+        .flatten()
+        .map(|cell| cell.velocity.length())
+        .fold(0.0, f32::max);
+    if max_speed == 0.0 {
+        piv.rms_error = 0.0;
+        return;
+    }
+    let separation = TARGET_SHIFT / (max_speed * PIXELS_PER_CELL as f32);
+    let moved: Vec<Vec2> = piv
+        .particles
+        .iter()
+        .map(|&particle| tracers::advect_tracer(grid, settings, particle, separation))
+        .collect();
+
+    let (width, height) = (grid.width(), grid.height());
+    let (image_width, image_height) = (width * PIXELS_PER_CELL, height * PIXELS_PER_CELL);
+    let first = ParticleImage::new(&piv.particles, image_width, image_height);
+    let second = ParticleImage::new(&moved, image_width, image_height);
+
+    let cells = WINDOW / PIXELS_PER_CELL;
+    let mut squared_error = 0.0;
+    for wy in 0..image_height / WINDOW {
+        for wx in 0..image_width / WINDOW {
+            let shift = match correlate(&first, &second, (wx * WINDOW, wy * WINDOW)) {
+                Some(shift) => shift,
+                None => continue,
+            };
+            let measured = shift / PIXELS_PER_CELL as f32 / separation;
+            let (x0, y0) = (wx * cells, wy * cells);
+            let truth = (y0..y0 + cells)
+                .flat_map(|y| (x0..x0 + cells).map(move |x| (x, y)))
+                .map(|(x, y)| grid.0[y][x].velocity)
+                .fold(Vec2::ZERO, |sum, velocity| sum + velocity)
+                / (cells * cells) as f32;
+            squared_error += (measured - truth).length_squared();
+            piv.vectors.push(PivVector {
+                center: Vec2::new(x0 as f32, y0 as f32) + Vec2::splat((cells - 1) as f32 / 2.0),
+                measured,
+                truth,
+            });
+        }
+    }
+    piv.rms_error = if piv.vectors.is_empty() {
+        0.0
+    } else {
+        (squared_error / piv.vectors.len() as f32).sqrt()
+    };
+}
+
+fn piv_setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut pipelines: ResMut<Assets<PipelineDescriptor>>,
+    mut shaders: ResMut<Assets<Shader>>,
+    support: Res<ShaderSupport>,
+    layers: Res<Layers>,
+) {
+    if let Some(pipeline) = lines::vertex_color_pipeline(
+        &support,
+        &mut pipelines,
+        &mut shaders,
+        layers.blend(Layer::Overlays),
+    ) {
+        lines::spawn_line_layer(&mut commands, &mut meshes, pipeline, 0.3, PivLayer);
+    }
+}
+
+/// Ctrl+I starts the measurements from a fresh seeding, or stops them
+fn piv_keys_system(keyboard_input: Res<Input<KeyCode>>, mut piv: ResMut<Piv>) {
+    let ctrl =
+        keyboard_input.pressed(KeyCode::LControl) || keyboard_input.pressed(KeyCode::RControl);
+    if ctrl && keyboard_input.just_pressed(KeyCode::I) {
+        piv.active = !piv.active;
+        piv.particles.clear();
+        piv.vectors.clear();
+    }
+}
+
+/// Move the particles with the flow and measure the velocity after every step
+fn piv_system(
+    control: Res<StepControl>,
+    settings: Res<SolverSettings>,
+    mut piv: ResMut<Piv>,
+    qg: Query<&Grid>,
+) {
+    if !piv.active || control.time == piv.last_time {
+        return;
+    }
+    let grid = match qg.single() {
+        Ok(grid) => grid,
+        Err(_) => return,
+    };
+
+    if piv.particles.is_empty() {
+        piv.seed(grid.width(), grid.height());
+    } else {
+        let dt = control.dt;
+        for particle in piv.particles.iter_mut() {
+            *particle = tracers::advect_tracer(grid, &settings, *particle, dt);
+        }
+    }
+    measure(&mut piv, grid, &settings);
+    piv.last_time = control.time;
+}
+
+/// The measured and true velocities of the windows, scaled so the fastest spans a window
+fn piv_render_system(
+    piv: Res<Piv>,
+    qg: Query<&Grid>,
+    mut errors: ResMut<ErrorLog>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut query: Query<(&PivLayer, &Handle<Mesh>, &mut Visible)>,
+) {
+    let grid = match qg.single() {
+        Ok(grid) => grid,
+        Err(_) => return,
+    };
+
+    for (_layer, mesh_handle, mut visible) in query.iter_mut() {
+        visible.is_visible = piv.active;
+        if !piv.active {
+            continue;
+        }
+
+        let (width, height) = (grid.width(), grid.height());
+        let max_len = piv
+            .vectors
+            .iter()
+            .map(|vector| vector.measured.length().max(vector.truth.length()))
+            .fold(0.0, f32::max);
+        let scale = if max_len > 0.0 {
+            CELL_SIZE * (WINDOW / PIXELS_PER_CELL) as f32 / max_len
+        } else {
+            0.0
+        };
+
+        let mut segments: Vec<Segment> = Vec::with_capacity(2 * piv.vectors.len());
+        for vector in piv.vectors.iter() {
+            let start = grid_to_world(vector.center, width, height);
+            segments.push((start, start + vector.truth * scale, TRUE_COLOR));
+            segments.push((start, start + vector.measured * scale, MEASURED_COLOR));
+        }
+        match meshes.get_mut(&*mesh_handle) {
+            Some(mesh) => lines::set_segments(mesh, &segments),
+            None => errors.report("Missing mesh of the PIV vectors"),
+        }
+    }
+}
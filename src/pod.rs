@@ -0,0 +1,226 @@
+use std::fs;
+use std::path::Path;
+
+use bevy::prelude::*;
+
+use crate::emitters;
+use crate::inflow::Inflows;
+use crate::memory::{self, MemoryBudget};
+use crate::post::Frame;
+use crate::render;
+use crate::scene_file::SceneFile;
+use crate::settings::SolverSettings;
+use crate::solver::{self, Scratch, Splats};
+use crate::weather::Weather;
+use crate::CELL_SIZE;
+
+// Proper orthogonal decomposition: runs a scene file without a window, collects snapshots of
+// the velocity and splits their fluctuation around the mean into the modes holding the most
+// kinetic energy. The modes are the left singular vectors of the snapshot matrix, found by
+// the method of snapshots: the eigenvectors of the small matrix of the dot products of every
+// pair of snapshots, mapped back onto the grid. Each of the first modes is written as an
+// image, its direction as the hue and its magnitude as the lightness, and the energy of
+// every mode to energy.csv.
+
+const STEP_DT: f32 = 1.0 / 60.0;
+/// Sweeps of the eigenvalue solve, far more than its usual convergence in a handful
+const MAX_SWEEPS: usize = 100;
+
+pub struct PodOptions {
+    /// Overrides the grid size of the scene file
+    pub grid_size: Option<(usize, usize)>,
+    pub snapshots: usize,
+    /// Simulation steps between two snapshots
+    pub interval: usize,
+    /// Modes written as images
+    pub modes: usize,
+}
+
+/// Run the analysis, returning the energy fraction of every mode, the most energetic first
+pub fn run(
+    scene_path: &Path,
+    out: &Path,
+    options: &PodOptions,
+    settings: &SolverSettings,
+    budget: &MemoryBudget,
+) -> Result<Vec<f64>, String> {
+    if options.snapshots < 2 {
+        return Err("the decomposition needs at least 2 snapshots".to_string());
+    }
+    if options.interval == 0 {
+        return Err("the snapshots need at least one step between them".to_string());
+    }
+
+    let file = SceneFile::load(scene_path)?;
+    let (width, height) = options.grid_size.unwrap_or_else(|| file.grid_size());
+    memory::check(width, height, budget)?;
+    let snapshot_bytes = options.snapshots * width * height * std::mem::size_of::<Vec2>();
+    if memory::estimate(width, height) + snapshot_bytes > budget.0 {
+        return Err(format!(
+            "{} snapshots of a {}x{} grid need {}, over the memory budget",
+            options.snapshots,
+            width,
+            height,
+            memory::format_mb(snapshot_bytes)
+        ));
+    }
+    let mut grid = file.scene.build(width, height);
+    let mut splats = Splats::default();
+    let mut scratch = Scratch::default();
+    let mut weather = Weather::new(file.wind);
+    let mut fans = file.fans.clone();
+    let mut inflows = Inflows::new(file.inflows.clone());
+    let mut settings = SolverSettings { ..*settings };
+    if let Some(material) = file.material {
+        material.apply(&mut settings);
+    }
+
+    let mut snapshots: Vec<Vec<Vec2>> = Vec::with_capacity(options.snapshots);
+    while snapshots.len() < options.snapshots {
+        for _ in 0..options.interval {
+            emitters::inject(&mut grid, &file.emitters, STEP_DT);
+            for fan in fans.iter_mut() {
+                fan.blow(&mut grid, STEP_DT);
+            }
+            weather.blow(&mut grid, STEP_DT);
+            inflows.feed(&mut grid, STEP_DT, &mut scratch);
+            solver::step(&mut grid, STEP_DT, &settings, &mut splats, &mut scratch);
+        }
+        snapshots.push(grid.0.iter().flatten().map(|cell| cell.velocity).collect());
+    }
+
+    // Fluctuation around the mean of the snapshots
+    let count = snapshots.len();
+    let mut mean = vec![Vec2::ZERO; width * height];
+    for snapshot in snapshots.iter() {
+        for (mean, velocity) in mean.iter_mut().zip(snapshot.iter()) {
+            *mean += *velocity / count as f32;
+        }
+    }
+    for snapshot in snapshots.iter_mut() {
+        for (velocity, mean) in snapshot.iter_mut().zip(mean.iter()) {
+            *velocity -= *mean;
+        }
+    }
+
+    let correlation: Vec<Vec<f64>> = snapshots
+        .iter()
+        .map(|a| snapshots.iter().map(|b| dot(a, b)).collect())
+        .collect();
+    let (eigenvalues, eigenvectors) = symmetric_eigen(correlation);
+    let mut order: Vec<usize> = (0..count).collect();
+    // A blown up simulation can leave NaN eigenvalues, sorted last with no energy
+    let key = |k: usize| match eigenvalues[k] {
+        value if value.is_nan() => f64::NEG_INFINITY,
+        value => value,
+    };
+    order.sort_by(|&a, &b| key(b).total_cmp(&key(a)));
+    // Rounding leaves the eigenvalues of the missing energy slightly negative
+    let energies: Vec<f64> = order.iter().map(|&k| eigenvalues[k].max(0.0)).collect();
+    let total: f64 = energies.iter().sum();
+    if total == 0.0 {
+        return Err("the velocity doesn't change between the snapshots".to_string());
+    }
+
+    fs::create_dir_all(out).map_err(|err| err.to_string())?;
+    for (i, &k) in order.iter().take(options.modes).enumerate() {
+        if energies[i] == 0.0 {
+            break;
+        }
+        let mut mode = vec![Vec2::ZERO; width * height];
+        for (snapshot, &weight) in snapshots.iter().zip(eigenvectors.iter().map(|v| &v[k])) {
+            for (mode, velocity) in mode.iter_mut().zip(snapshot.iter()) {
+                *mode += *velocity * weight as f32;
+            }
+        }
+        let path = out.join(format!("mode_{}.png", i + 1));
+        render::frame_image(&mode_frame(&mode, width), CELL_SIZE as u32)
+            .save(&path)
+            .map_err(|err| format!("{}: {}", path.display(), err))?;
+    }
+
+    let fractions: Vec<f64> = energies.iter().map(|energy| energy / total).collect();
+    let mut csv = String::from("mode,eigenvalue,energy_fraction,cumulative_fraction\n");
+    let mut cumulative = 0.0;
+    for (i, (energy, fraction)) in energies.iter().zip(fractions.iter()).enumerate() {
+        cumulative += fraction;
+        csv += &format!("{},{},{},{}\n", i + 1, energy, fraction, cumulative);
+    }
+    let path = out.join("energy.csv");
+    fs::write(&path, csv).map_err(|err| format!("{}: {}", path.display(), err))?;
+
+    Ok(fractions)
+}
+
+fn dot(a: &[Vec2], b: &[Vec2]) -> f64 {
+    a.iter().zip(b.iter()).map(|(a, b)| a.dot(*b) as f64).sum()
+}
+
+/// Eigenvalues and eigenvectors of a symmetric matrix by cyclic Jacobi rotations, the
+/// eigenvector of the `k`th eigenvalue being the column `k`
+fn symmetric_eigen(mut a: Vec<Vec<f64>>) -> (Vec<f64>, Vec<Vec<f64>>) {
+    let n = a.len();
+    let mut v: Vec<Vec<f64>> = (0..n)
+        .map(|i| (0..n).map(|j| if i == j { 1.0 } else { 0.0 }).collect())
+        .collect();
+    let norm: f64 = a.iter().flatten().map(|value| value * value).sum();
+
+    for _ in 0..MAX_SWEEPS {
+        let off_diagonal: f64 = (0..n)
+            .flat_map(|p| (p + 1..n).map(move |q| (p, q)))
+            .map(|(p, q)| a[p][q] * a[p][q])
+            .sum();
+        if off_diagonal <= 1e-24 * norm {
+            break;
+        }
+
+        for p in 0..n {
+            for q in p + 1..n {
+                if a[p][q] == 0.0 {
+                    continue;
+                }
+                // Rotation in the plane of p and q zeroing a[p][q]
+                let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+                for row in a.iter_mut().chain(v.iter_mut()) {
+                    let (kp, kq) = (row[p], row[q]);
+                    row[p] = c * kp - s * kq;
+                    row[q] = s * kp + c * kq;
+                }
+                for k in 0..n {
+                    let (pk, qk) = (a[p][k], a[q][k]);
+                    a[p][k] = c * pk - s * qk;
+                    a[q][k] = s * pk + c * qk;
+                }
+            }
+        }
+    }
+
+    ((0..n).map(|k| a[k][k]).collect(), v)
+}
+
+/// Direction of a mode as the hue and its magnitude relative to the largest as the lightness
+fn mode_frame(mode: &[Vec2], width: usize) -> Frame {
+    let max = mode
+        .iter()
+        .map(|velocity| velocity.length())
+        .fold(0.0, f32::max);
+    mode.chunks(width)
+        .map(|row| {
+            row.iter()
+                .map(|velocity| {
+                    let hue = velocity.y.atan2(velocity.x).to_degrees().rem_euclid(360.0);
+                    let lightness = if max > 0.0 {
+                        0.5 * velocity.length() / max
+                    } else {
+                        0.0
+                    };
+                    let [r, g, b, _] = Color::hsl(hue, 1.0, lightness).as_rgba_f32();
+                    Vec3::new(r, g, b)
+                })
+                .collect()
+        })
+        .collect()
+}
@@ -0,0 +1,270 @@
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::layers::Layers;
+use crate::palette::Palette;
+use crate::species;
+use crate::Grid;
+
+// Post effects applied in order to the colors of the cells before they're displayed,
+// declared in the scene file so a look can be reproduced.
+
+/// Colors of the cells, indexed by row then column
+pub type Frame = Vec<Vec<Vec3>>;
+
+/// Most copies the depth effect draws, each one costing a pass over the frame
+const MAX_DEPTH_LAYERS: usize = 8;
+
+#[derive(Clone, Debug, Deserialize)]
+pub enum PostEffect {
+    /// Box blur over `radius` cells
+    Blur { radius: usize },
+    /// Blurred glow around the colors brighter than `threshold`
+    Bloom {
+        threshold: f32,
+        intensity: f32,
+        radius: usize,
+    },
+    /// Offset the image along the density gradient, like light going through the fluid
+    Refraction { strength: f32 },
+    /// Darken the corners, 0 leaves them as is and 1 makes them black
+    Vignette { strength: f32 },
+    /// Fake depth: `layers` copies of the image seen through the gaps of the one in front,
+    /// each shifted `offset` cells further up and to the right and darker, like parallax
+    Depth { layers: usize, offset: f32 },
+}
+
+impl PostEffect {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Blur { .. } => "Blur",
+            Self::Bloom { .. } => "Bloom",
+            Self::Refraction { .. } => "Refraction",
+            Self::Vignette { .. } => "Vignette",
+            Self::Depth { .. } => "Depth",
+        }
+    }
+
+    /// Check the parameters are in range, naming the wrong one
+    pub fn validate(&self) -> Result<(), String> {
+        let nonnegative = |field: &str, value: f32| {
+            if value.is_finite() && value >= 0.0 {
+                Ok(())
+            } else {
+                Err(format!(
+                    "{} must be a nonnegative number, not {}",
+                    field, value
+                ))
+            }
+        };
+
+        match *self {
+            Self::Blur { .. } => Ok(()),
+            Self::Bloom {
+                threshold,
+                intensity,
+                ..
+            } => nonnegative("threshold", threshold).and(nonnegative("intensity", intensity)),
+            Self::Refraction { strength } if !strength.is_finite() => {
+                Err(format!("strength must be a number, not {}", strength))
+            }
+            Self::Refraction { .. } => Ok(()),
+            Self::Vignette { strength } if !(0.0..=1.0).contains(&strength) => Err(format!(
+                "strength must be between 0 and 1, not {}",
+                strength
+            )),
+            Self::Vignette { .. } => Ok(()),
+            Self::Depth { layers, .. } if !(1..=MAX_DEPTH_LAYERS).contains(&layers) => {
+                Err(format!(
+                    "layers must be between 1 and {}, not {}",
+                    MAX_DEPTH_LAYERS, layers
+                ))
+            }
+            Self::Depth { offset, .. } if !offset.is_finite() => {
+                Err(format!("offset must be a number, not {}", offset))
+            }
+            Self::Depth { .. } => Ok(()),
+        }
+    }
+}
+
+/// Bilinear interpolation of a frame at a position in cells, clamped to its edges
+pub fn sample(frame: &Frame, x: f32, y: f32) -> Vec3 {
+    let height = frame.len();
+    let width = frame[0].len();
+    let at = |x: isize, y: isize| {
+        frame[y.max(0).min(height as isize - 1) as usize][x.max(0).min(width as isize - 1) as usize]
+    };
+
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let (x0, y0) = (x0 as isize, y0 as isize);
+    let bottom = at(x0, y0).lerp(at(x0 + 1, y0), fx);
+    let top = at(x0, y0 + 1).lerp(at(x0 + 1, y0 + 1), fx);
+    bottom.lerp(top, fy)
+}
+
+/// Effects chain of the current scene
+#[derive(Default)]
+pub struct PostEffects(pub Vec<PostEffect>);
+
+/// Final colors of the grid, with the density and dye blended as the layers say, then the
+/// palette and the post effects applied
+pub fn compose(grid: &Grid, palette: &Palette, layers: &Layers, effects: &[PostEffect]) -> Frame {
+    let mut frame: Frame = grid
+        .0
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|cell| {
+                    let dye = cell.dye + species::color(cell);
+                    palette.apply(layers.cell_color(cell.density, dye))
+                })
+                .collect()
+        })
+        .collect();
+
+    for effect in effects {
+        frame = match *effect {
+            PostEffect::Blur { radius } => blur(&frame, radius),
+            PostEffect::Bloom {
+                threshold,
+                intensity,
+                radius,
+            } => bloom(&frame, threshold, intensity, radius),
+            PostEffect::Refraction { strength } => refraction(&frame, grid, strength),
+            PostEffect::Vignette { strength } => vignette(&frame, strength),
+            PostEffect::Depth { layers, offset } => depth(&frame, layers, offset),
+        };
+    }
+
+    frame
+}
+
+/// Clamp a coordinate to the frame
+fn clamp(v: isize, len: usize) -> usize {
+    v.max(0).min(len as isize - 1) as usize
+}
+
+fn blur(frame: &Frame, radius: usize) -> Frame {
+    let height = frame.len();
+    let width = frame[0].len();
+    let r = radius as isize;
+    let count = (2 * radius + 1) as f32;
+
+    // Separable: rows first, then columns
+    let horizontal: Frame = (0..height)
+        .map(|y| {
+            (0..width)
+                .map(|x| {
+                    let sum: Vec3 = (-r..=r)
+                        .map(|i| frame[y][clamp(x as isize + i, width)])
+                        .sum();
+                    sum / count
+                })
+                .collect()
+        })
+        .collect();
+
+    (0..height)
+        .map(|y| {
+            (0..width)
+                .map(|x| {
+                    let sum: Vec3 = (-r..=r)
+                        .map(|j| horizontal[clamp(y as isize + j, height)][x])
+                        .sum();
+                    sum / count
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn bloom(frame: &Frame, threshold: f32, intensity: f32, radius: usize) -> Frame {
+    let bright: Frame = frame
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|c| (*c - Vec3::splat(threshold)).max(Vec3::ZERO))
+                .collect()
+        })
+        .collect();
+    let glow = blur(&bright, radius);
+
+    frame
+        .iter()
+        .zip(glow)
+        .map(|(row, glow_row)| {
+            row.iter()
+                .zip(glow_row)
+                .map(|(c, g)| *c + intensity * g)
+                .collect()
+        })
+        .collect()
+}
+
+fn refraction(frame: &Frame, grid: &Grid, strength: f32) -> Frame {
+    let height = frame.len();
+    let width = frame[0].len();
+    let density = |x: isize, y: isize| grid.0[clamp(y, height)][clamp(x, width)].density;
+
+    (0..height)
+        .map(|y| {
+            (0..width)
+                .map(|x| {
+                    let (xi, yi) = (x as isize, y as isize);
+                    let gradient = Vec2::new(
+                        density(xi + 1, yi) - density(xi - 1, yi),
+                        density(xi, yi + 1) - density(xi, yi - 1),
+                    ) / 2.0;
+                    let offset = (gradient * strength).round();
+                    frame[clamp(yi + offset.y as isize, height)]
+                        [clamp(xi + offset.x as isize, width)]
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn vignette(frame: &Frame, strength: f32) -> Frame {
+    let height = frame.len();
+    let width = frame[0].len();
+    let center = Vec2::new(width as f32 - 1.0, height as f32 - 1.0) / 2.0;
+    let max_distance = center.length().max(1.0);
+
+    (0..height)
+        .map(|y| {
+            (0..width)
+                .map(|x| {
+                    let distance = (Vec2::new(x as f32, y as f32) - center).length() / max_distance;
+                    frame[y][x] * (1.0 - strength * distance * distance).max(0.0)
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn depth(frame: &Frame, layers: usize, offset: f32) -> Frame {
+    let height = frame.len();
+    let width = frame[0].len();
+
+    (0..height)
+        .map(|y| {
+            (0..width)
+                .map(|x| {
+                    let mut color = frame[y][x];
+                    for i in 1..=layers {
+                        // Further back, further shifted and darker
+                        let shift = i as f32 * offset;
+                        let shade = 1.0 - i as f32 / (layers + 1) as f32;
+                        let behind = sample(frame, x as f32 - shift, y as f32 - shift);
+                        // Seen only where the layers in front let it through
+                        let coverage = color.max_element().min(1.0).max(0.0);
+                        color += behind * shade * (1.0 - coverage);
+                    }
+                    color
+                })
+                .collect()
+        })
+        .collect()
+}
@@ -0,0 +1,146 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::emitters;
+use crate::inflow::Inflows;
+use crate::layers::Layers;
+use crate::memory::{self, MemoryBudget};
+use crate::palette::Palette;
+use crate::post::{self, Frame};
+use crate::scene_file::SceneFile;
+use crate::settings::SolverSettings;
+use crate::solver::{self, Scratch, Splats};
+use crate::weather::Weather;
+
+// Poster exports: simulates a scene, then draws the final frame tile by tile and
+// streams the rows of tiles to the PNG, so the whole image never sits in memory.
+
+/// Side of a tile, in pixels
+const TILE_SIZE: u32 = 512;
+const STEP_DT: f32 = 1.0 / 60.0;
+
+pub struct PosterOptions {
+    /// Overrides the grid size of the scene file
+    pub grid_size: Option<(usize, usize)>,
+    pub steps: usize,
+    /// Pixels per cell
+    pub scale: u32,
+}
+
+pub fn render_poster(
+    scene_path: &Path,
+    out: &Path,
+    options: &PosterOptions,
+    settings: &SolverSettings,
+    budget: &MemoryBudget,
+) -> Result<(), String> {
+    if options.scale == 0 {
+        return Err("the scale must be at least one pixel per cell".to_string());
+    }
+
+    let file = SceneFile::load(scene_path)?;
+    let (width, height) = options.grid_size.unwrap_or_else(|| file.grid_size());
+    memory::check(width, height, budget)?;
+    let mut grid = file.scene.build(width, height);
+    let mut splats = Splats::default();
+    let mut scratch = Scratch::default();
+    let mut weather = Weather::new(file.wind);
+    let mut fans = file.fans.clone();
+    let mut inflows = Inflows::new(file.inflows.clone());
+    let mut settings = SolverSettings { ..*settings };
+    if let Some(material) = file.material {
+        material.apply(&mut settings);
+    }
+
+    for _ in 0..options.steps {
+        emitters::inject(&mut grid, &file.emitters, STEP_DT);
+        for fan in fans.iter_mut() {
+            fan.blow(&mut grid, STEP_DT);
+        }
+        weather.blow(&mut grid, STEP_DT);
+        inflows.feed(&mut grid, STEP_DT, &mut scratch);
+        solver::step(&mut grid, STEP_DT, &settings, &mut splats, &mut scratch);
+    }
+    let frame = post::compose(
+        &grid,
+        &Palette::default(),
+        &Layers::from_styles(&file.layers),
+        &file.post_effects,
+    );
+
+    let image_width = width as u32 * options.scale;
+    let image_height = height as u32 * options.scale;
+    write_tiled(&frame, options.scale, image_width, image_height, out)
+        .map_err(|err| format!("{}: {}", out.display(), err))
+}
+
+fn write_tiled(
+    frame: &Frame,
+    scale: u32,
+    image_width: u32,
+    image_height: u32,
+    out: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(out)?),
+        image_width,
+        image_height,
+    );
+    encoder.set_color(png::ColorType::RGB);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    let mut stream = writer.stream_writer();
+
+    let row_bytes = image_width as usize * 3;
+    for tile_y in (0..image_height).step_by(TILE_SIZE as usize) {
+        let rows = TILE_SIZE.min(image_height - tile_y);
+        let mut strip = vec![0; row_bytes * rows as usize];
+
+        for tile_x in (0..image_width).step_by(TILE_SIZE as usize) {
+            let columns = TILE_SIZE.min(image_width - tile_x);
+            let tile = render_tile(
+                frame,
+                scale,
+                image_height,
+                (tile_x, tile_y),
+                (columns, rows),
+            );
+
+            // Stitch the tile into the strip
+            for row in 0..rows as usize {
+                let start = row * row_bytes + tile_x as usize * 3;
+                let tile_row = &tile[row * columns as usize * 3..(row + 1) * columns as usize * 3];
+                strip[start..start + tile_row.len()].copy_from_slice(tile_row);
+            }
+        }
+
+        stream.write_all(&strip)?;
+    }
+
+    stream.finish()?;
+    Ok(())
+}
+
+/// RGB bytes of a tile, with the cells interpolated so they don't show up as squares
+fn render_tile(
+    frame: &Frame,
+    scale: u32,
+    image_height: u32,
+    origin: (u32, u32),
+    size: (u32, u32),
+) -> Vec<u8> {
+    let mut data = Vec::with_capacity((size.0 * size.1 * 3) as usize);
+    for py in origin.1..origin.1 + size.1 {
+        for px in origin.0..origin.0 + size.0 {
+            // Image rows go from top to bottom, grid rows from bottom to top
+            let x = (px as f32 + 0.5) / scale as f32 - 0.5;
+            let y = ((image_height - 1 - py) as f32 + 0.5) / scale as f32 - 0.5;
+            let color = post::sample(frame, x, y);
+
+            let to_byte = |c: f32| (c.min(1.0).max(0.0) * 255.0) as u8;
+            data.extend_from_slice(&[to_byte(color.x), to_byte(color.y), to_byte(color.z)]);
+        }
+    }
+    data
+}
@@ -0,0 +1,155 @@
+use std::fs;
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::accessibility::Accessibility;
+use crate::palette::{Palette, PaletteMode};
+use crate::scenes::{ScenePreset, SceneSelection};
+use crate::{HEIGHT, WIDTH};
+
+// User preferences, kept in the config directory of the platform and saved as soon as
+// they change. Physics settings belong to the scene files, not here. l toggles the theme.
+
+pub struct PrefsPlugin;
+
+impl Plugin for PrefsPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_startup_system(prefs_startup_system.system())
+            .add_system(theme_keys_system.system())
+            .add_system(prefs_save_system.system());
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Theme {
+    Dark,
+    Light,
+}
+
+impl Theme {
+    fn background(self) -> Color {
+        match self {
+            Self::Dark => Color::rgb(0.1, 0.1, 0.1),
+            Self::Light => Color::rgb(0.85, 0.85, 0.85),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UserPrefs {
+    pub theme: Theme,
+    /// Scene and grid size last picked in the menu
+    pub scene: ScenePreset,
+    pub grid_size: (usize, usize),
+    pub palette: PaletteMode,
+    pub palette_speed: f32,
+    pub speed_glyphs: bool,
+    pub ui_scale: usize,
+    pub arrow_occlusion: f32,
+}
+
+impl Default for UserPrefs {
+    fn default() -> Self {
+        let palette = Palette::default();
+        let accessibility = Accessibility::default();
+        Self {
+            theme: Theme::Dark,
+            scene: ScenePreset::ALL[0],
+            grid_size: (WIDTH, HEIGHT),
+            palette: palette.mode,
+            palette_speed: palette.speed,
+            speed_glyphs: accessibility.speed_glyphs,
+            ui_scale: accessibility.ui_scale,
+            arrow_occlusion: accessibility.arrow_occlusion,
+        }
+    }
+}
+
+impl UserPrefs {
+    fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("fluid_simulation").join("settings.ron"))
+    }
+
+    /// The saved preferences, or the default ones if there are none or they can't be read
+    pub fn load() -> Self {
+        let text = match Self::path().and_then(|path| fs::read_to_string(path).ok()) {
+            Some(text) => text,
+            None => return Self::default(),
+        };
+        ron::from_str(&text).unwrap_or_else(|err| {
+            eprintln!("Ignoring the invalid settings file: {}", err);
+            Self::default()
+        })
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let path = Self::path().ok_or("no config directory")?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+        }
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::new())
+            .map_err(|err| err.to_string())?;
+        fs::write(&path, text).map_err(|err| err.to_string())
+    }
+}
+
+fn prefs_startup_system(
+    prefs: Res<UserPrefs>,
+    mut palette: ResMut<Palette>,
+    mut accessibility: ResMut<Accessibility>,
+    mut clear_color: ResMut<ClearColor>,
+) {
+    palette.mode = prefs.palette;
+    palette.speed = prefs.palette_speed;
+    accessibility.speed_glyphs = prefs.speed_glyphs;
+    accessibility.ui_scale = prefs.ui_scale;
+    accessibility.arrow_occlusion = prefs.arrow_occlusion;
+    clear_color.0 = prefs.theme.background();
+}
+
+fn theme_keys_system(
+    mut prefs: ResMut<UserPrefs>,
+    mut clear_color: ResMut<ClearColor>,
+    mut char_input_events: EventReader<ReceivedCharacter>,
+) {
+    for event in char_input_events.iter() {
+        if event.char == 'l' {
+            prefs.theme = match prefs.theme {
+                Theme::Dark => Theme::Light,
+                Theme::Light => Theme::Dark,
+            };
+            clear_color.0 = prefs.theme.background();
+        }
+    }
+}
+
+/// Save the preferences whenever they differ from the last saved ones
+fn prefs_save_system(
+    selection: Res<SceneSelection>,
+    palette: Res<Palette>,
+    accessibility: Res<Accessibility>,
+    mut prefs: ResMut<UserPrefs>,
+    mut saved: Local<Option<UserPrefs>>,
+) {
+    prefs.scene = selection.scene();
+    prefs.grid_size = selection.grid_size();
+    prefs.palette = palette.mode;
+    prefs.palette_speed = palette.speed;
+    prefs.speed_glyphs = accessibility.speed_glyphs;
+    prefs.ui_scale = accessibility.ui_scale;
+    prefs.arrow_occlusion = accessibility.arrow_occlusion;
+
+    if saved.as_ref() == Some(&*prefs) {
+        return;
+    }
+    // Nothing changed yet on the first frame
+    if saved.is_some() {
+        if let Err(err) = prefs.save() {
+            warn!("Couldn't save the settings: {}", err);
+        }
+    }
+    *saved = Some(prefs.clone());
+}
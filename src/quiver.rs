@@ -0,0 +1,128 @@
+use bevy::prelude::*;
+use bevy::render::pipeline::PipelineDescriptor;
+
+use crate::errors::ErrorLog;
+use crate::layers::{Layer, Layers};
+use crate::lines::{self, Segment, ShaderSupport};
+use crate::viewport::Viewport;
+use crate::{grid_to_world, Grid, SolverSettings, CELL_SIZE};
+
+// Debug overlay sampling the velocity between the cell centers with the active
+// interpolation scheme, so its artifacts (blockiness, overshoot) show up as kinks in the quiver
+
+/// Samples per cell along each axis
+const UPSAMPLING: usize = 4;
+
+pub struct QuiverPlugin;
+
+impl Plugin for QuiverPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(QuiverOverlay { active: false })
+            .add_startup_system(quiver_setup.system())
+            .add_system(quiver_keys_system.system())
+            .add_system(quiver_render_system.system());
+    }
+}
+
+struct QuiverLayer;
+
+pub struct QuiverOverlay {
+    pub active: bool,
+}
+
+fn quiver_setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut pipelines: ResMut<Assets<PipelineDescriptor>>,
+    mut shaders: ResMut<Assets<Shader>>,
+    support: Res<ShaderSupport>,
+    layers: Res<Layers>,
+) {
+    if let Some(pipeline) = lines::vertex_color_pipeline(
+        &support,
+        &mut pipelines,
+        &mut shaders,
+        layers.blend(Layer::Overlays),
+    ) {
+        lines::spawn_line_layer(&mut commands, &mut meshes, pipeline, 0.3, QuiverLayer);
+    }
+}
+
+/// u toggles the overlay, i cycles through the interpolation schemes
+fn quiver_keys_system(
+    mut overlay: ResMut<QuiverOverlay>,
+    mut settings: ResMut<SolverSettings>,
+    mut char_input_events: EventReader<ReceivedCharacter>,
+) {
+    for event in char_input_events.iter() {
+        match event.char {
+            'u' => overlay.active = !overlay.active,
+            'i' => {
+                settings.interpolation = settings.interpolation.next();
+                info!("Interpolation: {:?}", settings.interpolation);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn quiver_render_system(
+    overlay: Res<QuiverOverlay>,
+    settings: Res<SolverSettings>,
+    viewport: Res<Viewport>,
+    qg: Query<&Grid>,
+    mut errors: ResMut<ErrorLog>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut query: Query<(&QuiverLayer, &Handle<Mesh>, &mut Visible)>,
+) {
+    if let Ok(grid) = qg.single() {
+        for (_layer, mesh_handle, mut visible) in query.iter_mut() {
+            visible.is_visible = overlay.active;
+            if !overlay.active {
+                continue;
+            }
+
+            // Only the cells in view, which are all of them unless the grid is bigger than
+            // the window
+            let (width, height) = (grid.width(), grid.height());
+            let (columns, rows) = viewport.visible_cells(grid);
+            let step = 1.0 / UPSAMPLING as f32;
+            let count = columns.len() * rows.len() * UPSAMPLING * UPSAMPLING;
+            let mut samples = Vec::with_capacity(count);
+            for j in rows.start * UPSAMPLING..rows.end * UPSAMPLING {
+                for i in columns.start * UPSAMPLING..columns.end * UPSAMPLING {
+                    // Centered in the sub-cells, cell centers being on integers
+                    let pos =
+                        Vec2::new(i as f32 * step, j as f32 * step) - Vec2::splat(0.5 - step / 2.0);
+                    samples.push((
+                        pos,
+                        grid.sample_velocity(pos, settings.interpolation, settings.boundary),
+                    ));
+                }
+            }
+
+            // Scale so the fastest sample spans a whole sub-cell
+            let max_len = samples
+                .iter()
+                .map(|(_, vel)| vel.length())
+                .fold(0.0, f32::max);
+            let scale = if max_len > 0.0 {
+                CELL_SIZE * step / max_len
+            } else {
+                0.0
+            };
+
+            let segments: Vec<Segment> = samples
+                .into_iter()
+                .map(|(pos, vel)| {
+                    let start = grid_to_world(pos, width, height);
+                    (start, start + vel * scale, [1.0, 1.0, 1.0])
+                })
+                .collect();
+            match meshes.get_mut(&*mesh_handle) {
+                Some(mesh) => lines::set_segments(mesh, &segments),
+                None => errors.report("Missing mesh of the quiver overlay"),
+            }
+        }
+    }
+}
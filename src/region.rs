@@ -0,0 +1,190 @@
+use bevy::prelude::*;
+use bevy::render::pipeline::PipelineDescriptor;
+
+use crate::errors::ErrorLog;
+use crate::layers::{Layer, Layers};
+use crate::lines::{self, ShaderSupport};
+use crate::viewport::Viewport;
+use crate::{grid_to_world, AppState, Cell, Grid};
+
+// Rectangular selections: drag with the right mouse button to select cells, c copies them,
+// v pastes them over the cells under the cursor and b adds them to those cells instead.
+// The cursor marks the bottom left corner of the pasted region.
+
+pub struct RegionPlugin;
+
+impl Plugin for RegionPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(RegionTool::default())
+            .add_startup_system(region_setup.system())
+            .add_system_set(
+                SystemSet::on_update(AppState::Running)
+                    .with_system(region_select_system.system())
+                    .with_system(region_keys_system.system())
+                    .with_system(region_outline_system.system()),
+            );
+    }
+}
+
+struct RegionOutline;
+
+#[derive(Default)]
+pub struct RegionTool {
+    /// Corners of the selection, in cells
+    selection: Option<((usize, usize), (usize, usize))>,
+    /// Copied cells, indexed by row then column
+    clipboard: Vec<Vec<Cell>>,
+}
+
+impl RegionTool {
+    pub fn memory_bytes(&self) -> usize {
+        self.clipboard
+            .iter()
+            .map(|row| row.capacity() * std::mem::size_of::<Cell>())
+            .sum()
+    }
+
+    /// Lower and upper corners of the selection, inclusive
+    pub fn bounds(&self) -> Option<((usize, usize), (usize, usize))> {
+        self.selection
+            .map(|(a, b)| ((a.0.min(b.0), a.1.min(b.1)), (a.0.max(b.0), a.1.max(b.1))))
+    }
+}
+
+fn cursor_cell(windows: &Windows, viewport: &Viewport, grid: &Grid) -> Option<(usize, usize)> {
+    let cursor = windows.get_primary()?.cursor_position()?;
+    viewport.cursor_cell(cursor, grid)
+}
+
+fn region_setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut pipelines: ResMut<Assets<PipelineDescriptor>>,
+    mut shaders: ResMut<Assets<Shader>>,
+    support: Res<ShaderSupport>,
+    layers: Res<Layers>,
+) {
+    if let Some(pipeline) = lines::vertex_color_pipeline(
+        &support,
+        &mut pipelines,
+        &mut shaders,
+        layers.blend(Layer::Overlays),
+    ) {
+        lines::spawn_line_layer(&mut commands, &mut meshes, pipeline, 0.4, RegionOutline);
+    }
+}
+
+fn region_select_system(
+    windows: Res<Windows>,
+    viewport: Res<Viewport>,
+    mouse_button_input: Res<Input<MouseButton>>,
+    mut tool: ResMut<RegionTool>,
+    qg: Query<&Grid>,
+) {
+    if let Ok(grid) = qg.single() {
+        if let Some(cell) = cursor_cell(&windows, &viewport, grid) {
+            if mouse_button_input.just_pressed(MouseButton::Right) {
+                tool.selection = Some((cell, cell));
+            } else if mouse_button_input.pressed(MouseButton::Right) {
+                if let Some((_, end)) = &mut tool.selection {
+                    *end = cell;
+                }
+            }
+        }
+    }
+}
+
+fn region_keys_system(
+    windows: Res<Windows>,
+    viewport: Res<Viewport>,
+    mut tool: ResMut<RegionTool>,
+    mut qg: Query<&mut Grid>,
+    mut char_input_events: EventReader<ReceivedCharacter>,
+) {
+    if let Ok(mut grid) = qg.single_mut() {
+        for event in char_input_events.iter() {
+            match event.char {
+                'c' => {
+                    if let Some(((x0, y0), (x1, y1))) = tool.bounds() {
+                        tool.clipboard = (y0..=y1).map(|y| grid.0[y][x0..=x1].to_vec()).collect();
+                        info!("Copied {}x{} cells", x1 - x0 + 1, y1 - y0 + 1);
+                    }
+                }
+                c @ 'v' | c @ 'b' => {
+                    let (origin_x, origin_y) = match cursor_cell(&windows, &viewport, &grid) {
+                        Some(origin) => origin,
+                        None => continue,
+                    };
+                    let (width, height) = (grid.width(), grid.height());
+
+                    // Pasted cells that don't fit wrap around like the rest of the grid
+                    for (j, row) in tool.clipboard.iter().enumerate() {
+                        for (i, source) in row.iter().enumerate() {
+                            let x = (origin_x + i) % width;
+                            let y = (origin_y + j) % height;
+                            let target = &mut grid.0[y][x];
+                            if c == 'v' {
+                                *target = source.clone();
+                            } else if !target.obstacle && !source.obstacle {
+                                // Only pasting brings obstacles, adding mixes fluid
+                                target.velocity += source.velocity;
+                                target.density += source.density;
+                                target.dye += source.dye;
+                                target.temperature += source.temperature;
+                                for (amount, added) in
+                                    target.species.iter_mut().zip(source.species.iter())
+                                {
+                                    *amount += added;
+                                }
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn region_outline_system(
+    tool: Res<RegionTool>,
+    qg: Query<&Grid>,
+    mut errors: ResMut<ErrorLog>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut query: Query<(&RegionOutline, &Handle<Mesh>, &mut Visible)>,
+) {
+    if let Ok(grid) = qg.single() {
+        for (_outline, mesh_handle, mut visible) in query.iter_mut() {
+            let ((x0, y0), (x1, y1)) = match tool.bounds() {
+                Some(bounds) => bounds,
+                None => {
+                    visible.is_visible = false;
+                    continue;
+                }
+            };
+            visible.is_visible = true;
+
+            // The outline goes around the cells, half a cell away from their centers
+            let (width, height) = (grid.width(), grid.height());
+            let low = Vec2::new(x0 as f32 - 0.5, y0 as f32 - 0.5);
+            let high = Vec2::new(x1 as f32 + 0.5, y1 as f32 + 0.5);
+            let low = grid_to_world(low, width, height);
+            let high = grid_to_world(high, width, height);
+            let color = [1.0, 1.0, 1.0];
+            let corners = [
+                low,
+                Vec2::new(high.x, low.y),
+                high,
+                Vec2::new(low.x, high.y),
+            ];
+            let segments: Vec<_> = (0..4)
+                .map(|i| (corners[i], corners[(i + 1) % 4], color))
+                .collect();
+
+            match meshes.get_mut(&*mesh_handle) {
+                Some(mesh) => lines::set_segments(mesh, &segments),
+                None => errors.report("Missing mesh of the region outline"),
+            }
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use std::fs;
+use std::path::Path;
+
+use image::{Rgb, RgbImage};
+
+use crate::emitters;
+use crate::inflow::Inflows;
+use crate::layers::Layers;
+use crate::memory::{self, MemoryBudget};
+use crate::palette::Palette;
+use crate::post::{self, Frame};
+use crate::scene_file::SceneFile;
+use crate::settings::SolverSettings;
+use crate::solver::{self, Scratch, Splats};
+use crate::weather::Weather;
+use crate::CELL_SIZE;
+
+// Offline rendering: runs a scene file without a window and writes every frame
+// as a PNG, for making animations without touching the mouse.
+
+/// Simulated time between two frames
+const FRAME_DT: f32 = 1.0 / 60.0;
+
+pub fn render_scene(
+    scene_path: &Path,
+    frames: usize,
+    out: &Path,
+    settings: &SolverSettings,
+    budget: &MemoryBudget,
+) -> Result<(), String> {
+    let file = SceneFile::load(scene_path)?;
+    let (width, height) = file.grid_size();
+    memory::check(width, height, budget)?;
+    let mut grid = file.scene.build(width, height);
+    let mut splats = Splats::default();
+    let mut scratch = Scratch::default();
+    let mut weather = Weather::new(file.wind);
+    let mut fans = file.fans.clone();
+    let mut inflows = Inflows::new(file.inflows.clone());
+    let palette = Palette::default();
+    let layers = Layers::from_styles(&file.layers);
+    let mut settings = SolverSettings { ..*settings };
+    if let Some(material) = file.material {
+        material.apply(&mut settings);
+    }
+
+    fs::create_dir_all(out).map_err(|err| err.to_string())?;
+
+    for i in 0..frames {
+        emitters::inject(&mut grid, &file.emitters, FRAME_DT);
+        for fan in fans.iter_mut() {
+            fan.blow(&mut grid, FRAME_DT);
+        }
+        weather.blow(&mut grid, FRAME_DT);
+        inflows.feed(&mut grid, FRAME_DT, &mut scratch);
+        solver::step(&mut grid, FRAME_DT, &settings, &mut splats, &mut scratch);
+
+        let frame = post::compose(&grid, &palette, &layers, &file.post_effects);
+        let path = out.join(format!("frame_{:05}.png", i));
+        frame_image(&frame, CELL_SIZE as u32)
+            .save(&path)
+            .map_err(|err| format!("{}: {}", path.display(), err))?;
+    }
+
+    Ok(())
+}
+
+/// Draw each cell as a square of `scale` pixels, like in the window
+pub fn frame_image(frame: &Frame, scale: u32) -> RgbImage {
+    let height = frame.len() as u32;
+    let width = frame[0].len() as u32;
+
+    RgbImage::from_fn(width * scale, height * scale, |px, py| {
+        // Image rows go from top to bottom
+        let color = frame[(height - 1 - py / scale) as usize][(px / scale) as usize];
+        let to_byte = |c: f32| (c.min(1.0).max(0.0) * 255.0) as u8;
+        Rgb([to_byte(color.x), to_byte(color.y), to_byte(color.z)])
+    })
+}
+
+/// RGBA bytes of a frame at one pixel per cell, top row first like textures
+pub fn frame_rgba(frame: &Frame) -> Vec<u8> {
+    let to_byte = |c: f32| (c.min(1.0).max(0.0) * 255.0) as u8;
+    let mut data = Vec::with_capacity(frame.len() * frame[0].len() * 4);
+    for color in frame.iter().rev().flatten() {
+        data.extend_from_slice(&[to_byte(color.x), to_byte(color.y), to_byte(color.z), 255]);
+    }
+    data
+}
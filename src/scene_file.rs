@@ -0,0 +1,387 @@
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::emitters::Emitter;
+use crate::fans::Fan;
+use crate::inflow::Inflow;
+use crate::layers::{self, LayerStyle};
+use crate::post::PostEffect;
+use crate::scenes::ScenePreset;
+use crate::settings::Material;
+use crate::weather::Wind;
+use crate::{HEIGHT, WIDTH};
+
+/// Scene description loaded from a RON file, e.g.
+///
+/// ```ron
+/// (
+///     version: 1,
+///     scene: Vortex,
+///     grid_size: Some((60, 40)),
+///     material: Some(Water),
+///     post_effects: [
+///         Bloom(threshold: 0.8, intensity: 0.5, radius: 2),
+///         Vignette(strength: 0.6),
+///         Depth(layers: 3, offset: 1.5),
+///     ],
+///     layers: [(layer: Density), (layer: Dye, blend: Multiply)],
+///     emitters: [(position: (30.0, 2.0), radius: 2.0, density_rate: 3.0, jet: (0.0, 10.0))],
+///     fans: [(position: (0.0, 20.0), direction: 0.0, strength: 30.0)],
+///     wind: Some((strength: 4.0, turn_rate: 1.5, gustiness: 0.5)),
+///     inflows: [(edge: Left, profile: "inlet.csv", looping: true)],
+/// )
+/// ```
+#[derive(Debug, Deserialize)]
+pub struct SceneFile {
+    /// Format of the file, missing in the files written before it was versioned
+    #[serde(default = "unversioned")]
+    pub version: u32,
+    pub scene: ScenePreset,
+    #[serde(default)]
+    pub grid_size: Option<(usize, usize)>,
+    /// Viscosity, buoyancy and damping of the fluid, the ones of the settings when missing
+    #[serde(default)]
+    pub material: Option<Material>,
+    /// Applied in order to the displayed colors
+    #[serde(default)]
+    pub post_effects: Vec<PostEffect>,
+    /// Draw order from the bottom up and blending of the layers, see `layers`
+    #[serde(default)]
+    pub layers: Vec<LayerStyle>,
+    /// Continuous sources of density and velocity, see `emitters`
+    #[serde(default)]
+    pub emitters: Vec<Emitter>,
+    /// Cones blowing on the fluid, see `fans`
+    #[serde(default)]
+    pub fans: Vec<Fan>,
+    /// Wind blowing over the grid, see `weather`
+    #[serde(default)]
+    pub wind: Option<Wind>,
+    /// Edges the fluid enters through following a CSV time series, see `inflow`
+    #[serde(default)]
+    pub inflows: Vec<Inflow>,
+}
+
+/// Current scene file format, files of older versions are migrated when loading:
+/// 0. before versioning
+/// 1. adds the version field
+pub const SCENE_VERSION: u32 = 1;
+
+fn unversioned() -> u32 {
+    0
+}
+
+/// Grid sizes a scene file or the command line may ask for, on each side
+pub const GRID_SIDE: RangeInclusive<usize> = 3..=4096;
+
+impl SceneFile {
+    /// Read and validate a scene file, the errors saying which line and field are wrong
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|err| err.to_string())?;
+        // Parse errors already start with the line and column
+        let mut file: Self = ron::from_str(&text).map_err(|err| err.to_string())?;
+        file.migrate()?;
+        file.validate(&text)?;
+        file.load_profiles(path, &text)?;
+        Ok(file)
+    }
+
+    /// Bring an older file to the current version, one version at a time
+    fn migrate(&mut self) -> Result<(), String> {
+        if self.version > SCENE_VERSION {
+            return Err(format!(
+                "version {} is newer than this build reads, up to {}",
+                self.version, SCENE_VERSION
+            ));
+        }
+
+        while self.version < SCENE_VERSION {
+            // Fields renamed or reinterpreted by a version get converted here, the
+            // ones added with a default need nothing
+            match self.version {
+                0 => {}
+                _ => unreachable!(),
+            }
+            self.version += 1;
+        }
+        Ok(())
+    }
+
+    fn validate(&self, text: &str) -> Result<(), String> {
+        if let Some((width, height)) = self.grid_size {
+            if !GRID_SIDE.contains(&width) || !GRID_SIDE.contains(&height) {
+                return Err(format!(
+                    "line {}: grid_size: {}x{} is outside of {}x{} to {}x{}",
+                    field_line(text, "grid_size"),
+                    width,
+                    height,
+                    GRID_SIDE.start(),
+                    GRID_SIDE.start(),
+                    GRID_SIDE.end(),
+                    GRID_SIDE.end()
+                ));
+            }
+        }
+
+        for (i, effect) in self.post_effects.iter().enumerate() {
+            if let Err(err) = effect.validate() {
+                return Err(format!(
+                    "line {}: post_effects[{}] {}: {}",
+                    item_line(text, "post_effects", i),
+                    i,
+                    effect.name(),
+                    err
+                ));
+            }
+        }
+
+        if let Err(err) = layers::validate(&self.layers) {
+            return Err(format!(
+                "line {}: layers: {}",
+                field_line(text, "layers"),
+                err
+            ));
+        }
+
+        for (i, emitter) in self.emitters.iter().enumerate() {
+            if let Err(err) = emitter.validate() {
+                return Err(format!(
+                    "line {}: emitters[{}]: {}",
+                    item_line(text, "emitters", i),
+                    i,
+                    err
+                ));
+            }
+        }
+
+        for (i, fan) in self.fans.iter().enumerate() {
+            if let Err(err) = fan.validate() {
+                return Err(format!(
+                    "line {}: fans[{}]: {}",
+                    item_line(text, "fans", i),
+                    i,
+                    err
+                ));
+            }
+        }
+
+        if let Some(Err(err)) = self.wind.map(|wind| wind.validate()) {
+            return Err(format!("line {}: wind: {}", field_line(text, "wind"), err));
+        }
+
+        Ok(())
+    }
+
+    /// Read the time series of the inflows, their paths being relative to the scene file
+    fn load_profiles(&mut self, path: &Path, text: &str) -> Result<(), String> {
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        for (i, inflow) in self.inflows.iter_mut().enumerate() {
+            if let Err(err) = inflow.load(dir) {
+                return Err(format!(
+                    "line {}: inflows[{}]: {}",
+                    item_line(text, "inflows", i),
+                    i,
+                    err
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Period of the forcing of the scene, the one of the first oscillating fan or else of
+    /// the first looping inflow
+    pub fn forcing_period(&self) -> Option<f32> {
+        let fans = self.fans.iter().filter_map(|fan| fan.oscillation);
+        let inflows = self.inflows.iter().filter_map(Inflow::period);
+        fans.map(|oscillation| oscillation.period)
+            .chain(inflows)
+            .next()
+    }
+
+    pub fn grid_size(&self) -> (usize, usize) {
+        self.grid_size.unwrap_or((WIDTH, HEIGHT))
+    }
+}
+
+/// Line of the top-level field `name`, the first one when it's missing
+fn field_line(text: &str, name: &str) -> usize {
+    line_at(text, field_offset(text, name).unwrap_or(0))
+}
+
+/// Line of the `nth` item of the list in the top-level field `name`, or of the field if the
+/// list is shorter
+fn item_line(text: &str, name: &str, nth: usize) -> usize {
+    let offset = item_offset(text, name, nth).or_else(|| field_offset(text, name));
+    line_at(text, offset.unwrap_or(0))
+}
+
+fn line_at(text: &str, offset: usize) -> usize {
+    text[..offset].matches('\n').count() + 1
+}
+
+/// Where the top-level field `name` is, the fields of the values in the file having the
+/// same name not counting
+fn field_offset(text: &str, name: &str) -> Option<usize> {
+    nesting(text)
+        .into_iter()
+        .find(|&(offset, _, depth)| {
+            depth == 1
+                && text[offset..].starts_with(name)
+                && !text[..offset].ends_with(|c: char| c.is_alphanumeric() || c == '_')
+                && text[offset + name.len()..].trim_start().starts_with(':')
+        })
+        .map(|(offset, _, _)| offset)
+}
+
+/// Where the `nth` item of the list in the top-level field `name` starts
+fn item_offset(text: &str, name: &str, nth: usize) -> Option<usize> {
+    let field = field_offset(text, name)?;
+    let mut items = 0;
+    let mut next_item = false;
+    for (offset, c, depth) in nesting(text).into_iter().filter(|&(o, _, _)| o > field) {
+        match (c, depth) {
+            ('[', 1) | (',', 2) => next_item = true,
+            (']', 1) => break,
+            (c, depth) if depth >= 2 && next_item && !c.is_whitespace() => {
+                if items == nth {
+                    return Some(offset);
+                }
+                items += 1;
+                next_item = false;
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The characters of a scene file outside of its strings and comments, where they are and
+/// within how many parentheses and brackets
+fn nesting(text: &str) -> Vec<(usize, char, usize)> {
+    let mut chars = text.char_indices().peekable();
+    let mut depth = 0usize;
+    let mut nested = Vec::new();
+    while let Some((offset, c)) = chars.next() {
+        match c {
+            '"' => {
+                while let Some((_, c)) = chars.next() {
+                    match c {
+                        '\\' => {
+                            chars.next();
+                        }
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            '/' if matches!(chars.peek(), Some((_, '/'))) => {
+                for (_, c) in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '(' | '[' => {
+                nested.push((offset, c, depth));
+                depth += 1;
+            }
+            ')' | ']' => {
+                depth = depth.saturating_sub(1);
+                nested.push((offset, c, depth));
+            }
+            _ => nested.push((offset, c, depth)),
+        }
+    }
+    nested
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse, migrate and validate a scene file without reading its inflow profiles
+    fn check(text: &str) -> Result<SceneFile, String> {
+        let mut file: SceneFile = ron::from_str(text).map_err(|err| err.to_string())?;
+        file.migrate()?;
+        file.validate(text)?;
+        Ok(file)
+    }
+
+    #[test]
+    fn valid_files_load() {
+        let file = check("(\n    scene: Vortex,\n    grid_size: Some((60, 40)),\n)").unwrap();
+        assert_eq!(file.version, SCENE_VERSION);
+        assert_eq!(file.grid_size(), (60, 40));
+    }
+
+    #[test]
+    fn grid_size_errors_give_its_line() {
+        let text = "(\n    scene: Vortex,\n    grid_size: Some((2, 40)),\n)";
+        assert_eq!(
+            check(text).unwrap_err(),
+            "line 3: grid_size: 2x40 is outside of 3x3 to 4096x4096"
+        );
+    }
+
+    #[test]
+    fn emitter_errors_give_the_line_of_the_wrong_one() {
+        let text = "(
+    scene: Vortex,
+    emitters: [
+        (position: (10.0, 2.0), radius: 2.0, density_rate: 3.0),
+        (position: (20.0, 2.0), radius: -1.0, density_rate: 3.0),
+    ],
+)";
+        assert_eq!(
+            check(text).unwrap_err(),
+            "line 5: emitters[1]: radius must be a positive number, not -1"
+        );
+    }
+
+    #[test]
+    fn fan_errors_give_the_line_of_the_wrong_one() {
+        let text = "(
+    scene: Vortex,
+    fans: [
+        (position: (0.0, 20.0), direction: 0.0, strength: 30.0),
+        (position: (0.0, 20.0), direction: 0.0, spread: 200.0, strength: 30.0),
+    ],
+)";
+        let err = check(text).unwrap_err();
+        assert!(err.starts_with("line 5: fans[1]: spread"), "{}", err);
+    }
+
+    #[test]
+    fn list_errors_skip_the_fields_of_the_same_name_before_them() {
+        let text = "(
+    scene: Vortex,
+    post_effects: [Vignette(strength: 0.6), Depth(layers: 3, offset: 1.5)],
+    wind: Some((strength: 4.0, turn_rate: 1.5, gustiness: 0.5)),
+    fans: [(position: (0.0, 20.0), direction: 0.0, strength: 30.0), (position: (0.0, 20.0),
+        direction: 0.0, spread: 200.0, strength: 30.0)],
+)";
+        let err = check(text).unwrap_err();
+        assert!(err.starts_with("line 5: fans[1]: spread"), "{}", err);
+
+        let text = "(
+    scene: Vortex,
+    fans: [(position: (0.0, 20.0), direction: 0.0, strength: 30.0)],
+    emitters: [
+        (position: (20.0, 2.0), radius: -1.0, density_rate: 3.0),
+    ],
+)";
+        assert_eq!(
+            check(text).unwrap_err(),
+            "line 5: emitters[0]: radius must be a positive number, not -1"
+        );
+    }
+
+    #[test]
+    fn newer_versions_are_refused() {
+        let err = check("(version: 2, scene: Vortex)").unwrap_err();
+        assert!(err.starts_with("version 2 is newer"), "{}", err);
+    }
+}
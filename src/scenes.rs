@@ -0,0 +1,228 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::patterns;
+use crate::stamp;
+use crate::{Grid, HEIGHT, WIDTH};
+
+/// Grid sizes offered by the menu, the largest ones scroll with the arrow keys
+pub const GRID_SIZES: [(usize, usize); 6] = [
+    (20, 20),
+    (30, 30),
+    (WIDTH, HEIGHT),
+    (60, 40),
+    (120, 80),
+    (240, 160),
+];
+
+/// Built-in initial conditions
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ScenePreset {
+    Stripe,
+    Blob,
+    Vortex,
+    Shear,
+    Clouds,
+    Stripes,
+    Checkerboard,
+    Text,
+    Fire,
+    Explosion,
+    DamBreak,
+    Splash,
+    Empty,
+}
+
+impl ScenePreset {
+    pub const ALL: [ScenePreset; 13] = [
+        ScenePreset::Stripe,
+        ScenePreset::Blob,
+        ScenePreset::Vortex,
+        ScenePreset::Shear,
+        ScenePreset::Clouds,
+        ScenePreset::Stripes,
+        ScenePreset::Checkerboard,
+        ScenePreset::Text,
+        ScenePreset::Fire,
+        ScenePreset::Explosion,
+        ScenePreset::DamBreak,
+        ScenePreset::Splash,
+        ScenePreset::Empty,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Stripe => "Stripe",
+            Self::Blob => "Blob",
+            Self::Vortex => "Vortex",
+            Self::Shear => "Shear layer",
+            Self::Clouds => "Perlin clouds",
+            Self::Stripes => "Stripes",
+            Self::Checkerboard => "Checkerboard",
+            Self::Text => "Text",
+            Self::Fire => "Fire",
+            Self::Explosion => "Explosion",
+            Self::DamBreak => "Dam break",
+            Self::Splash => "Splash",
+            Self::Empty => "Empty",
+        }
+    }
+
+    /// Create the initial grid of the scene
+    pub fn build(self, width: usize, height: usize) -> Grid {
+        let mut grid = Grid::new(width, height);
+        let center = Vec2::new(width as f32 - 1.0, height as f32 - 1.0) / 2.0;
+        let radius = width.min(height) as f32 / 6.0;
+
+        for y in 0..height {
+            for x in 0..width {
+                let pos = Vec2::new(x as f32, y as f32);
+                let offset = pos - center;
+                let cell = &mut grid.0[y][x];
+
+                match self {
+                    Self::Stripe => {
+                        if y == 4.min(height - 1) {
+                            cell.density = 20.0;
+                        }
+                    }
+                    Self::Blob => {
+                        if offset.length() < radius {
+                            cell.density = 5.0;
+                        }
+                    }
+                    Self::Vortex => {
+                        // Solid body rotation inside twice the radius, with a ring of dye
+                        if offset.length() < 2.0 * radius {
+                            cell.velocity = 0.3 * Vec2::new(-offset.y, offset.x);
+                        }
+                        if (offset.length() - radius).abs() < 1.5 {
+                            cell.density = 5.0;
+                        }
+                    }
+                    Self::Shear => {
+                        let top = y as f32 > center.y;
+                        cell.velocity.x = if top { 3.0 } else { -3.0 };
+                        // Small perturbation to get the instability going
+                        let phase = x as f32 * std::f32::consts::TAU / width as f32;
+                        cell.velocity.y = 0.3 * (2.0 * phase).sin();
+                        if (y as f32 - center.y).abs() < 1.5 {
+                            cell.density = 5.0;
+                        }
+                    }
+                    Self::Clouds => {
+                        let scale = width.min(height) as f32 / 3.0;
+                        cell.density = patterns::clouds(pos.x, pos.y, scale, 0);
+                    }
+                    Self::Stripes => {
+                        let period = width.min(height) as f32 / 5.0;
+                        cell.density = patterns::stripes(pos.x, pos.y, period);
+                    }
+                    Self::Checkerboard => {
+                        let size = (width.min(height) / 8).max(1);
+                        cell.density = patterns::checkerboard(x, y, size);
+                    }
+                    Self::Fire => {
+                        // Pool of fuel along the bottom, lit in the middle
+                        if y < (height / 8).max(1) && offset.x.abs() < width as f32 / 4.0 {
+                            cell.fuel = 5.0;
+                            if offset.x.abs() < radius / 2.0 {
+                                cell.temperature = 2.0;
+                            }
+                        }
+                    }
+                    Self::Explosion => {
+                        // Ball of fuel with a hot core, burning all at once
+                        if offset.length() < radius {
+                            cell.fuel = 5.0;
+                        }
+                        if offset.length() < radius / 3.0 {
+                            cell.temperature = 2.0;
+                        }
+                    }
+                    Self::DamBreak => {
+                        // Column of liquid against the left wall, the rest being air
+                        let corner = Vec2::new(width as f32 / 3.0, 0.6 * height as f32);
+                        let from_corner = pos + Vec2::splat(0.5) - corner;
+                        let outside = from_corner.max(Vec2::ZERO);
+                        cell.level = if outside == Vec2::ZERO {
+                            from_corner.max_element()
+                        } else {
+                            outside.length()
+                        };
+                        if cell.level <= 0.0 {
+                            cell.density = 1.0;
+                        }
+                    }
+                    Self::Splash => {
+                        // Drop falling into a pool filling the bottom quarter
+                        let pool = pos.y + 0.5 - height as f32 / 4.0;
+                        let drop_center = Vec2::new(center.x, 0.7 * height as f32);
+                        cell.level = pool.min((pos - drop_center).length() - radius);
+                        if cell.level <= 0.0 {
+                            cell.density = 1.0;
+                        }
+                    }
+                    Self::Text | Self::Empty => {}
+                }
+            }
+        }
+
+        if self == Self::Text {
+            let text = stamp::DEFAULT_TEXT;
+            let scale = stamp::fitting_scale(text, width, height);
+            stamp::stamp_text(&mut grid, text, (width / 2, height / 2), scale, 5.0);
+        }
+
+        grid
+    }
+}
+
+/// Scene and grid size picked in the menu
+pub struct SceneSelection {
+    pub scene_index: usize,
+    pub size_index: usize,
+    /// Size of a scene file that isn't among the menu's, until the menu picks another one
+    pub custom_size: Option<(usize, usize)>,
+}
+
+impl Default for SceneSelection {
+    fn default() -> Self {
+        Self {
+            scene_index: 0,
+            size_index: 2,
+            custom_size: None,
+        }
+    }
+}
+
+impl SceneSelection {
+    /// Start the menu on the given scene and grid size, a custom one if the menu doesn't
+    /// offer it
+    pub fn with_scene(scene: ScenePreset, grid_size: (usize, usize)) -> Self {
+        let offered = GRID_SIZES.iter().position(|size| *size == grid_size);
+        Self {
+            scene_index: ScenePreset::ALL.iter().position(|s| *s == scene).unwrap(),
+            size_index: offered.unwrap_or(Self::default().size_index),
+            custom_size: if offered.is_some() {
+                None
+            } else {
+                Some(grid_size)
+            },
+        }
+    }
+
+    /// Pick one of the grid sizes of the menu, dropping the custom one
+    pub fn pick_size(&mut self, index: usize) {
+        self.size_index = index.min(GRID_SIZES.len() - 1);
+        self.custom_size = None;
+    }
+
+    pub fn scene(&self) -> ScenePreset {
+        ScenePreset::ALL[self.scene_index]
+    }
+
+    pub fn grid_size(&self) -> (usize, usize) {
+        self.custom_size.unwrap_or(GRID_SIZES[self.size_index])
+    }
+}
@@ -0,0 +1,161 @@
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+use rand::Rng;
+
+use crate::settings::SolverSettings;
+use crate::solver::{self, Scratch, Splats, Stage};
+use crate::Grid;
+
+// Solver self-test: quick sanity checks of the stages on a small grid, for after building
+// on a new platform. They run with the backend, precision and boundaries of the settings,
+// so each backend can be checked on its own.
+
+const SIZE: usize = 32;
+const STEP_DT: f32 = 1.0 / 60.0;
+/// Waves summed into the random velocity fields
+const WAVES: usize = 4;
+/// Enough for the projection to converge on the smooth fields of the check
+const PROJECTION_ITERATIONS: usize = 200;
+/// Largest divergence the projection may leave, relative to the divergence before it.
+/// Even converged it leaves some, its pressure and its gradient use different stencils.
+const PROJECTION_TOLERANCE: f32 = 0.1;
+/// Largest relative change of the total density made by the diffusion
+const DIFFUSION_TOLERANCE: f32 = 1e-3;
+/// Largest change of a constant field made by the advection, 0.75 being exact in f16
+const ADVECTION_TOLERANCE: f32 = 1e-5;
+const CONSTANT: f32 = 0.75;
+
+/// Measurements of a check, as the error when it failed
+type Outcome = Result<String, String>;
+
+/// Run every check and print whether it passed, failing if any did
+pub fn run(settings: &SolverSettings) -> Result<(), String> {
+    let checks: [(&str, fn(&SolverSettings) -> Outcome); 3] = [
+        ("projection", check_projection),
+        ("diffusion", check_diffusion),
+        ("advection", check_advection),
+    ];
+
+    let mut failed = 0;
+    for (name, check) in checks.iter() {
+        let (status, details) = match check(settings) {
+            Ok(details) => ("pass", details),
+            Err(details) => {
+                failed += 1;
+                ("FAIL", details)
+            }
+        };
+        println!("{:<12} {}  {}", name, status, details);
+    }
+
+    if failed == 0 {
+        println!("All {} checks passed", checks.len());
+        Ok(())
+    } else {
+        Err(format!("{} of {} checks failed", failed, checks.len()))
+    }
+}
+
+fn run_stage(grid: &mut Grid, stage: Stage, settings: &SolverSettings) -> Scratch {
+    let mut scratch = Scratch::default();
+    let mut splats = Splats::default();
+    solver::run_stage(grid, stage, STEP_DT, settings, &mut splats, &mut scratch);
+    scratch
+}
+
+/// Sum of waves of random directions and phases, long enough for the grid to resolve them
+fn random_velocity() -> Grid {
+    let mut rng = rand::thread_rng();
+    let waves: Vec<_> = (0..WAVES)
+        .map(|_| {
+            let (kx, ky) = (rng.gen_range(0..=2), rng.gen_range(1..=2));
+            let wavenumber = Vec2::new(kx as f32, ky as f32) * TAU / SIZE as f32;
+            let amplitude = Vec2::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+            (wavenumber, amplitude, rng.gen_range(0.0..TAU))
+        })
+        .collect();
+
+    let mut grid = Grid::new(SIZE, SIZE);
+    for (y, row) in grid.0.iter_mut().enumerate() {
+        for (x, cell) in row.iter_mut().enumerate() {
+            let pos = Vec2::new(x as f32, y as f32);
+            cell.velocity = waves
+                .iter()
+                .map(|&(wavenumber, amplitude, phase)| {
+                    amplitude * (wavenumber.dot(pos) + phase).sin()
+                })
+                .sum();
+        }
+    }
+    grid
+}
+
+fn check_projection(settings: &SolverSettings) -> Outcome {
+    let settings = SolverSettings {
+        projection_iterations: PROJECTION_ITERATIONS,
+        ..*settings
+    };
+    let mut grid = random_velocity();
+    let before = (0..SIZE)
+        .flat_map(|y| (0..SIZE).map(move |x| (x, y)))
+        .map(|(x, y)| grid.get_velocity_gradient(x, y, settings.boundary).abs())
+        .fold(0.0, f32::max);
+    let scratch = run_stage(&mut grid, Stage::Project, &settings);
+    let after = scratch
+        .residual_divergence()
+        .map(f32::abs)
+        .fold(0.0, f32::max);
+
+    let details = format!("max divergence {:.4} down from {:.4}", after, before);
+    if after <= PROJECTION_TOLERANCE * before {
+        Ok(details)
+    } else {
+        Err(details)
+    }
+}
+
+fn check_diffusion(settings: &SolverSettings) -> Outcome {
+    let mut grid = Grid::new(SIZE, SIZE);
+    grid.0[SIZE / 2][SIZE / 2].density = 1.0;
+    let scratch = run_stage(&mut grid, Stage::Diffuse, settings);
+
+    let densities = grid.0.iter().flatten().map(|cell| cell.density);
+    let total: f32 = densities.clone().sum();
+    let min = densities.fold(f32::INFINITY, f32::min);
+    let details = format!(
+        "total density {:.6} from 1, lowest {}, residual {:.1e} after {} iterations",
+        total, min, scratch.diffusion.residual, scratch.diffusion.iterations
+    );
+    if min >= 0.0 && (total - 1.0).abs() <= DIFFUSION_TOLERANCE {
+        Ok(details)
+    } else {
+        Err(details)
+    }
+}
+
+fn check_advection(settings: &SolverSettings) -> Outcome {
+    let mut grid = Grid::new(SIZE, SIZE);
+    for cell in grid.0.iter_mut().flatten() {
+        cell.velocity = Vec2::new(1.3, -0.7);
+        cell.density = CONSTANT;
+        cell.dye = Vec3::splat(CONSTANT);
+    }
+    run_stage(&mut grid, Stage::Advect, settings);
+
+    let error = grid
+        .0
+        .iter()
+        .flatten()
+        .map(|cell| {
+            let dye = (cell.dye - Vec3::splat(CONSTANT)).abs().max_element();
+            (cell.density - CONSTANT).abs().max(dye)
+        })
+        .fold(0.0, f32::max);
+    let details = format!("largest change of a constant field {}", error);
+    if error <= ADVECTION_TOLERANCE {
+        Ok(details)
+    } else {
+        Err(details)
+    }
+}
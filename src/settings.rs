@@ -0,0 +1,627 @@
+use std::str::FromStr;
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::backend::Backend;
+use crate::boundary::BoundaryMode;
+use crate::species::{self, SpeciesRates, SPECIES};
+use crate::units::Units;
+use crate::InterpolationKind;
+
+/// Knobs shared by the solver and everything sampling the grid. The presets set the ones
+/// trading speed for accuracy, see `SolverPreset::apply`, the others depend on the scene,
+/// the material or the machine and are kept when switching presets.
+pub struct SolverSettings {
+    pub diffusion_iterations: usize,
+    /// The diffusion stops iterating once an iteration started from a residual this small, 0
+    /// running every iteration
+    pub diffusion_tolerance: f32,
+    /// Substeps the advection splits the time step into
+    pub advection_iterations: usize,
+    pub projection_iterations: usize,
+    pub pressure_solver: SolverBackend,
+    /// How fast the velocity spreads to the neighbouring cells
+    pub viscosity: f32,
+    /// How fast the density, dye and heat spread to the neighbouring cells
+    pub diffusion: f32,
+    /// Epsilon of the vorticity confinement bringing back the small swirls the grid smooths
+    /// out, 0 turns it off
+    pub vorticity: f32,
+    pub advection: AdvectionScheme,
+    pub backtrace: Backtrace,
+    /// Blend from PIC at 0 to FLIP at 1 of the particles carrying the velocity instead of
+    /// the grid, see `flip`, none advecting it on the grid
+    pub flip: Option<f32>,
+    pub buoyancy: Buoyancy,
+    pub external: ExternalForces,
+    /// How fast the velocity dies out, per second. Set by the material, like the buoyancy.
+    pub damping: f32,
+    /// Each dye species has its own
+    pub species: [SpeciesRates; SPECIES],
+    /// How fast the heat flows through the obstacles and between them and the fluid, per
+    /// second
+    pub conductivity: f32,
+    pub dissipation: Dissipation,
+    pub combustion: Combustion,
+    pub free_surface: FreeSurface,
+    pub boundary: BoundaryMode,
+    pub interpolation: InterpolationKind,
+    pub backend: Backend,
+    /// Trades accuracy for memory
+    pub precision: Precision,
+}
+
+/// Coefficients of the buoyancy force `(beta * temperature - alpha * density)` pushing the
+/// fluid up, the ambient temperature being 0
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Buoyancy {
+    /// How much the smoke weighs, making dense and cold smoke sink
+    pub alpha: f32,
+    /// How much the heat lifts the fluid
+    pub beta: f32,
+}
+
+impl Default for Buoyancy {
+    fn default() -> Self {
+        Self {
+            alpha: 0.0,
+            beta: 1.0,
+        }
+    }
+}
+
+/// How the fuel burns: above the ignition temperature a share of it burns every second,
+/// each unit releasing heat, soot added to the density and gas expanding the fluid around
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Combustion {
+    pub ignition: f32,
+    /// Share of the fuel of a burning cell burnt per second
+    pub burn_rate: f32,
+    /// Temperature a unit of fuel raises a cell by
+    pub heat: f32,
+    /// Density a unit of fuel leaves
+    pub soot: f32,
+    /// Divergence of the velocity a unit of fuel burnt per second creates
+    pub expansion: f32,
+}
+
+impl Default for Combustion {
+    fn default() -> Self {
+        Self {
+            ignition: 1.0,
+            burn_rate: 3.0,
+            heat: 2.0,
+            soot: 0.5,
+            expansion: 1.0,
+        }
+    }
+}
+
+/// How the liquid of a grid with a free surface moves, see `surface`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FreeSurface {
+    /// Acceleration of the liquid downward, in cells per second squared
+    pub gravity: f32,
+    /// Cells of air the velocity of the liquid is extended into, as far as the surface may
+    /// move in a step
+    pub extrapolation: usize,
+}
+
+impl Default for FreeSurface {
+    fn default() -> Self {
+        Self {
+            gravity: 40.0,
+            extrapolation: 4,
+        }
+    }
+}
+
+/// How the advection carries the fields along the velocity
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AdvectionScheme {
+    /// Each cell takes the values found where its velocity traces back to, blurring the
+    /// fields a little every step
+    SemiLagrangian,
+    /// Semi-Lagrangian, then corrected by half the error of tracing the result forward
+    /// again, clamped to the values it interpolated between. Keeps the dye much sharper for
+    /// twice the work.
+    MacCormack,
+    /// Back and Forth Error Compensation and Correction: the fields are traced forward and
+    /// back again, compensated by half the error of that round trip, then advected again
+    /// and clamped like MacCormack. Sharper still, for three times the work.
+    Bfecc,
+}
+
+impl Default for AdvectionScheme {
+    fn default() -> Self {
+        Self::SemiLagrangian
+    }
+}
+
+impl AdvectionScheme {
+    pub fn next(self) -> Self {
+        match self {
+            Self::SemiLagrangian => Self::MacCormack,
+            Self::MacCormack => Self::Bfecc,
+            Self::Bfecc => Self::SemiLagrangian,
+        }
+    }
+}
+
+impl FromStr for AdvectionScheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "semi-lagrangian" => Ok(Self::SemiLagrangian),
+            "maccormack" => Ok(Self::MacCormack),
+            "bfecc" => Ok(Self::Bfecc),
+            _ => Err(format!(
+                "unknown advection scheme {:?}, expected semi-lagrangian, maccormack or bfecc",
+                s
+            )),
+        }
+    }
+}
+
+/// How the advection traces the cells back along the velocity
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Backtrace {
+    /// A single step along the velocity of the cell, drifting outward in circular flows
+    Euler,
+    /// Midpoint method, with the velocity sampled halfway
+    Rk2,
+    /// Classic Runge-Kutta, with four samples of the velocity
+    Rk4,
+}
+
+impl Default for Backtrace {
+    fn default() -> Self {
+        Self::Euler
+    }
+}
+
+impl Backtrace {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Euler => Self::Rk2,
+            Self::Rk2 => Self::Rk4,
+            Self::Rk4 => Self::Euler,
+        }
+    }
+}
+
+impl FromStr for Backtrace {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "euler" => Ok(Self::Euler),
+            "rk2" => Ok(Self::Rk2),
+            "rk4" => Ok(Self::Rk4),
+            _ => Err(format!(
+                "unknown backtrace {:?}, expected euler, rk2 or rk4",
+                s
+            )),
+        }
+    }
+}
+
+/// Linear solver of the pressure equation of the projection
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SolverBackend {
+    /// Red-black Gauss-Seidel relaxation, each color on every thread of the threaded
+    /// backend. Cheap iterations, but the large swirls take more of them the larger the grid.
+    GaussSeidel,
+    /// Conjugate gradient preconditioned by the diagonal, converging in far fewer
+    /// iterations on large grids, always on the main thread
+    Pcg,
+    /// V-cycles over grids of halving sizes, each costing a few relaxations but clearing
+    /// the large swirls too. Best on power-of-two grids, on the main thread.
+    Multigrid,
+}
+
+impl Default for SolverBackend {
+    fn default() -> Self {
+        Self::GaussSeidel
+    }
+}
+
+impl FromStr for SolverBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "gauss-seidel" => Ok(Self::GaussSeidel),
+            "pcg" => Ok(Self::Pcg),
+            "multigrid" => Ok(Self::Multigrid),
+            _ => Err(format!(
+                "unknown pressure solver {:?}, expected gauss-seidel, pcg or multigrid",
+                s
+            )),
+        }
+    }
+}
+
+/// Factors the density and the velocity are multiplied by every step, so what's injected
+/// fades and the flow settles instead of piling up in long runs. 1 keeps them as they are.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Dissipation {
+    /// Also fading the dye
+    pub density: f32,
+    pub velocity: f32,
+}
+
+impl Default for Dissipation {
+    fn default() -> Self {
+        Self {
+            density: 0.999,
+            velocity: 0.9995,
+        }
+    }
+}
+
+/// Named fluids setting the viscosity, diffusion, buoyancy and damping, for plausible behavior
+/// without tuning each coefficient. The settings of smoke are the defaults.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub enum Material {
+    Air,
+    Water,
+    Honey,
+    Smoke,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self::Smoke
+    }
+}
+
+impl Material {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Air => Self::Water,
+            Self::Water => Self::Honey,
+            Self::Honey => Self::Smoke,
+            Self::Smoke => Self::Air,
+        }
+    }
+
+    /// Set the coefficients of the material, leaving the other settings as they are
+    pub fn apply(self, settings: &mut SolverSettings) {
+        // Viscosity, diffusion, buoyancy alpha and beta, damping
+        let (viscosity, diffusion, (alpha, beta), damping) = match self {
+            // Thin and light, the heat lifting it a little
+            Self::Air => (0.5, 1.0, (0.0, 0.5), 0.0),
+            // The dye spreads and sinks slowly, the heat barely lifts it
+            Self::Water => (2.0, 0.5, (0.2, 0.1), 0.1),
+            // Thick and heavy, every push dies out quickly and the dye hardly spreads
+            Self::Honey => (50.0, 0.1, (0.5, 0.0), 2.0),
+            Self::Smoke => (5.0, 5.0, (0.0, 1.0), 0.0),
+        };
+        settings.viscosity = viscosity;
+        settings.diffusion = diffusion;
+        settings.buoyancy = Buoyancy { alpha, beta };
+        settings.damping = damping;
+    }
+}
+
+impl FromStr for Material {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "air" => Ok(Self::Air),
+            "water" => Ok(Self::Water),
+            "honey" => Ok(Self::Honey),
+            "smoke" => Ok(Self::Smoke),
+            _ => Err(format!(
+                "unknown material {:?}, expected air, water, honey or smoke",
+                s
+            )),
+        }
+    }
+}
+
+/// Forces from outside of the fluid pushing every cell, like gravity or a steady wind
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ExternalForces {
+    /// Acceleration of every cell, in cells per second squared
+    pub body: Vec2,
+    /// Acceleration varying over the grid, added to the body one
+    pub field: Option<ForceField>,
+}
+
+/// Accelerations depending on where the cell is, with their strength in cells per second
+/// squared at the edges of the grid
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ForceField {
+    /// Turning counterclockwise around the center, stronger away from it
+    Swirl(f32),
+    /// Wind to the right, from nothing at the bottom to its full strength at the top
+    Shear(f32),
+}
+
+impl ForceField {
+    /// Acceleration of the cell at `pos` of a grid of `size`
+    pub fn acceleration(self, pos: Vec2, size: Vec2) -> Vec2 {
+        match self {
+            Self::Swirl(strength) => {
+                let offset = pos - size / 2.0;
+                Vec2::new(-offset.y, offset.x) / (size.min_element() / 2.0) * strength
+            }
+            Self::Shear(strength) => Vec2::new(strength * pos.y / size.y, 0.0),
+        }
+    }
+}
+
+impl FromStr for ForceField {
+    type Err = String;
+
+    /// A name with an optional strength, e.g. swirl or shear:2.5
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, strength) = match s.split_once(':') {
+            Some((name, strength)) => {
+                let strength = strength
+                    .parse()
+                    .map_err(|_| format!("invalid force field strength {:?}", strength))?;
+                (name, strength)
+            }
+            None => (s, 1.0),
+        };
+        match name.to_lowercase().as_str() {
+            "swirl" => Ok(Self::Swirl(strength)),
+            "shear" => Ok(Self::Shear(strength)),
+            _ => Err(format!(
+                "unknown force field {:?}, expected swirl or shear",
+                name
+            )),
+        }
+    }
+}
+
+/// How the density and dye are stored between steps, they're always computed as f32
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Precision {
+    Full,
+    /// Rounded to f16 after every step, to see the accuracy lost to half precision storage
+    Half,
+}
+
+impl Default for SolverSettings {
+    fn default() -> Self {
+        SolverPreset::default().settings()
+    }
+}
+
+/// Named bundles of solver settings, for when tuning each knob isn't the point
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SolverPreset {
+    Fast,
+    Balanced,
+    Accurate,
+}
+
+impl Default for SolverPreset {
+    fn default() -> Self {
+        Self::Balanced
+    }
+}
+
+impl SolverPreset {
+    pub fn settings(self) -> SolverSettings {
+        let base = SolverSettings {
+            diffusion_iterations: 5,
+            diffusion_tolerance: 0.0,
+            advection_iterations: 2,
+            projection_iterations: 5,
+            pressure_solver: SolverBackend::default(),
+            viscosity: 5.0,
+            diffusion: 5.0,
+            vorticity: 0.5,
+            buoyancy: Buoyancy::default(),
+            external: ExternalForces::default(),
+            damping: 0.0,
+            species: species::DEFAULT_RATES,
+            conductivity: 1.0,
+            dissipation: Dissipation::default(),
+            combustion: Combustion::default(),
+            free_surface: FreeSurface::default(),
+            advection: AdvectionScheme::MacCormack,
+            backtrace: Backtrace::Rk2,
+            flip: None,
+            boundary: BoundaryMode::default(),
+            interpolation: InterpolationKind::Bilinear,
+            backend: Backend::Scalar,
+            precision: Precision::Full,
+        };
+        match self {
+            Self::Fast => SolverSettings {
+                diffusion_iterations: 2,
+                advection_iterations: 1,
+                projection_iterations: 3,
+                vorticity: 0.0,
+                advection: AdvectionScheme::SemiLagrangian,
+                backtrace: Backtrace::Euler,
+                interpolation: InterpolationKind::Nearest,
+                ..base
+            },
+            Self::Balanced => base,
+            // Less of the swirls are smoothed out, so less confinement brings them back
+            Self::Accurate => SolverSettings {
+                diffusion_iterations: 20,
+                advection_iterations: 4,
+                projection_iterations: 40,
+                vorticity: 0.25,
+                advection: AdvectionScheme::Bfecc,
+                backtrace: Backtrace::Rk4,
+                interpolation: InterpolationKind::CatmullRom,
+                ..base
+            },
+        }
+    }
+
+    /// `settings` with the knobs of the preset, keeping the others
+    pub fn apply(self, settings: &SolverSettings) -> SolverSettings {
+        let preset = self.settings();
+        SolverSettings {
+            diffusion_iterations: preset.diffusion_iterations,
+            advection_iterations: preset.advection_iterations,
+            projection_iterations: preset.projection_iterations,
+            vorticity: preset.vorticity,
+            advection: preset.advection,
+            backtrace: preset.backtrace,
+            interpolation: preset.interpolation,
+            ..*settings
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            Self::Fast => Self::Balanced,
+            Self::Balanced => Self::Accurate,
+            Self::Accurate => Self::Fast,
+        }
+    }
+}
+
+impl FromStr for SolverPreset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "fast" => Ok(Self::Fast),
+            "balanced" => Ok(Self::Balanced),
+            "accurate" => Ok(Self::Accurate),
+            _ => Err(format!(
+                "unknown preset {:?}, expected fast, balanced or accurate",
+                s
+            )),
+        }
+    }
+}
+
+/// o cycles through the presets, overwriting the knobs they set
+pub fn preset_keys_system(
+    mut preset: ResMut<SolverPreset>,
+    mut settings: ResMut<SolverSettings>,
+    mut char_input_events: EventReader<ReceivedCharacter>,
+) {
+    for event in char_input_events.iter() {
+        if event.char == 'o' {
+            *preset = preset.next();
+            *settings = preset.apply(&settings);
+            info!("Solver preset: {:?}", *preset);
+        }
+    }
+}
+
+/// 7 cycles through the advection schemes, 9 through the backtraces
+pub fn advection_keys_system(
+    mut settings: ResMut<SolverSettings>,
+    mut char_input_events: EventReader<ReceivedCharacter>,
+) {
+    for event in char_input_events.iter() {
+        match event.char {
+            '7' => {
+                settings.advection = settings.advection.next();
+                info!("Advection: {:?}", settings.advection);
+            }
+            '9' => {
+                settings.backtrace = settings.backtrace.next();
+                info!("Backtrace: {:?}", settings.backtrace);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// 4 cycles through the materials
+pub fn material_keys_system(
+    units: Res<Units>,
+    mut material: ResMut<Material>,
+    mut settings: ResMut<SolverSettings>,
+    mut char_input_events: EventReader<ReceivedCharacter>,
+) {
+    for event in char_input_events.iter() {
+        if event.char == '4' {
+            *material = material.next();
+            material.apply(&mut settings);
+            units.apply_viscosity(&mut settings);
+            info!("Material: {:?}", *material);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_trade_speed_for_accuracy() {
+        let fast = SolverPreset::Fast.settings();
+        assert_eq!(
+            (fast.diffusion_iterations, fast.advection_iterations),
+            (2, 1)
+        );
+        assert_eq!((fast.projection_iterations, fast.vorticity), (3, 0.0));
+        assert_eq!(fast.advection, AdvectionScheme::SemiLagrangian);
+        assert_eq!(fast.backtrace, Backtrace::Euler);
+        assert_eq!(fast.interpolation, InterpolationKind::Nearest);
+
+        let balanced = SolverPreset::Balanced.settings();
+        assert_eq!(
+            (balanced.diffusion_iterations, balanced.advection_iterations),
+            (5, 2)
+        );
+        assert_eq!(
+            (balanced.projection_iterations, balanced.vorticity),
+            (5, 0.5)
+        );
+        assert_eq!(balanced.advection, AdvectionScheme::MacCormack);
+        assert_eq!(balanced.backtrace, Backtrace::Rk2);
+        assert_eq!(balanced.interpolation, InterpolationKind::Bilinear);
+
+        let accurate = SolverPreset::Accurate.settings();
+        assert_eq!(
+            (accurate.diffusion_iterations, accurate.advection_iterations),
+            (20, 4)
+        );
+        assert_eq!(
+            (accurate.projection_iterations, accurate.vorticity),
+            (40, 0.25)
+        );
+        assert_eq!(accurate.advection, AdvectionScheme::Bfecc);
+        assert_eq!(accurate.backtrace, Backtrace::Rk4);
+        assert_eq!(accurate.interpolation, InterpolationKind::CatmullRom);
+    }
+
+    #[test]
+    fn applying_a_preset_keeps_the_other_knobs() {
+        let mut settings = SolverSettings::default();
+        settings.viscosity = 0.1;
+        settings.damping = 0.3;
+        settings.boundary = BoundaryMode::FreeSlip;
+        settings.backend = Backend::Threaded(4);
+
+        let fast = SolverPreset::Fast.apply(&settings);
+        assert_eq!(fast.projection_iterations, 3);
+        assert_eq!(fast.advection, AdvectionScheme::SemiLagrangian);
+        assert_eq!((fast.viscosity, fast.damping), (0.1, 0.3));
+        assert_eq!(fast.boundary, BoundaryMode::FreeSlip);
+        assert_eq!(fast.backend, Backend::Threaded(4));
+    }
+
+    #[test]
+    fn presets_cycle_and_parse() {
+        let mut preset = SolverPreset::default();
+        for _ in 0..3 {
+            let name = format!("{:?}", preset);
+            assert_eq!(name.parse::<SolverPreset>(), Ok(preset));
+            preset = preset.next();
+        }
+        assert_eq!(preset, SolverPreset::default());
+        assert!("slow".parse::<SolverPreset>().is_err());
+    }
+}
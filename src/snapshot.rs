@@ -0,0 +1,287 @@
+use std::convert::TryInto;
+
+use bevy::prelude::*;
+
+use crate::species::SPECIES;
+use crate::{AppState, Cell, Grid};
+
+// Compact snapshots of the grid: every field is quantized to 16 bits over its own range,
+// delta encoded along the rows so smooth areas become runs of small values, then LZ4
+// compressed. F5 saves a snapshot of the running simulation and F9 restores it.
+
+const MAGIC: &[u8; 4] = b"FSNP";
+/// Format versions, older ones are migrated when decoding:
+/// 1. the velocity, density and dye, 13 bytes of header
+/// 2. the header ends with the number of fields, so new fields can be appended to the
+///    list and older snapshots leave them at their default
+const VERSION: u8 = 2;
+
+/// Fields stored for every cell, read and written in this order. New fields go at the end.
+/// Snapshots without the faces get them averaged from the velocity, like new grids.
+pub const FIELDS: usize = 12 + SPECIES;
+
+pub struct SnapshotPlugin;
+
+impl Plugin for SnapshotPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(Snapshot::default()).add_system_set(
+            SystemSet::on_update(AppState::Running).with_system(snapshot_keys_system.system()),
+        );
+    }
+}
+
+/// Last saved snapshot
+#[derive(Default)]
+pub struct Snapshot {
+    data: Option<Vec<u8>>,
+    /// Size of the cells in memory
+    pub raw_bytes: usize,
+    pub compressed_bytes: usize,
+}
+
+impl Snapshot {
+    pub fn ratio(&self) -> f32 {
+        if self.compressed_bytes == 0 {
+            0.0
+        } else {
+            self.raw_bytes as f32 / self.compressed_bytes as f32
+        }
+    }
+}
+
+pub fn field(cell: &Cell, i: usize) -> f32 {
+    match i {
+        0 => cell.velocity.x,
+        1 => cell.velocity.y,
+        2 => cell.density,
+        3 => cell.dye.x,
+        4 => cell.dye.y,
+        5 => cell.dye.z,
+        6 => cell.temperature,
+        7 => cell.obstacle as u8 as f32,
+        i if i < 8 + SPECIES => cell.species[i - 8],
+        i if i == 8 + SPECIES => cell.fuel,
+        i if i == 9 + SPECIES => cell.level,
+        i if i == 10 + SPECIES => cell.faces.x,
+        _ => cell.faces.y,
+    }
+}
+
+pub fn set_field(cell: &mut Cell, i: usize, value: f32) {
+    match i {
+        0 => cell.velocity.x = value,
+        1 => cell.velocity.y = value,
+        2 => cell.density = value,
+        3 => cell.dye.x = value,
+        4 => cell.dye.y = value,
+        5 => cell.dye.z = value,
+        6 => cell.temperature = value,
+        7 => cell.obstacle = value > 0.5,
+        i if i < 8 + SPECIES => cell.species[i - 8] = value,
+        i if i == 8 + SPECIES => cell.fuel = value,
+        i if i == 9 + SPECIES => cell.level = value,
+        i if i == 10 + SPECIES => cell.faces.x = value,
+        _ => cell.faces.y = value,
+    }
+}
+
+pub fn encode(grid: &Grid) -> Vec<u8> {
+    let (width, height) = (grid.width(), grid.height());
+    let cells: Vec<&Cell> = grid.0.iter().flatten().collect();
+
+    let mut payload = Vec::with_capacity(FIELDS * (8 + 2 * cells.len()));
+    for i in 0..FIELDS {
+        let min = cells
+            .iter()
+            .map(|c| field(c, i))
+            .fold(f32::INFINITY, f32::min);
+        let max = cells
+            .iter()
+            .map(|c| field(c, i))
+            .fold(f32::NEG_INFINITY, f32::max);
+        let step = if max > min {
+            (max - min) / u16::MAX as f32
+        } else {
+            1.0
+        };
+        payload.extend_from_slice(&min.to_le_bytes());
+        payload.extend_from_slice(&step.to_le_bytes());
+
+        let mut previous = 0u16;
+        for cell in &cells {
+            let quantized = ((field(cell, i) - min) / step).round() as u16;
+            payload.extend_from_slice(&quantized.wrapping_sub(previous).to_le_bytes());
+            previous = quantized;
+        }
+    }
+
+    let mut data = Vec::new();
+    data.extend_from_slice(MAGIC);
+    data.push(VERSION);
+    data.extend_from_slice(&(width as u32).to_le_bytes());
+    data.extend_from_slice(&(height as u32).to_le_bytes());
+    data.push(FIELDS as u8);
+    data.extend_from_slice(&lz4_flex::compress_prepend_size(&payload));
+    data
+}
+
+pub fn decode(data: &[u8]) -> Result<Grid, String> {
+    if data.len() < 13 || &data[..4] != MAGIC {
+        return Err("not a snapshot".to_string());
+    }
+    let (fields, header) = match data[4] {
+        1 => (6, 13),
+        VERSION if data.len() > 13 => (data[13] as usize, 14),
+        VERSION => return Err("corrupted snapshot".to_string()),
+        version => {
+            return Err(format!(
+                "snapshot version {} is newer than this build reads",
+                version
+            ))
+        }
+    };
+    if fields > FIELDS {
+        return Err(format!(
+            "snapshot has {} fields, this build knows {}",
+            fields, FIELDS
+        ));
+    }
+
+    let read_u32 = |at: usize| u32::from_le_bytes(data[at..at + 4].try_into().unwrap()) as usize;
+    let (width, height) = (read_u32(5), read_u32(9));
+    let payload =
+        lz4_flex::decompress_size_prepended(&data[header..]).map_err(|err| err.to_string())?;
+
+    // The sizes come from the header, which can't be trusted not to overflow
+    let cell_count = width.checked_mul(height);
+    let expected = cell_count
+        .and_then(|count| count.checked_mul(2))
+        .and_then(|bytes| bytes.checked_add(8))
+        .and_then(|bytes| bytes.checked_mul(fields));
+    let cell_count = match (cell_count, expected) {
+        (Some(count), Some(expected)) if count > 0 && payload.len() == expected => count,
+        _ => return Err("corrupted snapshot".to_string()),
+    };
+
+    // Fields missing from older snapshots keep the default of new cells
+    let mut grid = Grid::new(width, height);
+    let mut chunks = payload.chunks_exact(4 * 2 + 2 * cell_count);
+    for i in 0..fields {
+        let chunk = chunks.next().unwrap();
+        let read_f32 = |at: usize| f32::from_le_bytes(chunk[at..at + 4].try_into().unwrap());
+        let (min, step) = (read_f32(0), read_f32(4));
+
+        let mut value = 0u16;
+        let deltas = chunk[8..].chunks_exact(2);
+        for (cell, delta) in grid.0.iter_mut().flatten().zip(deltas) {
+            value = value.wrapping_add(u16::from_le_bytes([delta[0], delta[1]]));
+            set_field(cell, i, min + value as f32 * step);
+        }
+    }
+    Ok(grid)
+}
+
+fn snapshot_keys_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut snapshot: ResMut<Snapshot>,
+    mut qg: Query<&mut Grid>,
+) {
+    if let Ok(mut grid) = qg.single_mut() {
+        if keyboard_input.just_pressed(KeyCode::F5) {
+            let data = encode(&grid);
+            snapshot.raw_bytes = grid.width() * grid.height() * std::mem::size_of::<Cell>();
+            snapshot.compressed_bytes = data.len();
+            snapshot.data = Some(data);
+            info!(
+                "Saved a {} bytes snapshot, {:.1} times smaller than the grid",
+                snapshot.compressed_bytes,
+                snapshot.ratio()
+            );
+        }
+
+        if keyboard_input.just_pressed(KeyCode::F9) {
+            match snapshot.data.as_deref().map(decode) {
+                // The entities showing the grid were made for its size
+                Some(Ok(saved))
+                    if saved.width() == grid.width() && saved.height() == grid.height() =>
+                {
+                    *grid = saved
+                }
+                Some(Ok(_)) => warn!("The snapshot was taken on a grid of another size"),
+                Some(Err(err)) => error!("Couldn't restore the snapshot: {}", err),
+                None => info!("No snapshot to restore, F5 saves one"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A grid with a different value in every field of every cell
+    fn varied_grid(width: usize, height: usize) -> Grid {
+        let mut grid = Grid::new(width, height);
+        for (n, cell) in grid.0.iter_mut().flatten().enumerate() {
+            for i in 0..FIELDS {
+                set_field(cell, i, ((n * 7 + i * 3) % 11) as f32 * 0.37 - 1.5);
+            }
+        }
+        grid
+    }
+
+    #[test]
+    fn decoding_gives_back_the_encoded_grid() {
+        let grid = varied_grid(6, 5);
+        let decoded = decode(&encode(&grid)).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (6, 5));
+        // Every field is quantized to 16 bits over a range of less than 4
+        for (cell, decoded) in grid.0.iter().flatten().zip(decoded.0.iter().flatten()) {
+            for i in 0..FIELDS {
+                assert!(
+                    (field(cell, i) - field(decoded, i)).abs() < 1e-3,
+                    "field {}",
+                    i
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn version_1_snapshots_leave_the_newer_fields_at_their_default() {
+        let grid = varied_grid(4, 3);
+        let data = encode(&grid);
+
+        // Keep the 6 fields of version 1, with its header without the number of fields
+        let payload = lz4_flex::decompress_size_prepended(&data[14..]).unwrap();
+        let chunk = 8 + 2 * 4 * 3;
+        let mut old = data[..13].to_vec();
+        old[4] = 1;
+        old.extend_from_slice(&lz4_flex::compress_prepend_size(&payload[..6 * chunk]));
+
+        let decoded = decode(&old).unwrap();
+        let fresh = Grid::new(4, 3);
+        let cells = grid.0.iter().flatten().zip(fresh.0.iter().flatten());
+        for ((cell, fresh), decoded) in cells.zip(decoded.0.iter().flatten()) {
+            for i in 0..6 {
+                assert!(
+                    (field(cell, i) - field(decoded, i)).abs() < 1e-3,
+                    "field {}",
+                    i
+                );
+            }
+            for i in 6..FIELDS {
+                assert_eq!(field(fresh, i), field(decoded, i), "field {}", i);
+            }
+        }
+    }
+
+    #[test]
+    fn newer_and_corrupted_snapshots_are_refused() {
+        let mut data = encode(&Grid::new(3, 3));
+        data[4] = VERSION + 1;
+        assert!(decode(&data).unwrap_err().contains("newer"));
+        assert!(decode(b"FSNP").is_err());
+        assert!(decode(&encode(&Grid::new(3, 3))[..20]).is_err());
+    }
+}
@@ -0,0 +1,1383 @@
+use bevy::prelude::*;
+use half::f16;
+
+use crate::backend::{Backend, Workers};
+use crate::boundary::BoundaryMode;
+use crate::flip::Particles;
+use crate::mac::MacGrid;
+use crate::multigrid::Multigrid;
+use crate::settings::{
+    AdvectionScheme, Buoyancy, Combustion, Dissipation, ExternalForces, Precision, SolverBackend,
+    SolverSettings,
+};
+use crate::species::SPECIES;
+use crate::surface;
+use crate::{Cell, Grid};
+
+/// The stages of a simulation step, in the order they run
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stage {
+    Forces,
+    Diffuse,
+    Project,
+    Advect,
+}
+
+impl Stage {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Forces => Self::Diffuse,
+            Self::Diffuse => Self::Project,
+            Self::Project => Self::Advect,
+            Self::Advect => Self::Forces,
+        }
+    }
+}
+
+/// Density, heat and velocity added to a cell by the user, waiting for the forces stage
+#[derive(Clone, Debug)]
+pub struct Splat {
+    pub x: usize,
+    pub y: usize,
+    pub velocity: Vec2,
+    pub density: f32,
+    pub temperature: f32,
+    /// Dye species receiving the density instead of the density field
+    pub species: Option<usize>,
+}
+
+/// Splats queued since the last forces stage
+#[derive(Default)]
+pub struct Splats(pub Vec<Splat>);
+
+/// Buffers the stages work in, kept from one step to the next so that stepping doesn't
+/// allocate, whichever the backend
+pub struct Scratch {
+    size: (usize, usize),
+    /// Second buffer of the grid, swapped with it by the stages writing a new grid
+    grid: Grid,
+    /// Result of the first pass of the MacCormack and BFECC advection, and of the last of
+    /// BFECC
+    forward: Grid,
+    pressure: PField,
+    /// Pressure before the half sweep of the threaded relaxation, which reads it
+    previous_pressure: PField,
+    conjugate: Conjugate,
+    multigrid: Multigrid,
+    /// Faces of the grid the diffusion, the projection and the advection work on, loaded
+    /// from it and stored back
+    mac: MacGrid,
+    /// Velocity gradient divided by 4, what's left of it once the projection is done
+    divergence: Vec<Vec<f32>>,
+    /// Heat flowing into every cell from the obstacles it touches, or into the obstacles
+    heat: Vec<Vec<f32>>,
+    /// Divergence the burning fuel expands every cell by, divided by 4 like the divergence,
+    /// which the projection keeps
+    expansion: Vec<Vec<f32>>,
+    /// Carrying the velocity instead of the grid with FLIP, kept from one step to the next
+    particles: Particles,
+    /// Speeds along the edge of an inflow at the current time, see `Inflows::feed`
+    inflow_speeds: Vec<f32>,
+    /// Largest change made by the last rounding to half precision
+    pub rounding_error: f32,
+    pub diffusion: Convergence,
+    /// Threads of the threaded backend
+    workers: Workers,
+}
+
+/// How far the iterations of the last diffusion got
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Convergence {
+    /// Iterations run, the most of the velocity's and the carried fields'
+    pub iterations: usize,
+    /// Largest residual of the equations when the last iteration relaxed them
+    pub residual: f32,
+}
+
+impl Convergence {
+    /// Both passes of a diffusion together
+    fn max(self, other: Self) -> Self {
+        Self {
+            iterations: self.iterations.max(other.iterations),
+            residual: self.residual.max(other.residual),
+        }
+    }
+}
+
+impl Default for Scratch {
+    fn default() -> Self {
+        Self {
+            size: (0, 0),
+            grid: Grid(Vec::new()),
+            forward: Grid(Vec::new()),
+            pressure: PField(Vec::new()),
+            previous_pressure: PField(Vec::new()),
+            conjugate: Conjugate::default(),
+            multigrid: Multigrid::default(),
+            mac: MacGrid::default(),
+            divergence: Vec::new(),
+            heat: Vec::new(),
+            expansion: Vec::new(),
+            particles: Particles::default(),
+            inflow_speeds: Vec::new(),
+            rounding_error: 0.0,
+            diffusion: Convergence::default(),
+        }
+    }
+}
+
+impl Scratch {
+    /// Pressure found by the last projection, row by row
+    pub fn pressure(&self) -> &[Vec<f32>] {
+        &self.pressure.0
+    }
+
+    pub fn particles(&self) -> &Particles {
+        &self.particles
+    }
+
+    /// Seed the FLIP particles on a new grid, see `Particles::prepare`
+    pub fn prepare_particles(&mut self, grid: &Grid) {
+        self.particles.prepare(grid);
+    }
+
+    /// Make room for `samples` inflow speeds, only allocating when there are more than ever
+    pub fn prepare_inflows(&mut self, samples: usize) {
+        self.inflow_speeds.reserve(samples);
+    }
+
+    /// Have the threads of the backend, only spawning them when their count changed
+    pub fn prepare_workers(&mut self, backend: Backend) {
+        self.workers.prepare(backend);
+    }
+
+    pub fn inflow_speeds(&mut self) -> &mut Vec<f32> {
+        &mut self.inflow_speeds
+    }
+
+    /// Divergence of every cell left by the last projection, row by row
+    pub fn residual_divergence(&self) -> impl Iterator<Item = f32> + '_ {
+        self.divergence
+            .iter()
+            .flatten()
+            .map(|quarter| 4.0 * quarter)
+    }
+
+    /// Size the buffers like the grid, only allocating when its size changed
+    pub fn prepare(&mut self, grid: &Grid) {
+        let size = (grid.width(), grid.height());
+        if self.size != size {
+            self.size = size;
+            self.grid = grid.clone();
+            self.forward = grid.clone();
+            self.pressure = PField::new(size.0, size.1);
+            self.previous_pressure = PField::new(size.0, size.1);
+            self.divergence = vec![vec![0.0; size.0]; size.1];
+            self.heat = vec![vec![0.0; size.0]; size.1];
+            self.expansion = vec![vec![0.0; size.0]; size.1];
+        }
+    }
+}
+
+/// Run every stage of a simulation step
+pub fn step(
+    grid: &mut Grid,
+    dt: f32,
+    settings: &SolverSettings,
+    splats: &mut Splats,
+    scratch: &mut Scratch,
+) {
+    let mut stage = Stage::Forces;
+    loop {
+        run_stage(grid, stage, dt, settings, splats, scratch);
+        stage = stage.next();
+        if stage == Stage::Forces {
+            break;
+        }
+    }
+}
+
+pub fn run_stage(
+    grid: &mut Grid,
+    stage: Stage,
+    dt: f32,
+    settings: &SolverSettings,
+    splats: &mut Splats,
+    scratch: &mut Scratch,
+) {
+    match stage {
+        Stage::Forces => {
+            apply_splats(grid, splats);
+            burn_fuel(grid, dt, settings, scratch);
+            apply_buoyancy(grid, dt, settings);
+            apply_external_forces(grid, dt, settings);
+            surface::apply_gravity(grid, dt, settings);
+            apply_damping(grid, dt, settings);
+            apply_dissipation(grid, settings);
+            fade_species(grid, dt, settings);
+            confine_vorticity(grid, dt, settings);
+            // The faces take up what the forces and the sources did to the cells
+            scratch.mac.sync(grid, settings.boundary);
+            scratch.mac.store(grid);
+        }
+        Stage::Diffuse => {
+            diffuse(grid, dt, settings, scratch);
+            conduct_heat(grid, dt, settings, scratch);
+        }
+        Stage::Project => {
+            clear_divergence(grid, settings, scratch);
+            surface::extrapolate_velocity(grid, settings);
+        }
+        Stage::Advect => {
+            if let Some(blend) = settings.flip {
+                scratch.particles.advect(grid, blend, dt, settings.boundary);
+            }
+            advect(grid, dt, settings, scratch);
+            if settings.flip.is_some() {
+                scratch.particles.transfer_to_grid(grid, settings.boundary);
+            }
+            surface::reinitialize(grid, settings.boundary);
+            // The advected velocity diverges again, so it's projected a second time
+            clear_divergence(grid, settings, scratch);
+            surface::extrapolate_velocity(grid, settings);
+            if settings.precision == Precision::Half {
+                scratch.rounding_error = round_to_half(grid);
+            }
+            grid.update_vorticity(settings.boundary);
+        }
+    }
+}
+
+/// Round the density, dye and temperature like f16 storage would, returning the largest error
+pub fn round_to_half(grid: &mut Grid) -> f32 {
+    let mut max_error: f32 = 0.0;
+    let mut round = |v: &mut f32| {
+        let rounded = f16::from_f32(*v).to_f32();
+        max_error = max_error.max((rounded - *v).abs());
+        *v = rounded;
+    };
+
+    for cell in grid.0.iter_mut().flatten() {
+        round(&mut cell.density);
+        round(&mut cell.dye.x);
+        round(&mut cell.dye.y);
+        round(&mut cell.dye.z);
+        round(&mut cell.temperature);
+        round(&mut cell.fuel);
+        cell.species.iter_mut().for_each(&mut round);
+    }
+    max_error
+}
+
+pub fn apply_splats(grid: &mut Grid, splats: &mut Splats) {
+    for splat in splats.0.drain(..) {
+        if splat.x < grid.width() && splat.y < grid.height() {
+            let cell = &mut grid.0[splat.y][splat.x];
+            if cell.obstacle {
+                continue;
+            }
+            cell.velocity += splat.velocity;
+            match splat.species {
+                Some(species) => cell.species[species] += splat.density,
+                None => cell.density += splat.density,
+            }
+            cell.temperature += splat.temperature;
+        }
+    }
+}
+
+/// Burn the fuel of the cells above the ignition temperature, heating them, adding the soot
+/// to their density and setting the expansion of the projection. The grid being closed, the
+/// rest of the fluid is compressed by as much as the burning cells expand.
+pub fn burn_fuel(grid: &mut Grid, dt: f32, settings: &SolverSettings, scratch: &mut Scratch) {
+    scratch.prepare(grid);
+    let expansion = &mut scratch.expansion;
+    expansion.iter_mut().flatten().for_each(|v| *v = 0.0);
+    let Combustion {
+        ignition,
+        burn_rate,
+        heat,
+        soot,
+        expansion: gas,
+    } = settings.combustion;
+    if dt == 0.0 {
+        return;
+    }
+
+    let mut total = 0.0;
+    let mut fluid = 0;
+    for (y, row) in grid.0.iter_mut().enumerate() {
+        for (x, cell) in row.iter_mut().enumerate() {
+            if cell.obstacle {
+                continue;
+            }
+            fluid += 1;
+            if cell.fuel <= 0.0 || cell.temperature < ignition {
+                continue;
+            }
+            let burnt = cell.fuel * (1.0 - (-burn_rate * dt).exp());
+            cell.fuel -= burnt;
+            cell.temperature += heat * burnt;
+            cell.density += soot * burnt;
+            expansion[y][x] = gas * burnt / dt / 4.0;
+            total += expansion[y][x];
+        }
+    }
+
+    if total == 0.0 {
+        return;
+    }
+    let mean = total / fluid as f32;
+    for (y, row) in expansion.iter_mut().enumerate() {
+        for (x, value) in row.iter_mut().enumerate() {
+            if !grid.0[y][x].obstacle {
+                *value -= mean;
+            }
+        }
+    }
+}
+
+/// Lift the hot fluid and sink the dense smoke, the ambient temperature being 0
+pub fn apply_buoyancy(grid: &mut Grid, dt: f32, settings: &SolverSettings) {
+    let Buoyancy { alpha, beta } = settings.buoyancy;
+    for cell in grid.0.iter_mut().flatten().filter(|cell| !cell.obstacle) {
+        cell.velocity.y -= (alpha * cell.density - beta * cell.temperature) * dt;
+    }
+}
+
+/// Accelerate the fluid with the forces from outside of it, like gravity or the wind
+pub fn apply_external_forces(grid: &mut Grid, dt: f32, settings: &SolverSettings) {
+    let ExternalForces { body, field } = settings.external;
+    if body == Vec2::ZERO && field.is_none() {
+        return;
+    }
+
+    let size = Vec2::new(grid.width() as f32, grid.height() as f32);
+    for (y, row) in grid.0.iter_mut().enumerate() {
+        for (x, cell) in row.iter_mut().enumerate() {
+            if cell.obstacle {
+                continue;
+            }
+            let pos = Vec2::new(x as f32, y as f32);
+            let field = field.map_or(Vec2::ZERO, |field| field.acceleration(pos, size));
+            cell.velocity += (body + field) * dt;
+        }
+    }
+}
+
+/// Slow the fluid down, like thick materials do
+pub fn apply_damping(grid: &mut Grid, dt: f32, settings: &SolverSettings) {
+    if settings.damping == 0.0 {
+        return;
+    }
+    let factor = (-settings.damping * dt).exp();
+    for cell in grid.0.iter_mut().flatten().filter(|cell| !cell.obstacle) {
+        cell.velocity *= factor;
+    }
+}
+
+/// Fade the density and the dye and slow the fluid down by the factors of the settings,
+/// once per step whatever its length
+pub fn apply_dissipation(grid: &mut Grid, settings: &SolverSettings) {
+    let Dissipation { density, velocity } = settings.dissipation;
+    if density == 1.0 && velocity == 1.0 {
+        return;
+    }
+    for cell in grid.0.iter_mut().flatten().filter(|cell| !cell.obstacle) {
+        cell.density *= density;
+        cell.dye *= density;
+        cell.velocity *= velocity;
+    }
+}
+
+/// Dissipate the dye species, each at its own rate
+pub fn fade_species(grid: &mut Grid, dt: f32, settings: &SolverSettings) {
+    let factors = settings
+        .species
+        .map(|rates| (-rates.dissipation * dt).exp());
+    for cell in grid.0.iter_mut().flatten() {
+        for (amount, factor) in cell.species.iter_mut().zip(factors.iter()) {
+            *amount *= factor;
+        }
+    }
+}
+
+/// Vorticity confinement: push the velocity around the local maxima of the curl, so the
+/// swirls the grid is too coarse to keep spin a little longer
+pub fn confine_vorticity(grid: &mut Grid, dt: f32, settings: &SolverSettings) {
+    if settings.vorticity == 0.0 {
+        return;
+    }
+
+    let (width, height) = (grid.width(), grid.height());
+    let boundary = settings.boundary;
+    // The forces of the step changed the velocity since the last update
+    grid.update_vorticity(boundary);
+
+    for y in 0..height {
+        for x in 0..width {
+            if grid.0[y][x].obstacle {
+                continue;
+            }
+            // Gradient of the curl magnitude, pointing toward the center of the swirl
+            let magnitude = |dx, dy| grid.neighbor(x, y, dx, dy, boundary).vorticity.abs();
+            let gradient = Vec2::new(
+                magnitude(1, 0) - magnitude(-1, 0),
+                magnitude(0, 1) - magnitude(0, -1),
+            ) / 2.0;
+            let length = gradient.length();
+            if length < 1e-5 {
+                continue;
+            }
+
+            let n = gradient / length;
+            let force = settings.vorticity * Vec2::new(n.y, -n.x) * grid.vorticity(x, y);
+            grid.0[y][x].velocity += force * dt;
+        }
+    }
+}
+
+pub fn diffuse(grid: &mut Grid, dt: f32, settings: &SolverSettings, scratch: &mut Scratch) {
+    scratch.prepare(grid);
+    scratch.prepare_workers(settings.backend);
+    // The velocity spreads with the viscosity, on the faces
+    let (mac, workers) = (&mut scratch.mac, &scratch.workers);
+    mac.sync(grid, settings.boundary);
+    mac.begin_diffusion();
+    let k = settings.viscosity * dt;
+    let velocity = iterate(settings, || mac.relax(grid, k, settings.boundary, workers));
+
+    // What the fluid carries spreads with the diffusion
+    let buffers = (&mut scratch.grid, &mut scratch.forward);
+    let carried = match settings.backend {
+        Backend::Threaded(_) => diffuse_threaded(grid, dt, settings, workers, buffers),
+        Backend::Gpu | Backend::Scalar => diffuse_carried(grid, dt, settings, buffers.0),
+    };
+    scratch.mac.store(grid);
+    scratch.diffusion = velocity.max(carried);
+}
+
+/// Diffusion of what the fluid carries, relaxing the cells of `new_grid` in place
+fn diffuse_carried(
+    grid: &mut Grid,
+    dt: f32,
+    settings: &SolverSettings,
+    new_grid: &mut Grid,
+) -> Convergence {
+    new_grid.0.clone_from(&grid.0);
+    let boundary = settings.boundary;
+    let (width, height) = (grid.width(), grid.height());
+    let k = settings.diffusion * dt;
+    let carried = iterate(settings, || {
+        let mut residual: f32 = 0.0;
+        for (x, y) in red_black(width, height) {
+            let source = &grid.0[y][x];
+            if source.obstacle {
+                continue;
+            }
+            let avg = new_grid.get_average(x, y, boundary, |cell| cell.density);
+            let r = relax(&mut new_grid.0[y][x].density, source.density, k, avg);
+            residual = residual.max(r);
+
+            let avg = new_grid.get_average(x, y, boundary, |cell| cell.temperature);
+            let r = relax(
+                &mut new_grid.0[y][x].temperature,
+                source.temperature,
+                k,
+                avg,
+            );
+            residual = residual.max(r);
+
+            let avg = new_grid.get_average(x, y, boundary, |cell| cell.dye.x);
+            let r = relax(&mut new_grid.0[y][x].dye.x, source.dye.x, k, avg);
+            residual = residual.max(r);
+            let avg = new_grid.get_average(x, y, boundary, |cell| cell.dye.y);
+            let r = relax(&mut new_grid.0[y][x].dye.y, source.dye.y, k, avg);
+            residual = residual.max(r);
+            let avg = new_grid.get_average(x, y, boundary, |cell| cell.dye.z);
+            let r = relax(&mut new_grid.0[y][x].dye.z, source.dye.z, k, avg);
+            residual = residual.max(r);
+
+            for (i, rates) in settings.species.iter().enumerate() {
+                let k = rates.diffusion * dt;
+                let avg = new_grid.get_average(x, y, boundary, |cell| cell.species[i]);
+                let r = relax(&mut new_grid.0[y][x].species[i], source.species[i], k, avg);
+                residual = residual.max(r);
+            }
+        }
+        residual
+    });
+    std::mem::swap(grid, new_grid);
+    carried
+}
+
+/// Solve `(1 + k) * d - k * avg(d) = source` for one unknown, returning the residual of the
+/// equation before
+fn relax(value: &mut f32, source: f32, k: f32, avg: f32) -> f32 {
+    let residual = (source + k * avg - (1.0 + k) * *value).abs();
+    *value = (source + k * avg) / (1.0 + k);
+    residual
+}
+
+/// Run the iterations of a diffusion pass, each returning its residual, until there's
+/// none left or the residual is within the tolerance of the settings
+fn iterate(settings: &SolverSettings, mut iteration: impl FnMut() -> f32) -> Convergence {
+    let mut convergence = Convergence::default();
+    while convergence.iterations < settings.diffusion_iterations {
+        convergence.residual = iteration();
+        convergence.iterations += 1;
+        if convergence.residual <= settings.diffusion_tolerance {
+            break;
+        }
+    }
+    convergence
+}
+
+/// Cells of a red-black sweep: those of one color of the checkerboard then the others. The
+/// four neighbors of a cell have the other color, so the cells of a color only read cells
+/// relaxed before the sweep or in its first half, whatever their order, and can be relaxed
+/// at once. Only a periodic boundary across an odd size makes neighbors of the same color.
+pub fn red_black(width: usize, height: usize) -> impl Iterator<Item = (usize, usize)> {
+    (0..2).flat_map(move |color| {
+        (0..height).flat_map(move |y| colored(width, y, color).map(move |x| (x, y)))
+    })
+}
+
+/// Columns of row `y` having `color` on the checkerboard
+fn colored(width: usize, y: usize, color: usize) -> impl Iterator<Item = usize> {
+    ((y + color) % 2..width).step_by(2)
+}
+
+/// Heat conduction through the obstacles and between them and the fluid they touch, the
+/// diffusion leaving them out. Every pair of neighbors exchanges heat in proportion to
+/// their difference of temperature, so none is lost.
+pub fn conduct_heat(grid: &mut Grid, dt: f32, settings: &SolverSettings, scratch: &mut Scratch) {
+    // Past a quarter a cell would give its neighbors more than the difference
+    let k = (settings.conductivity * dt).min(0.25);
+    if k == 0.0 {
+        return;
+    }
+
+    scratch.prepare(grid);
+    let (width, height) = (grid.width(), grid.height());
+    for (y, row) in scratch.heat.iter_mut().enumerate() {
+        for (x, heat) in row.iter_mut().enumerate() {
+            let cell = &grid.0[y][x];
+            *heat = 0.0;
+            for &(dx, dy) in &[(1, 0), (-1, 0), (0, 1), (0, -1)] {
+                let (nx, ny, _) =
+                    settings
+                        .boundary
+                        .ghost(x as isize + dx, y as isize + dy, width, height);
+                let neighbor = &grid.0[ny][nx];
+                if cell.obstacle || neighbor.obstacle {
+                    *heat += k * (neighbor.temperature - cell.temperature);
+                }
+            }
+        }
+    }
+
+    for (row, heat_row) in grid.0.iter_mut().zip(scratch.heat.iter()) {
+        for (cell, heat) in row.iter_mut().zip(heat_row.iter()) {
+            cell.temperature += heat;
+        }
+    }
+}
+
+/// Threaded version of `diffuse_carried`: each half of a red-black sweep reads a copy of
+/// the grid from before it, which only differs from the grid in the cells it relaxes. The
+/// new grid and the copy are the two buffers, sized like the grid.
+fn diffuse_threaded(
+    grid: &mut Grid,
+    dt: f32,
+    settings: &SolverSettings,
+    workers: &Workers,
+    (new_grid, previous): (&mut Grid, &mut Grid),
+) -> Convergence {
+    new_grid.0.clone_from(&grid.0);
+    let source = &*grid;
+
+    let k = settings.diffusion * dt;
+    let carried = iterate(settings, || {
+        let mut residual: f32 = 0.0;
+        for color in 0..2 {
+            previous.0.clone_from(&new_grid.0);
+            let previous = &*previous;
+            workers.for_each_row(&mut new_grid.0, |y, row| {
+                for x in colored(row.len(), y, color) {
+                    let s = &source.0[y][x];
+                    if s.obstacle {
+                        continue;
+                    }
+                    let avg = |attr: fn(&Cell) -> f32| {
+                        previous.get_average(x, y, settings.boundary, attr)
+                    };
+                    let cell = &mut row[x];
+                    cell.density = (s.density + k * avg(|c| c.density)) / (1.0 + k);
+                    cell.temperature = (s.temperature + k * avg(|c| c.temperature)) / (1.0 + k);
+                    let dye = Vec3::new(avg(|c| c.dye.x), avg(|c| c.dye.y), avg(|c| c.dye.z));
+                    cell.dye = (s.dye + k * dye) / (1.0 + k);
+
+                    for (i, rates) in settings.species.iter().enumerate() {
+                        let k = rates.diffusion * dt;
+                        let avg = previous.get_average(x, y, settings.boundary, |c| c.species[i]);
+                        cell.species[i] = (s.species[i] + k * avg) / (1.0 + k);
+                    }
+                }
+            });
+            residual = residual.max(sweep_residual(previous, new_grid, |old, new| {
+                let change = (new.density - old.density)
+                    .abs()
+                    .max((new.temperature - old.temperature).abs())
+                    .max((new.dye - old.dye).abs().max_element());
+                let species = settings.species.iter().enumerate().map(|(i, rates)| {
+                    (1.0 + rates.diffusion * dt) * (new.species[i] - old.species[i]).abs()
+                });
+                species.fold((1.0 + k) * change, f32::max)
+            }));
+        }
+        residual
+    });
+    std::mem::swap(grid, new_grid);
+    carried
+}
+
+/// Residual of the equations of the cells a half sweep relaxed, before it: `residual` gets
+/// the cells before and after it, each having changed by its residual divided by `1 + k`
+fn sweep_residual(previous: &Grid, next: &Grid, residual: impl Fn(&Cell, &Cell) -> f32) -> f32 {
+    previous
+        .0
+        .iter()
+        .flatten()
+        .zip(next.0.iter().flatten())
+        .map(|(old, new)| residual(old, new))
+        .fold(0.0, f32::max)
+}
+
+/// Every carried field bilinearly interpolated at a fractional cell position, like
+/// `Grid::sample_bilinear` samples one of them, the corners being looked up once
+fn sample_cell(grid: &Grid, pos: Vec2, boundary: BoundaryMode) -> Cell {
+    let mut cell = Cell {
+        velocity: Vec2::ZERO,
+        faces: Vec2::ZERO,
+        density: 0.0,
+        dye: Vec3::ZERO,
+        temperature: 0.0,
+        species: [0.0; SPECIES],
+        fuel: 0.0,
+        level: 0.0,
+        obstacle: false,
+        vorticity: 0.0,
+        stream: 0.0,
+    };
+    let corners = grid.bilinear_corners(pos, boundary);
+    for (corner, &weight) in corners.iter().filter(|(_, weight)| *weight > 0.0) {
+        cell.velocity += corner.velocity * weight;
+        cell.density += corner.density * weight;
+        cell.dye += corner.dye * weight;
+        cell.temperature += corner.temperature * weight;
+        cell.fuel += corner.fuel * weight;
+        cell.level += corner.level * weight;
+        cell.stream += corner.stream * weight;
+        for (amount, corner_amount) in cell.species.iter_mut().zip(corner.species.iter()) {
+            *amount += corner_amount * weight;
+        }
+    }
+    cell
+}
+
+/// Cell whose every carried field is `f` of the fields of `a`, `b` and `c`, being an
+/// obstacle like `a` and having its faces
+fn zip_cells(a: &Cell, b: &Cell, c: &Cell, f: impl Fn(f32, f32, f32) -> f32) -> Cell {
+    let mut species = [0.0; SPECIES];
+    for (i, amount) in species.iter_mut().enumerate() {
+        *amount = f(a.species[i], b.species[i], c.species[i]);
+    }
+    Cell {
+        velocity: Vec2::new(
+            f(a.velocity.x, b.velocity.x, c.velocity.x),
+            f(a.velocity.y, b.velocity.y, c.velocity.y),
+        ),
+        faces: a.faces,
+        density: f(a.density, b.density, c.density),
+        dye: Vec3::new(
+            f(a.dye.x, b.dye.x, c.dye.x),
+            f(a.dye.y, b.dye.y, c.dye.y),
+            f(a.dye.z, b.dye.z, c.dye.z),
+        ),
+        temperature: f(a.temperature, b.temperature, c.temperature),
+        species,
+        fuel: f(a.fuel, b.fuel, c.fuel),
+        level: f(a.level, b.level, c.level),
+        obstacle: a.obstacle,
+        vorticity: a.vorticity,
+        stream: a.stream,
+    }
+}
+
+/// Carried fields of `cell` clamped within the ones of the fluid corners of the
+/// interpolation of `grid` at `pos`, so a correction can't create new extremes. None when
+/// the corners are all obstacles.
+fn limit(cell: &Cell, grid: &Grid, pos: Vec2, boundary: BoundaryMode) -> Option<Cell> {
+    let corners = grid.bilinear_corners(pos, boundary);
+    let mut fluid = corners
+        .iter()
+        .map(|(corner, _)| corner)
+        .filter(|corner| !corner.obstacle);
+    let first = fluid.next()?;
+    let (mut low, mut high) = (first.clone(), first.clone());
+    for corner in fluid {
+        low = zip_cells(&low, corner, corner, |l, c, _| l.min(c));
+        high = zip_cells(&high, corner, corner, |h, c, _| h.max(c));
+    }
+    Some(zip_cells(cell, &low, &high, |v, l, h| v.max(l).min(h)))
+}
+
+/// Where the face velocity carries the cell (x, y) in `dt` seconds, back in time for a
+/// negative `dt`, see `MacGrid::trace`
+fn trace(mac: &MacGrid, x: usize, y: usize, dt: f32, settings: &SolverSettings) -> Vec2 {
+    mac.trace(Vec2::new(x as f32, y as f32), dt, settings)
+}
+
+/// Semi-Lagrangian advection of the fluid cell (x, y), sampling `source` where the face
+/// velocity traces back to
+fn semi_lagrangian_cell(
+    mac: &MacGrid,
+    source: &Grid,
+    x: usize,
+    y: usize,
+    dt: f32,
+    settings: &SolverSettings,
+) -> Cell {
+    sample_cell(source, trace(mac, x, y, -dt, settings), settings.boundary)
+}
+
+/// MacCormack correction of the fluid cell (x, y), `forward` being the semi-Lagrangian
+/// advection of `grid`: tracing the forward result back along the velocity should give the
+/// cell again, half of the difference is the error the forward pass made
+fn maccormack_cell(
+    mac: &MacGrid,
+    grid: &Grid,
+    forward: &Grid,
+    x: usize,
+    y: usize,
+    dt: f32,
+    settings: &SolverSettings,
+) -> Cell {
+    let predicted = &forward.0[y][x];
+    let traced = sample_cell(forward, trace(mac, x, y, dt, settings), settings.boundary);
+    let corrected = zip_cells(predicted, &grid.0[y][x], &traced, |p, c, t| {
+        p + 0.5 * (c - t)
+    });
+    limit(
+        &corrected,
+        grid,
+        trace(mac, x, y, -dt, settings),
+        settings.boundary,
+    )
+    .unwrap_or_else(|| predicted.clone())
+}
+
+/// First half of the BFECC correction of the fluid cell (x, y), `forward` being the
+/// semi-Lagrangian advection of `grid`: the cell compensated by half the error of a round
+/// trip, forward then back, to be advected again
+fn bfecc_source_cell(
+    mac: &MacGrid,
+    grid: &Grid,
+    forward: &Grid,
+    x: usize,
+    y: usize,
+    dt: f32,
+    settings: &SolverSettings,
+) -> Cell {
+    let cell = &grid.0[y][x];
+    let round_trip = sample_cell(forward, trace(mac, x, y, dt, settings), settings.boundary);
+    zip_cells(cell, cell, &round_trip, |c, _, r| c + 0.5 * (c - r))
+}
+
+/// Second half of the BFECC correction: the compensated `source` advected along the face
+/// velocity, limited like the MacCormack correction
+fn bfecc_cell(
+    mac: &MacGrid,
+    grid: &Grid,
+    source: &Grid,
+    x: usize,
+    y: usize,
+    dt: f32,
+    settings: &SolverSettings,
+) -> Cell {
+    let pos = trace(mac, x, y, -dt, settings);
+    let corrected = sample_cell(source, pos, settings.boundary);
+    limit(&corrected, grid, pos, settings.boundary)
+        .unwrap_or_else(|| semi_lagrangian_cell(mac, grid, x, y, dt, settings))
+}
+
+/// Write `cell(x, y)` to the fluid cells of `target`, its obstacles being the ones of `grid`.
+/// The fluid cells keep the velocity and the faces of `grid`, which `MacGrid` advects.
+fn fill(grid: &Grid, target: &mut Grid, cell: impl Fn(usize, usize) -> Cell) {
+    for (y, row) in target.0.iter_mut().enumerate() {
+        for (x, target) in row.iter_mut().enumerate() {
+            let source = &grid.0[y][x];
+            *target = if source.obstacle {
+                source.clone()
+            } else {
+                Cell {
+                    velocity: source.velocity,
+                    faces: source.faces,
+                    ..cell(x, y)
+                }
+            };
+        }
+    }
+}
+
+/// Threaded version of `fill` writing over the fluid cells of `grid` itself, the closure
+/// reading copies of it
+fn fill_threaded(grid: &mut Grid, workers: &Workers, cell: impl Fn(usize, usize) -> Cell + Sync) {
+    workers.for_each_row(&mut grid.0, |y, row| {
+        for (x, target) in row
+            .iter_mut()
+            .enumerate()
+            .filter(|(_, cell)| !cell.obstacle)
+        {
+            *target = Cell {
+                velocity: target.velocity,
+                faces: target.faces,
+                ..cell(x, y)
+            };
+        }
+    });
+}
+
+/// Advection of the density, the dye, the species, the temperature and the velocity itself
+/// with the scheme of the settings, see `AdvectionScheme`, in `advection_iterations` substeps
+/// of the time step. The cells are advected along the faces, then the faces along
+/// themselves, unless the FLIP particles carry the velocity.
+pub fn advect(grid: &mut Grid, dt: f32, settings: &SolverSettings, scratch: &mut Scratch) {
+    let substeps = settings.advection_iterations.max(1);
+    let dt = dt / substeps as f32;
+    scratch.prepare(grid);
+    scratch.prepare_workers(settings.backend);
+    scratch.mac.sync(grid, settings.boundary);
+    for _ in 0..substeps {
+        match settings.backend {
+            Backend::Threaded(_) => advect_threaded(grid, dt, settings, scratch),
+            Backend::Gpu | Backend::Scalar => advect_cells(grid, dt, settings, scratch),
+        }
+        if settings.flip.is_none() {
+            scratch.mac.advect(grid, dt, settings, &scratch.workers);
+        }
+    }
+    scratch.mac.store(grid);
+}
+
+/// One substep of the advection of the cells along the faces
+fn advect_cells(grid: &mut Grid, dt: f32, settings: &SolverSettings, scratch: &mut Scratch) {
+    let Scratch {
+        grid: buffer,
+        forward,
+        mac,
+        ..
+    } = scratch;
+    let (g, mac) = (&*grid, &*mac);
+    match settings.advection {
+        AdvectionScheme::SemiLagrangian => {
+            fill(g, buffer, |x, y| {
+                semi_lagrangian_cell(mac, g, x, y, dt, settings)
+            });
+            std::mem::swap(grid, buffer);
+        }
+        AdvectionScheme::MacCormack => {
+            fill(g, forward, |x, y| {
+                semi_lagrangian_cell(mac, g, x, y, dt, settings)
+            });
+            let f = &*forward;
+            fill(g, buffer, |x, y| {
+                maccormack_cell(mac, g, f, x, y, dt, settings)
+            });
+            std::mem::swap(grid, buffer);
+        }
+        AdvectionScheme::Bfecc => {
+            fill(g, forward, |x, y| {
+                semi_lagrangian_cell(mac, g, x, y, dt, settings)
+            });
+            let f = &*forward;
+            fill(g, buffer, |x, y| {
+                bfecc_source_cell(mac, g, f, x, y, dt, settings)
+            });
+            let source = &*buffer;
+            fill(g, forward, |x, y| {
+                bfecc_cell(mac, g, source, x, y, dt, settings)
+            });
+            std::mem::swap(grid, forward);
+        }
+    }
+}
+
+/// Threaded version of `advect_cells`, each band of rows reading copies of the previous pass
+fn advect_threaded(grid: &mut Grid, dt: f32, settings: &SolverSettings, scratch: &mut Scratch) {
+    let Scratch {
+        grid: previous,
+        forward,
+        mac,
+        workers,
+        ..
+    } = scratch;
+    let mac = &*mac;
+    previous.0.clone_from(&grid.0);
+    let p = &*previous;
+    fill_threaded(grid, workers, |x, y| {
+        semi_lagrangian_cell(mac, p, x, y, dt, settings)
+    });
+
+    match settings.advection {
+        AdvectionScheme::SemiLagrangian => {}
+        AdvectionScheme::MacCormack => {
+            forward.0.clone_from(&grid.0);
+            let f = &*forward;
+            fill_threaded(grid, workers, |x, y| {
+                maccormack_cell(mac, p, f, x, y, dt, settings)
+            });
+        }
+        AdvectionScheme::Bfecc => {
+            forward.0.clone_from(&grid.0);
+            let f = &*forward;
+            fill_threaded(grid, workers, |x, y| {
+                bfecc_source_cell(mac, p, f, x, y, dt, settings)
+            });
+            // The forward pass isn't needed anymore, its buffer holds the source
+            forward.0.clone_from(&grid.0);
+            let source = &*forward;
+            fill_threaded(grid, workers, |x, y| {
+                bfecc_cell(mac, p, source, x, y, dt, settings)
+            });
+        }
+    }
+}
+
+struct PField(Vec<Vec<f32>>);
+
+impl PField {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self(vec![vec![0.0; width]; height])
+    }
+
+    fn get_average(&self, x: usize, y: usize, grid: &Grid, boundary: BoundaryMode) -> f32 {
+        let at = |dx, dy| scalar_at(&self.0, grid, x, y, dx, dy, boundary);
+
+        let px1 = at(1, 0);
+        let px2 = at(-1, 0);
+        let py1 = at(0, 1);
+        let py2 = at(0, -1);
+
+        let p = (px1 + px2 + py1 + py2) / 4.0;
+        p
+    }
+}
+
+/// Value of a scalar field at the neighbor (x + dx, y + dy) of a cell, the value of the
+/// cell standing for it outside of the grid. Nothing flows through walls nor obstacles,
+/// the value of the cell is carried behind them.
+fn scalar_at(
+    field: &[Vec<f32>],
+    grid: &Grid,
+    x: usize,
+    y: usize,
+    dx: isize,
+    dy: isize,
+    boundary: BoundaryMode,
+) -> f32 {
+    let (width, height) = (grid.width(), grid.height());
+    let (nx, ny, _) = boundary.ghost(x as isize + dx, y as isize + dy, width, height);
+    if grid.0[ny][nx].obstacle {
+        field[y][x]
+    } else {
+        field[ny][nx]
+    }
+}
+
+/// Fields of the preconditioned conjugate gradient, only sized once it's used
+#[derive(Default)]
+struct Conjugate {
+    residual: Vec<Vec<f32>>,
+    /// Residual divided by the diagonal of the equations
+    preconditioned: Vec<Vec<f32>>,
+    /// Direction the pressure moves along in the next iteration
+    search: Vec<Vec<f32>>,
+    /// The equations applied to the search direction
+    product: Vec<Vec<f32>>,
+}
+
+impl Conjugate {
+    fn prepare(&mut self, width: usize, height: usize) {
+        if self.residual.len() != height || self.residual[0].len() != width {
+            self.residual = vec![vec![0.0; width]; height];
+            self.preconditioned = vec![vec![0.0; width]; height];
+            self.search = vec![vec![0.0; width]; height];
+            self.product = vec![vec![0.0; width]; height];
+        }
+    }
+}
+
+/// Largest residual of the pressure equations the conjugate gradient and the multigrid
+/// stop at
+pub const PRESSURE_TOLERANCE: f32 = 1e-6;
+
+/// Left side of the pressure equation of a fluid cell, `p - avg(p) = -divergence / 4`
+fn pressure_equation(
+    field: &[Vec<f32>],
+    grid: &Grid,
+    x: usize,
+    y: usize,
+    boundary: BoundaryMode,
+) -> f32 {
+    let at = |dx, dy| scalar_at(field, grid, x, y, dx, dy, boundary);
+    field[y][x] - (at(1, 0) + at(-1, 0) + at(0, 1) + at(0, -1)) / 4.0
+}
+
+/// Diagonal of the pressure equation of a fluid cell, the walls and obstacles standing in
+/// for their neighbor taking their part away. 0 for a cell closed in on every side.
+fn pressure_diagonal(grid: &Grid, x: usize, y: usize, boundary: BoundaryMode) -> f32 {
+    let (width, height) = (grid.width(), grid.height());
+    let open = [(1, 0), (-1, 0), (0, 1), (0, -1)]
+        .iter()
+        .filter(|&&(dx, dy)| {
+            let (nx, ny, _) = boundary.ghost(x as isize + dx, y as isize + dy, width, height);
+            (nx, ny) != (x, y) && !grid.0[ny][nx].obstacle
+        })
+        .count();
+    open as f32 / 4.0
+}
+
+fn dot(a: &[Vec<f32>], b: &[Vec<f32>]) -> f64 {
+    let products = a.iter().flatten().zip(b.iter().flatten());
+    products.map(|(a, b)| *a as f64 * *b as f64).sum()
+}
+
+/// Solve the pressure equations of the fluid cells by conjugate gradient, preconditioned by
+/// their diagonal. The pressure is only known up to a constant, so the part of the
+/// divergence no pressure can clear, its mean, is left out, unless the air of a free
+/// surface sets the pressure.
+fn conjugate_gradient(
+    grid: &Grid,
+    settings: &SolverSettings,
+    quarter_divergence: &[Vec<f32>],
+    pressure: &mut [Vec<f32>],
+    buffers: &mut Conjugate,
+) {
+    let (width, height) = (grid.width(), grid.height());
+    let boundary = settings.boundary;
+    buffers.prepare(width, height);
+    let Conjugate {
+        residual,
+        preconditioned,
+        search,
+        product,
+    } = buffers;
+
+    let fluid = || {
+        (0..height)
+            .flat_map(move |y| (0..width).map(move |x| (x, y)))
+            .filter(move |&(x, y)| {
+                let cell = &grid.0[y][x];
+                !cell.obstacle
+                    && !surface::is_air(cell)
+                    && pressure_diagonal(grid, x, y, boundary) > 0.0
+            })
+    };
+    let (sum, count) = fluid().fold((0.0, 0), |(sum, count), (x, y)| {
+        (sum + quarter_divergence[y][x] as f64, count + 1)
+    });
+    let mean = if count > 0 && !surface::has_air(grid) {
+        (sum / count as f64) as f32
+    } else {
+        0.0
+    };
+
+    // The pressure starts at 0, the residual at the right side
+    residual.iter_mut().flatten().for_each(|r| *r = 0.0);
+    for (x, y) in fluid() {
+        residual[y][x] = mean - quarter_divergence[y][x];
+    }
+    let precondition = |residual: &[Vec<f32>], preconditioned: &mut [Vec<f32>]| {
+        for (x, y) in fluid() {
+            preconditioned[y][x] = residual[y][x] / pressure_diagonal(grid, x, y, boundary);
+        }
+    };
+    preconditioned.iter_mut().flatten().for_each(|z| *z = 0.0);
+    precondition(residual, preconditioned);
+    for (search_row, row) in search.iter_mut().zip(preconditioned.iter()) {
+        search_row.copy_from_slice(row);
+    }
+    let mut rz = dot(residual, preconditioned);
+
+    for _ in 0..settings.projection_iterations {
+        let largest = residual
+            .iter()
+            .flatten()
+            .fold(0.0f32, |max, r| max.max(r.abs()));
+        if largest <= PRESSURE_TOLERANCE {
+            break;
+        }
+
+        for (x, y) in fluid() {
+            product[y][x] = pressure_equation(search, grid, x, y, boundary);
+        }
+        let curvature = dot(search, product);
+        if curvature <= 0.0 {
+            break;
+        }
+        let alpha = (rz / curvature) as f32;
+        for (x, y) in fluid() {
+            pressure[y][x] += alpha * search[y][x];
+            residual[y][x] -= alpha * product[y][x];
+        }
+
+        precondition(residual, preconditioned);
+        let next_rz = dot(residual, preconditioned);
+        let beta = (next_rz / rz) as f32;
+        rz = next_rz;
+        for (x, y) in fluid() {
+            search[y][x] = preconditioned[y][x] + beta * search[y][x];
+        }
+    }
+}
+
+/// Pressure projection, making the velocity divergence-free so the fluid is incompressible:
+/// solve the Poisson equation of the pressure against the divergence, then subtract the
+/// pressure gradient from the velocity. Runs as the Project stage after Diffuse, so that the
+/// advection moves the fluid along a divergence-free velocity, and again at the end of the
+/// Advect stage, whose self-advected velocity diverges.
+pub fn clear_divergence(grid: &mut Grid, settings: &SolverSettings, scratch: &mut Scratch) {
+    let (width, height) = (grid.width(), grid.height());
+    scratch.prepare(grid);
+    scratch.prepare_workers(settings.backend);
+    let p = &mut scratch.pressure;
+    p.0.iter_mut().flatten().for_each(|v| *v = 0.0);
+    // vel_grad_field_quarter contains the divergence of the faces divided by 4
+    scratch.mac.sync(grid, settings.boundary);
+    scratch
+        .mac
+        .fill_quarter_divergence(grid, &mut scratch.divergence);
+    subtract_expansion(&mut scratch.divergence, &scratch.expansion);
+    let vel_grad_field_quarter = &scratch.divergence;
+
+    // The conjugate gradient and the multigrid run their own iterations instead of the
+    // relaxation ones. The multigrid doesn't know about the air, the conjugate gradient
+    // solves the liquid of a free surface instead.
+    let quarter = vel_grad_field_quarter;
+    let air = surface::has_air(grid);
+    let relaxations = match settings.pressure_solver {
+        SolverBackend::GaussSeidel => settings.projection_iterations,
+        SolverBackend::Multigrid if !air => {
+            let cycles = settings.projection_iterations;
+            let multigrid = &mut scratch.multigrid;
+            multigrid.solve(grid, settings.boundary, quarter, &mut p.0, cycles);
+            0
+        }
+        SolverBackend::Pcg | SolverBackend::Multigrid => {
+            conjugate_gradient(grid, settings, quarter, &mut p.0, &mut scratch.conjugate);
+            0
+        }
+    };
+    let previous = &mut scratch.previous_pressure;
+    for _ in 0..relaxations {
+        if let Backend::Threaded(_) = settings.backend {
+            // Each color reads the pressure from before it, only its own cells changing
+            for color in 0..2 {
+                previous.0.clone_from(&p.0);
+                let previous = &*previous;
+                let solid = &*grid;
+                scratch.workers.for_each_row(&mut p.0, |y, row| {
+                    for x in colored(row.len(), y, color) {
+                        let cell = &solid.0[y][x];
+                        if !cell.obstacle && !surface::is_air(cell) {
+                            row[x] = previous.get_average(x, y, solid, settings.boundary)
+                                - vel_grad_field_quarter[y][x];
+                        }
+                    }
+                });
+            }
+            continue;
+        }
+
+        for (x, y) in red_black(width, height) {
+            let cell = &grid.0[y][x];
+            if !cell.obstacle && !surface::is_air(cell) {
+                p.0[y][x] =
+                    p.get_average(x, y, grid, settings.boundary) - vel_grad_field_quarter[y][x];
+            }
+        }
+    }
+
+    // Substracting the curl-free vector field from the original field
+    // to get a divergence-free field, on the faces the grid stores
+    scratch.mac.subtract_gradient(grid, &p.0, settings.boundary);
+    scratch.mac.store(grid);
+
+    // Keep what's left of the stored faces for the diagnostics, a failing projection
+    // leaves a lot
+    scratch
+        .mac
+        .fill_quarter_divergence(grid, &mut scratch.divergence);
+    subtract_expansion(&mut scratch.divergence, &scratch.expansion);
+    surface::clear_air(grid, &mut scratch.divergence);
+}
+
+/// Leave out of the divergence the expansion of the burning fuel, which the projection
+/// keeps instead of clearing
+fn subtract_expansion(quarter: &mut [Vec<f32>], expansion: &[Vec<f32>]) {
+    for (row, expansion) in quarter.iter_mut().zip(expansion.iter()) {
+        for (value, expansion) in row.iter_mut().zip(expansion.iter()) {
+            *value -= expansion;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: usize = 16;
+
+    /// A grid whose velocity both swirls and spreads out and gathers in waves
+    fn diverging_grid() -> Grid {
+        let mut grid = Grid::new(SIZE, SIZE);
+        let wave = std::f32::consts::TAU / SIZE as f32;
+        for (y, row) in grid.0.iter_mut().enumerate() {
+            for (x, cell) in row.iter_mut().enumerate() {
+                let (x, y) = (x as f32 * wave, y as f32 * wave);
+                cell.velocity = Vec2::new(x.sin() + y.cos(), (2.0 * y).cos() - x.sin());
+            }
+        }
+        grid
+    }
+
+    /// Largest divergence left by a projection with `settings`
+    fn divergence_after(settings: &SolverSettings) -> f32 {
+        let mut grid = diverging_grid();
+        let mut scratch = Scratch::default();
+        clear_divergence(&mut grid, settings, &mut scratch);
+        scratch
+            .residual_divergence()
+            .fold(0.0, |max, d| max.max(d.abs()))
+    }
+
+    fn assert_projects(pressure_solver: SolverBackend, backend: Backend) {
+        for &boundary in &[BoundaryMode::Periodic, BoundaryMode::NoSlip] {
+            let mut settings = SolverSettings {
+                pressure_solver,
+                backend,
+                boundary,
+                projection_iterations: 500,
+                ..SolverSettings::default()
+            };
+            let after = divergence_after(&settings);
+            // Without relaxing, the gradient of the zero pressure leaves the divergence
+            settings.pressure_solver = SolverBackend::GaussSeidel;
+            settings.projection_iterations = 0;
+            let before = divergence_after(&settings);
+
+            assert!(before > 0.1, "{:?}: {}", boundary, before);
+            assert!(
+                after < 1e-3 * before,
+                "{:?}: {} of {}",
+                boundary,
+                after,
+                before
+            );
+        }
+    }
+
+    #[test]
+    fn gauss_seidel_clears_the_divergence() {
+        assert_projects(SolverBackend::GaussSeidel, Backend::Scalar);
+    }
+
+    #[test]
+    fn threaded_gauss_seidel_clears_the_divergence() {
+        assert_projects(SolverBackend::GaussSeidel, Backend::Threaded(3));
+    }
+
+    #[test]
+    fn conjugate_gradient_clears_the_divergence() {
+        assert_projects(SolverBackend::Pcg, Backend::Scalar);
+    }
+
+    #[test]
+    fn multigrid_clears_the_divergence() {
+        assert_projects(SolverBackend::Multigrid, Backend::Scalar);
+    }
+
+    #[test]
+    fn the_stored_velocity_is_left_without_divergence() {
+        let settings = SolverSettings {
+            pressure_solver: SolverBackend::Pcg,
+            boundary: BoundaryMode::Periodic,
+            projection_iterations: 500,
+            ..SolverSettings::default()
+        };
+        let mut grid = diverging_grid();
+        let mut scratch = Scratch::default();
+        clear_divergence(&mut grid, &settings, &mut scratch);
+
+        // Loading the faces the grid kept measures them again
+        let mut quarter = vec![vec![0.0; SIZE]; SIZE];
+        let mut mac = MacGrid::default();
+        mac.sync(&grid, settings.boundary);
+        mac.fill_quarter_divergence(&grid, &mut quarter);
+        let left = quarter
+            .iter()
+            .flatten()
+            .fold(0.0f32, |max, d| max.max(d.abs()));
+        assert!(left < 1e-4, "{}", left);
+    }
+
+    #[test]
+    fn a_checkerboard_of_the_cells_is_cleared() {
+        let mut grid = Grid::new(SIZE, SIZE);
+        for (y, row) in grid.0.iter_mut().enumerate() {
+            for (x, cell) in row.iter_mut().enumerate() {
+                let sign = if (x + y) % 2 == 0 { 1.0 } else { -1.0 };
+                cell.velocity = Vec2::new(sign, -sign);
+            }
+        }
+        let settings = SolverSettings {
+            boundary: BoundaryMode::Periodic,
+            ..SolverSettings::default()
+        };
+        clear_divergence(&mut grid, &settings, &mut Scratch::default());
+
+        for cell in grid.0.iter().flatten() {
+            assert!(
+                cell.velocity.abs().max_element() < 1e-5,
+                "{}",
+                cell.velocity
+            );
+        }
+    }
+
+    #[test]
+    fn obstacles_keep_their_velocity() {
+        let mut grid = diverging_grid();
+        for y in 6..10 {
+            for x in 6..10 {
+                grid.0[y][x].obstacle = true;
+                grid.0[y][x].velocity = Vec2::ZERO;
+            }
+        }
+        let settings = SolverSettings {
+            pressure_solver: SolverBackend::Pcg,
+            projection_iterations: 500,
+            ..SolverSettings::default()
+        };
+        let mut scratch = Scratch::default();
+        clear_divergence(&mut grid, &settings, &mut scratch);
+
+        assert_eq!(grid.0[7][7].velocity, Vec2::ZERO);
+        let left = scratch
+            .residual_divergence()
+            .fold(0.0, |max, d| max.max(d.abs()));
+        assert!(left < 1e-3, "{}", left);
+    }
+}
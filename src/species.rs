@@ -0,0 +1,91 @@
+use bevy::prelude::*;
+
+use crate::{AppState, Cell};
+
+// Dye species: scalar fields carried by the flow like the density, each spreading and fading
+// at its own rate and drawn in its own color over the dye, so species injected side by side
+// mix on screen. 5 cycles through what the brush injects, the density or one of the species.
+
+/// Species every cell carries
+pub const SPECIES: usize = 4;
+const NAMES: [&str; SPECIES] = ["RED", "BLUE", "GREEN", "YELLOW"];
+const COLORS: [[f32; 3]; SPECIES] = [
+    [1.0, 0.15, 0.1],
+    [0.1, 0.3, 1.0],
+    [0.1, 0.9, 0.2],
+    [1.0, 0.85, 0.1],
+];
+
+/// How fast each species spreads and fades, a different pair for each so they can be told
+/// apart: red stays sharp, blue spreads, green spreads and fades, yellow fades quickly
+pub const DEFAULT_RATES: [SpeciesRates; SPECIES] = [
+    SpeciesRates {
+        diffusion: 0.2,
+        dissipation: 0.0,
+    },
+    SpeciesRates {
+        diffusion: 5.0,
+        dissipation: 0.0,
+    },
+    SpeciesRates {
+        diffusion: 2.0,
+        dissipation: 0.1,
+    },
+    SpeciesRates {
+        diffusion: 0.5,
+        dissipation: 0.5,
+    },
+];
+
+pub struct SpeciesPlugin;
+
+impl Plugin for SpeciesPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(SpeciesBrush::default()).add_system_set(
+            SystemSet::on_update(AppState::Running).with_system(species_keys_system.system()),
+        );
+    }
+}
+
+/// How a species spreads to the neighbouring cells and fades away, per second
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SpeciesRates {
+    pub diffusion: f32,
+    pub dissipation: f32,
+}
+
+/// Species the brush injects, the density when None
+#[derive(Default)]
+pub struct SpeciesBrush(pub Option<usize>);
+
+impl SpeciesBrush {
+    pub fn label(&self) -> &'static str {
+        self.0.map_or("DENSITY", |i| NAMES[i])
+    }
+}
+
+/// Color the species of a cell add to its dye
+pub fn color(cell: &Cell) -> Vec3 {
+    cell.species
+        .iter()
+        .zip(COLORS.iter())
+        .map(|(&amount, &color)| Vec3::from(color) * amount)
+        .sum()
+}
+
+/// 5 cycles the brush through the density and the species
+fn species_keys_system(
+    mut brush: ResMut<SpeciesBrush>,
+    mut char_input_events: EventReader<ReceivedCharacter>,
+) {
+    for event in char_input_events.iter() {
+        if event.char == '5' {
+            brush.0 = match brush.0 {
+                None => Some(0),
+                Some(i) if i + 1 < SPECIES => Some(i + 1),
+                Some(_) => None,
+            };
+            info!("Brush: {}", brush.label());
+        }
+    }
+}
@@ -0,0 +1,419 @@
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+
+use crate::boundary::BoundaryMode;
+use crate::layers::{Layer, OnLayer};
+use crate::settings::Buoyancy;
+use crate::stepping::StepControl;
+use crate::surface;
+use crate::{grid_to_world, Grid, SolverSettings, CELL_SIZE};
+
+// Smoothed particle hydrodynamics, the Lagrangian counterpart of the grid: the fluid is a
+// set of particles, each spreading its mass over the neighbors within a smoothing length.
+// Their density is the sum of the masses around them weighted by the poly6 kernel, the
+// pressure pushes apart the particles packed above the rest density along the gradient of
+// the spiky kernel, the fluid being slightly compressible, and the viscosity evens out their
+// velocities with the laplacian of the viscosity kernel. The neighbors are found through a
+// grid of bins as large as the smoothing length, so only the 9 bins around a particle are
+// searched.
+//
+// Ctrl+L fills the liquid of the grid with particles, or the smoke if there's no surface,
+// with the velocity of their cells, and steps them next to the grid with the same time step
+// so both can be compared from the same start. They feel the gravity of the liquid, the
+// buoyancy of the smoke and the body force of the settings, but not the brushes, the fans
+// or the emitters, which only act on the grid. They slide along the walls and stop against
+// the obstacles, and don't see their neighbors across periodic edges.
+
+/// Distance between the particles seeded in a cell, 2 by 2
+const SPACING: f32 = 0.5;
+/// Distance the particles see their neighbors at, in cells
+const SMOOTHING: f32 = 2.0 * SPACING;
+/// Pressure per density above the rest density, the square of the speed of sound
+const STIFFNESS: f32 = 20000.0;
+/// Viscosity added to the one of the settings so the particles don't jitter, cells^2/s
+const MIN_VISCOSITY: f32 = 1.0;
+/// Fraction of the smoothing length a particle may move in a substep
+const COURANT: f32 = 0.4;
+const MAX_SUBSTEPS: usize = 32;
+/// Density of the smoke cells seeded when the grid has no surface
+const SEED_DENSITY: f32 = 0.1;
+
+const LIQUID_COLOR: Color = Color::rgba(0.3, 0.6, 1.0, 0.8);
+const SMOKE_COLOR: Color = Color::rgba(1.0, 0.8, 0.4, 0.8);
+
+const NO_PARTICLE: usize = usize::MAX;
+
+pub struct SphPlugin;
+
+impl Plugin for SphPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(Sph::default())
+            .add_system(sph_keys_system.system())
+            .add_system(sph_system.system())
+            .add_system(sph_render_system.system());
+    }
+}
+
+/// Sprite of the particle at an index
+struct SphParticle(usize);
+
+pub struct Sph {
+    pub active: bool,
+    /// Whether the particles were seeded in a liquid, which falls, or in smoke, which rises
+    liquid: bool,
+    /// Size of the grid the particles were seeded in
+    size: (usize, usize),
+    /// Mass of a particle, giving the particles of the seeding lattice a density of 1
+    mass: f32,
+    /// In cells
+    positions: Vec<Vec2>,
+    velocities: Vec<Vec2>,
+    densities: Vec<f32>,
+    pressures: Vec<f32>,
+    accelerations: Vec<Vec2>,
+    /// Smoke density and temperature carried from the seeded cells, for the buoyancy
+    smoke: Vec<f32>,
+    heat: Vec<f32>,
+    /// Bins of the neighbor search, their first particle and the next one of every particle
+    bins: (usize, usize),
+    heads: Vec<usize>,
+    next: Vec<usize>,
+    /// Simulated time of the last step followed, so a paused frame isn't stepped again
+    last_time: f32,
+}
+
+impl Default for Sph {
+    fn default() -> Self {
+        Self {
+            active: false,
+            liquid: false,
+            size: (0, 0),
+            mass: 0.0,
+            positions: Vec::new(),
+            velocities: Vec::new(),
+            densities: Vec::new(),
+            pressures: Vec::new(),
+            accelerations: Vec::new(),
+            smoke: Vec::new(),
+            heat: Vec::new(),
+            bins: (0, 0),
+            heads: Vec::new(),
+            next: Vec::new(),
+            last_time: 0.0,
+        }
+    }
+}
+
+/// Weight of a neighbor at a squared distance in the density
+fn poly6(distance2: f32) -> f32 {
+    let h2 = SMOOTHING * SMOOTHING;
+    if distance2 >= h2 {
+        return 0.0;
+    }
+    4.0 / (PI * SMOOTHING.powi(8)) * (h2 - distance2).powi(3)
+}
+
+/// Length of the gradient of the spiky kernel at a distance, pointing away from the center
+fn spiky_gradient(distance: f32) -> f32 {
+    30.0 / (PI * SMOOTHING.powi(5)) * (SMOOTHING - distance).powi(2)
+}
+
+/// Laplacian of the viscosity kernel at a distance
+fn viscosity_laplacian(distance: f32) -> f32 {
+    40.0 / (PI * SMOOTHING.powi(5)) * (SMOOTHING - distance)
+}
+
+impl Sph {
+    pub fn memory_bytes(&self) -> usize {
+        self.positions.capacity() * 3 * std::mem::size_of::<Vec2>()
+            + self.positions.capacity() * 4 * std::mem::size_of::<f32>()
+            + (self.heads.capacity() + self.next.capacity()) * std::mem::size_of::<usize>()
+    }
+
+    fn clear(&mut self) {
+        self.positions.clear();
+        self.velocities.clear();
+        self.accelerations.clear();
+        self.densities.clear();
+        self.pressures.clear();
+        self.smoke.clear();
+        self.heat.clear();
+        self.next.clear();
+    }
+
+    /// Particles on a lattice over the liquid of the grid, or its smoke without a surface,
+    /// or over all of it without either
+    fn seed(&mut self, grid: &Grid) {
+        self.clear();
+        let (width, height) = (grid.width(), grid.height());
+        self.size = (width, height);
+        self.liquid = surface::has_air(grid);
+        let smoky = grid
+            .0
+            .iter()
+            .flatten()
+            .any(|cell| !cell.obstacle && cell.density > SEED_DENSITY);
+        for (y, row) in grid.0.iter().enumerate() {
+            for (x, cell) in row.iter().enumerate() {
+                let seeded = if cell.obstacle {
+                    false
+                } else if self.liquid {
+                    cell.level <= 0.0
+                } else {
+                    !smoky || cell.density > SEED_DENSITY
+                };
+                if !seeded {
+                    continue;
+                }
+                for i in 0..4 {
+                    let offset = Vec2::new((i % 2) as f32 - 0.5, (i / 2) as f32 - 0.5) * SPACING;
+                    self.positions.push(Vec2::new(x as f32, y as f32) + offset);
+                    self.velocities.push(cell.velocity);
+                    self.smoke.push(cell.density);
+                    self.heat.push(cell.temperature);
+                }
+            }
+        }
+        let count = self.positions.len();
+        self.densities.resize(count, 0.0);
+        self.pressures.resize(count, 0.0);
+        self.accelerations.resize(count, Vec2::ZERO);
+        self.next.resize(count, NO_PARTICLE);
+
+        // The density of a particle inside of the lattice, from its neighbors and itself
+        let reach = (SMOOTHING / SPACING).ceil() as i32;
+        let lattice: f32 = (-reach..=reach)
+            .flat_map(|i| (-reach..=reach).map(move |j| (i, j)))
+            .map(|(i, j)| poly6(Vec2::new(i as f32, j as f32).length_squared() * SPACING * SPACING))
+            .sum();
+        self.mass = 1.0 / lattice;
+
+        self.bins = (
+            (width as f32 / SMOOTHING).ceil() as usize,
+            (height as f32 / SMOOTHING).ceil() as usize,
+        );
+        self.heads = vec![NO_PARTICLE; self.bins.0 * self.bins.1];
+    }
+
+    fn bin(&self, pos: Vec2) -> (usize, usize) {
+        let x = ((pos.x + 0.5) / SMOOTHING).floor().max(0.0) as usize;
+        let y = ((pos.y + 0.5) / SMOOTHING).floor().max(0.0) as usize;
+        (x.min(self.bins.0 - 1), y.min(self.bins.1 - 1))
+    }
+
+    /// Chain the particles of every bin, the neighbor search only visiting the bins around
+    fn sort_into_bins(&mut self) {
+        self.heads.iter_mut().for_each(|head| *head = NO_PARTICLE);
+        for i in 0..self.positions.len() {
+            let (x, y) = self.bin(self.positions[i]);
+            let head = &mut self.heads[y * self.bins.0 + x];
+            self.next[i] = *head;
+            *head = i;
+        }
+    }
+
+    /// Call `visit` with every other particle in the bins around a particle, with the
+    /// offset from it and its distance, if within the smoothing length
+    fn for_neighbors(&self, i: usize, mut visit: impl FnMut(usize, Vec2, f32)) {
+        let pos = self.positions[i];
+        let (bx, by) = self.bin(pos);
+        for y in by.saturating_sub(1)..(by + 2).min(self.bins.1) {
+            for x in bx.saturating_sub(1)..(bx + 2).min(self.bins.0) {
+                let mut j = self.heads[y * self.bins.0 + x];
+                while j != NO_PARTICLE {
+                    let offset = pos - self.positions[j];
+                    let distance = offset.length();
+                    if j != i && distance < SMOOTHING {
+                        visit(j, offset, distance);
+                    }
+                    j = self.next[j];
+                }
+            }
+        }
+    }
+
+    /// Advance the particles by `dt` seconds, in substeps short enough for the speed of
+    /// sound and the fastest particle
+    fn step(&mut self, grid: &Grid, dt: f32, settings: &SolverSettings) {
+        let max_speed = self
+            .velocities
+            .iter()
+            .map(|velocity| velocity.length())
+            .fold(0.0, f32::max);
+        let substep = COURANT * SMOOTHING / (STIFFNESS.sqrt() + max_speed);
+        let substeps = ((dt / substep).ceil() as usize).max(1).min(MAX_SUBSTEPS);
+        for _ in 0..substeps {
+            self.substep(grid, dt / substeps as f32, settings);
+        }
+    }
+
+    fn substep(&mut self, grid: &Grid, dt: f32, settings: &SolverSettings) {
+        self.sort_into_bins();
+        let mass = self.mass;
+        for i in 0..self.positions.len() {
+            let mut density = mass * poly6(0.0);
+            self.for_neighbors(i, |_, _, distance| {
+                density += mass * poly6(distance * distance);
+            });
+            self.densities[i] = density;
+            // Only pushing, the particles at the surface don't pull their neighbors
+            self.pressures[i] = (STIFFNESS * (density - 1.0)).max(0.0);
+        }
+
+        let viscosity = settings.viscosity + MIN_VISCOSITY;
+        let Buoyancy { alpha, beta } = settings.buoyancy;
+        for i in 0..self.positions.len() {
+            let (density, pressure, velocity) =
+                (self.densities[i], self.pressures[i], self.velocities[i]);
+            let mut acceleration = settings.external.body;
+            if self.liquid {
+                acceleration.y -= settings.free_surface.gravity;
+            } else {
+                acceleration.y += beta * self.heat[i] - alpha * self.smoke[i];
+            }
+            self.for_neighbors(i, |j, offset, distance| {
+                let shared = (pressure + self.pressures[j]) / (2.0 * self.densities[j]);
+                if distance > 0.0 {
+                    acceleration +=
+                        offset / distance * mass * shared * spiky_gradient(distance) / density;
+                }
+                acceleration += (self.velocities[j] - velocity) * viscosity * mass
+                    / self.densities[j]
+                    * viscosity_laplacian(distance)
+                    / density;
+            });
+            self.accelerations[i] = acceleration;
+        }
+
+        let (width, height) = self.size;
+        for i in 0..self.positions.len() {
+            let velocity = &mut self.velocities[i];
+            *velocity += self.accelerations[i] * dt;
+            let moved = self.positions[i] + *velocity * dt;
+            let next = settings.boundary.confine(moved, width, height);
+            if settings.boundary != BoundaryMode::Periodic {
+                // Against a wall only the velocity into it stops
+                if next.x != moved.x {
+                    velocity.x = 0.0;
+                }
+                if next.y != moved.y {
+                    velocity.y = 0.0;
+                }
+            }
+            let (x, y) = (next.x.round() as usize, next.y.round() as usize);
+            if grid.0[y.min(height - 1)][x.min(width - 1)].obstacle {
+                *velocity = Vec2::ZERO;
+            } else {
+                self.positions[i] = next;
+            }
+        }
+    }
+}
+
+/// Ctrl+L seeds the particles from the grid and spawns their sprites, or removes them
+#[allow(clippy::too_many_arguments)]
+fn sph_keys_system(
+    mut commands: Commands,
+    keyboard_input: Res<Input<KeyCode>>,
+    control: Res<StepControl>,
+    mut sph: ResMut<Sph>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut material: Local<Option<(bool, Handle<ColorMaterial>)>>,
+    particles: Query<Entity, With<SphParticle>>,
+    qg: Query<&Grid>,
+) {
+    let ctrl =
+        keyboard_input.pressed(KeyCode::LControl) || keyboard_input.pressed(KeyCode::RControl);
+    if !ctrl || !keyboard_input.just_pressed(KeyCode::L) {
+        return;
+    }
+    let grid = match qg.single() {
+        Ok(grid) => grid,
+        Err(_) => return,
+    };
+
+    for entity in particles.iter() {
+        commands.entity(entity).despawn();
+    }
+    sph.active = !sph.active;
+    if !sph.active {
+        sph.clear();
+        return;
+    }
+
+    sph.seed(grid);
+    sph.last_time = control.time;
+    info!(
+        "SPH: {} particles in the {}",
+        sph.positions.len(),
+        if sph.liquid { "liquid" } else { "smoke" }
+    );
+    let liquid = sph.liquid;
+    let material = match &*material {
+        Some((shown, handle)) if *shown == liquid => handle.clone(),
+        _ => {
+            let color = if liquid { LIQUID_COLOR } else { SMOKE_COLOR };
+            let handle = materials.add(color.into());
+            *material = Some((liquid, handle.clone()));
+            handle
+        }
+    };
+    let (width, height) = (grid.width(), grid.height());
+    for (i, &pos) in sph.positions.iter().enumerate() {
+        commands
+            .spawn_bundle(SpriteBundle {
+                material: material.clone(),
+                sprite: Sprite::new(Vec2::splat(CELL_SIZE * SPACING)),
+                transform: Transform::from_translation(
+                    grid_to_world(pos, width, height).extend(0.0),
+                ),
+                ..Default::default()
+            })
+            .insert(SphParticle(i))
+            .insert(OnLayer {
+                layer: Layer::Particles,
+                offset: 0.1,
+            });
+    }
+}
+
+/// Step the particles after every step of the grid, dropping them when the grid is resized
+fn sph_system(
+    control: Res<StepControl>,
+    settings: Res<SolverSettings>,
+    mut sph: ResMut<Sph>,
+    qg: Query<&Grid>,
+) {
+    if !sph.active || control.time == sph.last_time {
+        return;
+    }
+    let grid = match qg.single() {
+        Ok(grid) => grid,
+        Err(_) => return,
+    };
+    if sph.size != (grid.width(), grid.height()) {
+        sph.clear();
+        return;
+    }
+    sph.step(grid, control.dt, &settings);
+    sph.last_time = control.time;
+}
+
+/// Move the sprites to their particles, hiding the ones of particles that are gone
+fn sph_render_system(
+    sph: Res<Sph>,
+    mut query: Query<(&SphParticle, &mut Transform, &mut Visible)>,
+) {
+    let (width, height) = sph.size;
+    for (particle, mut transform, mut visible) in query.iter_mut() {
+        match sph.positions.get(particle.0) {
+            Some(&pos) => {
+                let z = transform.translation.z;
+                transform.translation = grid_to_world(pos, width, height).extend(z);
+                visible.is_visible = true;
+            }
+            None => visible.is_visible = false,
+        }
+    }
+}
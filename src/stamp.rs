@@ -0,0 +1,80 @@
+use bevy::prelude::*;
+
+use crate::font;
+use crate::viewport::Viewport;
+use crate::{AppState, Grid};
+
+// t stamps the text given with --text (FLUID by default) as dye, centered on the cursor
+
+pub struct StampPlugin {
+    pub text: Option<String>,
+}
+
+impl Plugin for StampPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        let text = self
+            .text
+            .clone()
+            .unwrap_or_else(|| DEFAULT_TEXT.to_string());
+        app.insert_resource(StampText(text)).add_system_set(
+            SystemSet::on_update(AppState::Running).with_system(stamp_keys_system.system()),
+        );
+    }
+}
+
+pub const DEFAULT_TEXT: &str = "FLUID";
+
+pub struct StampText(pub String);
+
+/// Add dye where the rasterized text has pixels, centered on a cell.
+/// Pixels outside of the grid are dropped.
+pub fn stamp_text(grid: &mut Grid, text: &str, center: (usize, usize), scale: usize, density: f32) {
+    let pixels = font::rasterize(text, scale);
+    let text_height = pixels.len();
+    let text_width = pixels.first().map_or(0, |row| row.len());
+    let left = center.0 as isize - text_width as isize / 2;
+    let top = center.1 as isize + text_height as isize / 2;
+
+    for (row_index, row) in pixels.iter().enumerate() {
+        // Rows go from top to bottom while the grid y axis goes up
+        let y = top - row_index as isize;
+        for (column, &pixel) in row.iter().enumerate() {
+            let x = left + column as isize;
+            let inside =
+                x >= 0 && y >= 0 && (x as usize) < grid.width() && (y as usize) < grid.height();
+            if pixel && inside {
+                grid.0[y as usize][x as usize].density += density;
+            }
+        }
+    }
+}
+
+/// Largest scale at which the text fits in a third of the grid height and the whole width
+pub fn fitting_scale(text: &str, width: usize, height: usize) -> usize {
+    let text_width = font::rasterize(text, 1).first().map_or(0, |row| row.len());
+    let by_width = width / text_width.max(1);
+    let by_height = height / 3 / font::GLYPH_HEIGHT;
+    by_width.min(by_height).max(1)
+}
+
+fn stamp_keys_system(
+    windows: Res<Windows>,
+    viewport: Res<Viewport>,
+    text: Res<StampText>,
+    mut qg: Query<&mut Grid>,
+    mut char_input_events: EventReader<ReceivedCharacter>,
+) {
+    for event in char_input_events.iter() {
+        if event.char != 't' {
+            continue;
+        }
+
+        if let Ok(mut grid) = qg.single_mut() {
+            let cursor = windows.get_primary().and_then(|w| w.cursor_position());
+            let center = cursor
+                .and_then(|cursor| viewport.cursor_cell(cursor, &grid))
+                .unwrap_or((grid.width() / 2, grid.height() / 2));
+            stamp_text(&mut grid, &text.0, center, 1, 5.0);
+        }
+    }
+}
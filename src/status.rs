@@ -0,0 +1,120 @@
+use bevy::prelude::*;
+
+use crate::region::RegionTool;
+use crate::steering::Steering;
+use crate::stepping::StepControl;
+use crate::{AppState, Grid};
+
+// With --status, a one line summary of the simulation is printed every few seconds, for
+// screen readers or to pipe into other tools, e.g.
+//
+//     status: max speed 0.84 at 12,30 - densest 8x8 region 16,24 mean 1.23 - total density 456.7 - tool dye brush
+
+/// Side of the square regions compared to find the densest one, in cells
+const REGION_SIZE: usize = 8;
+
+pub struct StatusPlugin {
+    /// Seconds between summaries, none by default
+    pub interval: Option<f32>,
+}
+
+impl Plugin for StatusPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        if let Some(interval) = self.interval {
+            app.insert_resource(StatusTimer(Timer::from_seconds(interval, true)))
+                .add_system_set(
+                    SystemSet::on_update(AppState::Running).with_system(status_system.system()),
+                );
+        }
+    }
+}
+
+struct StatusTimer(Timer);
+
+/// Fastest cell with its speed
+fn max_speed(grid: &Grid) -> ((usize, usize), f32) {
+    let mut fastest = ((0, 0), 0.0);
+    for (y, row) in grid.0.iter().enumerate() {
+        for (x, cell) in row.iter().enumerate() {
+            let speed = cell.velocity.length();
+            if speed > fastest.1 {
+                fastest = ((x, y), speed);
+            }
+        }
+    }
+    fastest
+}
+
+/// Bottom left corner of the densest region with its mean density
+fn densest_region(grid: &Grid) -> ((usize, usize), f32) {
+    let mut densest = ((0, 0), 0.0);
+    for y0 in (0..grid.height()).step_by(REGION_SIZE) {
+        for x0 in (0..grid.width()).step_by(REGION_SIZE) {
+            let rows = &grid.0[y0..(y0 + REGION_SIZE).min(grid.height())];
+            let cells = rows
+                .iter()
+                .flat_map(|row| &row[x0..(x0 + REGION_SIZE).min(grid.width())]);
+            let (sum, count) = cells.fold((0.0, 0), |(sum, count), cell| {
+                (sum + cell.density, count + 1)
+            });
+            let mean = sum / count as f32;
+            if mean > densest.1 {
+                densest = ((x0, y0), mean);
+            }
+        }
+    }
+    densest
+}
+
+/// What the user is doing, the mouse buttons taking precedence
+fn active_tool(
+    mouse_button_input: &Input<MouseButton>,
+    region: &RegionTool,
+    steering: &Steering,
+) -> &'static str {
+    if mouse_button_input.pressed(MouseButton::Left) {
+        "dye brush"
+    } else if mouse_button_input.pressed(MouseButton::Right) {
+        "region selection"
+    } else if steering.active {
+        "steering"
+    } else if region.bounds().is_some() {
+        "region selected"
+    } else {
+        "none"
+    }
+}
+
+fn status_system(
+    time: Res<Time>,
+    mouse_button_input: Res<Input<MouseButton>>,
+    control: Res<StepControl>,
+    region: Res<RegionTool>,
+    steering: Res<Steering>,
+    mut timer: ResMut<StatusTimer>,
+    qg: Query<&Grid>,
+) {
+    if !timer.0.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    if let Ok(grid) = qg.single() {
+        let ((speed_x, speed_y), speed) = max_speed(grid);
+        let ((region_x, region_y), mean) = densest_region(grid);
+        let total: f32 = grid.0.iter().flatten().map(|cell| cell.density).sum();
+        println!(
+            "status: max speed {:.2} at {},{} - densest {}x{} region {},{} mean {:.2} - total density {:.1} - tool {}{}",
+            speed,
+            speed_x,
+            speed_y,
+            REGION_SIZE,
+            REGION_SIZE,
+            region_x,
+            region_y,
+            mean,
+            total,
+            active_tool(&mouse_button_input, &region, &steering),
+            if control.paused { " - paused" } else { "" }
+        );
+    }
+}
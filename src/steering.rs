@@ -0,0 +1,193 @@
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+
+use crate::errors::ErrorLog;
+use crate::import;
+use crate::stamp::{self, StampText};
+use crate::stepping::StepControl;
+use crate::{AppState, Grid};
+
+// Steering the smoke toward a target shape, after Fattal and Lischinski's "Target-driven
+// smoke animation": a force pushes the blurred density up the gradient of the blurred
+// target, while a drag keeps the velocities from growing. Their gathering term moving the
+// density itself is left out, so the shape forms over a few seconds rather than sharply.
+// q toggles the steering, toward the image given with --target or else the stamp text.
+
+/// Strength of the driving force
+const DRIVE: f32 = 4.0;
+/// Fraction of the velocity lost per second while steering
+const DRAG: f32 = 0.5;
+/// Keeps the driving force finite where the target is empty
+const EPSILON: f32 = 0.05;
+/// Blur radius of the fields, as a fraction of the smaller side of the grid
+const BLUR: f32 = 0.05;
+
+pub struct SteeringPlugin {
+    pub target: Option<PathBuf>,
+}
+
+impl Plugin for SteeringPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(Steering {
+            active: false,
+            image: self.target.clone(),
+            drive: Vec::new(),
+        })
+        .add_system_set(
+            SystemSet::on_update(AppState::Running)
+                .with_system(steering_keys_system.system())
+                .with_system(steering_system.system()),
+        );
+    }
+}
+
+pub struct Steering {
+    pub active: bool,
+    image: Option<PathBuf>,
+    /// Gradient of the blurred target divided by it, indexed by row then column
+    drive: Vec<Vec<Vec2>>,
+}
+
+/// Blur a field with two passes of a separable box blur, clamped at the edges
+fn blur(field: &[Vec<f32>], radius: usize) -> Vec<Vec<f32>> {
+    let box_blur = |field: &[Vec<f32>]| -> Vec<Vec<f32>> {
+        let (height, width) = (field.len(), field[0].len());
+        let average = |get: &dyn Fn(usize) -> f32, i: usize, len: usize| {
+            let low = i.saturating_sub(radius);
+            let high = (i + radius).min(len - 1);
+            (low..=high).map(get).sum::<f32>() / (high - low + 1) as f32
+        };
+        let rows: Vec<Vec<f32>> = field
+            .iter()
+            .map(|row| (0..width).map(|x| average(&|i| row[i], x, width)).collect())
+            .collect();
+        (0..height)
+            .map(|y| {
+                (0..width)
+                    .map(|x| average(&|i| rows[i][x], y, height))
+                    .collect()
+            })
+            .collect()
+    };
+    box_blur(&box_blur(field))
+}
+
+fn blur_radius(grid: &Grid) -> usize {
+    ((grid.width().min(grid.height()) as f32 * BLUR) as usize).max(1)
+}
+
+/// Target density of the grid size, from the image brightness or the stamp text
+fn target_density(
+    image: &Option<PathBuf>,
+    text: &str,
+    grid: &Grid,
+) -> Result<Vec<Vec<f32>>, String> {
+    let (width, height) = (grid.width(), grid.height());
+    match image {
+        Some(path) => {
+            let dye = import::load_image_dye(path, width, height)
+                .map_err(|err| format!("Couldn't load {}: {}", path.display(), err))?;
+            Ok(dye
+                .iter()
+                .map(|row| {
+                    row.iter()
+                        .map(|color| color.dot(Vec3::splat(1.0 / 3.0)))
+                        .collect()
+                })
+                .collect())
+        }
+        None => {
+            let mut target = Grid::new(width, height);
+            let scale = stamp::fitting_scale(text, width, height);
+            stamp::stamp_text(&mut target, text, (width / 2, height / 2), scale, 1.0);
+            Ok(target
+                .0
+                .iter()
+                .map(|row| row.iter().map(|cell| cell.density).collect())
+                .collect())
+        }
+    }
+}
+
+/// ∇target / target of the blurred target, with central differences clamped at the edges
+fn drive_field(target: &[Vec<f32>], radius: usize) -> Vec<Vec<Vec2>> {
+    let blurred = blur(target, radius);
+    let (height, width) = (blurred.len(), blurred[0].len());
+    let at = |x: usize, y: usize| blurred[y][x];
+
+    (0..height)
+        .map(|y| {
+            (0..width)
+                .map(|x| {
+                    let (left, right) = (x.saturating_sub(1), (x + 1).min(width - 1));
+                    let (down, up) = (y.saturating_sub(1), (y + 1).min(height - 1));
+                    let gradient = Vec2::new(
+                        (at(right, y) - at(left, y)) / (right - left).max(1) as f32,
+                        (at(x, up) - at(x, down)) / (up - down).max(1) as f32,
+                    );
+                    gradient / (at(x, y) + EPSILON)
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn steering_keys_system(
+    text: Res<StampText>,
+    mut steering: ResMut<Steering>,
+    mut errors: ResMut<ErrorLog>,
+    qg: Query<&Grid>,
+    mut char_input_events: EventReader<ReceivedCharacter>,
+) {
+    if !char_input_events.iter().any(|event| event.char == 'q') {
+        return;
+    }
+    if steering.active {
+        steering.active = false;
+        return;
+    }
+
+    if let Ok(grid) = qg.single() {
+        match target_density(&steering.image, &text.0, grid) {
+            Ok(target) => {
+                steering.drive = drive_field(&target, blur_radius(grid));
+                steering.active = true;
+            }
+            Err(err) => errors.report(err),
+        }
+    }
+}
+
+fn steering_system(
+    time: Res<Time>,
+    control: Res<StepControl>,
+    steering: Res<Steering>,
+    mut qg: Query<&mut Grid>,
+) {
+    if !steering.active || control.paused {
+        return;
+    }
+
+    if let Ok(mut grid) = qg.single_mut() {
+        // The target was made for another grid size
+        if steering.drive.len() != grid.height() || steering.drive[0].len() != grid.width() {
+            return;
+        }
+
+        let dt = time.delta_seconds();
+        let density: Vec<Vec<f32>> = grid
+            .0
+            .iter()
+            .map(|row| row.iter().map(|cell| cell.density).collect())
+            .collect();
+        let density = blur(&density, blur_radius(&grid));
+
+        for ((row, density_row), drive_row) in grid.0.iter_mut().zip(&density).zip(&steering.drive)
+        {
+            for ((cell, &density), &drive) in row.iter_mut().zip(density_row).zip(drive_row) {
+                cell.velocity += (DRIVE * density * drive - DRAG * cell.velocity) * dt;
+            }
+        }
+    }
+}
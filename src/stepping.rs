@@ -0,0 +1,174 @@
+use bevy::prelude::*;
+
+use crate::emitters::{self, Emitter};
+use crate::fans::Fan;
+use crate::inflow::Inflows;
+use crate::settings::SolverSettings;
+use crate::solver::{self, Scratch, Splats, Stage};
+use crate::units::Units;
+use crate::weather::Weather;
+use crate::{AppState, Grid};
+
+// Runs the solver stages in order every frame. Space pauses the simulation, then
+// '.' runs the rest of the current step and ',' runs a single stage, so the field
+// can be inspected after each of them. The emitters feed the grid, the fans and the wind
+// blow and the inflows set the velocity of their edges as a step starts.
+
+/// Time step used when stepping manually, unless the units set one
+const STEP_DT: f32 = 1.0 / 60.0;
+
+pub struct SteppingPlugin;
+
+impl Plugin for SteppingPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(StepControl::default())
+            .insert_resource(Scratch::default())
+            .add_system_set(
+                SystemSet::on_update(AppState::Running)
+                    .with_system(stepping_keys_system.system())
+                    .with_system(simulation_step_system.system())
+                    .with_system(stepping_title_system.system()),
+            );
+    }
+}
+
+/// Panic when a step allocates, in debug builds, the threads of the threaded backend
+/// counting as the one stepping
+#[derive(Default)]
+pub struct AllocationCheck(pub bool);
+
+pub struct StepControl {
+    pub paused: bool,
+    /// Stage that runs next, anything but Forces means a step is half done
+    pub next_stage: Stage,
+    /// Time step of the last step, in seconds
+    pub dt: f32,
+    /// Simulated seconds since the scene started, the clock of its periodic forcing
+    pub time: f32,
+    step_stage: bool,
+    step_frame: bool,
+}
+
+impl Default for StepControl {
+    fn default() -> Self {
+        Self {
+            paused: false,
+            next_stage: Stage::Forces,
+            dt: STEP_DT,
+            time: 0.0,
+            step_stage: false,
+            step_frame: false,
+        }
+    }
+}
+
+fn stepping_keys_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut control: ResMut<StepControl>,
+    mut char_input_events: EventReader<ReceivedCharacter>,
+) {
+    if keyboard_input.just_pressed(KeyCode::Space) {
+        control.paused = !control.paused;
+    }
+
+    for event in char_input_events.iter() {
+        match event.char {
+            ',' if control.paused => control.step_stage = true,
+            '.' if control.paused => control.step_frame = true,
+            _ => {}
+        }
+    }
+}
+
+#[cfg_attr(not(debug_assertions), allow(unused_variables))]
+fn simulation_step_system(
+    time: Res<Time>,
+    settings: Res<SolverSettings>,
+    units: Res<Units>,
+    (mut weather, mut inflows): (ResMut<Weather>, ResMut<Inflows>),
+    mut control: ResMut<StepControl>,
+    mut splats: ResMut<Splats>,
+    mut scratch: ResMut<Scratch>,
+    check: Res<AllocationCheck>,
+    emitters: Query<&Emitter>,
+    mut fans: Query<&mut Fan>,
+    mut qg: Query<&mut Grid>,
+) {
+    let (dt, single_stage) = if !control.paused {
+        (units.step_dt(time.delta_seconds()), false)
+    } else if control.step_stage {
+        (units.step_dt(STEP_DT), true)
+    } else if control.step_frame {
+        (units.step_dt(STEP_DT), false)
+    } else {
+        return;
+    };
+    control.step_stage = false;
+    control.step_frame = false;
+    control.dt = dt;
+
+    if let Ok(mut grid) = qg.single_mut() {
+        // Only allocates when the grid size changes, the inflows get more speeds or the
+        // backend more threads
+        scratch.prepare(&grid);
+        scratch.prepare_workers(settings.backend);
+        scratch.prepare_inflows(inflows.samples());
+        if settings.flip.is_some() {
+            scratch.prepare_particles(&grid);
+        }
+        #[cfg(debug_assertions)]
+        let allocations = crate::alloc_counter::allocations();
+
+        // Finish the current step, or only run its next stage
+        loop {
+            let stage = control.next_stage;
+            if stage == Stage::Forces {
+                control.time += dt;
+                emitters::inject(&mut grid, emitters.iter(), dt);
+                for mut fan in fans.iter_mut() {
+                    fan.blow(&mut grid, dt);
+                }
+                weather.blow(&mut grid, dt);
+                inflows.feed(&mut grid, dt, &mut scratch);
+            }
+            solver::run_stage(&mut grid, stage, dt, &settings, &mut splats, &mut scratch);
+            control.next_stage = stage.next();
+            if single_stage || control.next_stage == Stage::Forces {
+                break;
+            }
+        }
+
+        #[cfg(debug_assertions)]
+        if check.0 {
+            let allocations = crate::alloc_counter::allocations() - allocations;
+            assert_eq!(allocations, 0, "the solver allocated during a step");
+        }
+    }
+}
+
+/// Show the paused state in the window title, `shown` being what's currently displayed
+fn stepping_title_system(
+    control: Res<StepControl>,
+    mut shown: Local<Option<(bool, Stage)>>,
+    mut windows: ResMut<Windows>,
+) {
+    let state = (control.paused, control.next_stage);
+    let was_paused = shown.map_or(false, |(paused, _)| paused);
+    if *shown == Some(state) {
+        return;
+    }
+    *shown = Some(state);
+
+    let window = match windows.get_primary_mut() {
+        Some(window) => window,
+        None => return,
+    };
+    if control.paused {
+        window.set_title(format!(
+            "Fluid Simulation - paused, next stage: {:?} (Space: resume, ',': stage, '.': step)",
+            control.next_stage
+        ));
+    } else if was_paused {
+        window.set_title("Fluid Simulation".to_string());
+    }
+}
@@ -0,0 +1,158 @@
+use bevy::prelude::*;
+
+use crate::errors::ErrorLog;
+use crate::layers::{Layer, OnLayer};
+use crate::scenes::SceneSelection;
+use crate::viewport::{self, ViewSlot};
+use crate::{grid_to_world, AppState, Grid, Position, SolverSettings, CELL_SIZE};
+
+// Stream function: the scalar whose contours are the streamlines of the flow, solved from
+// the vorticity. Ctrl+S shows its contours over the density, red where it's positive and
+// blue where it's negative, so the closed streamlines around the eddies and the separatrices
+// between them stand out. The solve only runs while they're shown, a few sweeps a frame
+// catching up with the flow.
+
+/// Sweeps of the solve a frame
+const SWEEPS: usize = 20;
+/// Contours between zero and the largest stream function, on either side
+const CONTOURS: f32 = 8.0;
+
+pub struct StreamPlugin;
+
+impl Plugin for StreamPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(StreamOverlay::default())
+            .add_system_set(
+                SystemSet::on_enter(AppState::Running).with_system(stream_setup.system()),
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::Running)
+                    .with_system(stream_keys_system.system())
+                    .with_system(stream_function_system.system())
+                    .with_system(stream_square_system.system()),
+            );
+    }
+}
+
+struct StreamSquare;
+
+#[derive(Default)]
+pub struct StreamOverlay {
+    pub active: bool,
+}
+
+fn stream_setup(
+    mut commands: Commands,
+    selection: Res<SceneSelection>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let (width, height) = selection.grid_size();
+    let (columns, rows) = viewport::view_size(width, height);
+    for y in 0..rows {
+        for x in 0..columns {
+            let position = Vec2::new(x as f32, y as f32);
+            let translation = grid_to_world(position, width, height).extend(0.8);
+
+            commands
+                .spawn_bundle(SpriteBundle {
+                    material: materials.add(Color::BLACK.into()),
+                    transform: Transform::from_translation(translation),
+                    sprite: Sprite::new(Vec2::new(CELL_SIZE, CELL_SIZE)),
+                    visible: Visible {
+                        is_visible: false,
+                        is_transparent: false,
+                    },
+                    ..Default::default()
+                })
+                .insert(StreamSquare)
+                .insert(OnLayer {
+                    layer: Layer::Density,
+                    offset: 0.8,
+                })
+                .insert(Position { x, y })
+                .insert(ViewSlot { x, y });
+        }
+    }
+}
+
+/// Ctrl+S shows or hides the contours of the stream function
+fn stream_keys_system(keyboard_input: Res<Input<KeyCode>>, mut overlay: ResMut<StreamOverlay>) {
+    let ctrl =
+        keyboard_input.pressed(KeyCode::LControl) || keyboard_input.pressed(KeyCode::RControl);
+    if ctrl && keyboard_input.just_pressed(KeyCode::S) {
+        overlay.active = !overlay.active;
+    }
+}
+
+/// Relax the stream function towards the one of the current vorticity while it's shown
+fn stream_function_system(
+    overlay: Res<StreamOverlay>,
+    settings: Res<SolverSettings>,
+    mut qg: Query<&mut Grid>,
+) {
+    if !overlay.active {
+        return;
+    }
+    if let Ok(mut grid) = qg.single_mut() {
+        grid.update_stream(settings.boundary, SWEEPS);
+    }
+}
+
+/// Red for a positive stream function, blue for a negative one, brightest on the contours
+fn contour_color(stream: f32, max: f32) -> Color {
+    let level = stream.abs() / max * CONTOURS;
+    // Distance to the nearest contour, 0 on it and 0.5 halfway between two
+    let distance = (level - level.round()).abs();
+    let v = 0.25 + 0.75 * (1.0 - distance * 4.0).max(0.0);
+    if stream >= 0.0 {
+        Color::rgb(v, 0.3 * v, 0.2 * v)
+    } else {
+        Color::rgb(0.2 * v, 0.4 * v, v)
+    }
+}
+
+fn stream_square_system(
+    overlay: Res<StreamOverlay>,
+    mut errors: ResMut<ErrorLog>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    qg: Query<&Grid>,
+    mut query: Query<(
+        &StreamSquare,
+        &Position,
+        &Handle<ColorMaterial>,
+        &mut Visible,
+    )>,
+) {
+    let grid = match qg.single() {
+        Ok(grid) if overlay.active => Some(grid),
+        _ => None,
+    };
+    let max = grid.map_or(0.0, |grid| {
+        (0..grid.height())
+            .flat_map(|y| (0..grid.width()).map(move |x| (x, y)))
+            .map(|(x, y)| grid.stream(x, y).abs())
+            .fold(0.0, f32::max)
+    });
+
+    for (_stream_square, position, color, mut visible) in query.iter_mut() {
+        visible.is_visible = grid.is_some();
+        let grid = match grid {
+            Some(grid) => grid,
+            None => continue,
+        };
+
+        let color_mat = match materials.get_mut(&*color) {
+            Some(material) => material,
+            None => {
+                errors.report("Missing material of a stream function square");
+                continue;
+            }
+        };
+        let Position { x, y } = *position;
+        color_mat.color = if max > 0.0 {
+            contour_color(grid.stream(x, y), max)
+        } else {
+            Color::BLACK
+        };
+    }
+}
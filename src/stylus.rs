@@ -0,0 +1,142 @@
+use bevy::input::touch::{ForceTouch, TouchInput, TouchPhase};
+use bevy::prelude::*;
+
+use crate::solver::{Splat, Splats};
+use crate::species::SpeciesBrush;
+use crate::symmetry::Symmetry;
+use crate::viewport::Viewport;
+use crate::{trail_cells, AppState, Grid};
+
+// Pens and touch screens: a stroke paints dye and pushes the fluid like the mouse, with
+// the pressure setting both the radius of the brush and how much it injects. Winit only
+// reports the pressure on some platforms (iOS and Windows pens among them), the other
+// touches paint at a medium pressure. They inject what the mouse brush does.
+
+/// Pressure of the touches that don't report one, from 0 to 1
+const DEFAULT_PRESSURE: f32 = 0.5;
+/// Radius of the brush at full pressure, in cells
+const MAX_RADIUS: f32 = 3.0;
+/// Density per second injected in every cell of the brush at full pressure
+const MAX_DENSITY: f32 = 40.0;
+/// Velocity given by moving one cell at full pressure, like moving the mouse by one cell
+const FORCE_PER_CELL: f32 = 2.0;
+
+pub struct StylusPlugin;
+
+impl Plugin for StylusPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(Stylus::default()).add_system_set(
+            SystemSet::on_update(AppState::Running).with_system(stylus_system.system()),
+        );
+    }
+}
+
+#[derive(Default)]
+pub struct Stylus {
+    /// Touch drawing the stroke, and where it was last in grid coordinates
+    stroke: Option<(u64, Vec2)>,
+    /// Pressure of the current stroke from 0 to 1, None when nothing touches
+    pub pressure: Option<f32>,
+}
+
+fn pressure(force: Option<ForceTouch>) -> f32 {
+    let pressure = match force {
+        Some(ForceTouch::Calibrated {
+            force,
+            max_possible_force,
+            ..
+        }) if max_possible_force > 0.0 => force / max_possible_force,
+        Some(ForceTouch::Normalized(force)) => force,
+        _ => return DEFAULT_PRESSURE,
+    };
+    (pressure as f32).clamp(0.0, 1.0)
+}
+
+/// Cells within `radius` of a position, at least the one under it
+fn brush_cells(center: Vec2, radius: f32, width: usize, height: usize) -> Vec<(usize, usize)> {
+    let reach = radius.ceil() as isize;
+    let (cx, cy) = ((center.x + 0.5) as isize, (center.y + 0.5) as isize);
+    let mut cells = Vec::new();
+    for y in cy - reach..=cy + reach {
+        for x in cx - reach..=cx + reach {
+            let inside = x >= 0 && y >= 0 && (x as usize) < width && (y as usize) < height;
+            let near = Vec2::new(x as f32, y as f32).distance(center) <= radius.max(0.5);
+            if inside && near {
+                cells.push((x as usize, y as usize));
+            }
+        }
+    }
+    cells
+}
+
+#[allow(clippy::too_many_arguments)]
+fn stylus_system(
+    time: Res<Time>,
+    windows: Res<Windows>,
+    qg: Query<&Grid>,
+    symmetry: Res<Symmetry>,
+    viewport: Res<Viewport>,
+    brush: Res<SpeciesBrush>,
+    mut stylus: ResMut<Stylus>,
+    mut splats: ResMut<Splats>,
+    mut touch_events: EventReader<TouchInput>,
+) {
+    let (grid, window) = match (qg.single(), windows.get_primary()) {
+        (Ok(grid), Some(window)) => (grid, window),
+        _ => return,
+    };
+    let (width, height) = (grid.width(), grid.height());
+
+    for event in touch_events.iter() {
+        // Touches start at the top of the window, unlike the cursor
+        let position = Vec2::new(event.position.x, window.height() - event.position.y);
+        let pos = viewport.cursor_to_grid(position);
+        let inside = viewport.cursor_cell(position, grid).is_some();
+
+        match event.phase {
+            TouchPhase::Started if inside => {
+                stylus.stroke = Some((event.id, pos));
+                stylus.pressure = Some(pressure(event.force));
+            }
+            TouchPhase::Moved => {
+                let last = match stylus.stroke {
+                    Some((id, last)) if id == event.id && inside => last,
+                    _ => continue,
+                };
+                let pressure = pressure(event.force);
+                stylus.stroke = Some((event.id, pos));
+                stylus.pressure = Some(pressure);
+
+                // Spread the push over the path like the mouse does, and paint all of it
+                let path = trail_cells(last, pos, width, height);
+                let velocity = (pos - last) * FORCE_PER_CELL * pressure / path.len() as f32;
+                let density = MAX_DENSITY * pressure * time.delta_seconds();
+                for &(x, y) in &path {
+                    let center = Vec2::new(x as f32, y as f32);
+                    for (bx, by) in brush_cells(center, MAX_RADIUS * pressure, width, height) {
+                        let splat = Splat {
+                            x: bx,
+                            y: by,
+                            velocity: if (bx, by) == (x, y) {
+                                velocity
+                            } else {
+                                Vec2::ZERO
+                            },
+                            density,
+                            temperature: density,
+                            species: brush.0,
+                        };
+                        splats.0.extend(symmetry.expand(splat, width, height));
+                    }
+                }
+            }
+            TouchPhase::Ended | TouchPhase::Cancelled => {
+                if matches!(stylus.stroke, Some((id, _)) if id == event.id) {
+                    stylus.stroke = None;
+                    stylus.pressure = None;
+                }
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use bevy::prelude::*;
+
+use crate::boundary::BoundaryMode;
+use crate::settings::{FreeSurface, SolverSettings};
+use crate::species::SPECIES;
+use crate::{Cell, Grid};
+
+// Free surface liquid: the level of every cell is its signed distance to the surface of the
+// liquid, negative in the liquid and positive in the air. The cells start in the liquid,
+// so the grid only has a surface once a scene puts air in it, e.g. the dam break; until
+// then the stepping is the one of a fluid filling the grid. With a surface the liquid falls
+// with the gravity of the settings, the projection only solves the pressure of the liquid
+// cells, the air being at a pressure of 0, and the velocity of the liquid is extended into
+// the air along the surface so that the advection carries the level with the liquid.
+// Advected levels drift away from distances, so they're rebuilt from the surface after
+// every step by fast sweeping, the air then losing whatever the advection smeared into it.
+
+const NEIGHBORS: [(isize, isize); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+
+/// Air cell the pressure of the projection is 0 in
+pub fn is_air(cell: &Cell) -> bool {
+    cell.level > 0.0 && !cell.obstacle
+}
+
+/// Whether the grid has a surface, a fluid cell being in the air
+pub fn has_air(grid: &Grid) -> bool {
+    grid.0.iter().flatten().any(is_air)
+}
+
+/// Accelerate the liquid downward
+pub fn apply_gravity(grid: &mut Grid, dt: f32, settings: &SolverSettings) {
+    if !has_air(grid) {
+        return;
+    }
+    let gravity = settings.free_surface.gravity;
+    for cell in grid.0.iter_mut().flatten() {
+        if !cell.obstacle && cell.level <= 0.0 {
+            cell.velocity.y -= gravity * dt;
+        }
+    }
+}
+
+/// Give the air cells within `FreeSurface::extrapolation` cells of the surface the average
+/// velocity of their neighbors closer to it, layer after layer outward, and stop the rest
+/// of the air
+pub fn extrapolate_velocity(grid: &mut Grid, settings: &SolverSettings) {
+    if !has_air(grid) {
+        return;
+    }
+    let FreeSurface { extrapolation, .. } = settings.free_surface;
+    let boundary = settings.boundary;
+    let (width, height) = (grid.width(), grid.height());
+    for layer in 0..extrapolation {
+        let band = layer as f32..=(layer + 1) as f32;
+        for y in 0..height {
+            for x in 0..width {
+                let cell = &grid.0[y][x];
+                if !is_air(cell) || !band.contains(&cell.level) {
+                    continue;
+                }
+                let level = cell.level;
+                let (mut sum, mut count) = (Vec2::ZERO, 0);
+                for &(dx, dy) in NEIGHBORS.iter() {
+                    let (nx, ny, _) =
+                        boundary.ghost(x as isize + dx, y as isize + dy, width, height);
+                    let neighbor = &grid.0[ny][nx];
+                    if !neighbor.obstacle && neighbor.level < level {
+                        sum += neighbor.velocity;
+                        count += 1;
+                    }
+                }
+                grid.0[y][x].velocity = if count > 0 {
+                    sum / count as f32
+                } else {
+                    Vec2::ZERO
+                };
+            }
+        }
+    }
+
+    for cell in grid.0.iter_mut().flatten() {
+        if is_air(cell) && cell.level > extrapolation as f32 {
+            cell.velocity = Vec2::ZERO;
+        }
+    }
+}
+
+/// Rebuild the levels into distances to the surface and clear what the air carries. The
+/// cells on either side of the surface keep their level, within a cell of it, the others
+/// are swept over in the four diagonal directions solving `|grad level| = 1`.
+pub fn reinitialize(grid: &mut Grid, boundary: BoundaryMode) {
+    if !has_air(grid) {
+        return;
+    }
+    let (width, height) = (grid.width(), grid.height());
+    // The cells never change sides, so neither does which ones are on the surface
+    let on_surface = |grid: &Grid, x: usize, y: usize| {
+        let air = grid.0[y][x].level > 0.0;
+        NEIGHBORS.iter().any(|&(dx, dy)| {
+            let (nx, ny, _) = boundary.ghost(x as isize + dx, y as isize + dy, width, height);
+            let neighbor = &grid.0[ny][nx];
+            !neighbor.obstacle && (neighbor.level > 0.0) != air
+        })
+    };
+
+    let far = (width + height) as f32;
+    for y in 0..height {
+        for x in 0..width {
+            if grid.0[y][x].obstacle {
+                continue;
+            }
+            let surface = on_surface(grid, x, y);
+            let level = &mut grid.0[y][x].level;
+            *level = match (surface, *level > 0.0) {
+                (true, _) => level.max(-1.0).min(1.0),
+                (false, true) => far,
+                (false, false) => -far,
+            };
+        }
+    }
+
+    let distance = |grid: &Grid, x: usize, y: usize, dx: isize, dy: isize| {
+        let (nx, ny, _) = boundary.ghost(x as isize + dx, y as isize + dy, width, height);
+        let neighbor = &grid.0[ny][nx];
+        if neighbor.obstacle {
+            far
+        } else {
+            neighbor.level.abs()
+        }
+    };
+    for &(flip_x, flip_y) in [(false, false), (true, false), (false, true), (true, true)].iter() {
+        for j in 0..height {
+            let y = if flip_y { height - 1 - j } else { j };
+            for i in 0..width {
+                let x = if flip_x { width - 1 - i } else { i };
+                if grid.0[y][x].obstacle || on_surface(grid, x, y) {
+                    continue;
+                }
+                let a = distance(grid, x, y, -1, 0).min(distance(grid, x, y, 1, 0));
+                let b = distance(grid, x, y, 0, -1).min(distance(grid, x, y, 0, 1));
+                let candidate = if (a - b).abs() >= 1.0 {
+                    a.min(b) + 1.0
+                } else {
+                    (a + b + (2.0 - (a - b) * (a - b)).sqrt()) / 2.0
+                };
+                let level = &mut grid.0[y][x].level;
+                if candidate < level.abs() {
+                    *level = candidate.copysign(*level);
+                }
+            }
+        }
+    }
+
+    for cell in grid.0.iter_mut().flatten().filter(|cell| is_air(cell)) {
+        cell.density = 0.0;
+        cell.dye = Vec3::ZERO;
+        cell.species = [0.0; SPECIES];
+    }
+}
+
+/// Zero the divergence of the air cells, which the projection leaves as it is
+pub fn clear_air(grid: &Grid, quarter: &mut [Vec<f32>]) {
+    for (row, cells) in quarter.iter_mut().zip(grid.0.iter()) {
+        for (value, cell) in row.iter_mut().zip(cells.iter()) {
+            if is_air(cell) {
+                *value = 0.0;
+            }
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use bevy::prelude::*;
+use bevy::tasks::AsyncComputeTaskPool;
+use image::{Rgb, RgbImage};
+
+use crate::font;
+use crate::jobs::{Job, Jobs};
+use crate::layers::Layers;
+use crate::palette::Palette;
+use crate::post::{self, PostEffect, PostEffects};
+use crate::render;
+use crate::scenes::{ScenePreset, SceneSelection};
+use crate::settings::SolverSettings;
+use crate::solver::{self, Scratch, Splats};
+use crate::AppState;
+
+// Parameter sweeps: w runs the active scene once for every pair of viscosity and
+// vorticity confinement below, in the background, and saves the final density of each
+// run as a labelled thumbnail of a montage, viscosity across and vorticity down. The sweep
+// is a job, advancing with every step of its runs.
+
+const SWEEP_DIR: &str = "exports";
+const VISCOSITIES: [f32; 4] = [0.5, 2.0, 5.0, 20.0];
+const VORTICITIES: [f32; 3] = [0.0, 1.0, 4.0];
+const STEPS: usize = 300;
+const STEP_DT: f32 = 1.0 / 60.0;
+/// Rough side of a thumbnail in pixels, the cells stay square
+const THUMBNAIL_SIZE: usize = 192;
+const GAP: u32 = 4;
+
+pub struct SweepPlugin;
+
+impl Plugin for SweepPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(Sweeps::default()).add_system_set(
+            SystemSet::on_update(AppState::Running).with_system(sweep_keys_system.system()),
+        );
+    }
+}
+
+#[derive(Default)]
+pub struct Sweeps {
+    /// Numbers the montages
+    count: usize,
+    /// Set while a sweep runs, a second one would only compete for the cores
+    running: Arc<AtomicBool>,
+}
+
+/// Everything a run needs, copied out of the resources for the task
+struct SweepJob {
+    scene: ScenePreset,
+    grid_size: (usize, usize),
+    settings: SolverSettings,
+    layers: Layers,
+    effects: Vec<PostEffect>,
+}
+
+impl SweepJob {
+    /// The thumbnail of a run, none if the job was cancelled
+    fn run(&self, viscosity: f32, vorticity: f32, job: &Job) -> Option<RgbImage> {
+        let settings = SolverSettings {
+            viscosity,
+            vorticity,
+            ..self.settings
+        };
+        let (width, height) = self.grid_size;
+        let mut grid = self.scene.build(width, height);
+        let mut splats = Splats::default();
+        let mut scratch = Scratch::default();
+        for _ in 0..STEPS {
+            if job.is_cancelled() {
+                return None;
+            }
+            solver::step(&mut grid, STEP_DT, &settings, &mut splats, &mut scratch);
+            job.advance();
+        }
+
+        let frame = post::compose(&grid, &Palette::default(), &self.layers, &self.effects);
+        let scale = (THUMBNAIL_SIZE / width.max(height)).max(1);
+        let mut thumbnail = render::frame_image(&frame, scale as u32);
+        label(&mut thumbnail, &format!("V {}  E {}", viscosity, vorticity));
+        Some(thumbnail)
+    }
+
+    /// Every run tiled in one image, on a black background, none if the job was cancelled
+    fn montage(&self, job: &Job) -> Option<RgbImage> {
+        let thumbnails: Vec<Vec<_>> = VORTICITIES
+            .iter()
+            .map(|&vorticity| {
+                VISCOSITIES
+                    .iter()
+                    .map(|&viscosity| self.run(viscosity, vorticity, job))
+                    .collect::<Option<Vec<_>>>()
+            })
+            .collect::<Option<_>>()?;
+
+        let (tile_width, tile_height) = thumbnails[0][0].dimensions();
+        let columns = VISCOSITIES.len() as u32;
+        let rows = VORTICITIES.len() as u32;
+        let mut montage = RgbImage::new(
+            columns * (tile_width + GAP) + GAP,
+            rows * (tile_height + GAP) + GAP,
+        );
+        for (row, line) in thumbnails.iter().enumerate() {
+            for (column, thumbnail) in line.iter().enumerate() {
+                let x0 = GAP + column as u32 * (tile_width + GAP);
+                let y0 = GAP + row as u32 * (tile_height + GAP);
+                for (x, y, pixel) in thumbnail.enumerate_pixels() {
+                    montage.put_pixel(x0 + x, y0 + y, *pixel);
+                }
+            }
+        }
+        Some(montage)
+    }
+}
+
+/// Write the parameters in the top left corner of a thumbnail
+fn label(image: &mut RgbImage, text: &str) {
+    let pixels = font::rasterize(text, 1);
+    for (y, row) in pixels.iter().enumerate() {
+        for (x, &pixel) in row.iter().enumerate() {
+            let (px, py) = (x as u32 + 2, y as u32 + 2);
+            if pixel && px < image.width() && py < image.height() {
+                image.put_pixel(px, py, Rgb([255, 255, 255]));
+            }
+        }
+    }
+}
+
+fn save(montage: &RgbImage, path: &Path) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+    }
+    montage.save(path).map_err(|err| err.to_string())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn sweep_keys_system(
+    pool: Res<AsyncComputeTaskPool>,
+    selection: Res<SceneSelection>,
+    settings: Res<SolverSettings>,
+    layers: Res<Layers>,
+    post_effects: Res<PostEffects>,
+    mut sweeps: ResMut<Sweeps>,
+    mut jobs: ResMut<Jobs>,
+    mut char_input_events: EventReader<ReceivedCharacter>,
+) {
+    for event in char_input_events.iter() {
+        if event.char != 'w' {
+            continue;
+        }
+        if sweeps.running.load(Ordering::Acquire) {
+            info!("A parameter sweep is already running");
+            continue;
+        }
+
+        sweeps.count += 1;
+        let path = PathBuf::from(SWEEP_DIR).join(format!("sweep_{:04}.png", sweeps.count));
+        let job = SweepJob {
+            scene: selection.scene(),
+            grid_size: selection.grid_size(),
+            settings: SolverSettings { ..*settings },
+            layers: layers.clone(),
+            effects: post_effects.0.clone(),
+        };
+        info!(
+            "Sweeping viscosity {:?} across and vorticity {:?} down, {} steps each",
+            VISCOSITIES, VORTICITIES, STEPS
+        );
+
+        sweeps.running.store(true, Ordering::Release);
+        let running = sweeps.running.clone();
+        let runs = VISCOSITIES.len() * VORTICITIES.len();
+        let handle = jobs.start("PARAMETER SWEEP", runs * STEPS);
+        pool.spawn(async move {
+            match job.montage(&handle).map(|montage| save(&montage, &path)) {
+                Some(Ok(())) => info!("Saved the parameter sweep to {}", path.display()),
+                Some(Err(err)) => error!("Couldn't save {}: {}", path.display(), err),
+                None => info!("Cancelled the parameter sweep"),
+            }
+            running.store(false, Ordering::Release);
+        })
+        .detach();
+    }
+}
@@ -0,0 +1,111 @@
+use bevy::prelude::*;
+
+use crate::solver::Splat;
+
+// Mirrors or rotates every splat the user injects, for mandala-like flows.
+// m cycles through the modes.
+
+pub struct SymmetryPlugin;
+
+impl Plugin for SymmetryPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(Symmetry::None)
+            .add_system(symmetry_keys_system.system());
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Symmetry {
+    None,
+    /// Mirror across the vertical axis
+    MirrorX,
+    /// Mirror across the horizontal axis
+    MirrorY,
+    MirrorXY,
+    /// N-fold rotational symmetry around the center of the grid
+    Rotational(u32),
+}
+
+impl Symmetry {
+    pub fn next(self) -> Self {
+        match self {
+            Self::None => Self::MirrorX,
+            Self::MirrorX => Self::MirrorY,
+            Self::MirrorY => Self::MirrorXY,
+            Self::MirrorXY => Self::Rotational(3),
+            Self::Rotational(n) if n < 6 => Self::Rotational(n + 1),
+            Self::Rotational(_) => Self::None,
+        }
+    }
+
+    /// The splat and its symmetric copies that land inside the grid
+    pub fn expand(self, splat: Splat, width: usize, height: usize) -> Vec<Splat> {
+        let mirror_x = |s: &Splat| Splat {
+            x: width - 1 - s.x,
+            velocity: Vec2::new(-s.velocity.x, s.velocity.y),
+            ..s.clone()
+        };
+        let mirror_y = |s: &Splat| Splat {
+            y: height - 1 - s.y,
+            velocity: Vec2::new(s.velocity.x, -s.velocity.y),
+            ..s.clone()
+        };
+
+        match self {
+            Self::None => vec![splat],
+            Self::MirrorX => vec![mirror_x(&splat), splat],
+            Self::MirrorY => vec![mirror_y(&splat), splat],
+            Self::MirrorXY => {
+                let flipped = mirror_x(&splat);
+                vec![mirror_y(&splat), mirror_y(&flipped), flipped, splat]
+            }
+            Self::Rotational(n) => {
+                let center = Vec2::new(width as f32 - 1.0, height as f32 - 1.0) / 2.0;
+                let offset = Vec2::new(splat.x as f32, splat.y as f32) - center;
+
+                (0..n)
+                    .filter_map(|k| {
+                        let angle = k as f32 * std::f32::consts::TAU / n as f32;
+                        let rotation = Vec2::new(angle.cos(), angle.sin());
+                        let rotate = |v: Vec2| {
+                            Vec2::new(
+                                v.x * rotation.x - v.y * rotation.y,
+                                v.x * rotation.y + v.y * rotation.x,
+                            )
+                        };
+
+                        let pos = (center + rotate(offset)).round();
+                        let inside = pos.x >= 0.0
+                            && pos.y >= 0.0
+                            && (pos.x as usize) < width
+                            && (pos.y as usize) < height;
+                        if !inside {
+                            return None;
+                        }
+
+                        Some(Splat {
+                            x: pos.x as usize,
+                            y: pos.y as usize,
+                            velocity: rotate(splat.velocity),
+                            density: splat.density,
+                            temperature: splat.temperature,
+                            species: splat.species,
+                        })
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+fn symmetry_keys_system(
+    mut symmetry: ResMut<Symmetry>,
+    mut char_input_events: EventReader<ReceivedCharacter>,
+) {
+    for event in char_input_events.iter() {
+        if event.char == 'm' {
+            *symmetry = symmetry.next();
+            info!("Symmetry: {:?}", *symmetry);
+        }
+    }
+}
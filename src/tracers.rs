@@ -0,0 +1,266 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+use bevy::render::pipeline::PipelineDescriptor;
+
+use crate::errors::ErrorLog;
+use crate::layers::{Layer, Layers};
+use crate::lines::{self, Segment, ShaderSupport};
+use crate::stepping::StepControl;
+use crate::viewport::Viewport;
+use crate::{grid_to_world, Grid, SolverSettings};
+
+// Pathlines follow single tracers through time, streaklines join every tracer
+// released from the same point. Both only differ from streamlines when the flow is unsteady.
+
+const PATHLINE_TRACERS: usize = 8;
+const PATHLINE_LENGTH: usize = 300;
+const STREAKLINE_LENGTH: usize = 300;
+
+pub struct TracerPlugin;
+
+impl Plugin for TracerPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(Tracers::default())
+            .add_startup_system(tracers_setup.system())
+            .add_system(tracer_keys_system.system())
+            .add_system(tracer_advection_system.system())
+            .add_system(pathline_render_system.system())
+            .add_system(streakline_render_system.system());
+    }
+}
+
+struct PathlineLayer;
+struct StreaklineLayer;
+
+pub struct Tracers {
+    pub show_pathlines: bool,
+    pub show_streaklines: bool,
+    /// Trail of each pathline tracer, newest position last, seeded on the first step
+    pathlines: Vec<VecDeque<Vec2>>,
+    /// Where the streakline is released from, the center of the grid if None
+    streak_source: Option<Vec2>,
+    /// Tracers released from the streak source, newest first
+    streak_tracers: VecDeque<Vec2>,
+}
+
+impl Default for Tracers {
+    fn default() -> Self {
+        Self {
+            show_pathlines: false,
+            show_streaklines: false,
+            pathlines: Vec::new(),
+            streak_source: None,
+            streak_tracers: VecDeque::new(),
+        }
+    }
+}
+
+impl Tracers {
+    /// Restart the pathlines from seeds evenly spaced on a circle
+    fn seed_pathlines(&mut self, width: usize, height: usize) {
+        self.pathlines = (0..PATHLINE_TRACERS)
+            .map(|i| {
+                let angle = i as f32 * std::f32::consts::TAU / PATHLINE_TRACERS as f32;
+                let radius = width.min(height) as f32 / 4.0;
+                let center = Vec2::new(width as f32 / 2.0, height as f32 / 2.0);
+                let mut trail = VecDeque::with_capacity(PATHLINE_LENGTH);
+                trail.push_back(center + radius * Vec2::new(angle.cos(), angle.sin()));
+                trail
+            })
+            .collect();
+    }
+
+    pub fn memory_bytes(&self) -> usize {
+        let points: usize = self.pathlines.iter().map(VecDeque::capacity).sum::<usize>()
+            + self.streak_tracers.capacity();
+        points * std::mem::size_of::<Vec2>()
+    }
+
+    pub fn start_streakline(&mut self, source: Option<Vec2>) {
+        self.streak_source = source;
+        self.streak_tracers.clear();
+    }
+
+    pub fn clear(&mut self) {
+        self.pathlines.clear();
+        self.streak_tracers.clear();
+    }
+}
+
+/// Move a tracer along the velocity field, wrapping around the edges or stopping against
+/// the walls like the fluid
+pub fn advect_tracer(grid: &Grid, settings: &SolverSettings, pos: Vec2, dt: f32) -> Vec2 {
+    let next = pos + grid.sample_velocity(pos, settings.interpolation, settings.boundary) * dt;
+    settings.boundary.confine(next, grid.width(), grid.height())
+}
+
+/// Connect consecutive points, skipping the jumps made when a tracer wraps around the edges
+fn polyline(grid: &Grid, points: impl Iterator<Item = Vec2>, color: [f32; 3]) -> Vec<Segment> {
+    let (width, height) = (grid.width(), grid.height());
+    let mut segments = Vec::new();
+    let mut previous: Option<Vec2> = None;
+    for point in points {
+        if let Some(prev) = previous {
+            let jump = (point - prev).abs();
+            if jump.x < width as f32 / 2.0 && jump.y < height as f32 / 2.0 {
+                let start = grid_to_world(prev, width, height);
+                let end = grid_to_world(point, width, height);
+                segments.push((start, end, color));
+            }
+        }
+        previous = Some(point);
+    }
+    segments
+}
+
+fn tracers_setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut pipelines: ResMut<Assets<PipelineDescriptor>>,
+    mut shaders: ResMut<Assets<Shader>>,
+    support: Res<ShaderSupport>,
+    layers: Res<Layers>,
+) {
+    let pipeline = match lines::vertex_color_pipeline(
+        &support,
+        &mut pipelines,
+        &mut shaders,
+        layers.blend(Layer::Overlays),
+    ) {
+        Some(pipeline) => pipeline,
+        None => return,
+    };
+    lines::spawn_line_layer(
+        &mut commands,
+        &mut meshes,
+        pipeline.clone(),
+        0.2,
+        PathlineLayer,
+    );
+    lines::spawn_line_layer(&mut commands, &mut meshes, pipeline, 0.2, StreaklineLayer);
+}
+
+/// p toggles the pathlines, k toggles a streakline released from the cursor
+fn tracer_keys_system(
+    windows: Res<Windows>,
+    viewport: Res<Viewport>,
+    mut tracers: ResMut<Tracers>,
+    mut char_input_events: EventReader<ReceivedCharacter>,
+) {
+    for event in char_input_events.iter() {
+        match event.char {
+            'p' => {
+                tracers.show_pathlines = !tracers.show_pathlines;
+                tracers.pathlines.clear();
+            }
+            'k' => {
+                tracers.show_streaklines = !tracers.show_streaklines;
+                let cursor = windows.get_primary().and_then(|w| w.cursor_position());
+                tracers.start_streakline(cursor.map(|c| viewport.cursor_to_grid(c)));
+            }
+            'r' => tracers.clear(),
+            _ => {}
+        }
+    }
+}
+
+fn tracer_advection_system(
+    time: Res<Time>,
+    control: Res<StepControl>,
+    settings: Res<SolverSettings>,
+    mut tracers: ResMut<Tracers>,
+    qg: Query<&Grid>,
+) {
+    if control.paused {
+        return;
+    }
+
+    if let Ok(grid) = qg.single() {
+        let dt = time.delta_seconds();
+        let tracers = &mut *tracers;
+
+        if tracers.show_pathlines {
+            if tracers.pathlines.is_empty() {
+                tracers.seed_pathlines(grid.width(), grid.height());
+            }
+            for trail in &mut tracers.pathlines {
+                let head = *trail.back().unwrap();
+                if trail.len() == PATHLINE_LENGTH {
+                    trail.pop_front();
+                }
+                trail.push_back(advect_tracer(grid, &settings, head, dt));
+            }
+        }
+
+        if tracers.show_streaklines {
+            for tracer in &mut tracers.streak_tracers {
+                *tracer = advect_tracer(grid, &settings, *tracer, dt);
+            }
+            if tracers.streak_tracers.len() == STREAKLINE_LENGTH {
+                tracers.streak_tracers.pop_back();
+            }
+            let center = Vec2::new(grid.width() as f32, grid.height() as f32) / 2.0;
+            let source = tracers.streak_source.unwrap_or(center);
+            tracers.streak_tracers.push_front(source);
+        }
+    }
+}
+
+fn pathline_render_system(
+    tracers: Res<Tracers>,
+    qg: Query<&Grid>,
+    mut errors: ResMut<ErrorLog>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut query: Query<(&PathlineLayer, &Handle<Mesh>, &mut Visible)>,
+) {
+    let grid = match qg.single() {
+        Ok(grid) => grid,
+        Err(_) => return,
+    };
+
+    for (_layer, mesh_handle, mut visible) in query.iter_mut() {
+        visible.is_visible = tracers.show_pathlines;
+        if !tracers.show_pathlines {
+            continue;
+        }
+
+        let mut segments = Vec::new();
+        for (i, trail) in tracers.pathlines.iter().enumerate() {
+            let hue = i as f32 * 360.0 / PATHLINE_TRACERS as f32;
+            let [r, g, b, _] = Color::hsl(hue, 1.0, 0.6).as_rgba_f32();
+            segments.extend(polyline(grid, trail.iter().copied(), [r, g, b]));
+        }
+        match meshes.get_mut(&*mesh_handle) {
+            Some(mesh) => lines::set_segments(mesh, &segments),
+            None => errors.report("Missing mesh of the pathlines"),
+        }
+    }
+}
+
+fn streakline_render_system(
+    tracers: Res<Tracers>,
+    qg: Query<&Grid>,
+    mut errors: ResMut<ErrorLog>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut query: Query<(&StreaklineLayer, &Handle<Mesh>, &mut Visible)>,
+) {
+    let grid = match qg.single() {
+        Ok(grid) => grid,
+        Err(_) => return,
+    };
+
+    for (_layer, mesh_handle, mut visible) in query.iter_mut() {
+        visible.is_visible = tracers.show_streaklines;
+        if !tracers.show_streaklines {
+            continue;
+        }
+
+        let color = [1.0, 0.2, 0.8];
+        let segments = polyline(grid, tracers.streak_tracers.iter().copied(), color);
+        match meshes.get_mut(&*mesh_handle) {
+            Some(mesh) => lines::set_segments(mesh, &segments),
+            None => errors.report("Missing mesh of the streakline"),
+        }
+    }
+}
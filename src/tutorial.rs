@@ -0,0 +1,171 @@
+use bevy::input::mouse::MouseMotion;
+use bevy::prelude::*;
+
+use crate::ftle::Ftle;
+use crate::quiver::QuiverOverlay;
+use crate::tracers::Tracers;
+use crate::AppState;
+
+// Guided tour for first time users: each step prompts for an action in the window
+// title and moves on once the user actually did it. F1 starts or quits the tutorial.
+
+pub struct TutorialPlugin {
+    /// Start the tutorial as soon as the simulation runs
+    pub enabled: bool,
+}
+
+impl Plugin for TutorialPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        let tutorial = if self.enabled {
+            Tutorial::start()
+        } else {
+            Tutorial::default()
+        };
+
+        app.insert_resource(tutorial).add_system_set(
+            SystemSet::on_update(AppState::Running)
+                .with_system(tutorial_toggle_system.system())
+                .with_system(tutorial_progress_system.system())
+                .with_system(tutorial_prompt_system.system()),
+        );
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TutorialStep {
+    InjectDye,
+    AddForce,
+    ToggleLayer,
+    Reset,
+}
+
+impl TutorialStep {
+    const ALL: [TutorialStep; 4] = [
+        TutorialStep::InjectDye,
+        TutorialStep::AddForce,
+        TutorialStep::ToggleLayer,
+        TutorialStep::Reset,
+    ];
+
+    fn prompt(self) -> &'static str {
+        match self {
+            Self::InjectDye => "Hold the left mouse button to inject dye",
+            Self::AddForce => "Move the mouse quickly across the grid to push the fluid",
+            Self::ToggleLayer => {
+                "Toggle a layer: p for pathlines, k for streaklines, u for the quiver, f for FTLE"
+            }
+            Self::Reset => "Press r to clear the grid",
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Tutorial {
+    /// Index of the current step, None when the tutorial isn't running
+    step: Option<usize>,
+    /// How much of the current step has been done, from 0 to 1
+    progress: f32,
+    /// The window title needs to be rewritten
+    dirty: bool,
+}
+
+impl Tutorial {
+    fn start() -> Self {
+        Self {
+            step: Some(0),
+            progress: 0.0,
+            dirty: true,
+        }
+    }
+
+    fn current(&self) -> Option<TutorialStep> {
+        self.step.map(|i| TutorialStep::ALL[i])
+    }
+
+    fn advance(&mut self, amount: f32) {
+        self.progress += amount;
+        if self.progress >= 1.0 {
+            self.step = self
+                .step
+                .map(|i| i + 1)
+                .filter(|&i| i < TutorialStep::ALL.len());
+            self.progress = 0.0;
+            self.dirty = true;
+        }
+    }
+}
+
+fn tutorial_toggle_system(keyboard_input: Res<Input<KeyCode>>, mut tutorial: ResMut<Tutorial>) {
+    if keyboard_input.just_pressed(KeyCode::F1) {
+        *tutorial = match tutorial.step {
+            Some(_) => Tutorial {
+                dirty: true,
+                ..Default::default()
+            },
+            None => Tutorial::start(),
+        };
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn tutorial_progress_system(
+    time: Res<Time>,
+    mouse_button_input: Res<Input<MouseButton>>,
+    tracers: Res<Tracers>,
+    quiver: Res<QuiverOverlay>,
+    ftle: Res<Ftle>,
+    mut tutorial: ResMut<Tutorial>,
+    mut mouse_motion_events: EventReader<MouseMotion>,
+    mut char_input_events: EventReader<ReceivedCharacter>,
+) {
+    let motion: f32 = mouse_motion_events.iter().map(|e| e.delta.length()).sum();
+    let reset = char_input_events.iter().any(|e| e.char == 'r');
+
+    match tutorial.current() {
+        Some(TutorialStep::InjectDye) => {
+            if mouse_button_input.pressed(MouseButton::Left) {
+                tutorial.advance(time.delta_seconds());
+            }
+        }
+        Some(TutorialStep::AddForce) => tutorial.advance(motion / 500.0),
+        Some(TutorialStep::ToggleLayer) => {
+            let any_layer =
+                tracers.show_pathlines || tracers.show_streaklines || quiver.active || ftle.active;
+            if any_layer {
+                tutorial.advance(1.0);
+            }
+        }
+        Some(TutorialStep::Reset) => {
+            if reset {
+                tutorial.advance(1.0);
+            }
+        }
+        None => {}
+    }
+}
+
+fn tutorial_prompt_system(mut tutorial: ResMut<Tutorial>, mut windows: ResMut<Windows>) {
+    if !tutorial.dirty {
+        return;
+    }
+    tutorial.dirty = false;
+
+    let window = match windows.get_primary_mut() {
+        Some(window) => window,
+        None => return,
+    };
+    match tutorial.current() {
+        Some(step) => {
+            let index = tutorial.step.unwrap_or_default() + 1;
+            let prompt = step.prompt();
+            info!("Tutorial step {}: {}", index, prompt);
+            window.set_title(format!(
+                "Tutorial {}/{}: {} (F1 to quit)",
+                index,
+                TutorialStep::ALL.len(),
+                prompt
+            ));
+        }
+        None => window.set_title("Fluid Simulation".to_string()),
+    }
+}
@@ -0,0 +1,106 @@
+use crate::settings::SolverSettings;
+
+// Physical units: the solver works in cells and seconds, --meters-per-cell gives the side
+// of a cell so lengths, speeds and the viscosity read in SI units. --seconds-per-step fixes
+// the simulated time of a step instead of following the frame time, and --viscosity sets
+// the kinematic viscosity in m²/s, e.g. 1e-6 for water, whatever the preset.
+
+/// How the simulation maps to the physical world, the defaults keeping cells as the unit
+/// of length and the frame time as the time step
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Units {
+    /// Side of a cell in meters
+    pub meters_per_cell: Option<f32>,
+    /// Simulated seconds per step
+    pub seconds_per_step: Option<f32>,
+    /// Kinematic viscosity in m²/s, kept when changing presets
+    pub viscosity: Option<f32>,
+}
+
+impl Units {
+    /// Side of a cell in the unit of length
+    fn cell_size(&self) -> f32 {
+        self.meters_per_cell.unwrap_or(1.0)
+    }
+
+    fn length_unit(&self) -> &'static str {
+        if self.meters_per_cell.is_some() {
+            "M"
+        } else {
+            "CELLS"
+        }
+    }
+
+    /// Time step of a step run during a frame of `frame_dt` seconds
+    pub fn step_dt(&self, frame_dt: f32) -> f32 {
+        self.seconds_per_step.unwrap_or(frame_dt)
+    }
+
+    /// Number of cells spanning a length in the unit of length, also converting the
+    /// speeds and accelerations to the ones of the solver
+    pub fn cells(&self, length: f32) -> f32 {
+        length / self.cell_size()
+    }
+
+    /// Length of `cells` cells in the unit of length
+    pub fn length(&self, cells: f32) -> f32 {
+        cells * self.cell_size()
+    }
+
+    /// Speed of a velocity of the grid, in cells per second, in the unit of length per second
+    pub fn speed(&self, cells_per_second: f32) -> f32 {
+        cells_per_second * self.cell_size()
+    }
+
+    /// Kinematic viscosity of the viscosity setting of the solver, or diffusivity of its
+    /// diffusion setting. The implicit diffusion averages the four neighbors, so the
+    /// setting is 4 ν / dx², dx being the cell side.
+    pub fn kinematic_viscosity(&self, viscosity: f32) -> f32 {
+        viscosity * self.cell_size().powi(2) / 4.0
+    }
+
+    /// Viscosity setting of the solver giving a kinematic viscosity
+    pub fn solver_viscosity(&self, kinematic: f32) -> f32 {
+        4.0 * kinematic / self.cell_size().powi(2)
+    }
+
+    /// Set the viscosity given in physical units, if any, over the one of the preset or
+    /// the material
+    pub fn apply_viscosity(&self, settings: &mut SolverSettings) {
+        if let Some(viscosity) = self.viscosity {
+            settings.viscosity = self.solver_viscosity(viscosity);
+        }
+    }
+
+    /// For the displays, in the characters of the bitmap font
+    pub fn format_length(&self, cells: f32) -> String {
+        format!("{} {}", short(self.length(cells)), self.length_unit())
+    }
+
+    pub fn format_speed(&self, cells_per_second: f32) -> String {
+        format!(
+            "{} {} PER S",
+            short(self.speed(cells_per_second)),
+            self.length_unit()
+        )
+    }
+
+    pub fn format_viscosity(&self, viscosity: f32) -> String {
+        format!(
+            "{} {}2 PER S",
+            short(self.kinematic_viscosity(viscosity)),
+            self.length_unit()
+        )
+    }
+}
+
+/// A few significant digits, in scientific notation for the very small or large values
+/// real-world units give, like the viscosity of water
+fn short(value: f32) -> String {
+    let magnitude = value.abs();
+    if magnitude != 0.0 && !(1e-2..1e4).contains(&magnitude) {
+        format!("{:.2e}", value)
+    } else {
+        format!("{:.3}", value)
+    }
+}
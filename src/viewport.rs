@@ -0,0 +1,175 @@
+use std::ops::Range;
+
+use bevy::prelude::*;
+
+use crate::obstacles::Paddle;
+use crate::scenes::SceneSelection;
+use crate::{grid_to_world, AppState, Grid, Position, CELL_SIZE};
+
+// Grids bigger than the window: only the visible cells get entities, which are moved
+// onto the cells scrolled into view. The arrow keys scroll the view.
+
+/// Most cells shown at once
+pub const MAX_VIEW: (usize, usize) = (60, 40);
+/// Cells per second
+const SCROLL_SPEED: f32 = 30.0;
+
+pub struct ViewportPlugin;
+
+impl Plugin for ViewportPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(Viewport::default())
+            .add_system_set(
+                SystemSet::on_enter(AppState::Running).with_system(viewport_setup.system()),
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::Running)
+                    .with_system(viewport_scroll_system.system())
+                    .with_system(viewport_camera_system.system())
+                    .with_system(view_slot_system.system()),
+            );
+    }
+}
+
+/// The camera looking at the grid, as opposed to the UI one
+pub struct MainCamera;
+
+/// Visible cell an entity stands for, relative to the bottom left corner of the view
+pub struct ViewSlot {
+    pub x: usize,
+    pub y: usize,
+}
+
+#[derive(Default)]
+pub struct Viewport {
+    /// Bottom left corner of the view, in cells
+    origin: Vec2,
+    pub columns: usize,
+    pub rows: usize,
+}
+
+impl Viewport {
+    /// Grid cell shown at the bottom left corner
+    pub fn cell_origin(&self) -> (usize, usize) {
+        (self.origin.x as usize, self.origin.y as usize)
+    }
+
+    /// Columns and rows of the grid cells in view
+    pub fn visible_cells(&self, grid: &Grid) -> (Range<usize>, Range<usize>) {
+        let (x, y) = self.cell_origin();
+        let columns = x.min(grid.width())..(x + self.columns).min(grid.width());
+        let rows = y.min(grid.height())..(y + self.rows).min(grid.height());
+        (columns, rows)
+    }
+
+    /// Fractional cell position under the cursor, cell centers being on integers
+    pub fn cursor_to_grid(&self, cursor: Vec2) -> Vec2 {
+        let (x, y) = self.cell_origin();
+        cursor / CELL_SIZE - Vec2::splat(0.5) + Vec2::new(x as f32, y as f32)
+    }
+
+    /// Cell under the cursor, if it's inside the grid
+    pub fn cursor_cell(&self, cursor: Vec2, grid: &Grid) -> Option<(usize, usize)> {
+        let pos = self.cursor_to_grid(cursor) + Vec2::splat(0.5);
+        let (x, y) = (pos.x as usize, pos.y as usize);
+        if pos.x >= 0.0 && pos.y >= 0.0 && x < grid.width() && y < grid.height() {
+            Some((x, y))
+        } else {
+            None
+        }
+    }
+}
+
+/// Cells shown at once for a grid
+pub fn view_size(width: usize, height: usize) -> (usize, usize) {
+    (width.min(MAX_VIEW.0), height.min(MAX_VIEW.1))
+}
+
+fn viewport_setup(selection: Res<SceneSelection>, mut viewport: ResMut<Viewport>) {
+    let (width, height) = selection.grid_size();
+    let (columns, rows) = view_size(width, height);
+    *viewport = Viewport {
+        origin: Vec2::ZERO,
+        columns,
+        rows,
+    };
+}
+
+fn viewport_scroll_system(
+    time: Res<Time>,
+    keyboard_input: Res<Input<KeyCode>>,
+    mut viewport: ResMut<Viewport>,
+    qg: Query<&Grid>,
+    paddles: Query<&Paddle>,
+) {
+    // The arrows drive the paddle while there's one
+    if paddles.iter().next().is_some() {
+        return;
+    }
+
+    let mut direction = Vec2::ZERO;
+    if keyboard_input.pressed(KeyCode::Left) {
+        direction.x -= 1.0;
+    }
+    if keyboard_input.pressed(KeyCode::Right) {
+        direction.x += 1.0;
+    }
+    if keyboard_input.pressed(KeyCode::Down) {
+        direction.y -= 1.0;
+    }
+    if keyboard_input.pressed(KeyCode::Up) {
+        direction.y += 1.0;
+    }
+    if direction == Vec2::ZERO {
+        return;
+    }
+
+    if let Ok(grid) = qg.single() {
+        let max = Vec2::new(
+            (grid.width() - viewport.columns) as f32,
+            (grid.height() - viewport.rows) as f32,
+        );
+        let origin = viewport.origin + direction * SCROLL_SPEED * time.delta_seconds();
+        viewport.origin = origin.max(Vec2::ZERO).min(max);
+    }
+}
+
+fn viewport_camera_system(
+    viewport: Res<Viewport>,
+    qg: Query<&Grid>,
+    mut query: Query<&mut Transform, With<MainCamera>>,
+) {
+    if let Ok(grid) = qg.single() {
+        let (x, y) = viewport.cell_origin();
+        let view = Vec2::new(viewport.columns as f32, viewport.rows as f32);
+        let center = Vec2::new(x as f32, y as f32) + (view - Vec2::ONE) / 2.0;
+        let center = grid_to_world(center, grid.width(), grid.height());
+
+        for mut transform in query.iter_mut() {
+            transform.translation.x = center.x;
+            transform.translation.y = center.y;
+        }
+    }
+}
+
+/// Move the entities of the visible cells onto the cells they currently show
+fn view_slot_system(
+    viewport: Res<Viewport>,
+    qg: Query<&Grid>,
+    mut query: Query<(&ViewSlot, &mut Position, &mut Transform)>,
+) {
+    if let Ok(grid) = qg.single() {
+        let (origin_x, origin_y) = viewport.cell_origin();
+        for (slot, mut position, mut transform) in query.iter_mut() {
+            let (x, y) = (origin_x + slot.x, origin_y + slot.y);
+            if position.x == x && position.y == y {
+                continue;
+            }
+            *position = Position { x, y };
+
+            let world = grid_to_world(Vec2::new(x as f32, y as f32), grid.width(), grid.height());
+            transform.translation.x = world.x;
+            transform.translation.y = world.y;
+        }
+    }
+}
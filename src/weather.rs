@@ -0,0 +1,128 @@
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Deserialize;
+
+use crate::Grid;
+
+// Weather: a wind blowing over the whole grid like a body force, declared in the scene file
+// so outdoor smoke moves naturally. Its direction turns slowly and its strength varies with
+// gusts, noise filtered so that a gust lasts a few seconds. The noise is seeded, the same
+// scene blowing the same way every time it's rendered.
+
+/// Wind of a scene, e.g. a breeze turning a quarter every minute
+/// `(strength: 4.0, direction: 0.0, turn_rate: 1.5, gustiness: 0.5)`
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub struct Wind {
+    /// Mean acceleration, in cells/s²
+    pub strength: f32,
+    /// Direction at the start, in degrees counterclockwise from the right
+    #[serde(default)]
+    pub direction: f32,
+    /// Degrees per second the direction turns, clockwise when negative
+    #[serde(default)]
+    pub turn_rate: f32,
+    /// Spread of the gusts relative to the strength, calm when 0
+    #[serde(default)]
+    pub gustiness: f32,
+    /// Seconds a gust lasts
+    #[serde(default = "default_gust_time")]
+    pub gust_time: f32,
+    #[serde(default)]
+    pub seed: u64,
+}
+
+fn default_gust_time() -> f32 {
+    2.0
+}
+
+impl Wind {
+    /// Check the parameters are in range, naming the wrong one
+    pub fn validate(&self) -> Result<(), String> {
+        let number = |field: &str, value: f32| {
+            if value.is_finite() {
+                Ok(())
+            } else {
+                Err(format!("{} must be a number, not {}", field, value))
+            }
+        };
+        number("strength", self.strength)?;
+        number("direction", self.direction)?;
+        number("turn_rate", self.turn_rate)?;
+        if !(self.gustiness.is_finite() && self.gustiness >= 0.0) {
+            return Err(format!(
+                "gustiness must be a nonnegative number, not {}",
+                self.gustiness
+            ));
+        }
+        if !(self.gust_time.is_finite() && self.gust_time > 0.0) {
+            return Err(format!(
+                "gust_time must be a positive number, not {}",
+                self.gust_time
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Wind blowing as the simulation runs
+pub struct WindGenerator {
+    wind: Wind,
+    /// Simulated seconds since it started
+    time: f32,
+    /// Filtered noise of unit spread, scaled by the gustiness
+    gust: f32,
+    rng: StdRng,
+}
+
+impl WindGenerator {
+    pub fn new(wind: Wind) -> Self {
+        Self {
+            wind,
+            time: 0.0,
+            gust: 0.0,
+            rng: StdRng::seed_from_u64(wind.seed),
+        }
+    }
+
+    /// Acceleration the wind gives the fluid now, in cells/s²
+    pub fn acceleration(&self) -> Vec2 {
+        let angle = (self.wind.direction + self.wind.turn_rate * self.time) * PI / 180.0;
+        let strength = self.wind.strength * (1.0 + self.wind.gustiness * self.gust);
+        Vec2::new(angle.cos(), angle.sin()) * strength
+    }
+
+    /// Move on by `dt` seconds, returning the acceleration during that time
+    pub fn advance(&mut self, dt: f32) -> Vec2 {
+        let acceleration = self.acceleration();
+        self.time += dt;
+        // Ornstein-Uhlenbeck process, relaxing toward calm over the gust time, the uniform
+        // kicks of unit variance keeping its spread at 1 whatever the time step
+        let decay = (dt / self.wind.gust_time).min(1.0);
+        let kick = self.rng.gen_range(-1.0f32..1.0) * 3f32.sqrt();
+        self.gust += -self.gust * decay + (2.0 * decay).sqrt() * kick;
+        acceleration
+    }
+}
+
+/// Wind of the running simulation, none without a scene file asking for one
+#[derive(Default)]
+pub struct Weather(pub Option<WindGenerator>);
+
+impl Weather {
+    pub fn new(wind: Option<Wind>) -> Self {
+        Self(wind.map(WindGenerator::new))
+    }
+
+    /// Blow for a step of `dt` seconds
+    pub fn blow(&mut self, grid: &mut Grid, dt: f32) {
+        if let Some(generator) = &mut self.0 {
+            let acceleration = generator.advance(dt);
+            for cell in grid.0.iter_mut().flatten().filter(|cell| !cell.obstacle) {
+                cell.velocity += acceleration * dt;
+            }
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use bevy::prelude::*;
+use bevy::render::texture::{Extent3d, TextureDimension, TextureFormat};
+
+use crate::layers::Layers;
+use crate::palette::Palette;
+use crate::post;
+use crate::render;
+use crate::scenes::ScenePreset;
+use crate::settings::{SolverPreset, SolverSettings};
+use crate::solver::{self, Scratch, Splats};
+use crate::Grid;
+
+// Live fluid in bevy_ui: a FluidWidgetBundle is an ImageBundle whose texture shows its
+// own small simulation, stepped every frame, so it can sit in any UI layout, e.g. as
+// the background of a panel or the effect of a loading screen. The widgets don't touch
+// the main grid, and run with the fast preset unless told otherwise.
+
+pub struct FluidWidgetPlugin;
+
+impl Plugin for FluidWidgetPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system(fluid_widget_system.system());
+    }
+}
+
+pub struct FluidWidget {
+    scene: ScenePreset,
+    grid: Grid,
+    pub settings: SolverSettings,
+    pub layers: Layers,
+    /// Seconds after which the scene starts over, so the widget never settles down
+    pub restart_every: Option<f32>,
+    since_restart: f32,
+    splats: Splats,
+    scratch: Scratch,
+}
+
+impl FluidWidget {
+    pub fn new(scene: ScenePreset, width: usize, height: usize) -> Self {
+        Self {
+            scene,
+            grid: scene.build(width, height),
+            settings: SolverPreset::Fast.settings(),
+            layers: Layers::default(),
+            restart_every: None,
+            since_restart: 0.0,
+            splats: Splats::default(),
+            scratch: Scratch::default(),
+        }
+    }
+
+    pub fn scene(&self) -> ScenePreset {
+        self.scene
+    }
+
+    /// Start over with another scene, keeping the grid size
+    pub fn set_scene(&mut self, scene: ScenePreset) {
+        self.scene = scene;
+        self.restart();
+    }
+
+    pub fn restart(&mut self) {
+        self.grid = self.scene.build(self.grid.width(), self.grid.height());
+        self.since_restart = 0.0;
+    }
+}
+
+#[derive(Bundle)]
+pub struct FluidWidgetBundle {
+    pub widget: FluidWidget,
+    #[bundle]
+    pub image: ImageBundle,
+}
+
+impl FluidWidgetBundle {
+    /// A widget of the given size in the layout, its texture having one pixel per cell
+    pub fn new(
+        widget: FluidWidget,
+        size: Size<Val>,
+        textures: &mut Assets<Texture>,
+        materials: &mut Assets<ColorMaterial>,
+    ) -> Self {
+        let (width, height) = (widget.grid.width(), widget.grid.height());
+        let texture = textures.add(Texture::new(
+            Extent3d::new(width as u32, height as u32, 1),
+            TextureDimension::D2,
+            vec![0; width * height * 4],
+            TextureFormat::Rgba8UnormSrgb,
+        ));
+
+        Self {
+            widget,
+            image: ImageBundle {
+                style: Style {
+                    size,
+                    ..Default::default()
+                },
+                material: materials.add(texture.into()),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// Step every widget and redraw its texture
+fn fluid_widget_system(
+    time: Res<Time>,
+    mut textures: ResMut<Assets<Texture>>,
+    materials: Res<Assets<ColorMaterial>>,
+    mut query: Query<(&mut FluidWidget, &Handle<ColorMaterial>)>,
+) {
+    let dt = time.delta_seconds();
+    for (mut widget, material) in query.iter_mut() {
+        let widget = &mut *widget;
+        widget.since_restart += dt;
+        if matches!(widget.restart_every, Some(seconds) if widget.since_restart > seconds) {
+            widget.restart();
+        }
+        solver::step(
+            &mut widget.grid,
+            dt,
+            &widget.settings,
+            &mut widget.splats,
+            &mut widget.scratch,
+        );
+
+        let handle = materials.get(material).and_then(|m| m.texture.as_ref());
+        if let Some(texture) = handle.and_then(|handle| textures.get_mut(handle)) {
+            let frame = post::compose(&widget.grid, &Palette::default(), &widget.layers, &[]);
+            texture.data = render::frame_rgba(&frame);
+        }
+    }
+}